@@ -0,0 +1,268 @@
+// Scene archives: a scene file bundled with the models and textures it uses,
+// so it can be shared and rendered anywhere.
+//
+// Archives are plain (ustar) tar files, so they can also be inspected with
+// `tar -tf`. The scene is stored as scene.txt, and assets keep their paths
+// relative to the working directory (assets/models/..., assets/textures/...).
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use crate::scene_file::SceneFile;
+
+pub const SCENE_FILENAME: &str = "scene.txt";
+
+const BLOCK_SIZE: usize = 512;
+
+// bundle a scene and its assets into an archive, returning the number of
+// files written
+pub fn pack(scene_path: &Path, archive_path: &Path) -> Result<usize, String> {
+    let assets = SceneFile::referenced_assets(scene_path)
+        .map_err(|e| format!("Failed to parse scene: {e:?}"))?;
+
+    // texture names refer to folders, so pack everything in them
+    let mut files = Vec::new();
+    for asset in assets {
+        if asset.is_dir() {
+            let mut entries = std::fs::read_dir(&asset)
+                .map_err(|e| format!("{}: {e}", asset.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            entries.sort();
+            files.extend(entries.into_iter().filter(|path| path.is_file()));
+        } else {
+            files.push(asset);
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let archive = File::create(archive_path).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(archive);
+
+    let scene = std::fs::read(scene_path).map_err(|e| e.to_string())?;
+    write_entry(&mut writer, SCENE_FILENAME, &scene)?;
+
+    for file in &files {
+        let contents = std::fs::read(file).map_err(|e| format!("{}: {e}", file.display()))?;
+        let name = file.to_string_lossy().replace('\\', "/");
+        write_entry(&mut writer, &name, &contents)?;
+    }
+
+    // end of archive marker
+    writer
+        .write_all(&[0; BLOCK_SIZE * 2])
+        .map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+
+    Ok(files.len() + 1)
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut header = [0; BLOCK_SIZE];
+    file.read_exact(&mut header).is_ok() && &header[257..262] == b"ustar"
+}
+
+// extract an archive into a fresh folder, returning the folder. each
+// process has folders of its own, so two renders of archives with the same
+// name don't clear out each other's.
+pub fn unpack(archive_path: &Path) -> Result<PathBuf, String> {
+    let stem = archive_path
+        .file_stem()
+        .map_or("scene".into(), |stem| stem.to_string_lossy());
+    let folder = std::env::temp_dir()
+        .join("rust-raytracer")
+        .join(format!("{stem}-{}", std::process::id()));
+
+    // ignore errors, the folder might not exist yet
+    let _ = std::fs::remove_dir_all(&folder);
+
+    let archive = File::open(archive_path).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(archive);
+
+    loop {
+        let mut header = [0; BLOCK_SIZE];
+        reader.read_exact(&mut header).map_err(|e| e.to_string())?;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+
+        let name = read_string(&header[0..100]);
+        let size = read_octal(&header[124..136])?;
+        let typeflag = header[156];
+
+        // read as much as is there rather than trusting the size, so a
+        // corrupt header can't ask for an absurd amount of memory
+        let mut contents = Vec::new();
+        (&mut reader)
+            .take(size as u64)
+            .read_to_end(&mut contents)
+            .map_err(|e| e.to_string())?;
+        let padding = (BLOCK_SIZE - size % BLOCK_SIZE) % BLOCK_SIZE;
+        let skipped = io::copy(&mut (&mut reader).take(padding as u64), &mut io::sink())
+            .map_err(|e| e.to_string())?;
+        if contents.len() != size || skipped != padding as u64 {
+            return Err(format!("Archive ends in the middle of {name}"));
+        }
+
+        // only regular files, other entries aren't written by pack()
+        if typeflag != b'0' && typeflag != 0 {
+            continue;
+        }
+
+        // don't let a malicious archive write outside the folder
+        let path = Path::new(&name);
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("Invalid path in archive: {name}"));
+        }
+
+        let path = folder.join(path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&path, contents).map_err(|e| e.to_string())?;
+    }
+
+    if !folder.join(SCENE_FILENAME).exists() {
+        return Err(format!("Archive has no {SCENE_FILENAME}"));
+    }
+    Ok(folder)
+}
+
+fn write_entry(writer: &mut impl Write, name: &str, contents: &[u8]) -> Result<(), String> {
+    if name.len() >= 100 {
+        return Err(format!("Path too long to archive: {name}"));
+    }
+
+    let mut header = [0; BLOCK_SIZE];
+    header[0..name.len()].copy_from_slice(name.as_bytes());
+    write_octal(&mut header[100..108], 0o644); // mode
+    write_octal(&mut header[108..116], 0); // uid
+    write_octal(&mut header[116..124], 0); // gid
+    write_octal(&mut header[124..136], contents.len());
+    write_octal(&mut header[136..148], 0); // mtime
+    header[156] = b'0'; // regular file
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // the checksum is calculated with its own field set to spaces
+    header[148..156].fill(b' ');
+    let checksum: usize = header.iter().map(|b| *b as usize).sum();
+    write_octal(&mut header[148..155], checksum);
+
+    let padding = (BLOCK_SIZE - contents.len() % BLOCK_SIZE) % BLOCK_SIZE;
+    writer.write_all(&header).map_err(|e| e.to_string())?;
+    writer.write_all(contents).map_err(|e| e.to_string())?;
+    writer
+        .write_all(&vec![0; padding])
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// zero padded octal, followed by a nul
+fn write_octal(field: &mut [u8], value: usize) {
+    let digits = field.len() - 1;
+    let octal = format!("{value:0digits$o}");
+    field[..digits].copy_from_slice(octal.as_bytes());
+    field[digits] = 0;
+}
+
+fn read_octal(field: &[u8]) -> Result<usize, String> {
+    let octal = read_string(field);
+    let octal = octal.trim_matches(|c: char| c == ' ' || c == '\0');
+    usize::from_str_radix(octal, 8).map_err(|_| format!("Invalid number in archive: {octal}"))
+}
+
+fn read_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an archive path in the temporary folder, unpacked into a folder of
+    // its own name
+    fn temp_archive(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-raytracer-{name}.tar"))
+    }
+
+    #[test]
+    fn round_trip() {
+        let scene = Path::new("assets/scenes/patches.txt");
+        let archive = temp_archive("round-trip");
+        assert_eq!(pack(scene, &archive), Ok(2));
+        assert!(is_archive(&archive));
+        assert!(!is_archive(scene));
+
+        let folder = unpack(&archive).unwrap();
+        let unpacked = |path: &str| std::fs::read(folder.join(path)).unwrap();
+        assert_eq!(unpacked(SCENE_FILENAME), std::fs::read(scene).unwrap());
+        assert_eq!(
+            unpacked("assets/models/wave.bpt"),
+            std::fs::read("assets/models/wave.bpt").unwrap()
+        );
+
+        std::fs::remove_file(&archive).unwrap();
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_folder() {
+        let names = [
+            "../escaped.txt",
+            "/tmp/escaped.txt",
+            "assets/../../escaped.txt",
+        ];
+        for (index, name) in names.into_iter().enumerate() {
+            let mut contents = Vec::new();
+            write_entry(&mut contents, SCENE_FILENAME, b"scene Scene\n").unwrap();
+            write_entry(&mut contents, name, b"escaped").unwrap();
+            contents.extend([0; BLOCK_SIZE * 2]);
+
+            let archive = temp_archive(&format!("escape-{index}"));
+            std::fs::write(&archive, contents).unwrap();
+            let result = unpack(&archive);
+            std::fs::remove_file(&archive).unwrap();
+            assert_eq!(result, Err(format!("Invalid path in archive: {name}")));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_entries() {
+        let mut contents = Vec::new();
+        write_entry(&mut contents, SCENE_FILENAME, b"scene Scene\n").unwrap();
+        // far bigger than the whole archive
+        write_octal(&mut contents[124..136], 0o77_777_777_777);
+
+        let archive = temp_archive("truncated");
+        std::fs::write(&archive, contents).unwrap();
+        let result = unpack(&archive);
+        std::fs::remove_file(&archive).unwrap();
+        let e = format!("Archive ends in the middle of {SCENE_FILENAME}");
+        assert_eq!(result, Err(e));
+    }
+
+    #[test]
+    fn rejects_long_paths() {
+        let name = "a/".repeat(50);
+        assert!(write_entry(&mut Vec::new(), &name, b"").is_err());
+    }
+
+    #[test]
+    fn octal_fields() {
+        let mut field = [0; 12];
+        write_octal(&mut field, 0o1234567);
+        assert_eq!(&field, b"00001234567\0");
+        assert_eq!(read_octal(&field), Ok(0o1234567));
+        assert!(read_octal(b"12x4\0").is_err());
+    }
+}
@@ -0,0 +1,475 @@
+// Builders for putting scenes together in code, without going through a
+// scene file:
+//
+//     let scene = SceneBuilder::new()
+//         .add(Sphere::builder().centre(0.0, 0.0, 5.0).radius(1.0))
+//         .light(PointLight::new(Vertex::new(0.0, 5.0, 0.0), Colour::white()))
+//         .camera(FullCamera::builder().res(256).fov(60.0))
+//         .build();
+//
+// defaults match the scene file's where it has them.
+
+use std::sync::Arc;
+
+use crate::{
+    cameras::full_camera::FullCamera,
+    core::{
+        animation::Animation,
+        bloom::Bloom,
+        colour::{Colour, ColourSpace},
+        depth_of_field::DepthOfField,
+        framebuffer::{DepthOutput, MissDepth},
+        random,
+        resolve::ResolvePolicy,
+        sampler::Sampler,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::{
+        environment::Environment,
+        photon_scene::{GatherMethod, PhotonScene},
+        scene::Scene,
+    },
+    lights::light::Light,
+    materials::{compound_material::CompoundMaterial, material::Material},
+    objects::{cuboid_object::Cuboid, object::Object, plane_object::Plane, sphere_object::Sphere},
+    scene_file::LoadedScene,
+};
+
+// anything that can be added to a SceneBuilder: object builders, and
+// objects that have already been constructed
+pub trait IntoObject {
+    fn into_object(self) -> Box<dyn Object>;
+}
+
+impl IntoObject for Box<dyn Object> {
+    fn into_object(self) -> Box<dyn Object> {
+        self
+    }
+}
+
+impl<T: Object + 'static> IntoObject for Box<T> {
+    fn into_object(self) -> Box<dyn Object> {
+        self
+    }
+}
+
+pub struct SceneBuilder {
+    environment: Box<dyn Environment>,
+    camera: Option<CameraBuilder>,
+    animation: Option<Animation>,
+    seed: Option<u64>,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuilder {
+    // a plain raytraced scene
+    pub fn new() -> Self {
+        Self::with_environment(Box::new(Scene::new()))
+    }
+
+    // a photon mapped scene
+    pub fn photon() -> Self {
+        Self::with_environment(Box::new(PhotonScene::new()))
+    }
+
+    pub fn photon_with_gather(gather_method: GatherMethod) -> Self {
+        let mut scene = PhotonScene::new();
+        scene.gather_method = gather_method;
+        Self::with_environment(Box::new(scene))
+    }
+
+    // e.g. a Scene with a trace_limit set
+    pub fn with_environment(environment: Box<dyn Environment>) -> Self {
+        Self {
+            environment,
+            camera: None,
+            animation: None,
+            seed: None,
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(mut self, object: impl IntoObject) -> Self {
+        self.environment.add_object(object.into_object());
+        self
+    }
+
+    pub fn light(mut self, light: Box<dyn Light>) -> Self {
+        self.environment.add_light(light);
+        self
+    }
+
+    pub fn camera(mut self, camera: CameraBuilder) -> Self {
+        self.camera = Some(camera);
+        self
+    }
+
+    pub fn animation(mut self, animation: Animation) -> Self {
+        self.animation = Some(animation);
+        self
+    }
+
+    // makes renders of the scene reproducible, like the scene file's seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // without a camera, the default one (at the origin looking down +z) is used
+    pub fn build(self) -> LoadedScene {
+        random::set_seed(self.seed);
+        let mut scene = LoadedScene {
+            environment: self.environment,
+            camera: Box::new(self.camera.unwrap_or_default().build()),
+            other_cameras: Vec::new(),
+            animation: self.animation,
+            errors: Vec::new(),
+        };
+        scene.frame_cameras();
+        scene
+    }
+}
+
+// the material objects get if none is given, plain matte white
+fn default_material() -> Arc<dyn Material> {
+    CompoundMaterial::new_simple(Colour::white(), 0.0, 20.0)
+}
+
+pub struct SphereBuilder {
+    centre: Vertex,
+    radius: f32,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Sphere {
+    pub fn builder() -> SphereBuilder {
+        SphereBuilder {
+            centre: Vertex::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: None,
+        }
+    }
+}
+
+impl SphereBuilder {
+    pub fn centre(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.centre = Vertex::new(x, y, z);
+        self
+    }
+
+    pub fn radius(mut self, radius: f32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Box<Sphere> {
+        let material = self.material.unwrap_or_else(default_material);
+        Sphere::new(self.centre, self.radius, material)
+    }
+}
+
+impl IntoObject for SphereBuilder {
+    fn into_object(self) -> Box<dyn Object> {
+        self.build()
+    }
+}
+
+pub struct CuboidBuilder {
+    corner: Vertex,
+    size: Vector,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Cuboid {
+    pub fn builder() -> CuboidBuilder {
+        CuboidBuilder {
+            corner: Vertex::new(0.0, 0.0, 0.0),
+            size: Vector::new(1.0, 1.0, 1.0),
+            material: None,
+        }
+    }
+}
+
+impl CuboidBuilder {
+    pub fn corner(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.corner = Vertex::new(x, y, z);
+        self
+    }
+
+    pub fn size(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.size = Vector::new(x, y, z);
+        self
+    }
+
+    pub fn material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Box<Cuboid> {
+        let material = self.material.unwrap_or_else(default_material);
+        Cuboid::new(self.corner, self.size, material)
+    }
+}
+
+impl IntoObject for CuboidBuilder {
+    fn into_object(self) -> Box<dyn Object> {
+        self.build()
+    }
+}
+
+pub struct PlaneBuilder {
+    point: Vertex,
+    up: Vector,
+    normal: Vector,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Plane {
+    // a floor through the origin by default
+    pub fn builder() -> PlaneBuilder {
+        PlaneBuilder {
+            point: Vertex::new(0.0, 0.0, 0.0),
+            up: Vector::new(0.0, 0.0, 1.0),
+            normal: Vector::new(0.0, 1.0, 0.0),
+            material: None,
+        }
+    }
+}
+
+impl PlaneBuilder {
+    pub fn point(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.point = Vertex::new(x, y, z);
+        self
+    }
+
+    pub fn up(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.up = Vector::new(x, y, z);
+        self
+    }
+
+    pub fn normal(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.normal = Vector::new(x, y, z);
+        self
+    }
+
+    pub fn material(mut self, material: Arc<dyn Material>) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn build(self) -> Box<Plane> {
+        let material = self.material.unwrap_or_else(default_material);
+        Plane::new(&self.point, self.up, self.normal, material)
+    }
+}
+
+impl IntoObject for PlaneBuilder {
+    fn into_object(self) -> Box<dyn Object> {
+        self.build()
+    }
+}
+
+#[derive(Clone)]
+pub struct CameraBuilder {
+    width: u32,
+    height: Option<u32>,
+    fov: f32, // degrees
+    position: Vertex,
+    lookat: Vector,
+    up: Option<Vector>,
+    samples: u32,
+    sampler: Sampler,
+    depth_resolve: ResolvePolicy,
+    miss_depth: MissDepth,
+    depth_output: DepthOutput,
+    colour_space: ColourSpace,
+    auto_frame: bool,
+    depth_of_field: Option<DepthOfField>,
+    bloom: Option<Bloom>,
+    shutter: f32,
+    near: f32,
+    far: f32,
+    region: Option<[f32; 4]>,
+    threads: Option<u32>,
+}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
+        Self {
+            width: 1024,
+            height: None,
+            fov: 40.0,
+            position: Vertex::new(0.0, 0.0, 0.0),
+            lookat: Vector::new(0.0, 0.0, 1.0),
+            up: None,
+            samples: 1,
+            sampler: Sampler::Grid,
+            depth_resolve: ResolvePolicy::Closest,
+            miss_depth: MissDepth::Infinity,
+            depth_output: DepthOutput::default(),
+            colour_space: ColourSpace::Srgb,
+            auto_frame: false,
+            depth_of_field: None,
+            bloom: None,
+            shutter: 0.0,
+            near: 0.0,
+            far: f32::INFINITY,
+            region: None,
+            threads: None,
+        }
+    }
+}
+
+impl FullCamera {
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+}
+
+impl CameraBuilder {
+    // square image
+    pub fn res(mut self, res: u32) -> Self {
+        self.width = res;
+        self.height = None;
+        self
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = Some(height);
+        self
+    }
+
+    pub fn fov(mut self, degrees: f32) -> Self {
+        self.fov = degrees;
+        self
+    }
+
+    pub fn position(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.position = Vertex::new(x, y, z);
+        self
+    }
+
+    pub fn lookat(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.lookat = Vector::new(x, y, z);
+        self
+    }
+
+    // must be perpendicular to lookat. defaults to lookat tilted up by 90
+    // degrees.
+    pub fn up(mut self, x: f32, y: f32, z: f32) -> Self {
+        self.up = Some(Vector::new(x, y, z));
+        self
+    }
+
+    pub fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn sampler(mut self, sampler: Sampler) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn depth_resolve(mut self, depth_resolve: ResolvePolicy) -> Self {
+        self.depth_resolve = depth_resolve;
+        self
+    }
+
+    pub fn miss_depth(mut self, miss_depth: MissDepth) -> Self {
+        self.miss_depth = miss_depth;
+        self
+    }
+
+    pub fn depth_output(mut self, depth_output: DepthOutput) -> Self {
+        self.depth_output = depth_output;
+        self
+    }
+
+    pub fn colour_space(mut self, colour_space: ColourSpace) -> Self {
+        self.colour_space = colour_space;
+        self
+    }
+
+    // move back along lookat until the whole scene is in view, see
+    // FullCamera::frame()
+    pub fn auto_frame(mut self, auto_frame: bool) -> Self {
+        self.auto_frame = auto_frame;
+        self
+    }
+
+    pub fn depth_of_field(mut self, depth_of_field: DepthOfField) -> Self {
+        self.depth_of_field = Some(depth_of_field);
+        self
+    }
+
+    pub fn bloom(mut self, bloom: Bloom) -> Self {
+        self.bloom = Some(bloom);
+        self
+    }
+
+    pub fn shutter(mut self, shutter: f32) -> Self {
+        self.shutter = shutter.max(0.0);
+        self
+    }
+
+    // camera rays only see what's between near and far in front of it
+    pub fn clipping(mut self, near: f32, far: f32) -> Self {
+        self.near = near.max(0.0);
+        self.far = far.max(self.near);
+        self
+    }
+
+    // only render part of the image, from 0 to 1 across and down it
+    pub fn region(mut self, x0: f32, y0: f32, x1: f32, y1: f32) -> Self {
+        self.region = Some([x0, y0, x1, y1]);
+        self
+    }
+
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    pub fn build(self) -> FullCamera {
+        let up = self
+            .up
+            .unwrap_or(Vector::new(0.0, self.lookat.z, -self.lookat.y));
+        let mut camera = FullCamera::new(
+            self.width,
+            self.height.unwrap_or(self.width),
+            self.fov.to_radians(),
+            self.position,
+            self.lookat,
+            up,
+        );
+        camera.samples = self.samples;
+        camera.sampler = self.sampler;
+        camera.depth_resolve = self.depth_resolve;
+        camera.miss_depth = self.miss_depth;
+        camera.depth_output = self.depth_output;
+        camera.colour_space = self.colour_space;
+        camera.auto_frame = self.auto_frame;
+        camera.depth_of_field = self.depth_of_field;
+        camera.bloom = self.bloom;
+        camera.shutter = self.shutter;
+        camera.near = self.near;
+        camera.far = self.far;
+        camera.region = self.region;
+        camera.threads = self.threads;
+        camera
+    }
+}
@@ -1,47 +1,348 @@
-use crate::{core::framebuffer::FrameBuffer, environments::environment::Environment};
+use std::{
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    core::{
+        cancel,
+        colour::ColourSpace,
+        error::RenderError,
+        framebuffer::{FrameBuffer, RgbStream},
+        platform, stats,
+    },
+    environments::environment::Environment,
+};
+
+const TILE_SIZE: u32 = 32;
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+// a rectangle of pixels that is rendered in one go by a single thread
+#[derive(Debug, Copy, Clone)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    // split a width x height image into tiles, in spread out order
+    pub fn split(width: u32, height: u32) -> Vec<Self> {
+        Self::split_within(&Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+    }
+
+    // the same for just part of an image
+    pub fn split_within(region: &Self) -> Vec<Self> {
+        let mut tiles = Vec::new();
+        let (right, bottom) = (region.x + region.width, region.y + region.height);
+
+        for y in (region.y..bottom).step_by(TILE_SIZE as usize) {
+            for x in (region.x..right).step_by(TILE_SIZE as usize) {
+                tiles.push(Self {
+                    x,
+                    y,
+                    width: TILE_SIZE.min(right - x),
+                    height: TILE_SIZE.min(bottom - y),
+                });
+            }
+        }
+
+        spread(tiles)
+    }
+
+    // the overlap of two tiles, if there is any
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+        let right = (self.x + self.width).min(other.x + other.width);
+        let bottom = (self.y + self.height).min(other.y + other.height);
+        if right <= x || bottom <= y {
+            return None;
+        }
+        Some(Self {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        })
+    }
+}
+
+// reorder tiles so that each next one lands in the biggest gap left by the
+// ones before, rather than row by row. partly rendered images then show a
+// coarse version of the whole thing instead of only the top. the order is
+// the bit reversal of each tile's index, a van der Corput sequence.
+fn spread(tiles: Vec<Tile>) -> Vec<Tile> {
+    let bits = tiles.len().next_power_of_two().trailing_zeros();
+    if bits == 0 {
+        return tiles;
+    }
+
+    (0..1usize << bits)
+        .map(|i| i.reverse_bits() >> (usize::BITS - bits))
+        .filter_map(|index| tiles.get(index).copied())
+        .collect()
+}
 
 pub trait Camera: Send {
     fn width(&self) -> u32;
     fn height(&self) -> u32;
 
-    fn render(&self, environment: &mut dyn Environment) -> FrameBuffer
+    // number of threads to render with, None for one per core
+    fn threads(&self) -> Option<u32> {
+        None
+    }
+
+    // the part of the image to render, None for all of it. the rest is left
+    // black.
+    fn region(&self) -> Option<Tile> {
+        None
+    }
+
+    // what streamed images are encoded in
+    fn colour_space(&self) -> ColourSpace {
+        ColourSpace::Srgb
+    }
+
+    // on_update is called from the calling thread every so often with the
+    // image rendered so far, e.g. to display it in a preview window
+    fn render(
+        &self,
+        environment: &mut dyn Environment,
+        on_update: &mut dyn FnMut(&FrameBuffer),
+    ) -> FrameBuffer
     where
-        for<'a> &'a Self: Send,
+        Self: Sync,
     {
         environment.pre_render();
-        let environment = environment as &dyn Environment;
+        self.render_prepared(environment, on_update)
+    }
 
-        let num_threads = std::thread::available_parallelism().map_or(4, |n| n.get()) as u32;
-        // let num_threads = 1;
-        let rows_per_thread = self.height() / num_threads;
-        let extra_rows = self.height() % num_threads;
-        println!("Spawning {num_threads} threads to render {rows_per_thread} rows each...");
+    // render() without the pre_render() step, for when the environment has
+    // already been prepared (e.g. by an earlier pass)
+    fn render_prepared(
+        &self,
+        environment: &dyn Environment,
+        on_update: &mut dyn FnMut(&FrameBuffer),
+    ) -> FrameBuffer
+    where
+        Self: Sync,
+    {
+        let tiles = match self.region() {
+            Some(region) => Tile::split_within(&region),
+            None => Tile::split(self.width(), self.height()),
+        };
+        let progress = Progress::new(tiles.len() as u32);
 
-        let mut framebuffers = Vec::new();
+        #[cfg(feature = "threads")]
+        println!(
+            "Spawning {} threads to render {} tiles...",
+            num_threads(self),
+            progress.total
+        );
 
-        std::thread::scope(|scope| {
-            let mut threads = Vec::new();
+        let full_image = Tile {
+            x: 0,
+            y: 0,
+            width: self.width(),
+            height: self.height(),
+        };
+        let framebuffer =
+            render_tiles(self, environment, &full_image, &tiles, &progress, on_update);
+        println!();
+        stats::record_phase("render", progress.start.elapsed().as_secs_f32());
 
-            for thread_index in 0..num_threads {
-                let start_y = thread_index * rows_per_thread;
-                let mut end_y = (thread_index + 1) * rows_per_thread;
+        on_update(&framebuffer);
+        framebuffer
+    }
 
-                if thread_index == num_threads - 1 {
-                    end_y += extra_rows;
-                }
+    // render() for images too big to keep in memory. the image is rendered
+    // a band of rows at a time, and each band is written to filename as
+    // soon as it's done, so only the colours end up anywhere.
+    fn render_streamed(
+        &self,
+        environment: &dyn Environment,
+        filename: &PathBuf,
+    ) -> Result<(), RenderError>
+    where
+        Self: Sync,
+    {
+        let (width, height) = (self.width(), self.height());
+        let region = self.region().unwrap_or(Tile {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        });
+        let bands: Vec<Tile> = (0..height)
+            .step_by(TILE_SIZE as usize)
+            .map(|y| Tile {
+                x: 0,
+                y,
+                width,
+                height: TILE_SIZE.min(height - y),
+            })
+            .collect();
+        let band_tiles = |band: &Tile| match band.intersection(&region) {
+            Some(area) => Tile::split_within(&area),
+            None => Vec::new(),
+        };
+
+        let num_tiles = bands.iter().map(|band| band_tiles(band).len() as u32).sum();
+        let progress = Progress::new(num_tiles);
+        println!(
+            "Streaming {num_tiles} tiles in {} bands to {}...",
+            bands.len(),
+            filename.display()
+        );
 
-                let thread = scope.spawn(move || self.render_rows(environment, start_y, end_y));
-                threads.push(thread);
+        let mut stream = RgbStream::create(filename, width, height, self.colour_space())?;
+        for band in &bands {
+            if cancel::cancelled() {
+                break;
             }
+            let tiles = band_tiles(band);
+            let framebuffer = render_tiles(self, environment, band, &tiles, &progress, &mut |_| {});
+            stream.write_rows(&framebuffer)?;
+        }
+        println!();
+        stats::record_phase("render", progress.start.elapsed().as_secs_f32());
+
+        stream.finish()
+    }
+
+    // render a tile into a framebuffer the size of the tile
+    fn render_tile(&self, environment: &dyn Environment, tile: &Tile) -> FrameBuffer;
+}
+
+// how far through a render is, over however many render_tiles() calls it
+// takes
+struct Progress {
+    done: AtomicU32,
+    total: u32,
+    start: platform::Instant,
+}
+
+impl Progress {
+    fn new(total: u32) -> Self {
+        Self {
+            done: AtomicU32::new(0),
+            total,
+            start: platform::Instant::now(),
+        }
+    }
+
+    fn tile_done(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        print_progress(done, self.total, self.start);
+    }
+}
 
-            for thread in threads {
-                framebuffers.push(thread.join().unwrap());
+#[cfg(feature = "threads")]
+fn num_threads<C: Camera + ?Sized>(camera: &C) -> u32 {
+    camera
+        .threads()
+        .unwrap_or_else(|| platform::num_threads() as u32)
+}
+
+// render tiles into a framebuffer covering frame, which they must be inside
+fn render_tiles<C: Camera + Sync + ?Sized>(
+    camera: &C,
+    environment: &dyn Environment,
+    frame: &Tile,
+    tiles: &[Tile],
+    progress: &Progress,
+    on_update: &mut dyn FnMut(&FrameBuffer),
+) -> FrameBuffer {
+    let framebuffer = Mutex::new(FrameBuffer::new(frame.width, frame.height));
+
+    let render_and_blit = |tile: &Tile| {
+        let tile_start = platform::Instant::now();
+        let tile_framebuffer = camera.render_tile(environment, tile);
+        let seconds = tile_start.elapsed().as_secs_f32();
+        stats::record_tile(camera.width(), camera.height(), tile, seconds);
+        stats::flush();
+
+        framebuffer
+            .lock()
+            .unwrap()
+            .blit(&tile_framebuffer, tile.x - frame.x, tile.y - frame.y);
+
+        progress.tile_done();
+    };
+
+    // threads grab the next tile off the queue when they finish one, so
+    // a thread stuck on an expensive part of the image doesn't hold
+    // everyone else up
+    #[cfg(feature = "threads")]
+    {
+        let next_tile = AtomicU32::new(0);
+        let (next_tile, render_and_blit) = (&next_tile, &render_and_blit);
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..num_threads(camera))
+                .map(|thread_index| {
+                    scope.spawn(move || {
+                        platform::pin_thread(thread_index as usize);
+                        loop {
+                            if cancel::cancelled() {
+                                break;
+                            }
+                            let tile_index = next_tile.fetch_add(1, Ordering::Relaxed);
+                            let Some(tile) = tiles.get(tile_index as usize) else {
+                                break;
+                            };
+                            render_and_blit(tile);
+                        }
+                    })
+                })
+                .collect();
+
+            // once cancelled, the threads finish the tiles they're on and
+            // the rest are left blank. a thread that panics never finishes
+            // its tile, so wait for the threads rather than the tiles.
+            while !threads.iter().all(|thread| thread.is_finished()) {
+                std::thread::sleep(UPDATE_INTERVAL);
+                if let Ok(framebuffer) = framebuffer.lock() {
+                    on_update(&framebuffer);
+                }
             }
+            platform::join_all(threads);
         });
+    }
 
-        FrameBuffer::combine_rows(framebuffers)
+    #[cfg(not(feature = "threads"))]
+    for tile in tiles {
+        if cancel::cancelled() {
+            break;
+        }
+        render_and_blit(tile);
+        on_update(&framebuffer.lock().unwrap());
     }
 
-    fn render_rows(&self, environment: &dyn Environment, start_y: u32, end_y: u32)
-        -> FrameBuffer;
+    framebuffer.into_inner().unwrap()
+}
+
+pub fn print_progress(done: u32, total: u32, start: platform::Instant) {
+    let progress = done as f32 / total as f32;
+
+    let elapsed = start.elapsed().as_secs_f32();
+    let eta = elapsed / progress - elapsed;
+    let percent = (progress * 100.0) as u32;
+
+    let mut stdout = std::io::stdout().lock();
+    let _ = write!(
+        stdout,
+        "\r{percent}% ({done}/{total} tiles) {elapsed:.2}s elapsed, {eta:.2}s ETA"
+    );
+    let _ = stdout.flush();
 }
@@ -1,15 +1,52 @@
-use std::io::Write;
+use std::ops::Range;
 
 use crate::{
     core::{
-        framebuffer::FrameBuffer, ray::Ray, transform::Transform, vector::Vector, vertex::Vertex,
+        animation::Keyframes,
+        bloom::Bloom,
+        cancel,
+        colour::{Colour, ColourSpace},
+        depth_of_field::DepthOfField,
+        framebuffer::{DepthOutput, FrameBuffer, MissDepth},
+        random,
+        ray::Ray,
+        resolve::{PixelSamples, ResolvePolicy},
+        sampler::{self, Sampler},
+        stats::{self, Counter},
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
     },
     environments::environment::{Environment, RaytraceResult},
 };
 
-use super::camera::Camera;
+#[cfg(feature = "gpu")]
+use crate::gpu::mesh_tracer::{self, FirstHit};
 
+use super::camera::{Camera, Tile};
+
+// experimental time-of-flight rendering: bin i is an image of only the light
+// whose total path length (light to surfaces to camera) is in
+// start + i * bin_length .. start + (i + 1) * bin_length
+#[derive(Debug, Clone)]
+pub struct TransientBins {
+    pub start: f32,
+    pub bin_length: f32,
+    pub bins: u32,
+}
+
+impl TransientBins {
+    pub fn window(&self, bin: u32) -> Range<f32> {
+        let start = self.start + bin as f32 * self.bin_length;
+        start..start + self.bin_length
+    }
+}
+
+#[derive(Clone)]
 pub struct FullCamera {
+    // scenes with more than one camera render each into a directory named
+    // after it
+    pub name: Option<String>,
     pub width: u32,
     pub height: u32,
     pub fov: f32,
@@ -17,6 +54,59 @@ pub struct FullCamera {
     pub lookat: Vector,
     pub up: Vector,
     pub right: Vector,
+    // move the camera back along lookat until the whole scene fits in the
+    // image, see frame()
+    pub auto_frame: bool,
+
+    // supersampling: each pixel gets samples x samples samples, placed by
+    // the sampler
+    pub samples: u32,
+    pub sampler: Sampler,
+    pub depth_resolve: ResolvePolicy, // also resolves the AOV channels
+    pub miss_depth: MissDepth,
+    pub depth_output: DepthOutput,
+    // what the 8-bit images are encoded in. the PFMs are always linear.
+    pub colour_space: ColourSpace,
+    // clipping planes: camera rays only see what's between near and far in
+    // front of the camera
+    pub near: f32,
+    pub far: f32,
+    // only render this part of the image, as x0 y0 x1 y1 from 0 to 1 across
+    // and down it. the rest is left black.
+    pub region: Option<[f32; 4]>,
+    // write normal, albedo, object ID and position images alongside the render
+    pub write_aovs: bool,
+    // write the lighting split into diffuse, specular, indirect, caustic and
+    // emission images alongside the render
+    pub write_lighting_passes: bool,
+    // à-trous filter passes over the finished image, 0 for none
+    pub denoise_passes: u32,
+    // how many standard deviations brighter than the rest of its pixel a
+    // sample has to be to be left out as a firefly, 0 to keep them all
+    pub outlier_rejection: f32,
+    // blur what's out of focus using the depth channel, after denoising
+    pub depth_of_field: Option<DepthOfField>,
+    // make what's too bright for the image glow, after depth of field
+    pub bloom: Option<Bloom>,
+
+    // how long the shutter stays open (in seconds) after frame_time, each ray
+    // is traced at a random time within it. moving objects blur.
+    pub shutter: f32,
+    pub frame_time: f32,
+    // camera movement, its position is already set for frame_time
+    pub keyframes: Option<Keyframes>,
+
+    // render quick low resolution passes before the full one
+    pub progressive: bool,
+
+    // also render a stack of time-of-flight images after the normal one
+    pub transient: Option<TransientBins>,
+
+    // draw wireframes of the lights and inactive cameras over the image
+    pub show_gizmos: bool,
+    pub inactive_cameras: Vec<FullCamera>,
+
+    pub threads: Option<u32>,
 }
 
 impl FullCamera {
@@ -47,6 +137,7 @@ impl FullCamera {
         right.normalise();
 
         Self {
+            name: None,
             width,
             height,
             fov,
@@ -54,17 +145,224 @@ impl FullCamera {
             lookat,
             up,
             right,
+            auto_frame: false,
+            samples: 1,
+            sampler: Sampler::default(),
+            depth_resolve: ResolvePolicy::Closest,
+            miss_depth: MissDepth::Infinity,
+            depth_output: DepthOutput::default(),
+            colour_space: ColourSpace::Srgb,
+            near: 0.0,
+            far: f32::INFINITY,
+            region: None,
+            write_aovs: false,
+            write_lighting_passes: false,
+            denoise_passes: 0,
+            outlier_rejection: 0.0,
+            depth_of_field: None,
+            bloom: None,
+            shutter: 0.0,
+            frame_time: 0.0,
+            keyframes: None,
+            progressive: false,
+            transient: None,
+            show_gizmos: false,
+            inactive_cameras: Vec::new(),
+            threads: None,
         }
     }
 
+    // keep looking the same way, but from far enough back along lookat that
+    // the box from min to max fits in the image
+    pub fn frame(&mut self, min: &Vertex, max: &Vertex) {
+        let centre = min.clone() + min.vector_to(max) * 0.5;
+        let radius = (min.distance(max) / 2.0).max(f32::EPSILON);
+        // the image's edges are 0.5 either side of the middle, fov away
+        let half_angle = (0.5 / self.fov).atan();
+        self.position = centre - self.lookat * (radius / half_angle.sin());
+    }
+
+    // turn the camera by angle radians around the vertical line through
+    // centre, so it sees centre from the same angle as before
+    pub fn orbit(&mut self, centre: &Vertex, angle: f32) {
+        let rotation = Transform::from_rotation(Vector::new(0.0, angle, 0.0));
+        let mut offset = centre.vector_to(&self.position);
+        offset.apply_transform(&rotation);
+        self.position = centre.clone() + offset;
+        self.lookat.apply_transform(&rotation);
+        self.up.apply_transform(&rotation);
+        self.right.apply_transform(&rotation);
+    }
+
+    // render at 1/8th, 1/4th and 1/2 resolution with one sample per pixel,
+    // then at full quality. on_pass is called with each pass, upscaled to
+    // full resolution. the environment must already be prepared, see
+    // Camera::render_prepared().
+    pub fn render_progressive(
+        &self,
+        environment: &dyn Environment,
+        mut on_pass: impl FnMut(&FrameBuffer),
+        on_update: &mut dyn FnMut(&FrameBuffer),
+    ) -> FrameBuffer {
+        for divisor in [8, 4, 2] {
+            let mut preview = self.clone();
+            preview.width = self.width / divisor;
+            preview.height = self.height / divisor;
+            preview.samples = 1;
+            if preview.width == 0 || preview.height == 0 {
+                continue;
+            }
+
+            println!("Rendering preview pass at 1/{divisor} resolution...");
+            let mut on_preview_update = |framebuffer: &FrameBuffer| {
+                on_update(&framebuffer.upscaled(self.width, self.height))
+            };
+            let framebuffer = preview.render_prepared(environment, &mut on_preview_update);
+            let framebuffer = framebuffer.upscaled(self.width, self.height);
+            on_pass(&framebuffer);
+            if cancel::cancelled() {
+                return framebuffer;
+            }
+        }
+
+        println!("Rendering final pass...");
+        self.render_prepared(environment, on_update)
+    }
+
+    // render each of the transient bins in turn, calling on_bin with the bin
+    // index and its image. the environment must already be prepared (i.e.
+    // rendered normally first). fails if the environment doesn't support
+    // time-of-flight rendering.
+    pub fn render_transient(
+        &self,
+        environment: &mut dyn Environment,
+        mut on_bin: impl FnMut(u32, &FrameBuffer),
+        on_update: &mut dyn FnMut(&FrameBuffer),
+    ) -> Result<(), String> {
+        let Some(transient) = &self.transient else {
+            return Ok(());
+        };
+
+        for bin in 0..transient.bins {
+            if cancel::cancelled() {
+                break;
+            }
+            let window = transient.window(bin);
+            println!(
+                "Rendering transient bin {}/{} (path length {:.2}..{:.2})...",
+                bin + 1,
+                transient.bins,
+                window.start,
+                window.end
+            );
+            if !environment.set_path_window(Some(window)) {
+                return Err("environment does not support transient rendering".to_string());
+            }
+
+            let framebuffer = self.render_prepared(environment, on_update);
+            on_bin(bin, &framebuffer);
+        }
+
+        environment.set_path_window(None);
+        Ok(())
+    }
+
     // given a pixel coordinate, compute the corresponding ray
     pub fn get_ray_pixel(&self, x: u32, y: u32) -> Ray {
+        self.get_ray_subpixel(x, y, 0.5, 0.5)
+    }
+
+    // same as get_ray_pixel(), but offset within the pixel.
+    // 0 <= dx, dy < 1, with (0.5, 0.5) being the centre of the pixel.
+    pub fn get_ray_subpixel(&self, x: u32, y: u32, dx: f32, dy: f32) -> Ray {
         assert!(x < self.width && y < self.height);
 
-        let fx = (x as f32 + 0.5) / self.width as f32; // 0 <= fx < 1
-        let fy = (y as f32 + 0.5) / self.height as f32; // 0 <= fy < 1
+        let fx = (x as f32 + dx) / self.width as f32; // 0 <= fx < 1
+        let fy = (y as f32 + dy) / self.height as f32; // 0 <= fy < 1
+
+        if self.shutter == 0.0 {
+            return Ray::new(self.position.clone(), self.direction(fx, fy));
+        }
+
+        let time = sampler::next_1d() * self.shutter;
+        let mut position = self.position.clone();
+        if let Some(keyframes) = &self.keyframes {
+            position += keyframes.delta(self.frame_time, time);
+        }
+        Ray::new(position, self.direction(fx, fy)).at_time(time)
+    }
+
+    // raytrace a camera ray, without anything outside the clipping planes
+    fn raytrace_clipped(&self, environment: &dyn Environment, mut ray: Ray) -> RaytraceResult {
+        if self.near == 0.0 && self.far == f32::INFINITY {
+            return environment.raytrace(&ray);
+        }
+
+        let skipped = self.skip_to_near(&mut ray);
+        let mut result = environment.raytrace(&ray);
+        result.depth += skipped;
+        if result.depth * ray.direction.dot(&self.lookat) > self.far {
+            return RaytraceResult::none();
+        }
+        result
+    }
+
+    // move a camera ray forward to the near clipping plane, returning how
+    // far it went
+    fn skip_to_near(&self, ray: &mut Ray) -> f32 {
+        // the plane is flat, so it's further away towards the edges
+        let skipped = self.near / ray.direction.dot(&self.lookat);
+        ray.position = ray.position.clone() + ray.direction * skipped;
+        ray.travelled = skipped;
+        skipped
+    }
 
-        let position = self.position.clone();
+    // the rays render_tile() raytraces, in the same order, for tracing them
+    // all at once on the GPU. rays that draw from the random numbers left by
+    // shading the samples before them (e.g. with the Random sampler) can't
+    // be known in advance, and won't match.
+    #[cfg(feature = "gpu")]
+    fn tile_rays(&self, tile: &Tile) -> Vec<Ray> {
+        let count = self.samples * self.samples;
+        let mut rays = Vec::with_capacity((tile.width * tile.height * count) as usize);
+
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                random::reseed(random::key(random::PIXEL_STREAM, y * self.width + x));
+                for index in 0..count {
+                    sampler::begin(self.sampler, x, y, index, count);
+                    let (dx, dy) = sampler::next_2d();
+                    let mut ray = self.get_ray_subpixel(x, y, dx, dy);
+                    if self.near != 0.0 || self.far != f32::INFINITY {
+                        self.skip_to_near(&mut ray);
+                    }
+                    rays.push(ray);
+                }
+                sampler::end();
+            }
+        }
+
+        rays
+    }
+
+    // traces the tile's camera rays against the meshes on the GPU, returning
+    // their first hits, and lets the environment get ready to raytrace them
+    #[cfg(feature = "gpu")]
+    fn prepare_tile(&self, environment: &dyn Environment, tile: &Tile) -> Vec<FirstHit> {
+        let tracer = environment.mesh_tracer();
+        if tracer.is_none() && !environment.prepares_camera_rays() {
+            return Vec::new();
+        }
+        let rays = self.tile_rays(tile);
+        let first_hits = tracer.map_or_else(Vec::new, |tracer| tracer.first_hits(&rays));
+        if environment.prepares_camera_rays() {
+            environment.prepare_camera_rays(&rays, &first_hits);
+        }
+        first_hits
+    }
+
+    // direction through a point on the image, with 0 <= fx, fy <= 1
+    fn direction(&self, fx: f32, fy: f32) -> Vector {
         let mut direction = Vector::new(fx - 0.5, fy - 0.5, self.fov);
 
         let rotation_matrix = [
@@ -76,8 +374,87 @@ impl FullCamera {
         let rotation_matrix = rotation_matrix.transposed();
         direction.apply_transform(&rotation_matrix);
         direction.normalise();
+        direction
+    }
+
+    // inverse of direction(): where a point in camera space (x along right,
+    // y along up, z along lookat) lands on the image, in pixels
+    fn project(&self, point: &Vector) -> (f32, f32) {
+        let fx = point.x / point.z * self.fov + 0.5;
+        let fy = point.y / point.z * self.fov + 0.5;
+        (fx * self.width as f32, fy * self.height as f32)
+    }
+
+    fn to_camera_space(&self, point: &Vertex) -> Vector {
+        let offset = self.position.vector_to(point);
+        Vector::new(
+            offset.dot(&self.right),
+            offset.dot(&self.up),
+            offset.dot(&self.lookat),
+        )
+    }
+
+    // line segments outlining this camera's position and view frustum
+    pub fn gizmo(&self) -> Vec<(Vertex, Vertex)> {
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+            .map(|(fx, fy)| self.position.clone() + self.direction(fx, fy));
+
+        let mut lines = Vec::new();
+        for i in 0..corners.len() {
+            let next = (i + 1) % corners.len();
+            lines.push((self.position.clone(), corners[i].clone()));
+            lines.push((corners[i].clone(), corners[next].clone()));
+        }
+
+        // mark the top edge so the camera's roll is visible
+        let top = self.position.clone() + self.direction(0.5, -0.2);
+        lines.push((corners[0].clone(), top.clone()));
+        lines.push((top, corners[1].clone()));
+
+        lines
+    }
+
+    // draw world space line segments over a rendered image
+    pub fn draw_gizmo(
+        &self,
+        framebuffer: &mut FrameBuffer,
+        lines: &[(Vertex, Vertex)],
+        colour: &Colour,
+    ) {
+        const NEAR: f32 = 0.001;
+
+        for (start, end) in lines {
+            let mut start = self.to_camera_space(start);
+            let mut end = self.to_camera_space(end);
+
+            // clip the segment to the part in front of the camera
+            if start.z < NEAR && end.z < NEAR {
+                continue;
+            }
+            if start.z < NEAR || end.z < NEAR {
+                let t = (NEAR - start.z) / (end.z - start.z);
+                let clipped = start + (end - start) * t;
+                if start.z < NEAR {
+                    start = clipped;
+                } else {
+                    end = clipped;
+                }
+            }
 
-        Ray::new(position, direction)
+            let (x0, y0) = self.project(&start);
+            let (x1, y1) = self.project(&end);
+            framebuffer.draw_line(x0, y0, x1, y1, colour);
+        }
+    }
+
+    // draw the lights and any inactive cameras in the scene
+    pub fn draw_gizmos(&self, framebuffer: &mut FrameBuffer, environment: &dyn Environment) {
+        for light in environment.lights() {
+            self.draw_gizmo(framebuffer, &light.gizmo(), &Colour::new(1.0, 1.0, 0.0));
+        }
+        for camera in &self.inactive_cameras {
+            self.draw_gizmo(framebuffer, &camera.gizmo(), &Colour::new(0.0, 1.0, 1.0));
+        }
     }
 }
 
@@ -90,56 +467,67 @@ impl Camera for FullCamera {
         self.height
     }
 
-    fn render_rows(
-        &self,
-        environment: &dyn Environment,
-        start_y: u32,
-        end_y: u32,
-    ) -> FrameBuffer {
-        let mut framebuffer = FrameBuffer::new(self.width, end_y - start_y);
-        let start = std::time::Instant::now();
-
-        let is_first_thread = start_y == 0;
-        let mut stdout_lock = is_first_thread.then(|| std::io::stdout().lock());
-
-        for y in start_y..end_y {
-            for x in 0..self.width {
-                let ray = self.get_ray_pixel(x, y);
-                let RaytraceResult { colour, depth } = environment.raytrace(&ray);
-
-                framebuffer.plot_pixel(x, y - start_y, &colour);
-                framebuffer.plot_depth(x, y - start_y, depth);
-            }
+    fn threads(&self) -> Option<u32> {
+        self.threads
+    }
 
-            // print ETA
+    fn colour_space(&self) -> ColourSpace {
+        self.colour_space
+    }
 
-            if !is_first_thread {
-                continue;
-            }
+    fn region(&self) -> Option<Tile> {
+        let [x0, y0, x1, y1] = self.region?;
+        // every pixel the region touches, and at least one
+        let left = ((x0 * self.width as f32) as u32).min(self.width - 1);
+        let top = ((y0 * self.height as f32) as u32).min(self.height - 1);
+        let right = ((x1 * self.width as f32).ceil() as u32).clamp(left + 1, self.width);
+        let bottom = ((y1 * self.height as f32).ceil() as u32).clamp(top + 1, self.height);
+        Some(Tile {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
 
-            // only print for first, last two, and every 5th row
-            if y > 0 && y < end_y - 1 && y % 5 != 0 {
-                continue;
-            }
+    fn render_tile(&self, environment: &dyn Environment, tile: &Tile) -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(tile.width, tile.height);
+        let count = self.samples * self.samples;
+        let mut samples = PixelSamples::with_capacity(count as usize);
 
-            let Some(stdout) = &mut stdout_lock else {
-                panic!("stdout lock is None");
-            };
+        #[cfg(feature = "gpu")]
+        let mut first_hits = self.prepare_tile(environment, tile).into_iter();
 
-            let height = end_y;
-            let progress = (y + 1) as f32 / height as f32;
+        for y in tile.y..tile.y + tile.height {
+            for x in tile.x..tile.x + tile.width {
+                samples.clear();
+                random::reseed(random::key(random::PIXEL_STREAM, y * self.width + x));
 
-            let elapsed = start.elapsed().as_secs_f32();
-            let eta = elapsed / progress - elapsed;
-            let percent = (progress * 100.0) as u32;
+                for index in 0..count {
+                    sampler::begin(self.sampler, x, y, index, count);
+                    let (dx, dy) = sampler::next_2d();
+                    let ray = self.get_ray_subpixel(x, y, dx, dy);
+                    stats::count(Counter::PrimaryRays);
+                    #[cfg(feature = "gpu")]
+                    if let Some(first_hit) = first_hits.next() {
+                        mesh_tracer::expect_first_hit(first_hit);
+                    }
+                    samples.push(self.raytrace_clipped(environment, ray));
+                }
+                sampler::end();
 
-            // print!("\r{percent}% {elapsed:.2}s elapsed, {eta:.2}s ETA");
-            let _ = write!(stdout, "\r{percent}% {elapsed:.2}s elapsed, {eta:.2}s ETA");
-            let _ = stdout.flush();
-        }
+                if self.outlier_rejection > 0.0 {
+                    let rejected = samples.reject_outliers(self.outlier_rejection);
+                    stats::add(Counter::RejectedSamples, rejected as u64);
+                }
 
-        if start_y == 0 {
-            println!();
+                let (tx, ty) = (x - tile.x, y - tile.y);
+                framebuffer.plot_pixel(tx, ty, &samples.colour());
+                framebuffer.plot_depth(tx, ty, samples.depth(self.depth_resolve));
+                framebuffer.plot_surface(tx, ty, samples.surface(self.depth_resolve));
+                framebuffer.plot_throughput(tx, ty, samples.throughput());
+                framebuffer.plot_lighting(tx, ty, samples.lighting());
+            }
         }
 
         framebuffer
@@ -0,0 +1,373 @@
+// Command line options.
+
+use std::{ops::Range, path::PathBuf};
+
+use rust_raytracer::{core::compare::Threshold, scene_file::SceneOverride};
+
+const USAGE: &str = "\
+Usage: rust-raytracer [OPTIONS] [SCENE]
+       rust-raytracer --pack <SCENE> <ARCHIVE>
+       rust-raytracer --diff <REFERENCE> <IMAGE> [--threshold <METRIC=VALUE>] [--output <DIR>]
+       rust-raytracer --worker [--listen <ADDR>] [--threads <N>]
+
+Renders SCENE (default assets/scenes/scene2.txt), re-rendering whenever it changes.
+SCENE can also be an archive created with --pack.
+
+Options:
+  -o, --output <DIR>           directory to write the render to (default render)
+      --width <PIXELS>         override the camera width
+      --height <PIXELS>        override the camera height
+      --samples <N>            override the camera's samples per pixel (N x N)
+      --threads <N>            number of render and photon threads (default:
+                               one per core)
+      --reserve-core           leave a core free for the preview window
+      --pin-threads            keep each thread on its own core (Linux only)
+      --frames <START..END>    render an animation, frames START to END - 1
+      --fps <N>                frames per second of the animation (default 24)
+      --turntable <N>          render N frames of the camera going once around
+                               the middle of the scene, instead of its
+                               animation
+      --video <FORMAT>         what to assemble the frames of an animation into,
+                               mp4 (default) or gif
+      --bracket <EV>           also write the image EV stops darker and brighter,
+                               e.g. 2 for -2EV and +2EV
+      --photon-preview         build quick, low quality photon maps, see the
+                               scene's preview attribute
+      --stream                 write the image a band of rows at a time as it
+                               renders, instead of keeping it all in memory,
+                               for very big images. only writes rgb.ppm
+      --stats                  print ray counts and timings after each render,
+                               and write them to stats.json and tiles.png
+      --once                   render once and exit
+      --watch                  re-render when the scene file changes, skipping
+                               invalid paragraphs (default)
+  -s, --scene-override <KIND.KEY=VALUE>
+                               set an attribute on every paragraph of a kind,
+                               e.g. camera.fov=60 or light.colour=\"1 0 0\",
+                               or its class, e.g. scene.class=PhotonScene
+      --compare <KIND.KEY=VALUE>
+                               also render the scene with this override, and
+                               write both side by side and their difference
+      --pack <SCENE> <ARCHIVE> bundle a scene and the models and textures it
+                               uses into a single archive, then exit
+      --diff <REFERENCE> <IMAGE>
+                               print how far IMAGE is from REFERENCE (PPM or
+                               anything FFmpeg reads) and write a false colour
+                               map of where they differ to diff.png, then exit
+      --threshold <METRIC=VALUE>
+                               make --diff exit with status 1 if the images
+                               are further apart than this, e.g. rmse=0.01 or
+                               ssim=0.98 (at least). the metrics are
+                               mean_absolute, max_absolute, mean_relative,
+                               rmse and ssim
+      --workers <ADDR,...>     share the rendering with workers at these
+                               addresses, e.g. farm1:7878,farm2:7878
+      --worker                 wait for jobs from --workers and render them,
+                               instead of rendering a scene
+      --listen <ADDR>          address for --worker to listen on (default
+                               127.0.0.1:7878). anyone who can connect can
+                               make it render, so only listen on trusted
+                               networks
+  -h, --help                   print this message";
+
+#[derive(Clone)]
+pub struct Options {
+    pub scene_filename: String,
+    pub output: PathBuf,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub threads: Option<u32>,
+    pub reserve_core: bool,
+    pub pin_threads: bool,
+    pub frames: Option<Range<u32>>,
+    pub fps: Option<f32>,
+    pub turntable: Option<u32>,
+    pub video: VideoFormat,
+    pub bracket: Option<f32>,
+    pub stream: bool,
+    pub stats: bool,
+    pub watch: bool,
+    pub overrides: Vec<SceneOverride>,
+    // the second version's overrides, on top of overrides, see --compare
+    pub compare: Vec<SceneOverride>,
+    pub pack: Option<(String, PathBuf)>,
+    pub diff: Option<(PathBuf, PathBuf)>,
+    pub thresholds: Vec<Threshold>,
+    pub workers: Vec<String>,
+    pub worker: bool,
+    pub listen: String,
+}
+
+// what the frames of an animation are assembled into by FFmpeg
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum VideoFormat {
+    #[default]
+    Mp4,
+    Gif,
+}
+
+impl VideoFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mp4" => Some(Self::Mp4),
+            "gif" => Some(Self::Gif),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Gif => "gif",
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            scene_filename: "assets/scenes/scene2.txt".to_string(),
+            output: PathBuf::from("render"),
+            width: None,
+            height: None,
+            samples: None,
+            threads: None,
+            reserve_core: false,
+            pin_threads: false,
+            frames: None,
+            fps: None,
+            turntable: None,
+            video: VideoFormat::default(),
+            bracket: None,
+            stream: false,
+            stats: false,
+            watch: true,
+            overrides: Vec::new(),
+            compare: Vec::new(),
+            pack: None,
+            diff: None,
+            thresholds: Vec::new(),
+            workers: Vec::new(),
+            worker: false,
+            listen: "127.0.0.1:7878".to_string(),
+        }
+    }
+}
+
+impl Options {
+    // parse the process arguments, printing usage and exiting on --help or
+    // invalid arguments
+    pub fn from_env() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("{e}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // returns None if --help was passed
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Self::default();
+        let mut scene_filename = None;
+        let mut args = args;
+
+        while let Some(arg) = args.next() {
+            // --flag=value is the same as --flag value
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") => (flag.to_string(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline_value {
+                Some(value) => Ok(value.to_string()),
+                None => args
+                    .next()
+                    .ok_or_else(|| format!("Missing value for {flag}")),
+            };
+
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-o" | "--output" => options.output = PathBuf::from(value()?),
+                "--width" => options.width = Some(parse_number(&flag, &value()?)?),
+                "--height" => options.height = Some(parse_number(&flag, &value()?)?),
+                "--samples" => options.samples = Some(parse_number(&flag, &value()?)?),
+                "--threads" => options.threads = Some(parse_number(&flag, &value()?)?),
+                "--frames" => options.frames = Some(parse_range(&flag, &value()?)?),
+                "--fps" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(fps) if fps > 0.0 => options.fps = Some(fps),
+                        _ => return Err(format!("Invalid value for {flag}: {value}")),
+                    }
+                }
+                "--turntable" => options.turntable = Some(parse_number(&flag, &value()?)?),
+                "--video" => {
+                    let value = value()?;
+                    options.video = VideoFormat::from_name(&value)
+                        .ok_or_else(|| format!("Invalid value for {flag}: {value}"))?;
+                }
+                "--bracket" => {
+                    let value = value()?;
+                    match value.parse() {
+                        Ok(ev) if ev > 0.0 => options.bracket = Some(ev),
+                        _ => return Err(format!("Invalid value for {flag}: {value}")),
+                    }
+                }
+                "--pack" => {
+                    let scene = value()?;
+                    let archive = args
+                        .next()
+                        .ok_or_else(|| format!("Missing archive path for {flag}"))?;
+                    options.pack = Some((scene, PathBuf::from(archive)));
+                }
+                "--diff" => {
+                    let reference = value()?;
+                    let image = args
+                        .next()
+                        .ok_or_else(|| format!("Missing image to compare for {flag}"))?;
+                    options.diff = Some((PathBuf::from(reference), PathBuf::from(image)));
+                }
+                "--threshold" => {
+                    let value = value()?;
+                    let threshold = Threshold::parse(&value)
+                        .ok_or_else(|| format!("Invalid value for {flag}: {value}"))?;
+                    options.thresholds.push(threshold);
+                }
+                "--photon-preview" => {
+                    let preview = SceneOverride::parse("scene.preview=1").unwrap();
+                    options.overrides.push(preview);
+                }
+                "--workers" => {
+                    let value = value()?;
+                    options.workers = value.split(',').map(str::to_string).collect();
+                    if options.workers.iter().any(|worker| worker.is_empty()) {
+                        return Err(format!("Invalid value for {flag}: {value}"));
+                    }
+                }
+                "--worker" => options.worker = true,
+                "--listen" => options.listen = value()?,
+                "--reserve-core" => options.reserve_core = true,
+                "--pin-threads" => options.pin_threads = true,
+                "--stream" => options.stream = true,
+                "--stats" => options.stats = true,
+                "--once" => options.watch = false,
+                "--watch" => options.watch = true,
+                "-s" | "--scene-override" => {
+                    let value = value()?;
+                    let scene_override = SceneOverride::parse(&value)
+                        .ok_or_else(|| format!("Invalid scene override: {value}"))?;
+                    options.overrides.push(scene_override);
+                }
+                "--compare" => {
+                    let value = value()?;
+                    let scene_override = SceneOverride::parse(&value)
+                        .ok_or_else(|| format!("Invalid scene override: {value}"))?;
+                    options.compare.push(scene_override);
+                }
+                _ if flag.starts_with('-') => return Err(format!("Unknown option: {flag}")),
+                _ => {
+                    if scene_filename.is_some() {
+                        return Err(format!("Unexpected argument: {arg}"));
+                    }
+                    scene_filename = Some(arg);
+                }
+            }
+        }
+
+        if let Some(scene_filename) = scene_filename {
+            options.scene_filename = scene_filename;
+        }
+        Ok(Some(options))
+    }
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid value for {flag}: {value}")),
+    }
+}
+
+// "0..120", or a single frame "7"
+fn parse_range(flag: &str, value: &str) -> Result<Range<u32>, String> {
+    let invalid = || format!("Invalid value for {flag}: {value}");
+
+    let range = match value.split_once("..") {
+        Some((start, end)) => {
+            let start = start.parse().map_err(|_| invalid())?;
+            let end = end.parse().map_err(|_| invalid())?;
+            start..end
+        }
+        None => {
+            let frame = value.parse().map_err(|_| invalid())?;
+            frame..frame + 1
+        }
+    };
+
+    if range.is_empty() {
+        return Err(invalid());
+    }
+    Ok(range)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_raytracer::{cameras::camera::Camera, scene_file::SceneFile};
+
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn frame_ranges() {
+        assert_eq!(parse_range("--frames", "0..120"), Ok(0..120));
+        assert_eq!(parse_range("--frames", "7"), Ok(7..8));
+        for invalid in ["5..5", "9..3", "a..3", "1..", "..4", "-1", ""] {
+            assert!(parse_range("--frames", invalid).is_err(), "{invalid}");
+        }
+
+        let options = parse(&["--frames=2..4", "scene.txt"]).unwrap().unwrap();
+        assert_eq!(options.frames, Some(2..4));
+        assert_eq!(options.scene_filename, "scene.txt");
+    }
+
+    #[test]
+    fn scene_overrides() {
+        let options = parse(&[
+            "-s",
+            "camera.width=33",
+            "--scene-override=camera.height=22",
+            "--scene-override",
+            "scene.class=PhotonScene",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.overrides.len(), 3);
+
+        let contents = "scene Scene\n\ncamera Camera\n".to_string();
+        let scene = SceneFile::from_contents(contents, &options.overrides[..2], 0.0).unwrap();
+        assert_eq!((scene.camera.width(), scene.camera.height()), (33, 22));
+
+        for invalid in ["camera.width", "width=3", ".width=3", "camera.=3"] {
+            assert!(parse(&["-s", invalid]).is_err(), "{invalid}");
+        }
+        assert!(parse(&["-s"]).is_err());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["--video", "avi"]).is_err());
+        assert!(parse(&["a.txt", "b.txt"]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+// Keyframed animation of objects and cameras over a sequence of frames, and
+// objects moving while the shutter is open.
+
+use std::ops::Range;
+
+use super::{transform::Transform, vector::Vector};
+
+// the frames of the sequence, from the scene's animation paragraph
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub fps: f32,
+    pub frames: Range<u32>,
+}
+
+impl Animation {
+    pub fn time_of(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps
+    }
+}
+
+// offsets at points in time (in seconds), linearly interpolated in between.
+// before the first and after the last keyframe the offset stays constant.
+#[derive(Debug, Clone)]
+pub struct Keyframes {
+    keys: Vec<(f32, Vector)>,
+}
+
+impl Keyframes {
+    pub fn new(mut keys: Vec<(f32, Vector)>) -> Self {
+        assert!(!keys.is_empty(), "Keyframes need at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn at(&self, time: f32) -> Vector {
+        let next = self.keys.partition_point(|(t, _)| *t <= time);
+
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+
+        let (t0, v0) = self.keys[next - 1];
+        let (t1, v1) = self.keys[next];
+        let amount = (time - t0) / (t1 - t0);
+        v0 * (1.0 - amount) + v1 * amount
+    }
+
+    // how far the offset moves from frame_time to frame_time + time, used
+    // to move things while the shutter is open
+    pub fn delta(&self, frame_time: f32, time: f32) -> Vector {
+        if time == 0.0 {
+            return Vector::new(0.0, 0.0, 0.0);
+        }
+        self.at(frame_time + time) - self.at(frame_time)
+    }
+}
+
+// a placement of an object: scaled and then turned (by radians around x,
+// then y, then z) around the pivot, then moved by translate
+#[derive(Debug, Clone, Copy)]
+pub struct Pose {
+    pub translate: Vector,
+    pub rotate: Vector,
+    pub scale: Vector,
+    pub pivot: Vector,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            translate: Vector::zero(),
+            rotate: Vector::zero(),
+            scale: Vector::new(1.0, 1.0, 1.0),
+            pivot: Vector::zero(),
+        }
+    }
+}
+
+impl Pose {
+    pub fn transform(&self) -> Transform {
+        let scale = Transform::from_matrix([
+            [self.scale.x, 0.0, 0.0, 0.0],
+            [0.0, self.scale.y, 0.0, 0.0],
+            [0.0, 0.0, self.scale.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform::from_translation(self.translate + self.pivot)
+            * Transform::from_rotation(self.rotate)
+            * scale
+            * Transform::from_translation(-self.pivot)
+    }
+
+    // each part separately, so e.g. turning by 360 degrees spins all the way
+    // round instead of staying still
+    fn lerp(&self, other: &Self, amount: f32) -> Self {
+        let lerp = |a: Vector, b: Vector| a * (1.0 - amount) + b * amount;
+        Self {
+            translate: lerp(self.translate, other.translate),
+            rotate: lerp(self.rotate, other.rotate),
+            scale: lerp(self.scale, other.scale),
+            pivot: lerp(self.pivot, other.pivot),
+        }
+    }
+}
+
+// a rigid object's placement going from start when the shutter opens to end
+// end_time seconds later, and staying there after
+#[derive(Debug, Clone)]
+pub struct Motion {
+    pub start: Pose,
+    pub end: Pose,
+    pub end_time: f32,
+}
+
+impl Motion {
+    pub fn at(&self, time: f32) -> Pose {
+        self.start
+            .lerp(&self.end, (time / self.end_time).clamp(0.0, 1.0))
+    }
+}
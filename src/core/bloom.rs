@@ -0,0 +1,99 @@
+// Bloom: light brighter than the image can show spills into the pixels
+// around it, like it does in a camera lens or an eye, so bright caustics,
+// highlights and lights glow instead of just clipping to white. The part of
+// each pixel over a threshold is blurred with a gaussian (one row pass and
+// one column pass) and added back on top of the image.
+
+use super::{colour::Colour, framebuffer::FrameBuffer};
+
+// the blur is cut off this many standard deviations out
+const KERNEL_SIGMAS: f32 = 3.0;
+
+#[derive(Debug, Clone)]
+pub struct Bloom {
+    pub threshold: f32, // brightness (the largest of r, g and b) that glows
+    pub radius: f32,    // the blur's standard deviation, as a fraction of the image width
+    pub strength: f32,  // how much of the glow is added back
+}
+
+impl Bloom {
+    pub fn apply(&self, framebuffer: &FrameBuffer) -> FrameBuffer {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let bright: Vec<Colour> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.over_threshold(framebuffer.get_colour(x, y)))
+            .collect();
+
+        let kernel = gaussian(self.radius * width as f32);
+        let rows = blur(&bright, width, height, &kernel, true);
+        let glow = blur(&rows, width, height, &kernel, false);
+
+        let mut bloomed = framebuffer.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let colour = framebuffer.get_colour(x, y);
+                let glow = glow[(y * width + x) as usize];
+                bloomed.plot_pixel(x, y, &(colour + glow * self.strength));
+            }
+        }
+        bloomed
+    }
+
+    // the part of the colour brighter than the threshold, keeping its hue
+    fn over_threshold(&self, colour: Colour) -> Colour {
+        let brightness = colour.max_component();
+        if brightness <= self.threshold {
+            return Colour::black();
+        }
+        colour * ((brightness - self.threshold) / brightness)
+    }
+}
+
+// the weights from the middle of a gaussian outwards, adding up to 1 over
+// both sides
+fn gaussian(sigma: f32) -> Vec<f32> {
+    let sigma = sigma.max(0.5);
+    let half_width = (sigma * KERNEL_SIGMAS).ceil() as usize;
+    let mut kernel: Vec<f32> = (0..=half_width)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total = kernel[0] + 2.0 * kernel[1..].iter().sum::<f32>();
+    for weight in &mut kernel {
+        *weight /= total;
+    }
+    kernel
+}
+
+// blur along the rows if horizontal, else down the columns. past the edges
+// the edge pixel carries on.
+fn blur(
+    pixels: &[Colour],
+    width: u32,
+    height: u32,
+    kernel: &[f32],
+    horizontal: bool,
+) -> Vec<Colour> {
+    let (width, height) = (width as i64, height as i64);
+    let at = |x: i64, y: i64| {
+        let (x, y) = (x.clamp(0, width - 1), y.clamp(0, height - 1));
+        pixels[(y * width + x) as usize]
+    };
+
+    let mut blurred = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let mut total = at(x, y) * kernel[0];
+            for (offset, weight) in kernel.iter().enumerate().skip(1) {
+                let offset = offset as i64;
+                let (before, after) = if horizontal {
+                    (at(x - offset, y), at(x + offset, y))
+                } else {
+                    (at(x, y - offset), at(x, y + offset))
+                };
+                total += (before + after) * *weight;
+            }
+            blurred.push(total);
+        }
+    }
+    blurred
+}
@@ -0,0 +1,20 @@
+// Stopping a render early, e.g. when Ctrl-C is pressed. Long running loops
+// (rendering tiles, shooting photons) check cancelled() and stop where they
+// are, so whatever they finished can still be written out.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::Relaxed);
+}
+
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+// let renders run to the end again
+pub fn reset() {
+    CANCELLED.store(false, Ordering::Relaxed);
+}
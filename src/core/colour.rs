@@ -26,10 +26,68 @@ impl Colour {
         Self::new(1.0, 1.0, 1.0)
     }
 
+    // 0 to 255 per component
+    pub fn from_rgb8(r: u8, g: u8, b: u8) -> Self {
+        Self::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
+    }
+
+    // e.g. "#ff8800", or the short form "#f80"
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let digits = hex.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let component = |i: usize, width: usize| {
+            let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16).unwrap();
+            // "f" is short for "ff"
+            if width == 1 {
+                value * 17
+            } else {
+                value
+            }
+        };
+        let width = match digits.len() {
+            3 => 1,
+            6 => 2,
+            _ => return None,
+        };
+        Some(Self::from_rgb8(
+            component(0, width),
+            component(1, width),
+            component(2, width),
+        ))
+    }
+
+    // hue in degrees around the colour wheel from red, saturation and value
+    // from 0 to 1
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let hue = hue.rem_euclid(360.0) / 60.0;
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+        let (r, g, b) = match hue as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = value - chroma;
+        Self::new(r + m, g + m, b + m)
+    }
+
     pub fn is_black(&self) -> bool {
         self.r == 0.0 && self.g == 0.0 && self.b == 0.0
     }
 
+    pub fn sum(&self) -> f32 {
+        self.r + self.g + self.b
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.r.max(self.g).max(self.b)
+    }
+
     pub fn scale(&mut self, scalar: f32) {
         self.r *= scalar;
         self.g *= scalar;
@@ -39,6 +97,79 @@ impl Colour {
     pub fn scaled(&self, scalar: f32) -> Self {
         Self::new(self.r * scalar, self.g * scalar, self.b * scalar)
     }
+
+    pub fn map(&self, f: impl Fn(f32) -> f32) -> Self {
+        Self::new(f(self.r), f(self.g), f(self.b))
+    }
+
+    // rotate the hue by an angle in radians, keeping the brightness.
+    // this is a rotation of the rgb cube around its grey diagonal.
+    pub fn hue_rotated(&self, angle: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        let k = (1.0 - cos) / 3.0;
+        let s = sin / 3f32.sqrt();
+
+        Self::new(
+            self.r * (cos + k) + self.g * (k - s) + self.b * (k + s),
+            self.r * (k + s) + self.g * (cos + k) + self.b * (k - s),
+            self.r * (k - s) + self.g * (k + s) + self.b * (cos + k),
+        )
+    }
+}
+
+// how an image's values relate to light. shading is done in linear light,
+// where values add up like light does, but 8-bit images (photos, textures,
+// the render itself) are nearly always sRGB encoded, which spends more of
+// their 256 levels on the dark end.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ColourSpace {
+    #[default]
+    Srgb,
+    Linear, // the values as they are, e.g. for normal maps and other data
+}
+
+impl ColourSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Srgb" => Some(Self::Srgb),
+            "Linear" => Some(Self::Linear),
+            _ => None,
+        }
+    }
+
+    // from values read from an image to linear light
+    pub fn decode(&self, colour: Colour) -> Colour {
+        match self {
+            Self::Srgb => colour.map(srgb_to_linear),
+            Self::Linear => colour,
+        }
+    }
+
+    // from linear light to the values to write to an image
+    pub fn encode(&self, colour: Colour) -> Colour {
+        match self {
+            Self::Srgb => colour.map(linear_to_srgb),
+            Self::Linear => colour,
+        }
+    }
+}
+
+// the sRGB transfer function: a short straight segment near black, then
+// roughly a 2.2 gamma curve
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
 }
 
 impl std::ops::Mul<Colour> for Colour {
@@ -65,6 +196,14 @@ impl std::ops::Add<Colour> for Colour {
     }
 }
 
+impl std::ops::Sub<Colour> for Colour {
+    type Output = Colour;
+
+    fn sub(self, rhs: Colour) -> Self::Output {
+        Self::new(self.r - rhs.r, self.g - rhs.g, self.b - rhs.b)
+    }
+}
+
 impl std::ops::Mul<f32> for Colour {
     type Output = Colour;
 
@@ -88,3 +227,29 @@ impl std::ops::AddAssign<Colour> for Colour {
         self.b += rhs.b;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex() {
+        let orange = Some(Colour::from_rgb8(255, 136, 0));
+        assert_eq!(Colour::from_hex("#ff8800"), orange);
+        assert_eq!(Colour::from_hex("#FF8800"), orange);
+        assert_eq!(Colour::from_hex("#f80"), orange);
+
+        for hex in ["ff8800", "#", "#ff88", "#ff88000", "#ff880000", "#ff88zz"] {
+            assert_eq!(Colour::from_hex(hex), None, "{hex}");
+        }
+    }
+
+    #[test]
+    fn from_hsv() {
+        let red = Colour::new(1.0, 0.0, 0.0);
+        assert_eq!(Colour::from_hsv(0.0, 1.0, 1.0), red);
+        assert_eq!(Colour::from_hsv(120.0, 1.0, 1.0), Colour::new(0.0, 1.0, 0.0));
+        assert_eq!(Colour::from_hsv(360.0, 1.0, 1.0), red);
+        assert_eq!(Colour::from_hsv(240.0, 0.0, 0.5), Colour::grey(0.5));
+    }
+}
@@ -0,0 +1,355 @@
+// Two renders of the same view next to each other, to judge what a change
+// (e.g. Scene vs PhotonScene, or more samples) does to the image: split
+// down the middle, or as how far apart each pixel is. Also the numbers for
+// how far apart they are, for checking a render against a reference one.
+
+use std::{fmt, path::Path};
+
+use crate::{environments::photon_bake::heat_colour, materials::texture::Image};
+
+use super::{
+    colour::{Colour, ColourSpace},
+    error::RenderError,
+    framebuffer::FrameBuffer,
+};
+
+// relative errors are relative to the first image plus this, so black pixels
+// don't divide by zero
+const RELATIVE_EPSILON: f32 = 0.01;
+
+// SSIM compares the images in windows this many pixels across, this far
+// apart
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STRIDE: u32 = 4;
+
+// the line between the two halves of split()
+const DIVIDER: Colour = Colour {
+    r: 1.0,
+    g: 0.0,
+    b: 1.0,
+};
+
+// the left half of a and the right half of b, with a line between them.
+// a and b must be the same size.
+pub fn split(a: &FrameBuffer, b: &FrameBuffer) -> FrameBuffer {
+    assert!(a.width == b.width && a.height == b.height);
+
+    let middle = a.width / 2;
+    let mut split = FrameBuffer::new(a.width, a.height);
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let colour = match x.cmp(&middle) {
+                std::cmp::Ordering::Less => a.get_colour(x, y),
+                std::cmp::Ordering::Equal => DIVIDER,
+                std::cmp::Ordering::Greater => b.get_colour(x, y),
+            };
+            split.plot_pixel(x, y, &colour);
+        }
+    }
+    split
+}
+
+// how far apart a and b are in each colour channel, brightened so the
+// biggest difference is white. black if they're the same. a and b must be
+// the same size.
+pub fn difference(a: &FrameBuffer, b: &FrameBuffer) -> FrameBuffer {
+    assert!(a.width == b.width && a.height == b.height);
+
+    let apart = |x, y| {
+        let (a, b) = (a.get_colour(x, y), b.get_colour(x, y));
+        Colour::new((a.r - b.r).abs(), (a.g - b.g).abs(), (a.b - b.b).abs())
+    };
+    let pixels = (0..a.height).flat_map(|y| (0..a.width).map(move |x| (x, y)));
+    let biggest = pixels
+        .clone()
+        .map(|(x, y)| apart(x, y).max_component())
+        .fold(0.0, f32::max);
+    let scale = if biggest > 0.0 { 1.0 / biggest } else { 0.0 };
+
+    let mut difference = FrameBuffer::new(a.width, a.height);
+    for (x, y) in pixels {
+        difference.plot_pixel(x, y, &(apart(x, y) * scale));
+    }
+    difference
+}
+
+// the root mean square difference between a and b over every channel of
+// every pixel, 0 if they're the same. a and b must be the same size.
+pub fn rmse(a: &FrameBuffer, b: &FrameBuffer) -> f32 {
+    assert!(a.width == b.width && a.height == b.height);
+
+    let mut total = 0.0;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let (a, b) = (a.get_colour(x, y), b.get_colour(x, y));
+            total += (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2);
+        }
+    }
+    let channels = 3 * a.width as usize * a.height as usize;
+    (total / channels.max(1) as f32).sqrt()
+}
+
+// an 8-bit render (or any image), decoded from sRGB to linear like the
+// renderer works in. anything but a PPM needs FFmpeg to convert it.
+pub fn load(path: &Path) -> Result<FrameBuffer, RenderError> {
+    let image = if path.extension().is_some_and(|extension| extension == "ppm") {
+        Image::from_ppm(path.to_path_buf(), ColourSpace::Srgb)?
+    } else {
+        Image::from_image(path.to_path_buf(), ColourSpace::Srgb)?
+    };
+    Ok(image.to_framebuffer())
+}
+
+// how far apart two images of the same size are, see measure()
+pub struct Measurements {
+    pub mean_absolute: f32, // over every channel of every pixel
+    pub max_absolute: f32,
+    pub mean_relative: f32, // relative to the first image
+    pub rmse: f32,
+    pub ssim: f32, // structural similarity, 1 if they look the same
+}
+
+// a is the reference, b is compared to it. a and b must be the same size.
+pub fn measure(a: &FrameBuffer, b: &FrameBuffer) -> Measurements {
+    assert!(a.width == b.width && a.height == b.height);
+
+    let mut absolute = 0.0;
+    let mut max_absolute: f32 = 0.0;
+    let mut relative = 0.0;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let (a, b) = (a.get_colour(x, y), b.get_colour(x, y));
+            for (a, b) in [(a.r, b.r), (a.g, b.g), (a.b, b.b)] {
+                let error = (a - b).abs();
+                absolute += error;
+                max_absolute = max_absolute.max(error);
+                relative += error / (a.abs() + RELATIVE_EPSILON);
+            }
+        }
+    }
+    let channels = (3 * a.width as usize * a.height as usize).max(1) as f32;
+
+    Measurements {
+        mean_absolute: absolute / channels,
+        max_absolute,
+        mean_relative: relative / channels,
+        rmse: rmse(a, b),
+        ssim: ssim(a, b),
+    }
+}
+
+impl fmt::Display for Measurements {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Mean absolute error: {:.5}", self.mean_absolute)?;
+        writeln!(f, "Max absolute error: {:.5}", self.max_absolute)?;
+        writeln!(f, "Mean relative error: {:.5}", self.mean_relative)?;
+        writeln!(f, "RMS error: {:.5}", self.rmse)?;
+        write!(f, "SSIM: {:.5}", self.ssim)
+    }
+}
+
+// one of the Measurements
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    MeanAbsolute,
+    MaxAbsolute,
+    MeanRelative,
+    Rmse,
+    Ssim,
+}
+
+impl Metric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mean_absolute" => Some(Self::MeanAbsolute),
+            "max_absolute" => Some(Self::MaxAbsolute),
+            "mean_relative" => Some(Self::MeanRelative),
+            "rmse" => Some(Self::Rmse),
+            "ssim" => Some(Self::Ssim),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MeanAbsolute => "mean_absolute",
+            Self::MaxAbsolute => "max_absolute",
+            Self::MeanRelative => "mean_relative",
+            Self::Rmse => "rmse",
+            Self::Ssim => "ssim",
+        }
+    }
+}
+
+impl Measurements {
+    pub fn get(&self, metric: Metric) -> f32 {
+        match metric {
+            Metric::MeanAbsolute => self.mean_absolute,
+            Metric::MaxAbsolute => self.max_absolute,
+            Metric::MeanRelative => self.mean_relative,
+            Metric::Rmse => self.rmse,
+            Metric::Ssim => self.ssim,
+        }
+    }
+}
+
+// how far apart images can be before a regression check fails, e.g.
+// `rmse=0.01` for an RMS error of at most 0.01. SSIM is the other way
+// around, `ssim=0.98` being an SSIM of at least 0.98.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Threshold {
+    pub metric: Metric,
+    pub value: f32,
+}
+
+impl Threshold {
+    // "<metric>=<value>", None if it isn't one
+    pub fn parse(text: &str) -> Option<Self> {
+        let (metric, value) = text.split_once('=')?;
+        let metric = Metric::from_name(metric.trim())?;
+        let value: f32 = value.trim().parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        Some(Self { metric, value })
+    }
+
+    pub fn passes(&self, measurements: &Measurements) -> bool {
+        let measured = measurements.get(self.metric);
+        match self.metric {
+            Metric::Ssim => measured >= self.value,
+            _ => measured <= self.value,
+        }
+    }
+}
+
+// e.g. "rmse <= 0.01"
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comparison = match self.metric {
+            Metric::Ssim => ">=",
+            _ => "<=",
+        };
+        write!(f, "{} {comparison} {}", self.metric.name(), self.value)
+    }
+}
+
+// each pixel's mean error over its channels in false colour, from black
+// (the same) to white (the biggest error). a and b must be the same size.
+pub fn error_map(a: &FrameBuffer, b: &FrameBuffer) -> FrameBuffer {
+    assert!(a.width == b.width && a.height == b.height);
+
+    let error = |x, y| {
+        let (a, b) = (a.get_colour(x, y), b.get_colour(x, y));
+        ((a.r - b.r).abs() + (a.g - b.g).abs() + (a.b - b.b).abs()) / 3.0
+    };
+    let pixels = (0..a.height).flat_map(|y| (0..a.width).map(move |x| (x, y)));
+    let biggest = pixels.clone().map(|(x, y)| error(x, y)).fold(0.0, f32::max);
+
+    let mut map = FrameBuffer::new(a.width, a.height);
+    for (x, y) in pixels {
+        let amount = if biggest > 0.0 {
+            error(x, y) / biggest
+        } else {
+            0.0
+        };
+        map.plot_pixel(x, y, &heat_colour(amount));
+    }
+    map
+}
+
+// the mean structural similarity (Wang et al. 2004) of the images' sRGB
+// encoded luma, i.e. as they look, over windows spread across them
+fn ssim(a: &FrameBuffer, b: &FrameBuffer) -> f32 {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+
+    let luma = |framebuffer: &FrameBuffer, x, y| {
+        let colour = ColourSpace::Srgb.encode(framebuffer.get_colour(x, y));
+        (0.2126 * colour.r + 0.7152 * colour.g + 0.0722 * colour.b).clamp(0.0, 1.0)
+    };
+    // images smaller than a window are one window
+    let (window_width, window_height) = (SSIM_WINDOW.min(a.width), SSIM_WINDOW.min(a.height));
+    let count = (window_width * window_height) as f32;
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for top in (0..=a.height - window_height).step_by(SSIM_STRIDE as usize) {
+        for left in (0..=a.width - window_width).step_by(SSIM_STRIDE as usize) {
+            let pixels = (top..top + window_height)
+                .flat_map(|y| (left..left + window_width).map(move |x| (x, y)));
+            let (mut sum_a, mut sum_b) = (0.0, 0.0);
+            let (mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0);
+            for (x, y) in pixels {
+                let (la, lb) = (luma(a, x, y), luma(b, x, y));
+                sum_a += la;
+                sum_b += lb;
+                sum_aa += la * la;
+                sum_bb += lb * lb;
+                sum_ab += la * lb;
+            }
+
+            let (mean_a, mean_b) = (sum_a / count, sum_b / count);
+            let variance_a = sum_aa / count - mean_a * mean_a;
+            let variance_b = sum_bb / count - mean_b * mean_b;
+            let covariance = sum_ab / count - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // vertical stripes 4 pixels wide, moved right by shift pixels and
+    // brightened by offset
+    fn stripes(shift: u32, offset: f32) -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(32, 24);
+        for y in 0..24 {
+            for x in 0..32 {
+                let bright = (x + 32 - shift) / 4 % 2 == 1;
+                let grey = if bright { 0.8 } else { 0.1 };
+                framebuffer.plot_pixel(x, y, &Colour::grey(grey + offset));
+            }
+        }
+        framebuffer
+    }
+
+    #[test]
+    fn identical_images() {
+        let measured = measure(&stripes(0, 0.0), &stripes(0, 0.0));
+        assert_eq!(measured.mean_absolute, 0.0);
+        assert_eq!(measured.max_absolute, 0.0);
+        assert_eq!(measured.mean_relative, 0.0);
+        assert_eq!(measured.rmse, 0.0);
+        assert!((measured.ssim - 1.0).abs() < 1e-4, "{}", measured.ssim);
+    }
+
+    #[test]
+    fn shifted_images() {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+
+        // the same structure, a little brighter
+        let brighter = measure(&stripes(0, 0.0), &stripes(0, 0.05));
+        assert!(close(brighter.mean_absolute, 0.05));
+        assert!(close(brighter.max_absolute, 0.05));
+        assert!(close(brighter.rmse, 0.05));
+        assert!(brighter.ssim > 0.9 && brighter.ssim < 1.0, "{}", brighter.ssim);
+
+        // every stripe swapped for the other colour
+        let swapped = measure(&stripes(0, 0.0), &stripes(4, 0.0));
+        assert!(close(swapped.mean_absolute, 0.7));
+        assert!(close(swapped.rmse, 0.7));
+        assert!(swapped.ssim < 0.0, "{}", swapped.ssim);
+
+        // half the stripes swapped, so further apart than the brighter one
+        // and closer than the swapped one
+        let shifted = measure(&stripes(0, 0.0), &stripes(2, 0.0));
+        assert!(close(shifted.mean_absolute, 0.35));
+        assert!(shifted.ssim > swapped.ssim && shifted.ssim < brighter.ssim);
+    }
+}
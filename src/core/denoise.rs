@@ -0,0 +1,92 @@
+// Edge-avoiding à-trous wavelet denoising (Dammertz et al. 2010). Each pass
+// blurs with a 5x5 kernel whose taps are spread twice as far apart as the
+// last pass's, so a few passes cover a wide area cheaply. Neighbours only
+// count as much as they look like the same surface: similar normal, albedo
+// and depth (from the AOV channels), and similar colour, so edges and
+// texture detail survive while noise on flat areas is averaged out.
+
+use super::{colour::Colour, framebuffer::FrameBuffer};
+
+// B3 spline, the kernel from the paper
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// how quickly neighbours stop counting as they get less similar
+const COLOUR_SIGMA: f32 = 0.5; // halved every pass, as the noise goes down
+const NORMAL_SIGMA: f32 = 0.2;
+const ALBEDO_SIGMA: f32 = 0.1;
+const DEPTH_SIGMA: f32 = 0.05; // relative to the pixel's depth
+
+pub fn denoise(framebuffer: &FrameBuffer, passes: u32) -> FrameBuffer {
+    let mut denoised = framebuffer.clone();
+    for pass in 0..passes {
+        denoised = denoise_pass(&denoised, 1 << pass, COLOUR_SIGMA / (1 << pass) as f32);
+    }
+    denoised
+}
+
+fn denoise_pass(framebuffer: &FrameBuffer, step: i32, colour_sigma: f32) -> FrameBuffer {
+    let (width, height) = (framebuffer.width as i32, framebuffer.height as i32);
+    let mut denoised = framebuffer.clone();
+
+    for y in 0..height {
+        for x in 0..width {
+            let centre = (x as u32, y as u32);
+            let mut total = Colour::black();
+            let mut total_weight = 0.0;
+
+            for (j, ky) in KERNEL.iter().enumerate() {
+                for (i, kx) in KERNEL.iter().enumerate() {
+                    let nx = x + (i as i32 - 2) * step;
+                    let ny = y + (j as i32 - 2) * step;
+                    if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                        continue;
+                    }
+
+                    let neighbour = (nx as u32, ny as u32);
+                    let weight =
+                        kx * ky * similarity(framebuffer, centre, neighbour, colour_sigma);
+                    total += framebuffer.get_colour(neighbour.0, neighbour.1) * weight;
+                    total_weight += weight;
+                }
+            }
+
+            // the centre always has a weight, so total_weight > 0
+            denoised.plot_pixel(centre.0, centre.1, &(total / total_weight));
+        }
+    }
+
+    denoised
+}
+
+// 1 for a pixel that looks like the same surface, falling towards 0
+fn similarity(
+    framebuffer: &FrameBuffer,
+    (x, y): (u32, u32),
+    (nx, ny): (u32, u32),
+    colour_sigma: f32,
+) -> f32 {
+    let gaussian = |distance_sqrd: f32, sigma: f32| (-distance_sqrd / (sigma * sigma)).exp();
+    let colour_distance = |a: Colour, b: Colour| {
+        (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+    };
+
+    let colour = colour_distance(framebuffer.get_colour(x, y), framebuffer.get_colour(nx, ny));
+    let mut weight = gaussian(colour, colour_sigma);
+
+    match (framebuffer.get_surface(x, y), framebuffer.get_surface(nx, ny)) {
+        (Some(surface), Some(neighbour)) => {
+            let normal = (surface.normal - neighbour.normal).len_sqrd();
+            let albedo = colour_distance(surface.albedo, neighbour.albedo);
+            let depth = framebuffer.get_depth(x, y);
+            let depth_difference = (depth - framebuffer.get_depth(nx, ny)) / depth.max(0.0001);
+
+            weight *= gaussian(normal, NORMAL_SIGMA)
+                * gaussian(albedo, ALBEDO_SIGMA)
+                * gaussian(depth_difference.powi(2), DEPTH_SIGMA);
+        }
+        (None, None) => {} // both background
+        _ => weight = 0.0, // an object's edge against the background
+    }
+
+    weight
+}
@@ -0,0 +1,103 @@
+// Depth of field as a quick post-process: each pixel is blurred over a circle
+// (its circle of confusion) that grows the further its depth is from the
+// focus distance, using the depth channel the camera already writes. No
+// extra rays, so it's cheap enough for previews, but it can't see behind
+// blurred foreground edges like a real lens can.
+//
+// Each pixel gathers the neighbours whose circles reach it, from a spiral of
+// offsets over the largest circle. Neighbours behind a pixel only count if
+// its own circle reaches them too, so blurry background doesn't spread over
+// sharp foreground.
+
+use std::f32::consts::PI;
+
+use super::framebuffer::FrameBuffer;
+
+// how many neighbours each pixel gathers
+const SAMPLES: u32 = 64;
+
+#[derive(Debug, Clone)]
+pub struct DepthOfField {
+    // the distance that's sharp, None to focus on whatever is in the middle
+    // of the image
+    pub focus: Option<f32>,
+    // the radius of the circle of confusion of things infinitely far away,
+    // as a fraction of the image width. nothing is blurred more.
+    pub blur: f32,
+}
+
+impl DepthOfField {
+    pub fn apply(&self, framebuffer: &FrameBuffer) -> FrameBuffer {
+        let (width, height) = (framebuffer.width, framebuffer.height);
+        let max_radius = self.blur * width as f32;
+        if max_radius < 0.5 {
+            return framebuffer.clone();
+        }
+        let focus = self
+            .focus
+            .unwrap_or_else(|| framebuffer.get_depth(width / 2, height / 2));
+
+        let radii: Vec<f32> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| confusion(framebuffer.get_depth(x, y), focus) * max_radius)
+            .collect();
+        let radius_at = |x: u32, y: u32| radii[(y * width + x) as usize];
+        // spread evenly over the largest circle
+        let golden_angle = PI * (3.0 - 5f32.sqrt());
+        let offsets: Vec<(f32, f32)> = (0..SAMPLES)
+            .map(|i| {
+                let distance = ((i as f32 + 0.5) / SAMPLES as f32).sqrt() * max_radius;
+                let (sin, cos) = (i as f32 * golden_angle).sin_cos();
+                (cos * distance, sin * distance)
+            })
+            .collect();
+
+        let mut blurred = framebuffer.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (radius, depth) = (radius_at(x, y), framebuffer.get_depth(x, y));
+                let weight = area_weight(radius);
+                let mut total = framebuffer.get_colour(x, y) * weight;
+                let mut weights = weight;
+
+                for &(dx, dy) in &offsets {
+                    let nx = (x as f32 + dx).round();
+                    let ny = (y as f32 + dy).round();
+                    if nx < 0.0 || ny < 0.0 || nx >= width as f32 || ny >= height as f32 {
+                        continue;
+                    }
+                    let (nx, ny) = (nx as u32, ny as u32);
+                    let distance = dx.hypot(dy);
+                    let their_radius = radius_at(nx, ny);
+                    if distance > their_radius {
+                        continue;
+                    }
+                    if framebuffer.get_depth(nx, ny) > depth && distance > radius {
+                        continue;
+                    }
+
+                    // a big circle spreads the same light over more pixels
+                    let weight = area_weight(their_radius);
+                    total += framebuffer.get_colour(nx, ny) * weight;
+                    weights += weight;
+                }
+                blurred.plot_pixel(x, y, &(total * (1.0 / weights)));
+            }
+        }
+        blurred
+    }
+}
+
+// how blurred something at depth is, from 0 (in focus) to 1 (infinitely far
+// away, or further from focus than that)
+fn confusion(depth: f32, focus: f32) -> f32 {
+    match (depth.is_finite(), focus.is_finite()) {
+        (true, true) => (1.0 - focus / depth).abs().min(1.0),
+        (false, false) => 0.0,
+        _ => 1.0,
+    }
+}
+
+fn area_weight(radius: f32) -> f32 {
+    1.0 / radius.max(1.0).powi(2)
+}
@@ -0,0 +1,79 @@
+// Things that can go wrong while loading a scene's files or writing a render,
+// for reporting instead of panicking, so e.g. watching a scene survives a
+// broken model.
+
+use std::{fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum RenderError {
+    // a file couldn't be opened, read or written
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    // a line of an OBJ file didn't make sense, counting from 1
+    Obj {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    // a line of a .bpt patch file didn't make sense, counting from 1
+    Patches {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    // a point cloud file didn't make sense
+    PointCloud {
+        path: PathBuf,
+        message: String,
+    },
+    // an image couldn't be converted or read
+    Texture {
+        path: PathBuf,
+        message: String,
+    },
+    // a transform squashes space flat (e.g. a scale of 0), so it can't be
+    // undone
+    NotInvertible,
+}
+
+impl RenderError {
+    pub fn io(path: impl Into<PathBuf>, error: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Self::Obj {
+                path,
+                line,
+                message,
+            }
+            | Self::Patches {
+                path,
+                line,
+                message,
+            } => write!(f, "{} line {line}: {message}", path.display()),
+            Self::PointCloud { path, message } | Self::Texture { path, message } => {
+                write!(f, "{}: {message}", path.display())
+            }
+            Self::NotInvertible => write!(f, "Transform is not invertible"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// most of the crate reports errors as strings
+impl From<RenderError> for String {
+    fn from(error: RenderError) -> Self {
+        error.to_string()
+    }
+}
@@ -1,14 +1,24 @@
 use std::{
     fs::File,
-    io::{BufWriter, Write}, path::PathBuf,
+    io::{self, BufWriter, Write}, path::PathBuf,
 };
 
-use super::colour::Colour;
+use crate::environments::environment::{Lighting, SurfaceInfo};
+
+use super::{
+    colour::{Colour, ColourSpace},
+    error::RenderError,
+    vector::Vector,
+    vertex::Vertex,
+};
 
 #[derive(Clone)]
 struct Pixel {
     pub colour: Colour,
     pub depth: f32,
+    pub surface: Option<SurfaceInfo>,
+    pub throughput: f32,
+    pub lighting: Lighting,
 }
 
 impl Pixel {
@@ -16,6 +26,9 @@ impl Pixel {
         Self {
             colour: Colour::new(red, green, blue),
             depth,
+            surface: None,
+            throughput: 1.0,
+            lighting: Lighting::default(),
         }
     }
 
@@ -23,10 +36,139 @@ impl Pixel {
         Self {
             colour: Colour::black(),
             depth: 0.0,
+            surface: None,
+            throughput: 1.0,
+            lighting: Lighting::default(),
+        }
+    }
+}
+
+// what to write for pixels where the camera ray didn't hit anything
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MissDepth {
+    Infinity, // infinitely far away, saturates to white in 8-bit outputs
+    FarPlane, // as far as the far plane, see DepthOutput
+    Zero,     // the old behaviour, reads as closest to the camera
+}
+
+impl MissDepth {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Infinity" => Some(Self::Infinity),
+            "FarPlane" => Some(Self::FarPlane),
+            "Zero" => Some(Self::Zero),
+            _ => None,
+        }
+    }
+}
+
+// the file format of the depth image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepthFormat {
+    Gray8,  // 8-bit PGM
+    Gray16, // 16-bit PGM, for when 256 levels band
+    Float,  // the distances themselves as a PFM, not normalised
+}
+
+impl DepthFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Gray8" => Some(Self::Gray8),
+            "Gray16" => Some(Self::Gray16),
+            "Float" => Some(Self::Float),
+            _ => None,
         }
     }
 }
 
+// how depths are written. near and far are mapped to black and white,
+// defaulting to the closest and furthest hits in the image. inverse writes
+// 1 / depth instead, which is white up close and keeps more detail there.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DepthOutput {
+    pub near: Option<f32>,
+    pub far: Option<f32>,
+    pub inverse: bool,
+    pub format: DepthFormat,
+}
+
+impl Default for DepthOutput {
+    fn default() -> Self {
+        Self {
+            near: None,
+            far: None,
+            inverse: false,
+            format: DepthFormat::Gray8,
+        }
+    }
+}
+
+// the extra per-pixel channels, each written as its own image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    Normal,   // world space, raw -1 to 1
+    Albedo,   // base colour of the surface
+    ObjectId, // a different colour for each top level object
+    Position,   // world space hit position
+    Throughput, // how much light gets through fog, white where it's clear
+}
+
+impl Aov {
+    pub const ALL: [Self; 5] = [
+        Self::Normal,
+        Self::Albedo,
+        Self::ObjectId,
+        Self::Position,
+        Self::Throughput,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Albedo => "albedo",
+            Self::ObjectId => "object_id",
+            Self::Position => "position",
+            Self::Throughput => "throughput",
+        }
+    }
+
+    // whether the values fall outside 0 to 1, and so need a float image
+    pub fn is_float(&self) -> bool {
+        matches!(self, Self::Normal | Self::Position)
+    }
+}
+
+// the parts of the lighting (see Lighting), each written as its own image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightingPass {
+    Diffuse,
+    Specular,
+    Indirect,
+    Caustic,
+    Emission,
+}
+
+impl LightingPass {
+    pub const ALL: [Self; 5] = [
+        Self::Diffuse,
+        Self::Specular,
+        Self::Indirect,
+        Self::Caustic,
+        Self::Emission,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Diffuse => "diffuse",
+            Self::Specular => "specular",
+            Self::Indirect => "indirect",
+            Self::Caustic => "caustic",
+            Self::Emission => "emission",
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct FrameBuffer {
     pub width: u32,
     pub height: u32,
@@ -35,12 +177,8 @@ pub struct FrameBuffer {
 
 impl FrameBuffer {
     pub fn new(width: u32, height: u32) -> Self {
-        if width >= 2048 || height >= 2048 {
-            panic!("Invalid framebuffer size");
-        }
-
         let mut framebuffer = Vec::new();
-        framebuffer.resize((width * height) as usize, Pixel::black());
+        framebuffer.resize(width as usize * height as usize, Pixel::black());
 
         Self {
             width,
@@ -49,28 +187,168 @@ impl FrameBuffer {
         }
     }
 
-    pub fn combine_rows(framebuffers: Vec<Self>) -> Self {
-        let width = framebuffers[0].width;
-        let height = framebuffers.iter().fold(0, |acc, fb| {
-            assert!(fb.width == width);
-            acc + fb.height
-        });
+    // copy another framebuffer into this one, with its top left at (x, y)
+    pub fn blit(&mut self, other: &Self, x: u32, y: u32) {
+        assert!(x + other.width <= self.width && y + other.height <= self.height);
 
-        let mut pixels = Vec::with_capacity((width * height) as usize);
-        for fb in framebuffers {
-            pixels.extend(fb.pixels);
+        for row in 0..other.height {
+            let src = other.framebuffer_index(0, row);
+            let dst = self.framebuffer_index(x, y + row);
+            let len = other.width as usize;
+            self.pixels[dst..dst + len].clone_from_slice(&other.pixels[src..src + len]);
         }
+    }
 
-        Self {
-            width,
-            height,
-            pixels,
+    // everything about every pixel, to send the framebuffer somewhere else
+    // (e.g. a rendered tile back from a worker, see distributed.rs). read it
+    // back with from_bytes().
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut floats = Vec::new();
+        for pixel in &self.pixels {
+            let Colour { r, g, b } = pixel.colour;
+            let has_surface = if pixel.surface.is_some() { 1.0 } else { 0.0 };
+            floats.extend([r, g, b, pixel.depth, pixel.throughput, has_surface]);
+            for Colour { r, g, b } in pixel.lighting.parts() {
+                floats.extend([r, g, b]);
+            }
+            if let Some(surface) = &pixel.surface {
+                let (position, normal, albedo) =
+                    (&surface.position, surface.normal, surface.albedo);
+                floats.extend([position.x, position.y, position.z]);
+                floats.extend([normal.x, normal.y, normal.z]);
+                floats.extend([albedo.r, albedo.g, albedo.b]);
+                // the id's bits, not its value, so big ids survive
+                floats.push(f32::from_bits(surface.object_id));
+            }
         }
+        floats
+            .iter()
+            .flat_map(|float| float.to_le_bytes())
+            .collect()
+    }
+
+    pub fn from_bytes(width: u32, height: u32, bytes: &[u8]) -> Result<Self, String> {
+        let mut framebuffer = Self::new(width, height);
+        let mut floats = bytes
+            .chunks_exact(4)
+            .map(|float| f32::from_le_bytes([float[0], float[1], float[2], float[3]]));
+        let mut next = |count: usize| -> Result<Vec<f32>, String> {
+            let next: Vec<f32> = floats.by_ref().take(count).collect();
+            if next.len() < count {
+                return Err("Framebuffer data too short".to_string());
+            }
+            Ok(next)
+        };
+
+        for pixel in &mut framebuffer.pixels {
+            let [r, g, b, depth, throughput, has_surface] = next(6)?[..] else {
+                unreachable!()
+            };
+            *pixel = Pixel::new(r, g, b, depth);
+            pixel.throughput = throughput;
+            let lighting = next(15)?;
+            let part = |index: usize| {
+                let rgb = &lighting[index * 3..index * 3 + 3];
+                Colour::new(rgb[0], rgb[1], rgb[2])
+            };
+            pixel.lighting = Lighting {
+                diffuse: part(0),
+                specular: part(1),
+                indirect: part(2),
+                caustic: part(3),
+                emission: part(4),
+            };
+            if has_surface == 0.0 {
+                continue;
+            }
+            let surface = next(10)?;
+            pixel.surface = Some(SurfaceInfo {
+                position: Vertex::new(surface[0], surface[1], surface[2]),
+                normal: Vector::new(surface[3], surface[4], surface[5]),
+                albedo: Colour::new(surface[6], surface[7], surface[8]),
+                object_id: surface[9].to_bits(),
+            });
+        }
+        Ok(framebuffer)
+    }
+
+    // nearest neighbour upscale, used to display low resolution previews
+    pub fn upscaled(&self, width: u32, height: u32) -> Self {
+        let mut upscaled = Self::new(width, height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let src_x = (x * self.width / width).min(self.width - 1);
+                let src_y = (y * self.height / height).min(self.height - 1);
+                let src = self.framebuffer_index(src_x, src_y);
+                let dst = upscaled.framebuffer_index(x, y);
+                upscaled.pixels[dst] = self.pixels[src].clone();
+            }
+        }
+
+        upscaled
+    }
+
+    // a copy with the colours scaled by 2^ev, e.g. -2 for two stops darker
+    pub fn exposed(&self, ev: f32) -> Self {
+        let scale = ev.exp2();
+        let mut exposed = self.clone();
+        for pixel in &mut exposed.pixels {
+            pixel.colour = pixel.colour * scale;
+        }
+        exposed
+    }
+
+    // an image of one of the AOV channels. the ones about the surface are
+    // black where nothing was hit.
+    pub fn aov(&self, aov: Aov) -> Self {
+        let mut image = Self::new(self.width, self.height);
+        for (pixel, image_pixel) in self.pixels.iter().zip(&mut image.pixels) {
+            if aov == Aov::Throughput {
+                image_pixel.colour = Colour::white() * pixel.throughput;
+                continue;
+            }
+            let Some(surface) = &pixel.surface else {
+                continue;
+            };
+            image_pixel.colour = match aov {
+                Aov::Normal => Colour::new(surface.normal.x, surface.normal.y, surface.normal.z),
+                Aov::Albedo => surface.albedo,
+                Aov::ObjectId => {
+                    // golden ratio steps around the hue circle keep
+                    // neighbouring IDs far apart
+                    let hue = (surface.object_id as f32 * 0.618034).fract();
+                    Colour::new(1.0, 0.2, 0.2).hue_rotated(hue * std::f32::consts::TAU)
+                }
+                Aov::Position => {
+                    let position = &surface.position;
+                    Colour::new(position.x, position.y, position.z)
+                }
+                Aov::Throughput => unreachable!(),
+            };
+        }
+        image
+    }
+
+    // an image of one part of the lighting
+    pub fn lighting_pass(&self, pass: LightingPass) -> Self {
+        let mut image = Self::new(self.width, self.height);
+        for (pixel, image_pixel) in self.pixels.iter().zip(&mut image.pixels) {
+            let lighting = &pixel.lighting;
+            image_pixel.colour = match pass {
+                LightingPass::Diffuse => lighting.diffuse,
+                LightingPass::Specular => lighting.specular,
+                LightingPass::Indirect => lighting.indirect,
+                LightingPass::Caustic => lighting.caustic,
+                LightingPass::Emission => lighting.emission,
+            };
+        }
+        image
     }
 
     fn framebuffer_index(&self, x: u32, y: u32) -> usize {
         assert!(x < self.width && y < self.height);
-        (y * self.width + x) as usize
+        y as usize * self.width as usize + x as usize
     }
 
     pub fn plot_pixel(&mut self, x: u32, y: u32, colour: &Colour) {
@@ -83,6 +361,21 @@ impl FrameBuffer {
         self.pixels[index].depth = depth;
     }
 
+    pub fn plot_surface(&mut self, x: u32, y: u32, surface: Option<SurfaceInfo>) {
+        let index = self.framebuffer_index(x, y);
+        self.pixels[index].surface = surface;
+    }
+
+    pub fn plot_throughput(&mut self, x: u32, y: u32, throughput: f32) {
+        let index = self.framebuffer_index(x, y);
+        self.pixels[index].throughput = throughput;
+    }
+
+    pub fn plot_lighting(&mut self, x: u32, y: u32, lighting: Lighting) {
+        let index = self.framebuffer_index(x, y);
+        self.pixels[index].lighting = lighting;
+    }
+
     pub fn get_depth(&self, x: u32, y: u32) -> f32 {
         let index = self.framebuffer_index(x, y);
         self.pixels[index].depth
@@ -93,39 +386,237 @@ impl FrameBuffer {
         self.pixels[index].colour
     }
 
-    pub fn write_rgb_file(&self, filename: &PathBuf) {
-        let outfile = File::create(filename).unwrap();
-        let mut writer = BufWriter::new(outfile);
+    pub fn get_surface(&self, x: u32, y: u32) -> Option<&SurfaceInfo> {
+        let index = self.framebuffer_index(x, y);
+        self.pixels[index].surface.as_ref()
+    }
 
-        let header = format!("P6\n{} {}\n255\n", self.width, self.height);
-        writer.write_all(header.as_bytes()).unwrap();
+    // draw a line between two (possibly offscreen) points, only touching
+    // the colour channel
+    pub fn draw_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, colour: &Colour) {
+        let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil();
+        // don't spend forever on lines projected from very close to the camera
+        let steps = steps.min(4.0 * (self.width + self.height) as f32) as u32;
 
-        for pixel in &self.pixels {
-            // assume all colour values are between 0.0 and 1.0
-            let red = (pixel.colour.r * 255.0) as u8;
-            let green = (pixel.colour.g * 255.0) as u8;
-            let blue = (pixel.colour.b * 255.0) as u8;
+        for i in 0..=steps {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                i as f32 / steps as f32
+            };
+            let x = x0 + (x1 - x0) * t;
+            let y = y0 + (y1 - y0) * t;
+            if x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32 {
+                self.plot_pixel(x as u32, y as u32, colour);
+            }
+        }
+    }
 
-            writer.write_all(&[red, green, blue]).unwrap();
+    // 8 bit RGBA, row by row from the top, e.g. for a canvas
+    pub fn to_rgba(&self, colour_space: ColourSpace) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels {
+            let [red, green, blue] = to_rgb8(&pixel.colour, colour_space);
+            rgba.extend_from_slice(&[red, green, blue, 255]);
         }
+        rgba
+    }
+
+    // the colours encoded in colour_space, as an 8 bit PPM
+    pub fn write_rgb_file(
+        &self,
+        filename: &PathBuf,
+        colour_space: ColourSpace,
+    ) -> Result<(), RenderError> {
+        write_file(filename, |writer| {
+            let header = format!("P6\n{} {}\n255\n", self.width, self.height);
+            writer.write_all(header.as_bytes())?;
 
-        writer.flush().unwrap();
+            for pixel in &self.pixels {
+                writer.write_all(&to_rgb8(&pixel.colour, colour_space))?;
+            }
+            Ok(())
+        })
     }
 
-    pub fn write_depth_file(&self, filename: &PathBuf) {
-        let outfile = File::create(filename).unwrap();
-        let mut writer = BufWriter::new(outfile);
+    // the colours as a portable float map, without clamping to 0 to 1
+    pub fn write_pfm_file(&self, filename: &PathBuf) -> Result<(), RenderError> {
+        write_file(filename, |writer| {
+            // rows go from the bottom up, little endian (hence the negative scale)
+            let header = format!("PF\n{} {}\n-1.0\n", self.width, self.height);
+            writer.write_all(header.as_bytes())?;
 
-        let header = format!("P5\n{} {}\n255\n", self.width, self.height);
-        writer.write_all(header.as_bytes()).unwrap();
+            for y in (0..self.height).rev() {
+                for x in 0..self.width {
+                    let colour = self.get_colour(x, y);
+                    for component in [colour.r, colour.g, colour.b] {
+                        writer.write_all(&component.to_le_bytes())?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
 
-        for pixel in &self.pixels {
-            // assume all depth values are between 0.0 and 1.0
-            let depth = (pixel.depth * 255.0) as u8;
+    // misses are stored as infinity, and written according to miss_depth
+    pub fn write_depth_file(
+        &self,
+        filename: &PathBuf,
+        miss_depth: MissDepth,
+        output: &DepthOutput,
+    ) -> Result<(), RenderError> {
+        let hits = || {
+            self.pixels
+                .iter()
+                .map(|pixel| pixel.depth)
+                .filter(|depth| depth.is_finite())
+        };
+        let near = output
+            .near
+            .unwrap_or_else(|| hits().reduce(f32::min).unwrap_or(0.0));
+        let far = output.far.unwrap_or_else(|| hits().fold(0.0, f32::max));
+
+        let depths = self.pixels.iter().map(|pixel| match miss_depth {
+            _ if pixel.depth.is_finite() => pixel.depth,
+            MissDepth::Infinity => f32::INFINITY,
+            MissDepth::FarPlane => far,
+            MissDepth::Zero => 0.0,
+        });
+        // 0 at near and 1 at far (the other way round if inverse). misses
+        // at infinity end up at whichever end is furthest away.
+        let normalised = depths.clone().map(|depth| {
+            let fraction = if output.inverse {
+                (near / depth - near / far) / (1.0 - near / far)
+            } else {
+                (depth - near) / (far - near)
+            };
+            // nothing to spread out if everything is the same distance away
+            if fraction.is_nan() {
+                return 0.0;
+            }
+            fraction.clamp(0.0, 1.0)
+        });
+
+        write_file(filename, |writer| {
+            match output.format {
+                DepthFormat::Gray8 => {
+                    let header = format!("P5\n{} {}\n255\n", self.width, self.height);
+                    writer.write_all(header.as_bytes())?;
+                    for depth in normalised {
+                        writer.write_all(&[(depth * 255.0) as u8])?;
+                    }
+                }
+                DepthFormat::Gray16 => {
+                    // 16-bit PGMs are big endian
+                    let header = format!("P5\n{} {}\n65535\n", self.width, self.height);
+                    writer.write_all(header.as_bytes())?;
+                    for depth in normalised {
+                        let depth = (depth * 65535.0) as u16;
+                        writer.write_all(&depth.to_be_bytes())?;
+                    }
+                }
+                DepthFormat::Float => {
+                    // like write_pfm_file(), but with one channel
+                    let header = format!("Pf\n{} {}\n-1.0\n", self.width, self.height);
+                    writer.write_all(header.as_bytes())?;
+
+                    let depths: Vec<f32> = depths
+                        .map(|depth| if output.inverse { 1.0 / depth } else { depth })
+                        .collect();
+                    for row in depths.chunks(self.width as usize).rev() {
+                        for depth in row {
+                            writer.write_all(&depth.to_le_bytes())?;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+// create filename and write it with write, buffered
+fn write_file(
+    filename: &PathBuf,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> Result<(), RenderError> {
+    let io_error = |e| RenderError::io(filename, e);
+    let mut writer = BufWriter::new(File::create(filename).map_err(io_error)?);
+    write(&mut writer).map_err(io_error)?;
+    writer.flush().map_err(io_error)
+}
 
-            writer.write_all(&[depth]).unwrap();
+// assume all colour values are between 0.0 and 1.0. float to int casts
+// saturate, so anything outside is clamped.
+fn to_rgb8(colour: &Colour, colour_space: ColourSpace) -> [u8; 3] {
+    let colour = colour_space.encode(*colour);
+    [
+        (colour.r * 255.0) as u8,
+        (colour.g * 255.0) as u8,
+        (colour.b * 255.0) as u8,
+    ]
+}
+
+// writes an image like write_rgb_file() a few rows at a time, so the whole
+// thing never has to be in memory at once. rows must be written from the
+// top down.
+pub struct RgbStream {
+    filename: PathBuf,
+    writer: BufWriter<File>,
+    width: u32,
+    height: u32,
+    colour_space: ColourSpace,
+    rows_written: u32,
+}
+
+impl RgbStream {
+    pub fn create(
+        filename: &PathBuf,
+        width: u32,
+        height: u32,
+        colour_space: ColourSpace,
+    ) -> Result<Self, RenderError> {
+        let io_error = |e| RenderError::io(filename, e);
+        let mut writer = BufWriter::new(File::create(filename).map_err(io_error)?);
+
+        let header = format!("P6\n{width} {height}\n255\n");
+        writer.write_all(header.as_bytes()).map_err(io_error)?;
+
+        Ok(Self {
+            filename: filename.clone(),
+            writer,
+            width,
+            height,
+            colour_space,
+            rows_written: 0,
+        })
+    }
+
+    // append all of a framebuffer's rows, which must be as wide as the image
+    pub fn write_rows(&mut self, framebuffer: &FrameBuffer) -> Result<(), RenderError> {
+        assert!(
+            framebuffer.width == self.width
+                && self.rows_written + framebuffer.height <= self.height
+        );
+
+        for pixel in &framebuffer.pixels {
+            let rgb = to_rgb8(&pixel.colour, self.colour_space);
+            self.writer
+                .write_all(&rgb)
+                .map_err(|e| RenderError::io(&self.filename, e))?;
         }
+        self.rows_written += framebuffer.height;
+        Ok(())
+    }
 
-        writer.flush().unwrap();
+    // fill in any rows that weren't written (e.g. the render was stopped)
+    // with black, so the file is still a valid image
+    pub fn finish(mut self) -> Result<(), RenderError> {
+        let missing = (self.height - self.rows_written) as usize * self.width as usize;
+        let io_error = |e| RenderError::io(&self.filename, e);
+        for _ in 0..missing {
+            self.writer.write_all(&[0, 0, 0]).map_err(io_error)?;
+        }
+        self.writer.flush().map_err(io_error)
     }
 }
@@ -1,6 +1,6 @@
 use crate::{materials::material::Material, objects::object::Object};
 
-use super::{tex_coords::TexCoords, vector::Vector, vertex::Vertex};
+use super::{colour::Colour, tex_coords::TexCoords, vector::Vector, vertex::Vertex};
 
 pub struct Hit<'s> {
     pub distance: f32,                 // intersection distance
@@ -10,6 +10,11 @@ pub struct Hit<'s> {
     pub position: Vertex,              // position of intersection
     pub normal: Vector,                // normal at intersection
     pub tex_coords: Option<TexCoords>, // texture coordinates at intersection
+    pub colour: Option<Colour>,        // interpolated vertex colour, for meshes that have them
+    pub curvature: f32,                // mean curvature, > 0 if convex, < 0 if concave
+    pub barycentric: Option<[f32; 3]>, // weights of a triangle's corners, summing to 1
+    pub tangent: Option<Vector>,       // along the fibre, for hair
+    pub instance: u32,                 // index of the top level object that was hit
 }
 
 impl<'s> Hit<'s> {
@@ -30,6 +35,11 @@ impl<'s> Hit<'s> {
             position,
             normal,
             tex_coords,
+            colour: None,
+            curvature: 0.0,
+            barycentric: None,
+            tangent: None,
+            instance: 0,
         }
     }
 
@@ -48,8 +58,30 @@ impl<'s> Hit<'s> {
             position: Vertex::zero(),
             normal: Vector::zero(),
             tex_coords: None,
+            colour: None,
+            curvature: 0.0,
+            barycentric: None,
+            tangent: None,
+            instance: 0,
         }
     }
+
+    // pseudo random number in [0, 1) that is the same for every hit on the
+    // same object instance, so materials can vary each instance differently
+    pub fn random_per_instance(&self, seed: u32) -> f32 {
+        let hash = hash_u32(self.instance ^ hash_u32(seed));
+        (hash >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+// integer hash with good avalanche, from https://nullprogram.com/blog/2018/07/31/
+fn hash_u32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
 }
 
 // stack allocated vector of at most N hits
@@ -59,6 +91,12 @@ pub struct HitVec<'s> {
     len: u8,
 }
 
+impl Default for HitVec<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<'s> HitVec<'s> {
     pub const fn new() -> Self {
         Self {
@@ -82,7 +120,7 @@ impl<'s> HitVec<'s> {
             .map(|hit| hit.as_ref().unwrap())
     }
 
-    pub fn iter_mut(&'s mut self) -> impl Iterator<Item = &mut Hit<'_>> {
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Hit<'s>> {
         self.hits[..self.len as usize]
             .iter_mut()
             .map(move |hit| hit.as_mut().unwrap())
@@ -0,0 +1,60 @@
+// An orthonormal basis around a normal, for going between world space and
+// the surface's local space (tangent = x, bitangent = y, normal = z), e.g.
+// for normal maps and sampling directions over a hemisphere.
+
+use super::vector::Vector;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Onb {
+    pub tangent: Vector,
+    pub bitangent: Vector,
+    pub normal: Vector,
+}
+
+impl Onb {
+    // any basis around the normal. branchless and without a degenerate
+    // direction, from Duff et al. 2017, "Building an Orthonormal Basis,
+    // Revisited".
+    pub fn from_normal(normal: &Vector) -> Self {
+        let n = normal.normalised();
+        let sign = 1.0_f32.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+
+        Self {
+            tangent: Vector::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            bitangent: Vector::new(b, sign + n.y * n.y * a, -n.y),
+            normal: n,
+        }
+    }
+
+    // a basis with the tangent as close to the given one as possible. if the
+    // tangent is (nearly) parallel to the normal, falls back to from_normal().
+    pub fn from_normal_and_tangent(normal: &Vector, tangent: &Vector) -> Self {
+        let n = normal.normalised();
+        // make the tangent perpendicular to the normal
+        let t = *tangent - n * n.dot(tangent);
+        if t.len_sqrd() < 1e-12 || !t.len_sqrd().is_finite() {
+            return Self::from_normal(&n);
+        }
+
+        let t = t.normalised();
+        Self {
+            tangent: t,
+            bitangent: n.cross(&t),
+            normal: n,
+        }
+    }
+
+    pub fn to_world(&self, local: &Vector) -> Vector {
+        self.tangent * local.x + self.bitangent * local.y + self.normal * local.z
+    }
+
+    pub fn to_local(&self, world: &Vector) -> Vector {
+        Vector::new(
+            world.dot(&self.tangent),
+            world.dot(&self.bitangent),
+            world.dot(&self.normal),
+        )
+    }
+}
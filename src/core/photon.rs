@@ -1,15 +1,26 @@
-use kd_tree::KdPoint;
-
 use crate::core::{colour::Colour, vector::Vector, vertex::Vertex};
 
 use super::ray::Ray;
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum PhotonType {
+    Direct, // straight from a light, not bounced off anything yet
     Colour,
     Shadow,
-    Caustic,
+    Caustic, // only reflected or refracted cleanly since leaving a light
     Vueon,
+    Volume,        // scattered in fog
+    VolumeCaustic, // scattered in fog after a reflection or refraction
+}
+
+impl PhotonType {
+    pub fn is_volume(&self) -> bool {
+        matches!(self, Self::Volume | Self::VolumeCaustic)
+    }
+
+    pub fn is_caustic(&self) -> bool {
+        matches!(self, Self::Caustic | Self::VolumeCaustic)
+    }
 }
 
 pub struct Photon {
@@ -35,18 +46,111 @@ impl Photon {
     }
 }
 
-impl KdPoint for Photon {
-    type Scalar = f32;
-    type Dim = typenum::U3;
+// how photons are kept in the photon maps, which hold millions of them. the
+// position is kept separately (by PhotonTree and PhotonGrid), the direction
+// is quantised to two bytes and the intensity is stored as half floats, 10
+// bytes instead of the 28 a Photon spends on them. gpu::photon_gather
+// uploads the fields as they are.
+#[derive(Debug, Copy, Clone)]
+pub struct PackedPhoton {
+    pub incident: [u8; 2],
+    pub intensity: [u16; 3],
+    pub photon_type: PhotonType,
+}
 
-    fn at(&self, i: usize) -> Self::Scalar {
-        match i {
-            0 => self.position.x,
-            1 => self.position.y,
-            2 => self.position.z,
-            _ => unreachable!(),
+impl PackedPhoton {
+    pub fn pack(photon: &Photon) -> Self {
+        let intensity = photon.intensity;
+        Self {
+            incident: encode_direction(&photon.incident),
+            intensity: [
+                to_half(intensity.r),
+                to_half(intensity.g),
+                to_half(intensity.b),
+            ],
+            photon_type: photon.photon_type,
         }
     }
+
+    pub fn unpack(&self, position: Vertex) -> Photon {
+        let [r, g, b] = self.intensity.map(from_half);
+        Photon::new(
+            position,
+            decode_direction(self.incident),
+            Colour::new(r, g, b),
+            self.photon_type,
+        )
+    }
+}
+
+// octahedral encoding: the direction is projected onto an octahedron, which
+// is unfolded into a square, and the point on the square is stored as a byte
+// per axis. accurate to about a degree, everywhere on the sphere.
+fn encode_direction(direction: &Vector) -> [u8; 2] {
+    let length = direction.x.abs() + direction.y.abs() + direction.z.abs();
+    let (mut u, mut v) = (direction.x / length, direction.y / length);
+    if direction.z < 0.0 {
+        (u, v) = fold(u, v);
+    }
+    [u, v].map(|x| ((x * 0.5 + 0.5) * 255.0).round() as u8)
+}
+
+fn decode_direction(encoded: [u8; 2]) -> Vector {
+    let [mut u, mut v] = encoded.map(|x| x as f32 / 255.0 * 2.0 - 1.0);
+    let z = 1.0 - u.abs() - v.abs();
+    if z < 0.0 {
+        (u, v) = fold(u, v);
+    }
+    Vector::new(u, v, z).normalised()
+}
+
+// moves the lower half of the octahedron to the corners of the square, and
+// back again
+fn fold(u: f32, v: f32) -> (f32, f32) {
+    ((1.0 - v.abs()) * u.signum(), (1.0 - u.abs()) * v.signum())
+}
+
+// IEEE 754 half precision, rounded to nearest. too bright clamps to the
+// largest half (65504) rather than infinity, too dim goes to zero.
+fn to_half(value: f32) -> u16 {
+    if value.is_nan() {
+        return 0x7e00;
+    }
+
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+
+    let magnitude = if exponent >= 0x1f {
+        0x7bff
+    } else if exponent > 0 {
+        // rounding up can carry into the exponent, which is what we want
+        let half = ((exponent as u32) << 10) + ((mantissa + 0x1000) >> 13);
+        half.min(0x7bff)
+    } else if exponent >= -10 {
+        // subnormal
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa + (1 << (shift - 1))) >> shift
+    } else {
+        0
+    };
+    sign | magnitude as u16
+}
+
+fn from_half(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+
+    let magnitude = match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    };
+    sign * magnitude
 }
 
 pub struct InFlightPhoton {
@@ -90,3 +194,73 @@ impl From<Photon> for InFlightPhoton {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_round_trip() {
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(from_half(to_half(65504.0)), 65504.0);
+        assert_eq!(to_half(1e6), 0x7bff);
+        assert_eq!(to_half(1e-9), 0);
+        assert_eq!(from_half(to_half(2f32.powi(-20))), 2f32.powi(-20));
+
+        // 11 significant bits, so within half an ulp of 2^-10
+        for i in 0..1000 {
+            let value = 1e-4 * 1.02f32.powi(i);
+            if value > 65504.0 {
+                break;
+            }
+            let error = (from_half(to_half(value)) - value).abs() / value;
+            assert!(error <= 2f32.powi(-11), "{value} off by {error}");
+        }
+    }
+
+    #[test]
+    fn direction_round_trip() {
+        let axes = [
+            Vector::new(1.0, 0.0, 0.0),
+            Vector::new(-1.0, 0.0, 0.0),
+            Vector::new(0.0, 1.0, 0.0),
+            Vector::new(0.0, -1.0, 0.0),
+            Vector::new(0.0, 0.0, 1.0),
+            Vector::new(0.0, 0.0, -1.0),
+        ];
+
+        // points spread evenly over the sphere, along a spiral
+        let spiral = (0..500).map(|i| {
+            let z = 1.0 - (i as f32 + 0.5) / 250.0;
+            let angle = i as f32 * 2.399_963;
+            let r = (1.0 - z * z).sqrt();
+            Vector::new(r * angle.cos(), r * angle.sin(), z)
+        });
+
+        for direction in axes.into_iter().chain(spiral) {
+            let decoded = decode_direction(encode_direction(&direction));
+            let degrees = direction.dot(&decoded).min(1.0).acos().to_degrees();
+            assert!(degrees < 1.5, "{direction:?} off by {degrees} degrees");
+        }
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let position = Vertex::new(1.0, -2.0, 3.0);
+        let photon = Photon::new(
+            position.clone(),
+            Vector::new(0.3, -0.4, -0.5).normalised(),
+            Colour::new(0.25, 1.5, 300.0),
+            PhotonType::VolumeCaustic,
+        );
+        let unpacked = PackedPhoton::pack(&photon).unpack(position);
+
+        assert_eq!(unpacked.position.xyz(), photon.position.xyz());
+        assert_eq!(unpacked.photon_type, photon.photon_type);
+        assert!(unpacked.incident.dot(&photon.incident) > 0.999);
+        let intensity = unpacked.intensity;
+        assert_eq!((intensity.r, intensity.g), (0.25, 1.5));
+        assert!((intensity.b - 300.0).abs() < 0.25);
+    }
+}
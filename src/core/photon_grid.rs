@@ -0,0 +1,207 @@
+use super::{
+    photon::{PackedPhoton, Photon},
+    vertex::Vertex,
+};
+
+// a spatial hash of photons, an alternative to PhotonTree for fixed radius
+// gathers. each photon is bucketed by the grid cell it falls in, and the
+// buckets are stored back to back in flat arrays so the whole structure can
+// be copied around (e.g. to a GPU) in one go. like PhotonTree, positions are
+// kept apart from the packed rest of each photon.
+pub struct PhotonGrid {
+    cell_size: f32,
+    bucket_starts: Vec<u32>, // photons[bucket_starts[i]..bucket_starts[i + 1]] are in bucket i
+    positions: Vec<Vertex>,
+    photons: Vec<PackedPhoton>,
+}
+
+impl PhotonGrid {
+    // gathers are quickest with a radius of half of cell_size or less, when
+    // they only look through the 2 or 3 cells along each axis around them
+    pub fn build(photons: Vec<Photon>, cell_size: f32) -> Self {
+        let num_buckets = photons.len().next_power_of_two();
+        let bucket_of = |photon: &Photon| bucket(cell(&photon.position, cell_size), num_buckets);
+
+        // counting sort the photons by bucket
+        let mut bucket_starts = vec![0u32; num_buckets + 1];
+        for photon in &photons {
+            bucket_starts[bucket_of(photon) + 1] += 1;
+        }
+        for i in 0..num_buckets {
+            bucket_starts[i + 1] += bucket_starts[i];
+        }
+
+        let mut next_slot = bucket_starts.clone();
+        let mut slots: Vec<Option<Photon>> = (0..photons.len()).map(|_| None).collect();
+        for photon in photons {
+            let bucket = bucket_of(&photon);
+            slots[next_slot[bucket] as usize] = Some(photon);
+            next_slot[bucket] += 1;
+        }
+        let photons: Vec<Photon> = slots.into_iter().map(Option::unwrap).collect();
+
+        Self {
+            cell_size,
+            bucket_starts,
+            positions: photons
+                .iter()
+                .map(|photon| photon.position.clone())
+                .collect(),
+            photons: photons.iter().map(PackedPhoton::pack).collect(),
+        }
+    }
+
+    // same as PhotonTree::get_within_distance(), photons within an axis
+    // aligned box, but unsorted
+    pub fn get_within_distance(&self, position: &Vertex, radius: f32) -> Vec<Photon> {
+        let mut found = Vec::new();
+        if self.positions.is_empty() {
+            return found;
+        }
+
+        let num_buckets = self.bucket_starts.len() - 1;
+        let min = cell(
+            &Vertex::new(
+                position.x - radius,
+                position.y - radius,
+                position.z - radius,
+            ),
+            self.cell_size,
+        );
+        let max = cell(
+            &Vertex::new(
+                position.x + radius,
+                position.y + radius,
+                position.z + radius,
+            ),
+            self.cell_size,
+        );
+
+        // the box spans 2x2x2 cells (or 3 per axis if rounding is unlucky)
+        // when the radius is at most half a cell, and more when it's bigger.
+        // different cells can hash to the same bucket, so don't visit a
+        // bucket twice, and past as many cells as buckets visit them all.
+        let span = |min: i32, max: i32| (max as i64 - min as i64 + 1) as usize;
+        let cells = span(min.0, max.0)
+            .saturating_mul(span(min.1, max.1))
+            .saturating_mul(span(min.2, max.2));
+        let mut buckets = Vec::new();
+        if cells >= num_buckets {
+            buckets.extend(0..num_buckets);
+        } else {
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        buckets.push(bucket((x, y, z), num_buckets));
+                    }
+                }
+            }
+            buckets.sort_unstable();
+            buckets.dedup();
+        }
+
+        for bucket in buckets {
+            let start = self.bucket_starts[bucket] as usize;
+            let end = self.bucket_starts[bucket + 1] as usize;
+            for i in start..end {
+                let photon_position = &self.positions[i];
+                if (photon_position.x - position.x).abs() <= radius
+                    && (photon_position.y - position.y).abs() <= radius
+                    && (photon_position.z - position.z).abs() <= radius
+                {
+                    found.push(self.photons[i].unpack(photon_position.clone()));
+                }
+            }
+        }
+
+        found
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    // the flat arrays, e.g. for uploading to the GPU: where each bucket's
+    // photons start (and one past the end), and the photons in bucket order
+    pub fn bucket_starts(&self) -> &[u32] {
+        &self.bucket_starts
+    }
+
+    pub fn positions(&self) -> &[Vertex] {
+        &self.positions
+    }
+
+    pub fn photons(&self) -> &[PackedPhoton] {
+        &self.photons
+    }
+}
+
+fn cell(position: &Vertex, cell_size: f32) -> (i32, i32, i32) {
+    (
+        (position.x / cell_size).floor() as i32,
+        (position.y / cell_size).floor() as i32,
+        (position.z / cell_size).floor() as i32,
+    )
+}
+
+// num_buckets is a power of two
+fn bucket((x, y, z): (i32, i32, i32), num_buckets: usize) -> usize {
+    // large primes from "Optimized Spatial Hashing for Collision Detection of
+    // Deformable Objects" (Teschner et al.)
+    let hash = (x as u32).wrapping_mul(73856093)
+        ^ (y as u32).wrapping_mul(19349663)
+        ^ (z as u32).wrapping_mul(83492791);
+    hash as usize & (num_buckets - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::core::{colour::Colour, photon::PhotonType, vector::Vector};
+
+    #[test]
+    fn any_radius_matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(2555);
+        let mut random_vertex = || Vertex::new(rng.gen(), rng.gen(), rng.gen());
+        let positions: Vec<Vertex> = (0..500).map(|_| random_vertex()).collect();
+        let photons = positions
+            .iter()
+            .map(|position| {
+                Photon::new(
+                    position.clone(),
+                    Vector::new(0.0, -1.0, 0.0),
+                    Colour::white(),
+                    PhotonType::Colour,
+                )
+            })
+            .collect();
+        let grid = PhotonGrid::build(photons, 0.1);
+
+        // up to half a cell, a few cells, and more cells than buckets
+        for radius in [0.05, 0.2, 2.0] {
+            for _ in 0..20 {
+                let centre = random_vertex();
+                let inside = |p: &Vertex| {
+                    (p.x - centre.x).abs() <= radius
+                        && (p.y - centre.y).abs() <= radius
+                        && (p.z - centre.z).abs() <= radius
+                };
+                let mut expected: Vec<[f32; 3]> = positions
+                    .iter()
+                    .filter(|p| inside(p))
+                    .map(Vertex::xyz)
+                    .collect();
+                let mut found: Vec<[f32; 3]> = grid
+                    .get_within_distance(&centre, radius)
+                    .iter()
+                    .map(|photon| photon.position.xyz())
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                assert_eq!(found, expected, "radius {radius}");
+            }
+        }
+    }
+}
@@ -1,41 +1,54 @@
-use kd_tree::KdTree3;
+use std::ops::Range;
 
-use super::{photon::Photon, vector::Vector, vertex::Vertex};
+use super::{
+    photon::{PackedPhoton, Photon},
+    platform,
+    vertex::Vertex,
+};
 
+// below this many photons, sorting a subtree isn't worth another thread
+const MIN_PHOTONS_PER_THREAD: usize = 100_000;
+
+// the photons are kept in kd-tree order: the middle photon of a range splits
+// the rest in two, along x, y and z in turn. the positions are kept apart
+// from the rest of each photon, so searching the tree only reads them, and
+// the rest is packed.
 pub struct PhotonTree {
-    tree: KdTree3<Photon>,
+    positions: Vec<[f32; 3]>,
+    photons: Vec<PackedPhoton>,
 }
 
 impl PhotonTree {
-    pub fn build(photons: Vec<Photon>) -> Self {
-        let tree = KdTree3::build_by_ordered_float(photons);
-        Self { tree }
+    pub fn build(mut photons: Vec<Photon>) -> Self {
+        sort(&mut photons, 0, platform::num_threads());
+        Self {
+            positions: photons.iter().map(|photon| photon.position.xyz()).collect(),
+            photons: photons.iter().map(PackedPhoton::pack).collect(),
+        }
     }
 
+    // photons within an axis aligned box around position, nearest first
     pub fn get_within_distance(&self, position: &Vertex, radius: f32) -> Vec<PhotonAndDistance> {
-        let radius = Vector::new(radius, radius, radius);
-        let topleft = position.clone() - radius;
-        let bottomright = position.clone() + radius;
+        let mut found = Vec::new();
+        let all = 0..self.positions.len();
+        self.within(all, 0, &position.xyz(), radius, &mut found);
 
-        let mut vec: Vec<PhotonAndDistance> = self
-            .tree
-            .within(&[topleft, bottomright])
+        let mut vec: Vec<PhotonAndDistance> = found
             .into_iter()
-            .map(|photon| {
-                let squared_distance = (photon.position.vector() - position.vector()).len_sqrd();
-                PhotonAndDistance {
-                    item: photon,
-                    squared_distance,
-                }
-            })
+            .map(|i| self.photon_and_distance(i, position))
             .collect();
-
         vec.sort_unstable_by(|a, b| a.squared_distance.partial_cmp(&b.squared_distance).unwrap());
         vec
     }
 
     pub fn find_nearest(&self, position: &Vertex, n: usize) -> Vec<PhotonAndDistance> {
-        self.tree.nearests(&position.xyz(), n)
+        let mut nearest = Vec::with_capacity(n + 1);
+        let all = 0..self.positions.len();
+        self.nearest(all, 0, &position.xyz(), n, &mut nearest);
+        nearest
+            .into_iter()
+            .map(|(_, i)| self.photon_and_distance(i, position))
+            .collect()
     }
 
     pub fn get_n_within_radius(
@@ -48,6 +61,171 @@ impl PhotonTree {
         vec.truncate(n);
         vec
     }
+
+    fn within(
+        &self,
+        range: Range<usize>,
+        axis: usize,
+        centre: &[f32; 3],
+        radius: f32,
+        found: &mut Vec<usize>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let position = &self.positions[middle];
+        if (0..3).all(|k| (position[k] - centre[k]).abs() <= radius) {
+            found.push(middle);
+        }
+
+        // photons before the middle are no further along the axis than it,
+        // and the ones after it no less far
+        let offset = position[axis] - centre[axis];
+        let next_axis = (axis + 1) % 3;
+        if offset >= -radius {
+            self.within(range.start..middle, next_axis, centre, radius, found);
+        }
+        if offset <= radius {
+            self.within(middle + 1..range.end, next_axis, centre, radius, found);
+        }
+    }
+
+    // nearest is kept sorted by squared distance, and no longer than n
+    fn nearest(
+        &self,
+        range: Range<usize>,
+        axis: usize,
+        centre: &[f32; 3],
+        n: usize,
+        nearest: &mut Vec<(f32, usize)>,
+    ) {
+        if range.is_empty() || n == 0 {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let squared_distance = squared_distance(&self.positions[middle], centre);
+        let furthest = |nearest: &Vec<(f32, usize)>| match nearest.len() < n {
+            true => f32::INFINITY,
+            false => nearest[n - 1].0,
+        };
+        if squared_distance < furthest(nearest) {
+            let at = nearest.partition_point(|(other, _)| *other <= squared_distance);
+            nearest.insert(at, (squared_distance, middle));
+            nearest.truncate(n);
+        }
+
+        // search the side the centre is on first, then the other side if it
+        // could still have anything nearer
+        let offset = centre[axis] - self.positions[middle][axis];
+        let (near, far) = match offset < 0.0 {
+            true => (range.start..middle, middle + 1..range.end),
+            false => (middle + 1..range.end, range.start..middle),
+        };
+        let next_axis = (axis + 1) % 3;
+        self.nearest(near, next_axis, centre, n, nearest);
+        if offset * offset < furthest(nearest) {
+            self.nearest(far, next_axis, centre, n, nearest);
+        }
+    }
+
+    fn photon_and_distance(&self, i: usize, position: &Vertex) -> PhotonAndDistance {
+        let [x, y, z] = self.positions[i];
+        PhotonAndDistance {
+            photon: self.photons[i].unpack(Vertex::new(x, y, z)),
+            squared_distance: squared_distance(&self.positions[i], &position.xyz()),
+        }
+    }
 }
 
-type PhotonAndDistance<'a> = kd_tree::ItemAndDistance<'a, Photon, f32>;
+// sorts photons into kd-tree order in place, splitting along axis first.
+// once the middle photon is in place, the two halves are independent, so
+// they're sorted on threads of their own until threads runs out.
+fn sort(photons: &mut [Photon], axis: usize, threads: usize) {
+    if photons.len() < 2 {
+        return;
+    }
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        a.position.xyz()[axis].total_cmp(&b.position.xyz()[axis])
+    });
+
+    let (lower, rest) = photons.split_at_mut(middle);
+    let upper = &mut rest[1..];
+    let next_axis = (axis + 1) % 3;
+    if threads > 1 && upper.len() >= MIN_PHOTONS_PER_THREAD {
+        let halves = vec![(lower, threads / 2), (upper, threads - threads / 2)];
+        platform::map_parallel(halves, |(half, threads)| sort(half, next_axis, threads));
+    } else {
+        sort(lower, next_axis, 1);
+        sort(upper, next_axis, 1);
+    }
+}
+
+fn squared_distance(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    (0..3).map(|k| (a[k] - b[k]) * (a[k] - b[k])).sum()
+}
+
+pub struct PhotonAndDistance {
+    pub photon: Photon,
+    pub squared_distance: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::core::{colour::Colour, photon::PhotonType, vector::Vector};
+
+    fn random_vertex(rng: &mut SmallRng) -> Vertex {
+        Vertex::new(rng.gen(), rng.gen(), rng.gen())
+    }
+
+    fn random_photons(rng: &mut SmallRng, count: usize) -> Vec<Photon> {
+        (0..count)
+            .map(|_| {
+                Photon::new(
+                    random_vertex(rng),
+                    Vector::new(0.0, 0.0, -1.0),
+                    Colour::new(1.0, 1.0, 1.0),
+                    PhotonType::Colour,
+                )
+            })
+            .collect()
+    }
+
+    fn sorted_distances(found: &[PhotonAndDistance]) -> Vec<f32> {
+        found.iter().map(|found| found.squared_distance).collect()
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(2589);
+        let photons = random_photons(&mut rng, 2000);
+        let positions: Vec<[f32; 3]> = photons.iter().map(|p| p.position.xyz()).collect();
+        let tree = PhotonTree::build(photons);
+
+        for _ in 0..50 {
+            let centre = random_vertex(&mut rng);
+            let mut all: Vec<f32> = positions
+                .iter()
+                .map(|position| squared_distance(position, &centre.xyz()))
+                .collect();
+            all.sort_unstable_by(f32::total_cmp);
+
+            let nearest = tree.find_nearest(&centre, 10);
+            assert_eq!(sorted_distances(&nearest), all[..10]);
+
+            let radius = 0.1;
+            let mut within: Vec<f32> = positions
+                .iter()
+                .filter(|p| (0..3).all(|k| (p[k] - centre.xyz()[k]).abs() <= radius))
+                .map(|position| squared_distance(position, &centre.xyz()))
+                .collect();
+            within.sort_unstable_by(f32::total_cmp);
+            let found = tree.get_within_distance(&centre, radius);
+            assert_eq!(sorted_distances(&found), within);
+        }
+    }
+}
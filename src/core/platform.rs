@@ -0,0 +1,154 @@
+// Things that differ between native builds and wasm32 (in a browser), where
+// there are no threads and no clock.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+static RESERVE_CORE: AtomicBool = AtomicBool::new(false);
+static PIN_THREADS: AtomicBool = AtomicBool::new(false);
+
+// None goes back to one thread per core
+pub fn set_num_threads(threads: Option<u32>) {
+    THREADS.store(threads.unwrap_or(0) as usize, Ordering::Relaxed);
+}
+
+// leave the first core for the calling thread, which e.g. keeps the preview
+// window responsive while everything else renders
+pub fn set_reserve_core(reserve_core: bool) {
+    RESERVE_CORE.store(reserve_core, Ordering::Relaxed);
+}
+
+// keep each worker thread on a core of its own, see pin_thread()
+pub fn set_pin_threads(pin_threads: bool) {
+    PIN_THREADS.store(pin_threads, Ordering::Relaxed);
+}
+
+// how many threads to split work over
+pub fn num_threads() -> usize {
+    if !cfg!(feature = "threads") {
+        return 1;
+    }
+
+    match THREADS.load(Ordering::Relaxed) {
+        0 => {
+            let cores = std::thread::available_parallelism().map_or(4, |n| n.get());
+            let reserved = RESERVE_CORE.load(Ordering::Relaxed) as usize;
+            cores.saturating_sub(reserved).max(1)
+        }
+        threads => threads,
+    }
+}
+
+// if pinning is on, move the calling thread onto the index'th core this
+// process may run on (skipping the reserved one), so the OS doesn't shuffle
+// render threads around and lose their caches. only does anything on Linux.
+pub fn pin_thread(index: usize) {
+    if !PIN_THREADS.load(Ordering::Relaxed) {
+        return;
+    }
+
+    // SAFETY: cpu_set_t is a plain bitmask, so all zeroes is a valid empty
+    // set. both sets live on this stack frame and are passed with their own
+    // size, CPU_SET/CPU_ISSET only touch cores below CPU_SETSIZE, and pid 0
+    // only ever affects the calling thread.
+    #[cfg(all(feature = "threads", target_os = "linux"))]
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        if libc::sched_getaffinity(0, size, &mut allowed) != 0 {
+            return;
+        }
+        let cores: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&core| libc::CPU_ISSET(core, &allowed))
+            .collect();
+        let reserved = RESERVE_CORE.load(Ordering::Relaxed) && cores.len() > 1;
+        let cores = if reserved { &cores[1..] } else { &cores[..] };
+        let Some(&core) = cores.get(index % cores.len().max(1)) else {
+            return;
+        };
+
+        let mut pinned: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut pinned);
+        if libc::sched_setaffinity(0, size, &pinned) != 0 {
+            // rendering works just as well unpinned, so only say so once
+            static FAILED: std::sync::Once = std::sync::Once::new();
+            let e = std::io::Error::last_os_error();
+            FAILED.call_once(|| println!("Could not pin render threads to cores: {e}"));
+        }
+    }
+
+    #[cfg(not(all(feature = "threads", target_os = "linux")))]
+    let _ = index;
+}
+
+// f(item) for every item, each on its own thread with the "threads" feature,
+// or one after another on the calling thread without it. results are in the
+// same order as items, and each thread's stats are flushed when it finishes.
+pub fn map_parallel<I: Send, T: Send>(items: Vec<I>, f: impl Fn(I) -> T + Sync) -> Vec<T> {
+    #[cfg(feature = "threads")]
+    {
+        let f = &f;
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = items
+                .into_iter()
+                .map(|item| {
+                    scope.spawn(move || {
+                        let result = f(item);
+                        super::stats::flush();
+                        result
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        })
+    }
+
+    #[cfg(not(feature = "threads"))]
+    {
+        items.into_iter().map(f).collect()
+    }
+}
+
+// wait for every thread, then carry on the first one's panic (if any) in
+// this thread with its own message, instead of the scope's generic one
+pub fn join_all<T>(threads: Vec<std::thread::ScopedJoinHandle<'_, T>>) {
+    let mut panic = None;
+    for thread in threads {
+        if let Err(payload) = thread.join() {
+            panic.get_or_insert(payload);
+        }
+    }
+    if let Some(payload) = panic {
+        std::panic::resume_unwind(payload);
+    }
+}
+
+// std::time::Instant panics on wasm32, where every timing reads as zero instead
+#[derive(Debug, Copy, Clone)]
+pub struct Instant {
+    #[cfg(not(target_arch = "wasm32"))]
+    instant: std::time::Instant,
+}
+
+impl Instant {
+    pub fn now() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            instant: std::time::Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.instant.elapsed();
+
+        #[cfg(target_arch = "wasm32")]
+        return Duration::ZERO;
+    }
+}
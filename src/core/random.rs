@@ -0,0 +1,63 @@
+// All the randomness in a render goes through here. Each thread has its own
+// generator, seeded randomly. When the scene sets a seed, the generator is
+// reseeded at the start of each independent piece of work (a photon, a tile)
+// from the seed and a key for that piece of work, so renders are reproducible
+// no matter how the work is split between threads.
+
+use std::{
+    cell::RefCell,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use rand::{rngs::SmallRng, SeedableRng};
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+// None goes back to random results
+pub fn set_seed(seed: Option<u64>) {
+    SEED.store(seed.unwrap_or(0), Ordering::Relaxed);
+    SEEDED.store(seed.is_some(), Ordering::Relaxed);
+}
+
+pub fn seed() -> Option<u64> {
+    SEEDED
+        .load(Ordering::Relaxed)
+        .then(|| SEED.load(Ordering::Relaxed))
+}
+
+// the stream camera pixels are keyed in. photon streams count up from 0, two
+// per light.
+pub const PIXEL_STREAM: u32 = u32::MAX;
+
+// a key for the index'th piece of work of a kind (stream), e.g. photon 42
+// of the first light
+pub fn key(stream: u32, index: u32) -> u64 {
+    (stream as u64) << 32 | index as u64
+}
+
+// reseed this thread's generator for the piece of work with this key. does
+// nothing if no seed is set.
+pub fn reseed(key: u64) {
+    if let Some(seed) = seed() {
+        let rng = SmallRng::seed_from_u64(splitmix64(seed ^ splitmix64(key)));
+        RNG.with(|cell| *cell.borrow_mut() = rng);
+    }
+}
+
+// don't call anything that might use the generator from inside f
+pub fn with_rng<T>(f: impl FnOnce(&mut SmallRng) -> T) -> T {
+    RNG.with(|cell| f(&mut cell.borrow_mut()))
+}
+
+// from https://prng.di.unimi.it/splitmix64.c, so nearby keys give unrelated seeds
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
@@ -1,8 +1,34 @@
 use super::{vertex::Vertex, vector::Vector};
 
+// what a ray is looking for, so objects can choose which rays see them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RayKind {
+    Camera,    // straight from the camera
+    Secondary, // reflected or refracted on the way from the camera
+    Shadow,    // from a surface towards a light
+    Photon,    // from a light, carrying its photons
+}
+
+impl RayKind {
+    // the kind of a ray reflected or refracted from one of this kind
+    pub const fn bounced(self) -> Self {
+        match self {
+            Self::Camera => Self::Secondary,
+            kind => kind,
+        }
+    }
+}
+
 pub struct Ray {
     pub position: Vertex,
     pub direction: Vector,
+    pub time: f32, // seconds after the shutter opened, for motion blur
+    // distance travelled from the camera before this ray started, for
+    // time-of-flight rendering
+    pub travelled: f32,
+    // fraction of the light along this ray that reaches the camera
+    pub throughput: f32,
+    pub kind: RayKind,
 }
 
 impl Ray {
@@ -10,6 +36,36 @@ impl Ray {
         Self {
             position,
             direction,
+            time: 0.0,
+            travelled: 0.0,
+            throughput: 1.0,
+            kind: RayKind::Camera,
         }
     }
-}
\ No newline at end of file
+
+    // secondary rays should be traced at the same time as their parent
+    pub const fn at_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
+    }
+
+    // a secondary ray spawned distance along parent, e.g. a reflection
+    pub fn continued(mut self, parent: &Ray, distance: f32) -> Self {
+        self.time = parent.time;
+        self.travelled = parent.travelled + distance;
+        self.throughput = parent.throughput;
+        self.kind = parent.kind.bounced();
+        self
+    }
+
+    pub const fn of_kind(mut self, kind: RayKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    // scale the throughput, e.g. by how much of the light a mirror reflects
+    pub fn weighted(mut self, weight: f32) -> Self {
+        self.throughput *= weight;
+        self
+    }
+}
@@ -0,0 +1,212 @@
+// Combining the samples of a supersampled pixel into a single value.
+//
+// Colour is always averaged, but averaging non-colour outputs (depth,
+// object IDs, normals) across an anti-aliased edge produces values that
+// belong to neither surface, so those are resolved by a policy instead.
+
+use crate::environments::environment::{Lighting, RaytraceResult, SurfaceInfo};
+
+use super::{colour::Colour, vector::Vector, vertex::Vertex};
+
+// fewer samples than this don't say enough about a pixel to tell what's out
+// of place in it
+const MIN_OUTLIER_SAMPLES: usize = 4;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResolvePolicy {
+    Closest,      // the sample nearest to the camera wins
+    MostFrequent, // the value shared by the most samples wins
+    Average,      // plain average of the samples that hit something
+}
+
+impl ResolvePolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Closest" => Some(Self::Closest),
+            "MostFrequent" => Some(Self::MostFrequent),
+            "Average" => Some(Self::Average),
+            _ => None,
+        }
+    }
+}
+
+pub struct PixelSamples {
+    samples: Vec<RaytraceResult>,
+    rejected: Vec<bool>, // left out of the colour, see reject_outliers()
+}
+
+impl PixelSamples {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            samples: Vec::with_capacity(capacity),
+            rejected: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn push(&mut self, sample: RaytraceResult) {
+        self.samples.push(sample);
+        self.rejected.push(false);
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.rejected.clear();
+    }
+
+    // leave out fireflies: samples brighter than white, and more than
+    // deviations standard deviations brighter than the mean of the others.
+    // each is compared to the others without it, so a firefly can't hide by
+    // raising the mean and spread itself. the spread counts as at least a
+    // quarter of the mean, so where the others all agree (e.g. a blown out
+    // wall) a sample only a little brighter isn't taken for one. the dimmest
+    // sample is never brighter than the others' mean, so some are always
+    // kept. returns how many were rejected.
+    pub fn reject_outliers(&mut self, deviations: f32) -> usize {
+        let count = self.samples.len();
+        if count < MIN_OUTLIER_SAMPLES {
+            return 0;
+        }
+
+        let brightness: Vec<f32> = self
+            .samples
+            .iter()
+            .map(|sample| sample.colour.max_component())
+            .collect();
+        let total: f32 = brightness.iter().sum();
+        let total_sqrd: f32 = brightness.iter().map(|b| b * b).sum();
+        let others = (count - 1) as f32;
+
+        let mut rejected = 0;
+        for (b, is_rejected) in brightness.iter().zip(&mut self.rejected) {
+            let mean = (total - b) / others;
+            let variance = ((total_sqrd - b * b) / others - mean * mean).max(0.0);
+            let spread = variance.sqrt().max(mean * 0.25);
+            *is_rejected = *b > 1.0 && *b > mean + deviations * spread;
+            rejected += *is_rejected as usize;
+        }
+        rejected
+    }
+
+    // the samples that weren't rejected
+    fn kept(&self) -> impl Iterator<Item = &RaytraceResult> {
+        self.samples
+            .iter()
+            .zip(&self.rejected)
+            .filter(|(_, rejected)| !**rejected)
+            .map(|(sample, _)| sample)
+    }
+
+    pub fn colour(&self) -> Colour {
+        let total = self
+            .kept()
+            .fold(Colour::black(), |acc, sample| acc + sample.colour);
+        total / self.kept().count() as f32
+    }
+
+    // averaged like the colour, since it blends the same way
+    pub fn throughput(&self) -> f32 {
+        let total: f32 = self.samples.iter().map(|sample| sample.throughput).sum();
+        total / self.samples.len() as f32
+    }
+
+    // averaged like the colour, so the parts still add up to it
+    pub fn lighting(&self) -> Lighting {
+        let total = self
+            .kept()
+            .fold(Lighting::default(), |acc, sample| acc + sample.lighting);
+        total.map(|part| part / self.kept().count() as f32)
+    }
+
+    // infinite if no sample hit anything
+    pub fn depth(&self, policy: ResolvePolicy) -> f32 {
+        // an infinite depth means the sample didn't hit anything
+        let hits = self
+            .samples
+            .iter()
+            .map(|s| s.depth)
+            .filter(|d| d.is_finite());
+
+        match policy {
+            ResolvePolicy::Closest => hits.reduce(f32::min).unwrap_or(f32::INFINITY),
+            ResolvePolicy::MostFrequent => {
+                // depths are continuous, so bucket them to the nearest
+                // hundredth before counting. misses all share one bucket
+                let bucket = |d: f32| d.is_finite().then(|| (d * 100.0).round() as i64);
+                let buckets = self.samples.iter().map(|s| bucket(s.depth));
+                let most_frequent = most_frequent(buckets).flatten();
+                self.samples
+                    .iter()
+                    .map(|s| s.depth)
+                    .find(|d| bucket(*d) == most_frequent)
+                    .unwrap_or(f32::INFINITY)
+            }
+            ResolvePolicy::Average => {
+                // misses would make the average infinite, so only average
+                // the hits
+                let (total, count) =
+                    hits.fold((0.0, 0), |(total, count), d| (total + d, count + 1));
+                if count == 0 {
+                    f32::INFINITY
+                } else {
+                    total / count as f32
+                }
+            }
+        }
+    }
+
+    // None if no sample hit anything
+    pub fn surface(&self, policy: ResolvePolicy) -> Option<SurfaceInfo> {
+        let hits = self.samples.iter().filter_map(|s| s.surface.as_ref());
+
+        match policy {
+            ResolvePolicy::Closest => self
+                .samples
+                .iter()
+                .filter(|s| s.surface.is_some())
+                .min_by(|a, b| a.depth.total_cmp(&b.depth))
+                .and_then(|s| s.surface.clone()),
+            ResolvePolicy::MostFrequent => {
+                let object_id = most_frequent(hits.clone().map(|s| s.object_id))?;
+                hits.clone().find(|s| s.object_id == object_id).cloned()
+            }
+            ResolvePolicy::Average => {
+                // object IDs can't be averaged, so take the most frequent
+                let object_id = most_frequent(hits.clone().map(|s| s.object_id))?;
+                let count = hits.clone().count() as f32;
+                let total = hits.fold(
+                    (Vector::new(0.0, 0.0, 0.0), Vector::new(0.0, 0.0, 0.0), Colour::black()),
+                    |(position, normal, albedo), s| {
+                        (position + s.position.vector(), normal + s.normal, albedo + s.albedo)
+                    },
+                );
+                Some(SurfaceInfo {
+                    position: Vertex::from(total.0 * (1.0 / count)),
+                    normal: total.1.normalised(),
+                    albedo: total.2 / count,
+                    object_id,
+                })
+            }
+        }
+    }
+}
+
+// returns the value that appears most often, ties going to the one seen first
+pub fn most_frequent<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, u32)> = Vec::new();
+
+    for value in values {
+        match counts.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
+        }
+    }
+
+    let mut best: Option<(T, u32)> = None;
+    for (value, count) in counts {
+        if best.is_none_or(|(_, best_count)| count > best_count) {
+            best = Some((value, count));
+        }
+    }
+
+    best.map(|(value, _)| value)
+}
@@ -0,0 +1,259 @@
+// Where the samples of a pixel go. Anti-aliasing, motion blur, glossy
+// reflections, ambient occlusion and area lights draw their random numbers
+// through here, each from its own dimension, so a sampler that spreads the
+// samples of a pixel out evenly does so for all of them at once.
+//
+// The camera starts each of a pixel's samples with begin(). Outside of one,
+// e.g. while shooting photons, everything is independent random numbers.
+
+use std::{cell::Cell, sync::OnceLock};
+
+use rand::Rng;
+
+use super::random;
+
+// dimensions past this many (i.e. deep bounces) don't gain much from being
+// well spread out, and the Halton sequence gets worse in high dimensions
+const MAX_DIMENSIONS: u32 = 8;
+const PRIMES: [u32; 2 * MAX_DIMENSIONS as usize] =
+    [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+// the blue noise mask is tiled over the image
+const MASK_SIZE: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Sampler {
+    #[default]
+    Grid, // the centre of each cell of the pixel's grid, random everywhere else
+    Random,     // independent random numbers everywhere
+    Stratified, // a random point in each cell of a grid, in every dimension
+    Halton,     // the Halton sequence, shifted randomly for each pixel
+    BlueNoise,  // the Halton sequence, shifted by a blue noise mask
+}
+
+impl Sampler {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Grid" => Some(Self::Grid),
+            "Random" => Some(Self::Random),
+            "Stratified" => Some(Self::Stratified),
+            "Halton" => Some(Self::Halton),
+            "BlueNoise" => Some(Self::BlueNoise),
+            _ => None,
+        }
+    }
+
+    // a point in the unit square for sample index of count in the pixel at
+    // x, y
+    fn sample(&self, x: u32, y: u32, index: u32, count: u32, dimension: u32) -> (f32, f32) {
+        match self {
+            Self::Grid if dimension == 0 => {
+                let side = grid_side(count);
+                let cell = index % (side * side);
+                let cx = (cell % side) as f32 + 0.5;
+                let cy = (cell / side) as f32 + 0.5;
+                (cx / side as f32, cy / side as f32)
+            }
+
+            Self::Stratified => {
+                // shuffle which cell each sample gets, differently in each
+                // dimension, so the dimensions aren't correlated
+                let side = grid_side(count);
+                let cells = side * side;
+                let cell = permute(index % cells, cells, hash(x, y, dimension) as u32);
+                let (jx, jy): (f32, f32) = random::with_rng(|rng| (rng.gen(), rng.gen()));
+                let cx = (cell % side) as f32 + jx;
+                let cy = (cell / side) as f32 + jy;
+                (cx / side as f32, cy / side as f32)
+            }
+
+            Self::Halton | Self::BlueNoise if dimension < MAX_DIMENSIONS => {
+                let u = radical_inverse(PRIMES[2 * dimension as usize], index);
+                let v = radical_inverse(PRIMES[2 * dimension as usize + 1], index);
+
+                // every pixel gets the same sequence, shifted around the
+                // unit square so they don't all alias the same way
+                let (su, sv) = match self {
+                    Self::BlueNoise => {
+                        let offset = dimension * 23;
+                        (
+                            blue_noise(x + offset, y + offset * 3),
+                            blue_noise(x + offset + 32, y + offset * 3 + 17),
+                        )
+                    }
+                    _ => {
+                        let hash = hash(x, y, dimension);
+                        (unit(hash), unit(hash >> 24))
+                    }
+                };
+                ((u + su).fract(), (v + sv).fract())
+            }
+
+            _ => random::with_rng(|rng| (rng.gen(), rng.gen())),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PixelSample {
+    sampler: Sampler,
+    x: u32,
+    y: u32,
+    index: u32,
+    count: u32,
+    dimension: u32,
+}
+
+thread_local! {
+    static CURRENT: Cell<Option<PixelSample>> = const { Cell::new(None) };
+}
+
+// start sample index of count in the pixel at x, y. everything drawn until
+// the next begin() or end() belongs to it.
+pub fn begin(sampler: Sampler, x: u32, y: u32, index: u32, count: u32) {
+    CURRENT.with(|current| {
+        current.set(Some(PixelSample {
+            sampler,
+            x,
+            y,
+            index,
+            count,
+            dimension: 0,
+        }))
+    });
+}
+
+// back to independent random numbers
+pub fn end() {
+    CURRENT.with(|current| current.set(None));
+}
+
+// the next two dimensions of the current sample, each from 0 to 1
+pub fn next_2d() -> (f32, f32) {
+    let sample = CURRENT.with(|current| {
+        let sample = current.get()?;
+        current.set(Some(PixelSample {
+            dimension: sample.dimension + 1,
+            ..sample
+        }));
+        Some(sample)
+    });
+
+    match sample {
+        Some(s) => s.sampler.sample(s.x, s.y, s.index, s.count, s.dimension),
+        None => random::with_rng(|rng| (rng.gen(), rng.gen())),
+    }
+}
+
+pub fn next_1d() -> f32 {
+    next_2d().0
+}
+
+// the camera's samples are always a square number
+fn grid_side(count: u32) -> u32 {
+    ((count as f32).sqrt() as u32).max(1)
+}
+
+// digits of index in base, mirrored around the decimal point
+fn radical_inverse(base: u32, mut index: u32) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += (index % base) as f64 * scale;
+        index /= base;
+        scale *= inverse_base;
+    }
+    (result as f32).min(1.0 - f32::EPSILON)
+}
+
+// different for every pixel and dimension, and for every seed
+fn hash(x: u32, y: u32, dimension: u32) -> u64 {
+    let key = random::key(x, y) ^ (dimension as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    random::splitmix64(key ^ random::seed().unwrap_or(0))
+}
+
+// the low 24 bits as 0 to 1
+fn unit(bits: u64) -> f32 {
+    (bits & 0xffffff) as f32 / (1 << 24) as f32
+}
+
+// a shuffle of 0..length picked by seed, without storing it. from Kensler,
+// "Correlated Multi-Jittered Sampling" (2013).
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            return i.wrapping_add(seed) % length;
+        }
+    }
+}
+
+// the blue noise mask at x, y, from 0 to 1
+fn blue_noise(x: u32, y: u32) -> f32 {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    let mask = MASK.get_or_init(blue_noise_mask);
+    let (x, y) = (x as usize % MASK_SIZE, y as usize % MASK_SIZE);
+    mask[y * MASK_SIZE + x]
+}
+
+// each pixel ranked by when it gets filled in if pixels are added one at a
+// time, always in the emptiest spot left (the void-and-cluster method). any
+// threshold of it is then evenly spread without clumps, i.e. blue noise.
+fn blue_noise_mask() -> Vec<f32> {
+    const SIGMA: f32 = 1.5;
+    const RADIUS: i32 = 5; // the gaussian is negligible past this
+
+    let pixels = MASK_SIZE * MASK_SIZE;
+    // a tiny bit of noise to start with, so ties don't fill in a lattice
+    let mut energy: Vec<f32> = (0..pixels as u32)
+        .map(|i| unit(random::splitmix64(i as u64)) * 1e-3)
+        .collect();
+    let mut rank = vec![None; pixels];
+
+    for order in 0..pixels {
+        let void = (0..pixels)
+            .filter(|&i| rank[i].is_none())
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap();
+        rank[void] = Some(order);
+
+        // the mask tiles, so the energy wraps around the edges
+        let (vx, vy) = ((void % MASK_SIZE) as i32, (void / MASK_SIZE) as i32);
+        for dy in -RADIUS..=RADIUS {
+            for dx in -RADIUS..=RADIUS {
+                let x = (vx + dx).rem_euclid(MASK_SIZE as i32) as usize;
+                let y = (vy + dy).rem_euclid(MASK_SIZE as i32) as usize;
+                let distance_sqrd = (dx * dx + dy * dy) as f32;
+                energy[y * MASK_SIZE + x] += (-distance_sqrd / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+    }
+
+    rank.into_iter()
+        .map(|order| (order.unwrap() as f32 + 0.5) / pixels as f32)
+        .collect()
+}
@@ -0,0 +1,242 @@
+// Render statistics: how many rays of each kind were traced, how many
+// object intersection tests they took, how many photons were stored, and
+// how long each phase and tile of the render took. For finding out where
+// the time goes, and noticing when a change makes things slower.
+//
+// Counters are kept per thread and only added to the totals by flush(), so
+// the render threads don't slow each other down fighting over them. The
+// totals are global, so renders running at the same time are counted
+// together.
+
+use std::{
+    cell::Cell,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{cameras::camera::Tile, environments::photon_bake::heat_colour};
+
+use super::framebuffer::FrameBuffer;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Counter {
+    PrimaryRays,       // from the camera
+    SecondaryRays,     // reflections and refractions
+    ShadowRays,        // towards lights, and behind absorbed photons
+    PhotonRays,        // photons being traced through the scene
+    IntersectionTests, // ray against top level object
+    PhotonsStored,     // in the photon maps
+    RejectedSamples,   // fireflies left out of their pixel
+}
+
+impl Counter {
+    const ALL: [Self; 7] = [
+        Self::PrimaryRays,
+        Self::SecondaryRays,
+        Self::ShadowRays,
+        Self::PhotonRays,
+        Self::IntersectionTests,
+        Self::PhotonsStored,
+        Self::RejectedSamples,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::PrimaryRays => "primary_rays",
+            Self::SecondaryRays => "secondary_rays",
+            Self::ShadowRays => "shadow_rays",
+            Self::PhotonRays => "photon_rays",
+            Self::IntersectionTests => "intersection_tests",
+            Self::PhotonsStored => "photons_stored",
+            Self::RejectedSamples => "rejected_samples",
+        }
+    }
+}
+
+const NUM_COUNTERS: usize = Counter::ALL.len();
+
+thread_local! {
+    static LOCAL: [Cell<u64>; NUM_COUNTERS] = const { [const { Cell::new(0) }; NUM_COUNTERS] };
+}
+
+static TOTALS: [AtomicU64; NUM_COUNTERS] = [const { AtomicU64::new(0) }; NUM_COUNTERS];
+static PHASES: Mutex<Vec<(&'static str, f32)>> = Mutex::new(Vec::new());
+static TILE_TIMES: Mutex<Option<TileTimes>> = Mutex::new(None);
+
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+pub fn add(counter: Counter, amount: u64) {
+    LOCAL.with(|local| {
+        let cell = &local[counter as usize];
+        cell.set(cell.get() + amount);
+    });
+}
+
+// add this thread's counts to the totals. call when a thread finishes a
+// piece of work, anything not flushed is lost when the thread exits.
+pub fn flush() {
+    LOCAL.with(|local| {
+        for (cell, total) in local.iter().zip(&TOTALS) {
+            total.fetch_add(cell.take(), Ordering::Relaxed);
+        }
+    });
+}
+
+// time spent on part of the render, e.g. "shoot photons". phases with the
+// same name add up.
+pub fn record_phase(name: &'static str, seconds: f32) {
+    let mut phases = PHASES.lock().unwrap();
+    match phases.iter_mut().find(|(phase, _)| *phase == name) {
+        Some((_, total)) => *total += seconds,
+        None => phases.push((name, seconds)),
+    }
+}
+
+// how long a tile of a width x height image took to render
+pub fn record_tile(width: u32, height: u32, tile: &Tile, seconds: f32) {
+    let mut tile_times = TILE_TIMES.lock().unwrap();
+    // only keep the biggest renders, not e.g. the low resolution
+    // progressive passes before them
+    let is_bigger = tile_times
+        .as_ref()
+        .is_none_or(|times| width * height > times.width * times.height);
+    if is_bigger {
+        *tile_times = Some(TileTimes::new(width, height));
+    }
+    if let Some(times) = tile_times.as_mut() {
+        if (times.width, times.height) == (width, height) {
+            times.add(tile, seconds);
+        }
+    }
+}
+
+// everything recorded since the last take(), which starts counting again
+// from zero
+pub fn take() -> RenderStats {
+    flush();
+    let mut counts = [0; NUM_COUNTERS];
+    for (count, total) in counts.iter_mut().zip(&TOTALS) {
+        *count = total.swap(0, Ordering::Relaxed);
+    }
+
+    RenderStats {
+        counts,
+        phases: std::mem::take(&mut *PHASES.lock().unwrap()),
+        tile_times: TILE_TIMES.lock().unwrap().take(),
+    }
+}
+
+// per-tile render times of an image, added up over every render of it
+// (e.g. the normal image and its transient bins)
+pub struct TileTimes {
+    width: u32,
+    height: u32,
+    tiles: Vec<(Tile, f32)>,
+}
+
+impl TileTimes {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tiles: Tile::split(width, height)
+                .into_iter()
+                .map(|tile| (tile, 0.0))
+                .collect(),
+        }
+    }
+
+    fn add(&mut self, tile: &Tile, seconds: f32) {
+        let same_tile = |other: &Tile| (other.x, other.y) == (tile.x, tile.y);
+        if let Some((_, time)) = self.tiles.iter_mut().find(|(other, _)| same_tile(other)) {
+            *time += seconds;
+        }
+    }
+
+    // each tile coloured from black (quickest) to white (slowest)
+    pub fn heat_map(&self) -> FrameBuffer {
+        let slowest = self.tiles.iter().map(|(_, time)| *time).fold(0.0, f32::max);
+        let mut framebuffer = FrameBuffer::new(self.width, self.height);
+        for (tile, time) in &self.tiles {
+            let amount = if slowest > 0.0 { time / slowest } else { 0.0 };
+            let colour = heat_colour(amount);
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    framebuffer.plot_pixel(x, y, &colour);
+                }
+            }
+        }
+        framebuffer
+    }
+}
+
+pub struct RenderStats {
+    counts: [u64; NUM_COUNTERS],
+    pub phases: Vec<(&'static str, f32)>, // in the order they first happened
+    pub tile_times: Option<TileTimes>,
+}
+
+impl RenderStats {
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.counts[counter as usize]
+    }
+
+    pub fn rays(&self) -> u64 {
+        [
+            Counter::PrimaryRays,
+            Counter::SecondaryRays,
+            Counter::ShadowRays,
+            Counter::PhotonRays,
+        ]
+        .into_iter()
+        .map(|counter| self.get(counter))
+        .sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n");
+        json += &format!("  \"rays\": {},\n", self.rays());
+        for counter in Counter::ALL {
+            json += &format!("  \"{}\": {},\n", counter.name(), self.get(counter));
+        }
+
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, seconds)| format!("    \"{name}\": {seconds}"))
+            .collect();
+        json += &format!("  \"phase_seconds\": {{\n{}\n  }},\n", phases.join(",\n"));
+
+        let tiles: Vec<String> = self
+            .tile_times
+            .iter()
+            .flat_map(|times| &times.tiles)
+            .map(|(tile, seconds)| {
+                format!(
+                    "    {{ \"x\": {}, \"y\": {}, \"width\": {}, \"height\": {}, \"seconds\": {seconds} }}",
+                    tile.x, tile.y, tile.width, tile.height
+                )
+            })
+            .collect();
+        json += &format!("  \"tiles\": [\n{}\n  ]\n}}\n", tiles.join(",\n"));
+        json
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Stats:\n  rays: {}", self.rays())?;
+        for counter in Counter::ALL {
+            write!(f, "\n  {}: {}", counter.name(), self.get(counter))?;
+        }
+        for (name, seconds) in &self.phases {
+            write!(f, "\n  {name}: {seconds:.2}s")?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::vector::Vector;
+use super::{error::RenderError, vector::Vector};
 
 #[derive(Clone)]
 pub struct Transform {
@@ -43,7 +43,30 @@ impl Transform {
         }
     }
 
-    pub fn inverse(&self) -> Self {
+    // turns by angles.x radians around the x axis, then y, then z
+    pub fn from_rotation(angles: Vector) -> Self {
+        let (sin_x, cos_x) = angles.x.sin_cos();
+        let (sin_y, cos_y) = angles.y.sin_cos();
+        let (sin_z, cos_z) = angles.z.sin_cos();
+        let x = Self::from_rotation_matrix([
+            [1.0, 0.0, 0.0],
+            [0.0, cos_x, -sin_x],
+            [0.0, sin_x, cos_x],
+        ]);
+        let y = Self::from_rotation_matrix([
+            [cos_y, 0.0, sin_y],
+            [0.0, 1.0, 0.0],
+            [-sin_y, 0.0, cos_y],
+        ]);
+        let z = Self::from_rotation_matrix([
+            [cos_z, -sin_z, 0.0],
+            [sin_z, cos_z, 0.0],
+            [0.0, 0.0, 1.0],
+        ]);
+        z * y * x
+    }
+
+    pub fn inverse(&self) -> Result<Self, RenderError> {
         let mut inverted: [[f32; 4]; 4] = [[0.0; 4]; 4];
 
         inverted[0][0] = self.matrix[1][1] * self.matrix[2][2] * self.matrix[3][3]
@@ -163,7 +186,7 @@ impl Transform {
             + self.matrix[0][2] * inverted[2][0]
             + self.matrix[0][3] * inverted[3][0];
         if det == 0.0 {
-            panic!("Matrix is not invertible");
+            return Err(RenderError::NotInvertible);
         }
 
         let det = 1.0 / det;
@@ -173,7 +196,7 @@ impl Transform {
             }
         }
 
-        Self { matrix: inverted }
+        Ok(Self { matrix: inverted })
     }
 
     pub fn transposed(&self) -> Self {
@@ -221,3 +244,35 @@ impl std::ops::IndexMut<usize> for Transform {
         &mut self.matrix[index]
     }
 }
+
+// which way is up in a scene file or model. everything is rendered y-up, so
+// z-up coordinates (e.g. from Blender) are rotated on the way in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum UpAxis {
+    #[default]
+    Y,
+    Z,
+}
+
+impl UpAxis {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Y" => Some(Self::Y),
+            "Z" => Some(Self::Z),
+            _ => None,
+        }
+    }
+
+    // the rotation from this convention to y-up
+    pub fn to_y_up(&self) -> Transform {
+        match self {
+            Self::Y => Transform::identity(),
+            // (x, y, z) -> (x, z, -y), keeping the handedness
+            Self::Z => Transform::from_rotation_matrix([
+                [1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [0.0, -1.0, 0.0],
+            ]),
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::{transform::Transform, vertex::Vertex};
+use super::{onb::Onb, random, sampler, transform::Transform, vertex::Vertex};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Vector {
@@ -23,32 +23,43 @@ impl Vector {
     pub fn random() -> Self {
         use rand::distributions::{Distribution, Uniform};
 
-        let mut rng = rand::thread_rng();
         thread_local! {
             static DISTRIBUTION: Uniform<f32> = Uniform::new(-1.0, 1.0);
         }
 
-        DISTRIBUTION.with(|distribution| loop {
-            let vec = Self::new(
-                distribution.sample(&mut rng),
-                distribution.sample(&mut rng),
-                distribution.sample(&mut rng),
-            );
-
-            if vec.len_sqrd() <= 1.0 {
-                return vec.normalised();
-            }
+        DISTRIBUTION.with(|distribution| {
+            random::with_rng(|rng| loop {
+                let vec = Self::new(
+                    distribution.sample(rng),
+                    distribution.sample(rng),
+                    distribution.sample(rng),
+                );
+
+                if vec.len_sqrd() <= 1.0 {
+                    return vec.normalised();
+                }
+            })
         })
     }
 
+    // uniformly distributed over the hemisphere around the normal
     pub fn random_on_surface(normal: Vector) -> Self {
-        let mut vec = Self::random();
+        let (z, u) = sampler::next_2d();
+        let phi = u * std::f32::consts::TAU;
+        let r = (1.0 - z * z).max(0.0).sqrt();
 
-        if vec.dot(&normal) < 0.0 {
-            vec.negate();
-        }
+        Onb::from_normal(&normal).to_world(&Self::new(r * phi.cos(), r * phi.sin(), z))
+    }
 
-        vec
+    // a random direction at most acos(cos_max) away from axis, uniformly
+    // over that cone's solid angle
+    pub fn random_in_cone(axis: Vector, cos_max: f32) -> Self {
+        let (u, v) = sampler::next_2d();
+        let phi = v * std::f32::consts::TAU;
+        let z = 1.0 - u * (1.0 - cos_max);
+        let r = (1.0 - z * z).max(0.0).sqrt();
+
+        Onb::from_normal(&axis).to_world(&Self::new(r * phi.cos(), r * phi.sin(), z))
     }
 
     pub fn normalise(&mut self) {
@@ -130,19 +141,10 @@ impl Vector {
         self.z = z;
     }
 
-    pub fn to_tangent_space(mut self, tangent: &Self, normal: &Self) -> Self {
-        let tangent = tangent.normalised();
-        let normal = normal.normalised();
-        let bitangent = normal.cross(&tangent);
-
-        let rotation_matrix = Transform::from_rotation_matrix([
-            [tangent.x, bitangent.x, normal.x],
-            [tangent.y, bitangent.y, normal.y],
-            [tangent.z, bitangent.z, normal.z],
-        ]);
-
-        self.apply_transform(&rotation_matrix);
-        self.normalised()
+    pub fn to_tangent_space(self, tangent: &Self, normal: &Self) -> Self {
+        Onb::from_normal_and_tangent(normal, tangent)
+            .to_world(&self)
+            .normalised()
     }
 }
 
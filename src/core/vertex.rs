@@ -1,4 +1,4 @@
-use super::{tex_coords::TexCoords, transform::Transform, vector::Vector};
+use super::{colour::Colour, tex_coords::TexCoords, transform::Transform, vector::Vector};
 
 #[derive(Debug, Clone)]
 pub struct Vertex {
@@ -105,6 +105,7 @@ pub struct RichVertex {
     pub vertex: Vertex,
     pub normal: Option<Vector>,
     pub tex_coords: Option<TexCoords>,
+    pub colour: Option<Colour>, // from OBJ files with "v x y z r g b" lines
 }
 
 impl RichVertex {
@@ -113,6 +114,7 @@ impl RichVertex {
             vertex,
             normal,
             tex_coords,
+            colour: None,
         }
     }
 
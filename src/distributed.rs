@@ -0,0 +1,462 @@
+// Rendering an image on several machines. Workers (`--worker --listen
+// 192.168.1.2:7878`) wait for a coordinator to connect and send them a job: the
+// scene file's text, overrides and which camera to render. They build and
+// prepare the scene themselves, then render the tiles the coordinator asks
+// for, a batch of one per thread at a time, and send them back.
+//
+// The coordinator renders tiles too, and any a worker fails on are put back
+// for someone else to render. Only the scene text is sent, so the models and
+// textures it uses must be at the same paths on every worker, and photon
+// scenes need a seed for every worker to shoot the same photons.
+//
+// Everything is sent little-endian, with strings and byte arrays prefixed by
+// their length.
+//
+// There's no authentication or encryption: a worker renders for anyone who
+// can connect to it, and the scenes it's sent can read any model or texture
+// the worker's user can. So workers listen on 127.0.0.1 unless told
+// otherwise, and should only be given an address on a network where
+// everyone is trusted. What's received is still checked, so a bad job fails
+// instead of crashing the worker or making it allocate an absurd image.
+
+use std::{
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    cameras::camera::{print_progress, Camera, Tile},
+    core::{cancel, framebuffer::FrameBuffer, platform, stats},
+    environments::environment::Environment,
+    scene_file::{SceneFile, SceneOverride},
+    FullCamera,
+};
+
+// sent first, so workers don't try to make sense of anything else
+const MAGIC: &[u8; 8] = b"RTJOB\0\0\x01";
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+// the largest view a worker accepts, in pixels across and samples along each
+// side of a pixel
+const MAX_SIZE: u32 = 1 << 15;
+const MAX_SAMPLES: u32 = 1 << 8;
+
+// what every camera of a scene is rendered from
+pub struct Job {
+    pub filename: PathBuf, // only for its extension, which says the format
+    pub scene: String,
+    pub overrides: Vec<String>, // see SceneOverride
+    pub time: f32,
+}
+
+// listen for coordinators, rendering one job at a time until killed.
+// threads is how many tiles to render at once, None for one per core.
+pub fn serve(address: &str, threads: Option<u32>) -> Result<(), String> {
+    let listener = TcpListener::bind(address).map_err(|e| format!("{address}: {e}"))?;
+    let threads = threads.unwrap_or_else(|| platform::num_threads() as u32);
+    println!("Waiting for jobs on {address}...");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        let peer = stream
+            .peer_addr()
+            .map_or("unknown".to_string(), |peer| peer.to_string());
+        println!("Rendering for {peer}...");
+        match serve_job(stream, threads) {
+            Ok(tiles) => println!("Rendered {tiles} tiles for {peer}"),
+            Err(e) => println!("Job from {peer} failed: {e}"),
+        }
+    }
+    Ok(())
+}
+
+// returns how many tiles were rendered
+fn serve_job(stream: TcpStream, threads: u32) -> Result<u32, String> {
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut writer = BufWriter::new(stream);
+
+    let mut magic = [0; MAGIC.len()];
+    reader.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    if &magic != MAGIC {
+        return Err("Not a render job".to_string());
+    }
+    let job = Job::read(&mut reader).map_err(|e| e.to_string())?;
+    let view = View::read(&mut reader).map_err(|e| e.to_string())?;
+
+    // tell the coordinator whether the scene could be built, and how many
+    // tiles to send at once
+    let prepared = job.prepare(&view);
+    let sent = match &prepared {
+        Ok(_) => write_u32(&mut writer, 0).and_then(|_| write_u32(&mut writer, threads)),
+        Err(e) => write_u32(&mut writer, 1).and_then(|_| write_string(&mut writer, e)),
+    };
+    sent.and_then(|_| writer.flush())
+        .map_err(|e| e.to_string())?;
+    let (camera, scene) = prepared?;
+
+    let mut rendered = 0;
+    loop {
+        let count = read_u32(&mut reader).map_err(|e| e.to_string())?;
+        if count == 0 {
+            return Ok(rendered);
+        }
+        let tiles = (0..count)
+            .map(|_| read_tile(&mut reader))
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|e| e.to_string())?;
+        for tile in &tiles {
+            let outside = |start: u32, length: u32, size: u32| {
+                start.checked_add(length).is_none_or(|end| end > size)
+            };
+            if outside(tile.x, tile.width, camera.width)
+                || outside(tile.y, tile.height, camera.height)
+            {
+                return Err(format!("Tile outside the image: {tile:?}"));
+            }
+        }
+
+        let framebuffers = platform::map_parallel(tiles, |tile| {
+            camera.render_tile(scene.as_ref(), &tile).to_bytes()
+        });
+        for bytes in framebuffers {
+            write_bytes(&mut writer, &bytes).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+        rendered += count;
+    }
+}
+
+// render the already prepared scene from camera, with the workers at the
+// given addresses helping out. like Camera::render_prepared(), on_update is
+// called every so often with the image so far.
+pub fn render(
+    workers: &[String],
+    job: &Job,
+    camera: &FullCamera,
+    scene: &dyn Environment,
+    on_update: &mut dyn FnMut(&FrameBuffer),
+) -> FrameBuffer {
+    let mut tiles = match camera.region() {
+        Some(region) => Tile::split_within(&region),
+        None => Tile::split(camera.width, camera.height),
+    };
+    tiles.reverse(); // taken off the end, so rendered in order
+    let num_tiles = tiles.len() as u32;
+    let queue = Mutex::new(tiles);
+    let tiles_done = AtomicU32::new(0);
+
+    let framebuffer = Mutex::new(FrameBuffer::new(camera.width, camera.height));
+    let start = platform::Instant::now();
+
+    let take = |count: u32| {
+        let mut queue = queue.lock().unwrap();
+        let split = queue.len().saturating_sub(count as usize);
+        let mut batch = queue.split_off(split);
+        batch.reverse();
+        batch
+    };
+    let give_back = |tiles: &[Tile]| queue.lock().unwrap().extend(tiles.iter().rev());
+    let blit = |tile: &Tile, tile_framebuffer: &FrameBuffer| {
+        framebuffer
+            .lock()
+            .unwrap()
+            .blit(tile_framebuffer, tile.x, tile.y);
+        let done = tiles_done.fetch_add(1, Ordering::Relaxed) + 1;
+        print_progress(done, num_tiles, start);
+    };
+    // a thread that panics takes its tile with it, so everyone else stops
+    // waiting for the tiles to run out
+    let panicked = AtomicBool::new(false);
+    let all_done =
+        || tiles_done.load(Ordering::Relaxed) >= num_tiles || panicked.load(Ordering::Relaxed);
+
+    let threads = camera.threads().unwrap_or(platform::num_threads() as u32);
+    println!(
+        "Rendering {num_tiles} tiles on {threads} threads and {} worker(s)...",
+        workers.len()
+    );
+    std::thread::scope(|scope| {
+        let mut handles = Vec::new();
+        for address in workers {
+            let (take, give_back, blit) = (&take, &give_back, &blit);
+            let panicked = &panicked;
+            handles.push(scope.spawn(move || {
+                let _flag = PanicFlag(panicked);
+                let result = render_remote(address, job, camera, take, give_back, blit);
+                if let Err(e) = result {
+                    println!("\nWorker {address} failed: {e}");
+                }
+            }));
+        }
+
+        for thread_index in 0..threads {
+            let (take, blit, all_done) = (&take, &blit, &all_done);
+            let panicked = &panicked;
+            handles.push(scope.spawn(move || {
+                let _flag = PanicFlag(panicked);
+                platform::pin_thread(thread_index as usize);
+                // tiles a worker fails on can come back until the very end
+                while !all_done() && !cancel::cancelled() {
+                    let Some(tile) = take(1).pop() else {
+                        std::thread::sleep(WAIT_INTERVAL);
+                        continue;
+                    };
+                    blit(&tile, &camera.render_tile(scene, &tile));
+                }
+                stats::flush();
+            }));
+        }
+
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            std::thread::sleep(WAIT_INTERVAL);
+            if let Ok(framebuffer) = framebuffer.lock() {
+                on_update(&framebuffer);
+            }
+        }
+        platform::join_all(handles);
+    });
+    println!();
+    stats::record_phase("render", start.elapsed().as_secs_f32());
+
+    let framebuffer = framebuffer.into_inner().unwrap();
+    on_update(&framebuffer);
+    framebuffer
+}
+
+// sets the flag if the thread it's made in panics, when it's dropped while
+// unwinding
+struct PanicFlag<'a>(&'a AtomicBool);
+
+impl Drop for PanicFlag<'_> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// send the job to a worker, then keep it busy until the tiles run out.
+// tiles it doesn't send back are given back.
+fn render_remote(
+    address: &str,
+    job: &Job,
+    camera: &FullCamera,
+    take: &dyn Fn(u32) -> Vec<Tile>,
+    give_back: &dyn Fn(&[Tile]),
+    blit: &dyn Fn(&Tile, &FrameBuffer),
+) -> Result<(), String> {
+    let stream = TcpStream::connect(address).map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+    let mut writer = BufWriter::new(stream);
+
+    writer.write_all(MAGIC).map_err(|e| e.to_string())?;
+    job.write(&mut writer).map_err(|e| e.to_string())?;
+    View::of(camera)
+        .write(&mut writer)
+        .map_err(|e| e.to_string())?;
+    writer.flush().map_err(|e| e.to_string())?;
+
+    // the worker replies once it has built the scene (e.g. shot its photons)
+    if read_u32(&mut reader).map_err(|e| e.to_string())? != 0 {
+        let e = read_string(&mut reader).map_err(|e| e.to_string())?;
+        return Err(format!("Failed to build scene! {e}"));
+    }
+    let batch_size = read_u32(&mut reader).map_err(|e| e.to_string())?.max(1);
+
+    loop {
+        let tiles = if cancel::cancelled() {
+            Vec::new()
+        } else {
+            take(batch_size)
+        };
+        let mut received = 0;
+        let result = (|| -> Result<(), String> {
+            write_u32(&mut writer, tiles.len() as u32).map_err(|e| e.to_string())?;
+            for tile in &tiles {
+                write_tile(&mut writer, tile).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+
+            for tile in &tiles {
+                let bytes = read_bytes(&mut reader).map_err(|e| e.to_string())?;
+                let tile_framebuffer = FrameBuffer::from_bytes(tile.width, tile.height, &bytes)?;
+                blit(tile, &tile_framebuffer);
+                received += 1;
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            give_back(&tiles[received..]);
+            return Err(e);
+        }
+        if tiles.is_empty() {
+            return Ok(());
+        }
+    }
+}
+
+impl Job {
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_string(writer, &self.filename.to_string_lossy())?;
+        write_string(writer, &self.scene)?;
+        write_u32(writer, self.overrides.len() as u32)?;
+        for scene_override in &self.overrides {
+            write_string(writer, scene_override)?;
+        }
+        write_u32(writer, self.time.to_bits())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let filename = PathBuf::from(read_string(reader)?);
+        let scene = read_string(reader)?;
+        let num_overrides = read_u32(reader)?;
+        let overrides = (0..num_overrides)
+            .map(|_| read_string(reader))
+            .collect::<io::Result<_>>()?;
+        let time = f32::from_bits(read_u32(reader)?);
+        Ok(Self {
+            filename,
+            scene,
+            overrides,
+            time,
+        })
+    }
+
+    // build the scene and pick the camera the view is from
+    fn prepare(&self, view: &View) -> Result<(Box<FullCamera>, Box<dyn Environment>), String> {
+        let overrides = self
+            .overrides
+            .iter()
+            .map(|s| SceneOverride::parse(s).ok_or(format!("Invalid scene override: {s}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        // skip the same invalid paragraphs as a coordinator that's watching
+        let scene = SceneFile::from_named_contents_recovering(
+            &self.filename,
+            self.scene.clone(),
+            &overrides,
+            self.time,
+        )
+        .map_err(|e| e.to_string())?;
+
+        let mut cameras = std::iter::once(scene.camera).chain(scene.other_cameras);
+        let mut camera = cameras
+            .find(|camera| camera.name == view.camera)
+            .ok_or(format!("No camera named {:?}", view.camera))?;
+        camera.width = view.width;
+        camera.height = view.height;
+        camera.samples = view.samples;
+
+        let mut environment = scene.environment;
+        environment.pre_render();
+        Ok((camera, environment))
+    }
+}
+
+// which of the job's cameras to render, with the command line's changes
+struct View {
+    camera: Option<String>, // its name, None for an unnamed one
+    width: u32,
+    height: u32,
+    samples: u32,
+}
+
+impl View {
+    fn of(camera: &FullCamera) -> Self {
+        Self {
+            camera: camera.name.clone(),
+            width: camera.width,
+            height: camera.height,
+            samples: camera.samples,
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_string(writer, self.camera.as_deref().unwrap_or_default())?;
+        write_u32(writer, self.width)?;
+        write_u32(writer, self.height)?;
+        write_u32(writer, self.samples)
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let camera = read_string(reader)?;
+        let view = Self {
+            camera: (!camera.is_empty()).then_some(camera),
+            width: read_u32(reader)?,
+            height: read_u32(reader)?,
+            samples: read_u32(reader)?,
+        };
+        let sizes = [view.width, view.height];
+        if sizes.iter().any(|size| !(1..=MAX_SIZE).contains(size))
+            || !(1..=MAX_SAMPLES).contains(&view.samples)
+        {
+            let e = format!(
+                "Invalid view: {}x{} with {} samples",
+                view.width, view.height, view.samples
+            );
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(view)
+    }
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u32(writer, bytes.len() as u32)?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(reader)?;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn write_string(writer: &mut impl Write, string: &str) -> io::Result<()> {
+    write_bytes(writer, string.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    String::from_utf8(read_bytes(reader)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_tile(writer: &mut impl Write, tile: &Tile) -> io::Result<()> {
+    for value in [tile.x, tile.y, tile.width, tile.height] {
+        write_u32(writer, value)?;
+    }
+    Ok(())
+}
+
+fn read_tile(reader: &mut impl Read) -> io::Result<Tile> {
+    Ok(Tile {
+        x: read_u32(reader)?,
+        y: read_u32(reader)?,
+        width: read_u32(reader)?,
+        height: read_u32(reader)?,
+    })
+}
@@ -0,0 +1,54 @@
+// Ambient occlusion: how much of the hemisphere above a point isn't blocked
+// by anything nearby. Crevices and corners come out darker, which is what
+// flat ambient light is missing.
+
+use crate::core::{
+    hit::Hit,
+    onb::Onb,
+    ray::{Ray, RayKind},
+    sampler,
+    vector::Vector,
+};
+
+use super::environment::Integrator;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    // only objects closer than this block the ambient light
+    pub radius: f32,
+}
+
+impl AmbientOcclusion {
+    pub const fn new(samples: u32, radius: f32) -> Self {
+        Self { samples, radius }
+    }
+
+    // the fraction of the ambient light that reaches the hit, from 0 when
+    // it's completely enclosed to 1 when nothing is nearby
+    pub fn unoccluded(&self, integrator: &dyn Integrator, viewer: &Ray, hit: &Hit) -> f32 {
+        if self.samples == 0 {
+            return 1.0;
+        }
+
+        let onb = Onb::from_normal(&hit.normal);
+        let origin = integrator.settings().offset(&hit.position, &hit.normal);
+        let mut open = 0;
+        for _ in 0..self.samples {
+            // cosine weighted, like the light a diffuse surface would get
+            let (u, v) = sampler::next_2d();
+            let phi = v * std::f32::consts::TAU;
+            let r = u.sqrt();
+            let local = Vector::new(r * phi.cos(), r * phi.sin(), (1.0 - u).sqrt());
+
+            let ray = Ray::new(origin.clone(), onb.to_world(&local))
+                .at_time(viewer.time)
+                .of_kind(RayKind::Shadow);
+            if !integrator.occluded(&ray, self.radius) {
+                open += 1;
+            }
+        }
+
+        open as f32 / self.samples as f32
+    }
+}
@@ -1,39 +1,341 @@
+use std::ops::Range;
+
+use rand::Rng;
+
 use crate::{
     core::{
         colour::Colour,
         hit::{Hit, HitVec},
+        random,
         ray::Ray,
+        stats::{self, Counter},
+        vector::Vector,
+        vertex::Vertex,
     },
     lights::light::Light,
-    objects::object::Object,
+    objects::object::{bounds_of, Object},
 };
 
+#[cfg(feature = "gpu")]
+use crate::gpu::mesh_tracer::{FirstHit, MeshTracer};
+
+use super::{medium::Volume, photon_bake::PhotonBake};
+
+// how many portals and mirrors a ray can go through before giving up
+const MAX_TELEPORTS: u32 = 16;
+
 pub struct RaytraceResult {
     pub colour: Colour,
-    pub depth: f32,
+    pub depth: f32, // distance to the hit, infinite if nothing was hit
+    pub surface: Option<SurfaceInfo>, // None if nothing was hit
+    // how much of the light from the hit (or from beyond, if nothing was
+    // hit) makes it back along the ray, e.g. through fog
+    pub throughput: f32,
+    pub lighting: Lighting,
 }
 
 impl RaytraceResult {
     pub fn none() -> Self {
         Self {
             colour: Colour::black(),
-            depth: 0.0,
+            depth: f32::INFINITY,
+            surface: None,
+            throughput: 1.0,
+            lighting: Lighting::default(),
+        }
+    }
+}
+
+// the colour split up by where its light came from, for the lighting pass
+// images. the parts add up to the colour, so they can be graded separately
+// and recombined.
+#[derive(Debug, Copy, Clone)]
+pub struct Lighting {
+    pub diffuse: Colour,  // straight from the lights, scattered by the surface
+    pub specular: Colour, // straight from the lights, in highlights
+    // everything else lighting a surface: ambient light, light bounced off
+    // other surfaces, reflections and refractions, and fog
+    pub indirect: Colour,
+    pub caustic: Colour,  // focused onto a surface by mirrors and glass
+    pub emission: Colour, // the lights themselves, seen in the background
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            diffuse: Colour::black(),
+            specular: Colour::black(),
+            indirect: Colour::black(),
+            caustic: Colour::black(),
+            emission: Colour::black(),
+        }
+    }
+}
+
+impl Lighting {
+    // the same done to every part, e.g. dimming them all by fog
+    pub fn map(self, f: impl Fn(Colour) -> Colour) -> Self {
+        Self {
+            diffuse: f(self.diffuse),
+            specular: f(self.specular),
+            indirect: f(self.indirect),
+            caustic: f(self.caustic),
+            emission: f(self.emission),
+        }
+    }
+
+    pub fn parts(&self) -> [Colour; 5] {
+        [
+            self.diffuse,
+            self.specular,
+            self.indirect,
+            self.caustic,
+            self.emission,
+        ]
+    }
+}
+
+impl std::ops::Add<Lighting> for Lighting {
+    type Output = Lighting;
+
+    fn add(self, rhs: Lighting) -> Self::Output {
+        Self {
+            diffuse: self.diffuse + rhs.diffuse,
+            specular: self.specular + rhs.specular,
+            indirect: self.indirect + rhs.indirect,
+            caustic: self.caustic + rhs.caustic,
+            emission: self.emission + rhs.emission,
+        }
+    }
+}
+
+// what a ray hit, for the AOV (arbitrary output variable) images that
+// denoisers and compositing use alongside the colour
+#[derive(Debug, Clone)]
+pub struct SurfaceInfo {
+    pub position: Vertex,
+    pub normal: Vector,
+    pub albedo: Colour,
+    pub object_id: u32, // the hit's instance
+}
+
+impl SurfaceInfo {
+    pub fn from_hit(hit: &Hit) -> Self {
+        Self {
+            position: hit.position.clone(),
+            normal: hit.normal,
+            // mirrors and glass reflect everything
+            albedo: hit.material.albedo(hit).unwrap_or(Colour::white()),
+            object_id: hit.instance,
+        }
+    }
+}
+
+// limits how far camera rays (and their reflections, refractions and shadow
+// rays) travel. hits further than fade_start blend into the black background,
+// reaching it at max_distance.
+#[derive(Debug, Copy, Clone)]
+pub struct TraceLimit {
+    pub max_distance: f32,
+    pub fade_start: f32,
+}
+
+impl Default for TraceLimit {
+    fn default() -> Self {
+        Self {
+            max_distance: f32::MAX,
+            fade_start: f32::MAX,
+        }
+    }
+}
+
+impl TraceLimit {
+    // drop hits past the limit, as if nothing was hit at all
+    pub fn clip<'a>(&self, hit: Option<Hit<'a>>) -> Option<Hit<'a>> {
+        hit.filter(|hit| hit.distance <= self.max_distance)
+    }
+
+    pub fn fade(&self, colour: Colour, distance: f32) -> Colour {
+        if distance <= self.fade_start {
+            return colour;
+        }
+
+        let fade_length = self.max_distance - self.fade_start;
+        let remaining = (self.max_distance - distance) / fade_length;
+        colour * remaining.clamp(0.0, 1.0)
+    }
+}
+
+// how many times rays can bounce off mirrors and glass. past roulette_depth,
+// dim rays are randomly terminated (russian roulette), and the ones that
+// survive are brightened to make up for it.
+#[derive(Debug, Copy, Clone)]
+pub struct PathDepth {
+    pub max_depth: u8,
+    pub roulette_depth: u8,
+}
+
+impl Default for PathDepth {
+    fn default() -> Self {
+        Self {
+            max_depth: 16,
+            roulette_depth: 5,
         }
     }
 }
 
+impl PathDepth {
+    // the chance that a ray at depth carrying throughput of the light keeps
+    // going, or None if it was terminated. divide what it brings back by it.
+    pub fn survival(&self, depth: u8, throughput: f32) -> Option<f32> {
+        if depth >= self.max_depth {
+            return None;
+        }
+        if depth < self.roulette_depth {
+            return Some(1.0);
+        }
+
+        // never make it too unlikely, or the survivors become fireflies
+        let chance = throughput.clamp(0.05, 1.0);
+        let roll: f32 = random::with_rng(|rng| rng.gen());
+        (roll < chance).then_some(chance)
+    }
+}
+
+// per-scene tracing constants. rays leaving a surface (shadow rays,
+// reflections, refractions and teleports) start epsilon away from it, so
+// they don't hit it again straight away. scenes modelled at large scales
+// need a bigger epsilon, and small ones a smaller one.
+#[derive(Debug, Copy, Clone)]
+pub struct RenderSettings {
+    pub epsilon: f32,
+    pub path_depth: PathDepth,
+    // the brightest (in any channel) light a reflection, refraction or
+    // caustic can bring to a surface, so that rare bright paths don't turn
+    // into fireflies. None for no limit.
+    pub max_indirect: Option<f32>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            epsilon: 0.0001,
+            path_depth: PathDepth::default(),
+            max_indirect: None,
+        }
+    }
+}
+
+impl RenderSettings {
+    // where a ray leaving position in direction should start
+    pub fn offset(&self, position: &Vertex, direction: &Vector) -> Vertex {
+        position.clone() + *direction * self.epsilon
+    }
+
+    // indirect light dimmed to max_indirect, keeping its hue
+    pub fn clamp_indirect(&self, colour: Colour) -> Colour {
+        match self.max_indirect {
+            Some(max) if colour.max_component() > max => colour * (max / colour.max_component()),
+            _ => colour,
+        }
+    }
+}
+
+// the part of an environment that materials can call back into, so that a
+// single material implementation works in every environment
+pub trait Integrator: Sync {
+    // shoot a secondary ray into the environment.
+    // depth indicates the current recursion level.
+    fn raytrace(&self, ray: &Ray, depth: u8) -> RaytraceResult;
+
+    fn settings(&self) -> RenderSettings {
+        RenderSettings::default()
+    }
+
+    // whether light that has travelled this far in total (from the light,
+    // off surfaces, to the camera) counts, see Environment::set_path_window()
+    fn in_path_window(&self, _path_length: f32) -> bool {
+        true
+    }
+
+    // whether anything is closer than distance along the ray
+    fn occluded(&self, _ray: &Ray, _distance: f32) -> bool {
+        false
+    }
+
+    // how much of the ambient light reaches the hit, see AmbientOcclusion
+    fn ambient_visibility(&self, _viewer: &Ray, _hit: &Hit) -> f32 {
+        1.0
+    }
+}
+
 pub trait Environment: Send + Sync {
     fn pre_render(&mut self);
     fn raytrace(&self, ray: &Ray) -> RaytraceResult;
 
     fn add_object(&mut self, object: Box<dyn Object + 'static>);
     fn add_light(&mut self, light: Box<dyn Light + 'static>);
+    fn add_volume(&mut self, volume: Volume);
 
     fn objects(&self) -> &[Box<dyn Object>];
+    fn lights(&self) -> Vec<&dyn Light>;
+
+    // the smallest and largest corners of a box around every object that
+    // has one, None if none do
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        let corners = self
+            .objects()
+            .iter()
+            .filter_map(|object| object.bounds())
+            .flat_map(|(min, max)| [min, max]);
+        bounds_of(corners)
+    }
+
+    fn settings(&self) -> RenderSettings {
+        RenderSettings::default()
+    }
+
+    // time-of-flight rendering: only light whose total path length is in the
+    // window reaches the image, None for all of it. returns false if the
+    // environment can't tell how far its light has travelled.
+    fn set_path_window(&mut self, window: Option<Range<f32>>) -> bool {
+        window.is_none()
+    }
+
+    // the scene's photon heat map, if it has one. call after rendering.
+    fn photon_bake(&self) -> Option<PhotonBake> {
+        None
+    }
+
+    // where the photons landed, seen from above, if the scene asked for it.
+    // call after rendering.
+    fn photon_density(&self) -> Option<PhotonBake> {
+        None
+    }
+
+    // traces camera rays against the triangle meshes on the GPU, if the
+    // scene asked to and one could be found
+    #[cfg(feature = "gpu")]
+    fn mesh_tracer(&self) -> Option<&MeshTracer> {
+        None
+    }
+
+    // whether the camera should hand each tile's camera rays to
+    // prepare_camera_rays() before raytracing them
+    #[cfg(feature = "gpu")]
+    fn prepares_camera_rays(&self) -> bool {
+        false
+    }
+
+    // gets ready to raytrace a tile's camera rays, in the order they'll be
+    // raytraced on this thread, with their first hits on the meshes if the
+    // mesh tracer found them
+    #[cfg(feature = "gpu")]
+    fn prepare_camera_rays(&self, _rays: &[Ray], _first_hits: &[FirstHit]) {}
 
     fn select_first_hit<'s>(&self, hits: HitVec<'s>) -> Option<Hit<'s>> {
         let mut min_hit: Option<Hit> = None;
-        let mut min_distance = std::f32::MAX;
+        let mut min_distance = f32::MAX;
 
         for hit in hits {
             if hit.distance < 0.0 {
@@ -52,17 +354,52 @@ pub trait Environment: Send + Sync {
         min_hit
     }
 
-    fn trace(&self, ray: &Ray) -> Option<Hit> {
+    // the closest hit, following rays through portals and mirrors. the
+    // distance is the total along the way.
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        let mut hit = self.trace_nearest(ray)?;
+        let mut teleported = hit.material.teleport(ray, &hit);
+        let mut travelled = 0.0;
+
+        for _ in 0..MAX_TELEPORTS {
+            let Some(mut ray) = teleported else {
+                hit.distance += travelled;
+                return Some(hit);
+            };
+            travelled += hit.distance;
+            ray.position = self.settings().offset(&ray.position, &ray.direction);
+            hit = self.trace_nearest(&ray)?;
+            teleported = hit.material.teleport(&ray, &hit);
+        }
+
+        None // stuck between mirrors
+    }
+
+    fn trace_nearest(&self, ray: &Ray) -> Option<Hit<'_>> {
         let mut min_hit: Option<Hit> = None;
-        let mut min_distance = std::f32::MAX;
+        let mut min_distance = f32::MAX;
+
+        // camera rays may already have been traced against the meshes
+        #[cfg(feature = "gpu")]
+        let mut known_hit = self
+            .mesh_tracer()
+            .and_then(|tracer| tracer.take_first_hit(ray, self.objects()));
 
-        for object in self.objects() {
+        stats::add(Counter::IntersectionTests, self.objects().len() as u64);
+        for (instance, object) in self.objects().iter().enumerate() {
+            #[cfg(feature = "gpu")]
+            let hits = match &mut known_hit {
+                Some(known_hit) => known_hit.intersect(instance, object.as_ref(), ray),
+                None => object.intersect(ray),
+            };
+            #[cfg(not(feature = "gpu"))]
             let hits = object.intersect(ray);
 
             let hit = self.select_first_hit(hits);
-            let Some(hit) = hit else {
+            let Some(mut hit) = hit else {
                 continue;
             };
+            hit.instance = instance as u32;
 
             if hit.distance < min_distance {
                 min_distance = hit.distance;
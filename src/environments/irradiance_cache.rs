@@ -0,0 +1,265 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::core::{
+    colour::Colour,
+    onb::Onb,
+    photon::{Photon, PhotonType},
+    random,
+    vector::Vector,
+    vertex::Vertex,
+};
+
+use super::photon_scene::Gathered;
+
+// how far a record's normal can be from a hit's, as 1 - cos(angle), for it
+// to still be used. about 25 degrees.
+const MAX_NORMAL_DEVIATION: f32 = 0.1;
+// how many of the photons in each grid cell are tried as places for records
+const CANDIDATES_PER_CELL: usize = 8;
+
+type Cell = (i32, i32, i32);
+
+// remembers the photons gathered around points on the surfaces, so hits
+// close by facing the same way can blend them instead of gathering again.
+// gathering is the slow part of shading a photon mapped surface, and what it
+// finds changes slowly across a surface. records are bucketed by the grid
+// cell (spacing wide) they're in, and are used for hits up to spacing away.
+//
+// records are only made where photons landed, at places picked before
+// rendering, so what a hit blends doesn't depend on which pixels happened to
+// be rendered first, or on how many threads there are.
+// each record also keeps how what it gathered changes along the surface (its
+// translational gradient), to follow it across the gap to the hit. turning
+// the normal needs no gradient: a record keeps the direction its light comes
+// from, and every hit is shaded with its own normal.
+pub struct IrradianceCache {
+    spacing: f32,
+    cells: HashMap<Cell, CacheCell>,
+}
+
+struct Record {
+    position: Vertex,
+    normal: Vector,
+    incident: Vector,
+    // the intensity (black if no photons were found), direct and caustic
+    // parts of what was gathered
+    parts: [Colour; 3],
+    tangents: [Vector; 2],
+    // how much each part changes per unit along each tangent
+    gradients: [[Colour; 3]; 2],
+}
+
+impl Record {
+    // gathers at position, and half a cell along each tangent from it for the
+    // gradients
+    fn gather(
+        position: Vertex,
+        normal: Vector,
+        spacing: f32,
+        gather: &impl Fn(&Vertex, &Vector) -> Option<Gathered>,
+    ) -> Self {
+        let parts_at = |position: &Vertex| match gather(position, &normal) {
+            Some(gathered) => (
+                [gathered.photon.intensity, gathered.direct, gathered.caustic],
+                gathered.photon.incident,
+            ),
+            None => ([Colour::black(); 3], -normal),
+        };
+
+        let (parts, incident) = parts_at(&position);
+        let onb = Onb::from_normal(&normal);
+        let tangents = [onb.tangent, onb.bitangent];
+        let step = spacing / 2.0;
+        let gradients = tangents.map(|tangent| {
+            let (moved, _) = parts_at(&(position.clone() + tangent * step));
+            [0, 1, 2].map(|part| (moved[part] - parts[part]) / step)
+        });
+
+        Self {
+            position,
+            normal,
+            incident,
+            parts,
+            tangents,
+            gradients,
+        }
+    }
+
+    // how much the record counts at a hit, or None if it's too far away or
+    // facing too differently. the error adds up both (like Ward et al.'s
+    // irradiance caching), and the weight falls to zero as it reaches 1, so
+    // the blend doesn't jump between records.
+    fn weight(&self, spacing: f32, position: &Vertex, normal: &Vector) -> Option<f32> {
+        let distance = self.position.distance(position) / spacing;
+        let deviation = (1.0 - self.normal.dot(normal)).max(0.0) / MAX_NORMAL_DEVIATION;
+        let error = distance + deviation.sqrt();
+        (error < 1.0).then(|| 1.0 / error.max(1e-6) - 1.0)
+    }
+
+    // the parts at position, following the gradients from the record
+    fn parts_at(&self, position: &Vertex) -> [Colour; 3] {
+        let offset = self.position.vector_to(position);
+        let along = self.tangents.map(|tangent| offset.dot(&tangent));
+        [0, 1, 2].map(|part| {
+            let moved = self.parts[part]
+                + self.gradients[0][part] * along[0]
+                + self.gradients[1][part] * along[1];
+            moved.map(|channel| channel.max(0.0))
+        })
+    }
+}
+
+impl IrradianceCache {
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            spacing,
+            cells: HashMap::new(),
+        }
+    }
+
+    // forgets every record, and picks where the new ones can go: a few
+    // photons from every cell any landed in. they're picked by where they
+    // are, so it doesn't matter which order the photons were shot in.
+    pub fn place<'a>(&mut self, photons: impl Iterator<Item = &'a Photon>) {
+        let rank = |position: &Vertex| {
+            let bits = |x: f32| random::splitmix64(x.to_bits() as u64);
+            bits(position.x) ^ bits(position.y).rotate_left(21) ^ bits(position.z).rotate_left(42)
+        };
+
+        let mut cells: HashMap<Cell, Vec<(u64, Vertex, Vector)>> = HashMap::new();
+        for photon in photons {
+            let candidates = cells.entry(self.cell(&photon.position)).or_default();
+            let candidate = (
+                rank(&photon.position),
+                photon.position.clone(),
+                photon.incident,
+            );
+            if candidates.len() < CANDIDATES_PER_CELL {
+                candidates.push(candidate);
+                continue;
+            }
+            // keep the lowest ranked ones
+            let (highest, _) = candidates
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (rank, ..))| *rank)
+                .unwrap();
+            if candidate.0 < candidates[highest].0 {
+                candidates[highest] = candidate;
+            }
+        }
+
+        self.cells = cells
+            .into_iter()
+            .map(|(cell, mut candidates)| {
+                candidates.sort_by_key(|(rank, ..)| *rank);
+                let candidates = candidates
+                    .into_iter()
+                    .map(|(_, position, incident)| (position, incident))
+                    .collect();
+                let records = OnceLock::new();
+                (
+                    cell,
+                    CacheCell {
+                        candidates,
+                        records,
+                    },
+                )
+            })
+            .collect();
+    }
+
+    // the average photon at position (and its parts), blended from the
+    // records around it, or None if there aren't any close enough. cells
+    // are filled the first time they're looked at, see CacheCell::records().
+    pub fn get(
+        &self,
+        position: &Vertex,
+        normal: &Vector,
+        surface: impl Fn(&Vertex, &Vector) -> Option<(Vertex, Vector)>,
+        gather: impl Fn(&Vertex, &Vector) -> Option<Gathered>,
+    ) -> Option<Gathered> {
+        let (x, y, z) = self.cell(position);
+
+        let mut total_weight = 0.0;
+        let mut incident = Vector::zero();
+        let mut parts = [Colour::black(); 3];
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(cell) = self.cells.get(&(x + dx, y + dy, z + dz)) else {
+                        continue;
+                    };
+                    for record in cell.records(self.spacing, &surface, &gather) {
+                        let Some(weight) = record.weight(self.spacing, position, normal) else {
+                            continue;
+                        };
+                        total_weight += weight;
+                        incident += record.incident * weight;
+                        for (part, moved) in parts.iter_mut().zip(record.parts_at(position)) {
+                            *part += moved * weight;
+                        }
+                    }
+                }
+            }
+        }
+
+        if total_weight == 0.0 {
+            return None;
+        }
+        let [intensity, direct, caustic] = parts.map(|part| part / total_weight);
+        Some(Gathered {
+            photon: Photon::new(
+                position.clone(),
+                incident.normalised(),
+                intensity,
+                PhotonType::Colour,
+            ),
+            direct,
+            caustic,
+        })
+    }
+
+    fn cell(&self, position: &Vertex) -> Cell {
+        (
+            (position.x / self.spacing).floor() as i32,
+            (position.y / self.spacing).floor() as i32,
+            (position.z / self.spacing).floor() as i32,
+        )
+    }
+}
+
+struct CacheCell {
+    candidates: Vec<(Vertex, Vector)>, // where photons landed, and their incident
+    records: OnceLock<Vec<Record>>,
+}
+
+impl CacheCell {
+    // a record at each candidate that isn't already covered by one before
+    // it. they only depend on the candidates, so whichever thread fills a
+    // cell first fills it the same. surface finds the position and normal of
+    // the surface a candidate landed on, and gather gathers the photons at a
+    // point on a surface facing along a normal.
+    fn records(
+        &self,
+        spacing: f32,
+        surface: &impl Fn(&Vertex, &Vector) -> Option<(Vertex, Vector)>,
+        gather: &impl Fn(&Vertex, &Vector) -> Option<Gathered>,
+    ) -> &[Record] {
+        self.records.get_or_init(|| {
+            let mut records: Vec<Record> = Vec::new();
+            for (position, incident) in &self.candidates {
+                let Some((position, normal)) = surface(position, incident) else {
+                    continue;
+                };
+                let covered = records
+                    .iter()
+                    .any(|record| record.weight(spacing, &position, &normal).is_some());
+                if !covered {
+                    records.push(Record::gather(position, normal, spacing, gather));
+                }
+            }
+            records
+        })
+    }
+}
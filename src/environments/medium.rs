@@ -0,0 +1,252 @@
+// Participating media: fog filling the whole scene, or volumes inside closed
+// objects. Light passing through them is scattered in proportion to their
+// density, dimming what is behind, and some of the light from the scene's
+// lights is scattered towards the viewer instead.
+
+use std::ops::Range;
+
+use rand::Rng;
+
+use crate::{
+    core::{colour::Colour, random, ray::Ray, sampler, vector::Vector, vertex::Vertex},
+    lights::light::Light,
+    objects::object::Object,
+};
+
+// distance between ray marching samples, and the most samples per span
+const STEP_LENGTH: f32 = 0.25;
+const MAX_STEPS: u32 = 64;
+// stop marching once this little light gets through
+const MIN_TRANSMITTANCE: f32 = 0.001;
+// how far past a crossing of a volume's bounds to look for the next one, and
+// the most crossings to look for
+const CROSSING_GAP: f32 = 0.0001;
+const MAX_CROSSINGS: usize = 64;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    pub density: f32,   // chance of scattering per unit distance
+    pub colour: Colour, // fraction of the scattered light that isn't absorbed
+}
+
+impl Medium {
+    pub fn new(density: f32, colour: Colour) -> Self {
+        Self { density, colour }
+    }
+}
+
+// a medium filling the inside of an object, e.g. smoke in a glass box
+pub struct Volume {
+    pub bounds: Box<dyn Object>,
+    pub medium: Medium,
+}
+
+impl Volume {
+    pub fn new(bounds: Box<dyn Object>, medium: Medium) -> Self {
+        Self { bounds, medium }
+    }
+
+    // where the ray crosses the bounds, nearest first, as (distance,
+    // entering). some objects (e.g. meshes) only give their closest hit, so
+    // keep looking from just past the furthest crossing found, until there
+    // are no more before end.
+    fn crossings(&self, ray: &Ray, end: f32) -> Vec<(f32, bool)> {
+        let mut crossings: Vec<(f32, bool)> = Vec::new();
+        let mut hits = self.bounds.intersect(ray);
+        let mut from = 0.0;
+        loop {
+            let mut found: Vec<_> = hits
+                .iter()
+                // only the first look can see the ray start inside
+                .filter(|hit| crossings.is_empty() || hit.distance > 0.0)
+                .map(|hit| (from + hit.distance, hit.entering))
+                .collect();
+            found.sort_by(|a, b| a.0.total_cmp(&b.0));
+            crossings.extend(found);
+
+            let Some(&(last, _)) = crossings.last() else {
+                break;
+            };
+            if last < 0.0 || last >= end || crossings.len() >= MAX_CROSSINGS {
+                break;
+            }
+            from = last + CROSSING_GAP;
+            let next = Ray {
+                position: ray.position.clone() + ray.direction * from,
+                ..*ray
+            };
+            hits = self.bounds.intersect(&next);
+            if hits.iter().all(|hit| hit.distance <= 0.0) {
+                break;
+            }
+        }
+        crossings
+    }
+
+    // the parts of the ray, up to end, inside the bounds
+    fn intervals(&self, ray: &Ray, end: f32) -> Vec<Range<f32>> {
+        let mut intervals = Vec::new();
+        let mut entered: Option<f32> = None;
+        let mut first = true;
+
+        for (distance, entering) in self.crossings(ray, end) {
+            if entering {
+                entered = Some(distance.max(0.0));
+            } else {
+                // exiting without entering means the ray started inside
+                let start = entered.take().or(first.then_some(0.0));
+                if let Some(start) = start {
+                    intervals.push(start..distance.min(end));
+                }
+            }
+            first = false;
+        }
+        if let Some(start) = entered {
+            intervals.push(start..end);
+        }
+
+        intervals.retain(|interval| interval.start < interval.end);
+        intervals
+    }
+}
+
+// part of a ray with a constant mix of media
+pub struct Span {
+    pub range: Range<f32>,
+    pub density: f32,
+    pub colour: Colour,
+}
+
+impl Span {
+    fn length(&self) -> f32 {
+        self.range.end - self.range.start
+    }
+}
+
+// the media along a ray up to end (which can be infinite), split wherever
+// one starts or stops
+pub fn spans(fog: Option<&Medium>, volumes: &[Volume], ray: &Ray, end: f32) -> Vec<Span> {
+    let mut media = Vec::new();
+    if let Some(fog) = fog {
+        media.push((0.0..end, fog));
+    }
+    for volume in volumes {
+        for interval in volume.intervals(ray, end) {
+            media.push((interval, &volume.medium));
+        }
+    }
+    if media.is_empty() {
+        return Vec::new();
+    }
+
+    let mut cuts: Vec<f32> = media
+        .iter()
+        .flat_map(|(interval, _)| [interval.start, interval.end])
+        .collect();
+    cuts.sort_by(f32::total_cmp);
+    cuts.dedup();
+
+    cuts.windows(2)
+        .filter_map(|cut| {
+            let range = cut[0]..cut[1];
+            let middle = if range.end.is_finite() {
+                (range.start + range.end) / 2.0
+            } else {
+                range.start + 1.0
+            };
+
+            let mut density = 0.0;
+            let mut scattered = Colour::black();
+            for (interval, medium) in &media {
+                if interval.contains(&middle) {
+                    density += medium.density;
+                    scattered += medium.colour * medium.density;
+                }
+            }
+
+            (density > 0.0).then(|| Span {
+                range,
+                density,
+                colour: scattered / density,
+            })
+        })
+        .collect()
+}
+
+// how much light gets all the way through
+pub fn transmittance(spans: &[Span]) -> f32 {
+    let optical_depth: f32 = spans.iter().map(|span| span.density * span.length()).sum();
+    (-optical_depth).exp()
+}
+
+// how much of a light reaches a position (ldir being the light's direction
+// there) through the media. lights without a position (like the sun) are
+// taken to be above the fog.
+pub fn light_transmittance(
+    fog: Option<&Medium>,
+    volumes: &[Volume],
+    position: &Vertex,
+    ldir: &Vector,
+    light: &dyn Light,
+) -> f32 {
+    if fog.is_none() && volumes.is_empty() {
+        return 1.0;
+    }
+
+    let (fog, end) = match light.distance(position) {
+        0.0 => (None, f32::INFINITY),
+        distance => (fog, distance),
+    };
+    let ray = Ray::new(position.clone(), -ldir.normalised());
+    transmittance(&spans(fog, volumes, &ray, end))
+}
+
+// ray march through the spans. in_scatter gives the light arriving at a
+// distance along the ray that could scatter towards the viewer. returns
+// the light that does, and the transmittance of the whole ray.
+pub fn march(spans: &[Span], mut in_scatter: impl FnMut(f32) -> Colour) -> (Colour, f32) {
+    let mut scattered = Colour::black();
+    let mut transmittance = 1.0;
+    // offset the samples randomly, which trades banding for noise
+    let jitter = sampler::next_1d();
+
+    for span in spans {
+        // fog that goes on forever is only marched until it hides everything
+        let length = match span.length() {
+            length if length.is_finite() => length,
+            _ => -MIN_TRANSMITTANCE.ln() / span.density,
+        };
+        let steps = (length / STEP_LENGTH).ceil().clamp(1.0, MAX_STEPS as f32);
+        let step = length / steps;
+        let step_transmittance = (-span.density * step).exp();
+
+        for i in 0..steps as u32 {
+            let distance = span.range.start + (i as f32 + jitter) * step;
+            let scatter = transmittance * (1.0 - step_transmittance);
+            scattered += in_scatter(distance) * span.colour * scatter;
+
+            transmittance *= step_transmittance;
+            if transmittance < MIN_TRANSMITTANCE {
+                return (scattered, 0.0);
+            }
+        }
+    }
+
+    (scattered, transmittance)
+}
+
+// where along the ray a photon scatters, None if it makes it through all
+// the spans
+pub fn scatter_distance(spans: &[Span]) -> Option<f32> {
+    let roll: f32 = random::with_rng(|rng| rng.gen());
+    let mut optical_depth = -(1.0 - roll).ln();
+
+    for span in spans {
+        let span_depth = span.density * span.length();
+        if optical_depth < span_depth {
+            return Some(span.range.start + optical_depth / span.density);
+        }
+        optical_depth -= span_depth;
+    }
+    None
+}
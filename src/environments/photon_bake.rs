@@ -0,0 +1,111 @@
+// Photon "heat maps": the photon irradiance gathered over a rectangle in the
+// scene, baked into an image. Placing the rectangle on a surface (e.g. the
+// floor under a glass sphere) shows how light and caustics are distributed
+// across it, without any of the shading that goes into a render.
+
+use std::{fs::File, io::Write, path::Path};
+
+use crate::{
+    core::{colour::Colour, framebuffer::FrameBuffer, vertex::Vertex},
+    objects::portal_object::Quad,
+};
+
+// where to bake, from the scene's bake paragraph
+#[derive(Clone)]
+pub struct PhotonSlice {
+    pub quad: Quad,
+    pub resolution: u32, // texels across the width
+}
+
+impl PhotonSlice {
+    pub fn size(&self) -> (u32, u32) {
+        let height = self.resolution as f32 * self.quad.height / self.quad.width;
+        (self.resolution, (height.round() as u32).max(1))
+    }
+
+    // the centre of a texel, with (0, 0) in the top left corner
+    pub fn texel_position(&self, x: u32, y: u32) -> Vertex {
+        let (width, height) = self.size();
+        let u = (x as f32 + 0.5) / width as f32 - 0.5;
+        let v = 0.5 - (y as f32 + 0.5) / height as f32;
+        self.quad.centre.clone()
+            + self.quad.right * (u * self.quad.width)
+            + self.quad.up * (v * self.quad.height)
+    }
+}
+
+#[derive(Clone)]
+pub struct PhotonBake {
+    pub width: u32,
+    pub height: u32,
+    // row by row, from the top. photons per unit area instead for density
+    // maps, see photon_stats::density_map().
+    pub irradiance: Vec<Colour>,
+}
+
+impl PhotonBake {
+    pub fn get(&self, x: u32, y: u32) -> Colour {
+        self.irradiance[(y * self.width + x) as usize]
+    }
+
+    // the brightest texel, averaged over r, g and b
+    pub fn peak(&self) -> f32 {
+        self.irradiance.iter().map(brightness).fold(0.0, f32::max)
+    }
+
+    // false colour from black (no photons) through blue, red and yellow to
+    // white at the peak
+    pub fn heat_map(&self) -> FrameBuffer {
+        let peak = self.peak();
+        let mut framebuffer = FrameBuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let amount = if peak > 0.0 {
+                    brightness(&self.get(x, y)) / peak
+                } else {
+                    0.0
+                };
+                framebuffer.plot_pixel(x, y, &heat_colour(amount));
+            }
+        }
+        framebuffer
+    }
+
+    // the raw values as a portable float map, for measuring
+    pub fn write_pfm_file(&self, filename: &Path) -> Result<(), String> {
+        let mut contents = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+
+        // rows go from the bottom up, little endian (hence the negative scale)
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let colour = self.get(x, y);
+                for component in [colour.r, colour.g, colour.b] {
+                    contents.extend_from_slice(&component.to_le_bytes());
+                }
+            }
+        }
+
+        let mut file = File::create(filename).map_err(|e| e.to_string())?;
+        file.write_all(&contents).map_err(|e| e.to_string())
+    }
+}
+
+fn brightness(colour: &Colour) -> f32 {
+    (colour.r + colour.g + colour.b) / 3.0
+}
+
+// amount from 0 to 1, see heat_map()
+pub fn heat_colour(amount: f32) -> Colour {
+    const STOPS: [Colour; 5] = [
+        Colour::new(0.0, 0.0, 0.0),
+        Colour::new(0.0, 0.0, 1.0),
+        Colour::new(1.0, 0.0, 0.0),
+        Colour::new(1.0, 1.0, 0.0),
+        Colour::new(1.0, 1.0, 1.0),
+    ];
+
+    let scaled = amount.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (scaled as usize).min(STOPS.len() - 2);
+    let t = scaled - i as f32;
+    STOPS[i] * (1.0 - t) + STOPS[i + 1] * t
+}
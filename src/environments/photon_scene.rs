@@ -1,6 +1,5 @@
-use std::{io::Write, thread};
+use std::io::Write;
 
-use kd_tree::ItemAndDistance;
 use rand::{seq::SliceRandom, Rng};
 
 use crate::{
@@ -8,25 +7,274 @@ use crate::{
         colour::Colour,
         hit::Hit,
         photon::{InFlightPhoton, Photon, PhotonType},
+        photon_grid::PhotonGrid,
         photon_tree::PhotonTree,
-        ray::Ray,
+        platform, random,
+        ray::{Ray, RayKind},
+        stats::{self, Counter},
         vector::Vector,
+        vertex::Vertex,
     },
     lights::light::{Light, PhotonLight},
     materials::material::{PhotonBehaviour, PhotonMaterial},
-    objects::object::Object,
+    objects::object::{BoundingSphere, Object},
 };
 
-use super::environment::{Environment, RaytraceResult};
+#[cfg(feature = "gpu")]
+use crate::gpu::{
+    mesh_tracer::{self, FirstHit, MeshTracer},
+    photon_gather::{self, PhotonGatherer},
+};
+
+use super::{
+    environment::{
+        Environment, Integrator, Lighting, RaytraceResult, RenderSettings, SurfaceInfo, TraceLimit,
+    },
+    irradiance_cache::IrradianceCache,
+    medium::{self, Medium, Volume},
+    photon_bake::{heat_colour, PhotonBake, PhotonSlice},
+    photon_stats::{self, PhotonMapStats},
+};
+
+// no light gets fewer than this fraction of its even share of the photons,
+// so dim lights are still lit smoothly
+const MIN_PHOTON_SHARE: f32 = 0.1;
+
+// how many photons are shot, and how far around a hit they're gathered from.
+// a scene shoots the per light numbers times its number of lights, shared
+// out between them by how bright they are (see photon_shares()).
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonQuality {
+    pub photons_per_light: u32,
+    pub caustic_photons_per_light: u32, // 0 skips the second, caustic pass
+    pub gather_radius: f32,
+}
+
+impl Default for PhotonQuality {
+    fn default() -> Self {
+        Self {
+            photons_per_light: 5_000_000,
+            caustic_photons_per_light: 10_000,
+            gather_radius: 0.1,
+        }
+    }
+}
+
+impl PhotonQuality {
+    // noisy and blurry, but the photon maps take seconds instead of minutes
+    // to build. for iterating on a scene before the final render.
+    pub fn preview() -> Self {
+        Self {
+            photons_per_light: 100_000,
+            caustic_photons_per_light: 0,
+            gather_radius: 0.3,
+        }
+    }
+}
+
+// how photons near a hit are looked up
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GatherMethod {
+    KdTree,
+    HashGrid,
+}
+
+impl GatherMethod {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "KdTree" => Some(Self::KdTree),
+            "HashGrid" => Some(Self::HashGrid),
+            _ => None,
+        }
+    }
+}
+
+// how much each photon gathered around a hit counts, by how far it is from
+// the hit. favouring the nearest photons keeps caustic edges sharp where a
+// plain average blurs them over the whole gather radius (Jensen, "Realistic
+// Image Synthesis Using Photon Mapping", 2001, section 7.2).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GatherFilter {
+    Box,      // every photon counts the same
+    Cone,     // falling off linearly to almost nothing at the radius
+    Gaussian, // falling off smoothly to nothing at the radius
+}
+
+impl GatherFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Box" => Some(Self::Box),
+            "Cone" => Some(Self::Cone),
+            "Gaussian" => Some(Self::Gaussian),
+            _ => None,
+        }
+    }
+
+    // the weight of a photon distance_sqrd away from the hit, with the
+    // photons gathered from within radius
+    fn weight(&self, distance_sqrd: f32, radius: f32) -> f32 {
+        // the cone's slope: 1 would ignore the photons at the edge entirely
+        const CONE_K: f32 = 1.1;
+        // Pavicic's constants, see Jensen's book
+        const ALPHA: f32 = 0.918;
+        const BETA: f32 = 1.953;
+
+        // the gather is a box, but the filters are round
+        if *self != Self::Box && distance_sqrd > radius * radius {
+            return 0.0;
+        }
+        match self {
+            Self::Box => 1.0,
+            Self::Cone => 1.0 - distance_sqrd.sqrt() / (CONE_K * radius),
+            Self::Gaussian => {
+                let falloff = 1.0 - (-BETA * distance_sqrd / (2.0 * radius * radius)).exp();
+                ALPHA * (1.0 - falloff / (1.0 - (-BETA).exp()))
+            }
+        }
+    }
+}
 
-const PHOTONS_PER_LIGHT: usize = 5_000_000;
-const CAUSTIC_PHOTONS_PER_LIGHT: usize = 10_000;
+// show the photon maps themselves instead of rendering: what the camera sees
+// is coloured by how many photons are within the gather radius of it, to
+// check the photon count and radius before a slow final render
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PhotonView {
+    Density, // both surface photon maps
+    Caustic, // only the caustic map
+}
+
+impl PhotonView {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Density" => Some(Self::Density),
+            "Caustic" => Some(Self::Caustic),
+            _ => None,
+        }
+    }
+}
+
+// whether a hit can see the lights, going by the photons around it (Jensen's
+// shadow photons). if only photons straight from the lights reached them,
+// it's lit, if only shadow photons did, it's in shadow, and if both did
+// (e.g. at the edge of a shadow), shadow rays have to tell.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ShadowTest {
+    Lit,
+    Shadowed,
+    Trace,
+}
+
+impl ShadowTest {
+    fn from_sums(sums: &PhotonSums) -> Self {
+        match (sums.lit, sums.shadowed) {
+            (true, false) => Self::Lit,
+            (false, true) => Self::Shadowed,
+            _ => Self::Trace,
+        }
+    }
+}
+
+// the average of the photons gathered around a hit, and how much of its
+// intensity came straight from the lights and through mirrors and glass, to
+// split the lighting into passes
+pub struct Gathered {
+    pub photon: Photon,
+    pub direct: Colour,
+    pub caustic: Colour,
+}
+
+// what the photons gathered around a hit add up to, which is all
+// average_photon_of() needs of them. the GPU adds them up the same way (see
+// gpu::photon_gather).
+#[derive(Debug, Copy, Clone)]
+pub struct PhotonSums {
+    pub weight: f32,       // of every photon, by the gather filter
+    pub incident: Vector,  // weighted, like intensity
+    pub intensity: Colour, // weighted, without what direct_lighting leaves out
+    pub direct: Colour,    // the part of intensity straight from the lights
+    pub lit: bool,         // whether any photon came straight from a light
+    pub shadowed: bool,    // whether any was a shadow photon
+}
+
+impl PhotonSums {
+    pub const fn none() -> Self {
+        Self {
+            weight: 0.0,
+            incident: Vector::zero(),
+            intensity: Colour::black(),
+            direct: Colour::black(),
+            lit: false,
+            shadowed: false,
+        }
+    }
+}
+
+enum PhotonMap {
+    KdTree(PhotonTree),
+    HashGrid(PhotonGrid),
+}
+
+impl PhotonMap {
+    fn build(photons: Vec<Photon>, method: GatherMethod, gather_radius: f32) -> Self {
+        match method {
+            GatherMethod::KdTree => Self::KdTree(PhotonTree::build(photons)),
+            GatherMethod::HashGrid => {
+                Self::HashGrid(PhotonGrid::build(photons, gather_radius * 2.0))
+            }
+        }
+    }
+
+    fn get_within_distance(&self, position: &Vertex, radius: f32) -> Vec<Photon> {
+        match self {
+            Self::KdTree(tree) => tree
+                .get_within_distance(position, radius)
+                .into_iter()
+                .map(|found| found.photon)
+                .collect(),
+            Self::HashGrid(grid) => grid.get_within_distance(position, radius),
+        }
+    }
+}
 
 pub struct PhotonScene {
     objects: Vec<Box<dyn Object>>,
     lights: Vec<Box<dyn PhotonLight>>,
-    regular_photon_map: Option<PhotonTree>,
-    caustic_photon_map: Option<PhotonTree>,
+    volumes: Vec<Volume>,
+    pub fog: Option<Medium>,
+    regular_photon_map: Option<PhotonMap>,
+    caustic_photon_map: Option<PhotonMap>,
+    volume_photon_map: Option<PhotonMap>, // only if there is fog
+    pub gather_method: GatherMethod,
+    pub gather_filter: GatherFilter,
+    pub quality: PhotonQuality,
+    pub trace_limit: TraceLimit, // only applies to vueons, not photons
+    pub settings: RenderSettings,
+    pub bake: Option<PhotonSlice>,
+    // only reflections and refractions off surfaces smoother than this
+    // aim caustic photons, so slightly shiny floors don't
+    pub caustic_roughness: f32,
+    pub irradiance_cache: Option<IrradianceCache>,
+    // light straight from the lights is worked out from the lights instead
+    // of the photon maps, which only provide the rest. sharper shadows.
+    pub direct_lighting: bool,
+    pub photon_view: Option<PhotonView>,
+    // texels across the top-down photon density map, None to skip it
+    pub density_map_resolution: Option<u32>,
+    density_map: Option<PhotonBake>,
+    // trace camera rays against the meshes and gather the photons around
+    // their hits on the GPU, see gpu::mesh_tracer and gpu::photon_gather
+    #[cfg(feature = "gpu")]
+    pub gpu: bool,
+    #[cfg(feature = "gpu")]
+    mesh_tracer: Option<MeshTracer>,
+    #[cfg(feature = "gpu")]
+    photon_gatherer: Option<PhotonGatherer>,
+}
+
+impl Default for PhotonScene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PhotonScene {
@@ -34,28 +282,70 @@ impl PhotonScene {
         Self {
             objects: Vec::new(),
             lights: Vec::new(),
+            volumes: Vec::new(),
+            fog: None,
             regular_photon_map: None,
             caustic_photon_map: None,
+            volume_photon_map: None,
+            gather_method: GatherMethod::KdTree,
+            gather_filter: GatherFilter::Box,
+            quality: PhotonQuality::default(),
+            trace_limit: TraceLimit::default(),
+            settings: RenderSettings::default(),
+            bake: None,
+            caustic_roughness: 0.5,
+            irradiance_cache: None,
+            direct_lighting: false,
+            photon_view: None,
+            density_map_resolution: None,
+            density_map: None,
+            #[cfg(feature = "gpu")]
+            gpu: false,
+            #[cfg(feature = "gpu")]
+            mesh_tracer: None,
+            #[cfg(feature = "gpu")]
+            photon_gatherer: None,
         }
     }
 
     fn build_photon_maps(&mut self) {
         // returns caustic photons encountered while photon tracing
 
-        let (regular_photons, caustic_photons) = self.shoot_photons(|this, light| {
-            let photons = light.shoot_photons_mt(this, PHOTONS_PER_LIGHT as u32, None);
+        let quality = self.quality;
+        let shares = self.photon_shares();
+        let caustic_targets = self.caustic_targets();
+        let start = platform::Instant::now();
+        let (regular_photons, caustic_photons) = self.shoot_photons(|this, light_index, light| {
+            let share = |per_light: u32| {
+                let photons = per_light as f32 * shares.len() as f32 * shares[light_index];
+                photons.round() as u32
+            };
+
+            // two seeding streams per light, one for each pass
+            let stream = light_index as u32 * 2;
+            let num_photons = share(quality.photons_per_light);
+            let photons = light.shoot_photons_mt(this, num_photons, None, stream);
 
             let (caustic_photons, regular_photons): (Vec<Photon>, Vec<Photon>) = photons
                 .into_iter()
                 .flatten()
-                .partition(|photon| photon.photon_type == PhotonType::Caustic);
+                .partition(|photon| photon.photon_type.is_caustic());
+            if quality.caustic_photons_per_light == 0 || caustic_targets.is_empty() {
+                return (regular_photons, caustic_photons);
+            }
 
+            // the caustic pass only adds to the caustic map, the rest of the
+            // light it carries is already in the regular one
             let more_caustic_photons = light.shoot_photons_mt(
                 this,
-                CAUSTIC_PHOTONS_PER_LIGHT as u32,
-                Some(caustic_photons.as_slice()),
+                share(quality.caustic_photons_per_light),
+                Some(caustic_targets.as_slice()),
+                stream + 1,
             );
-            let more_caustic_photons = this.flatten_photons(more_caustic_photons);
+            let more_caustic_photons = this
+                .flatten_photons(more_caustic_photons)
+                .into_iter()
+                .filter(|photon| photon.photon_type.is_caustic());
 
             let caustic_photons = caustic_photons
                 .into_iter()
@@ -65,27 +355,128 @@ impl PhotonScene {
             (regular_photons, caustic_photons)
         });
 
-        println!("Building KD trees...");
+        // photons that scattered in fog from either pass go in their own map
+        let has_media = self.has_media();
+        let (volume_photons, regular_photons): (Vec<Photon>, Vec<Photon>) = match has_media {
+            true => regular_photons
+                .into_iter()
+                .partition(|photon| photon.photon_type.is_volume()),
+            false => (Vec::new(), regular_photons),
+        };
+        let (more_volume_photons, caustic_photons): (Vec<Photon>, Vec<Photon>) = match has_media {
+            true => caustic_photons
+                .into_iter()
+                .partition(|photon| photon.photon_type.is_volume()),
+            false => (Vec::new(), caustic_photons),
+        };
+
+        let mut photon_maps = vec![("Regular", regular_photons), ("Caustic", caustic_photons)];
+        if has_media {
+            let volume_photons = volume_photons.into_iter().chain(more_volume_photons).collect();
+            photon_maps.push(("Volume", volume_photons));
+        }
+
+        stats::record_phase("shoot photons", start.elapsed().as_secs_f32());
+        let photons_stored: usize = photon_maps
+            .iter()
+            .map(|(_, photons)| photons.len())
+            .sum();
+        stats::add(Counter::PhotonsStored, photons_stored as u64);
+
+        let all_photons = || photon_maps.iter().flat_map(|(_, photons)| photons);
+        let mut photon_stats = PhotonMapStats::of(all_photons(), quality.gather_radius);
+        let gather_positions = PhotonMapStats::gather_positions(photon_maps[0].1.iter());
+        if let Some(cache) = &mut self.irradiance_cache {
+            cache.place(photon_maps[0].1.iter());
+        }
+        let surface_photons = photon_maps[..2].iter().flat_map(|(_, photons)| photons);
+        self.density_map = self
+            .density_map_resolution
+            .and_then(|resolution| photon_stats::density_map(surface_photons, resolution));
+
+        let start = platform::Instant::now();
+        let method = self.gather_method;
+        println!("Building photon maps ({method:?})...");
         // self.regular_photon_map = Some(PhotonTree::build(regular_photons));
-        let regular_photon_map = thread::spawn(move || {
-            let regular_photon_map = PhotonTree::build(regular_photons);
-            println!("Regular photon map built");
-            regular_photon_map
-        });
-        let caustic_photon_map = thread::spawn(move || {
-            let caustic_photon_map = PhotonTree::build(caustic_photons);
-            println!("Caustic photon map built");
-            caustic_photon_map
-        });
+        let mut photon_maps = platform::map_parallel(photon_maps, |(name, photons)| {
+            let photon_map = PhotonMap::build(photons, method, quality.gather_radius);
+            println!("{name} photon map built");
+            photon_map
+        })
+        .into_iter();
+
+        self.regular_photon_map = photon_maps.next();
+        self.caustic_photon_map = photon_maps.next();
+        self.volume_photon_map = photon_maps.next();
+        stats::record_phase("build photon maps", start.elapsed().as_secs_f32());
+
+        if !gather_positions.is_empty() {
+            let surface_maps = [&self.regular_photon_map, &self.caustic_photon_map];
+            let found: usize = gather_positions
+                .iter()
+                .map(|position| self.photons_in_reach(&surface_maps, position))
+                .sum();
+            photon_stats.per_gather = Some(found as f32 / gather_positions.len() as f32);
+        }
+        println!("{photon_stats}");
+    }
 
-        self.regular_photon_map = Some(regular_photon_map.join().unwrap());
-        self.caustic_photon_map = Some(caustic_photon_map.join().unwrap());
+    // the position and normal of the surface a photon that arrived at
+    // position along incident landed on, found by looking back along its way
+    fn photon_surface(&self, position: &Vertex, incident: &Vector) -> Option<(Vertex, Vector)> {
+        let back = self.settings.epsilon * 10.0;
+        let ray = Ray::new(position.clone() - *incident * back, *incident);
+        let hit = self.trace(&ray).filter(|hit| hit.distance < back * 2.0)?;
+        Some((hit.position, hit.normal))
+    }
+
+    // the fraction of the photons each light shoots, by how much light it
+    // gives off, so bright lights aren't outnumbered by dim ones. photons
+    // keep the light's full intensity however many there are: surfaces are
+    // shaded by the average photon around them, not their sum.
+    fn photon_shares(&self) -> Vec<f32> {
+        let powers: Vec<f32> = self.lights.iter().map(|light| light.power()).collect();
+        let total: f32 = powers.iter().sum();
+        let even_share = 1.0 / powers.len() as f32;
+        if total <= 0.0 {
+            return vec![even_share; powers.len()];
+        }
+
+        let shares: Vec<f32> = powers
+            .iter()
+            .map(|power| (power / total).max(even_share * MIN_PHOTON_SHARE))
+            .collect();
+        let total_share: f32 = shares.iter().sum();
+        shares.iter().map(|share| share / total_share).collect()
+    }
+
+    // what the caustic pass aims at: spheres around every object smooth
+    // enough for light reflected or refracted off it to count as caustic.
+    // objects that don't fit in a sphere, or whose material varies, aren't
+    // aimed at, but still make caustics from the first pass.
+    fn caustic_targets(&self) -> Vec<BoundingSphere> {
+        self.objects
+            .iter()
+            .filter(|object| {
+                object.material().is_some_and(|material| {
+                    let material = material.photon_mapped();
+                    material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract) > 0.0
+                        && material.roughness() < self.caustic_roughness
+                })
+            })
+            .filter_map(|object| object.bounding_sphere())
+            .collect()
+    }
+
+    fn has_media(&self) -> bool {
+        self.fog.is_some() || !self.volumes.is_empty()
     }
 
     fn shoot_photons(
         &mut self,
         shoot_photons: impl Fn(
             &Self,
+            usize,
             &Box<dyn PhotonLight>,
         ) -> (
             Vec<Photon>, /* regular */
@@ -96,9 +487,9 @@ impl PhotonScene {
         let mut caustic_photons = Vec::new();
 
         let lights = std::mem::take(&mut self.lights);
-        for light in lights.iter() {
+        for (light_index, light) in lights.iter().enumerate() {
             // let light_photons = light.shoot_photons_mt(self, PHOTONS_PER_LIGHT as u32);
-            let light_photons = shoot_photons(self, light);
+            let light_photons = shoot_photons(self, light_index, light);
 
             regular_photons.push(light_photons.0);
             caustic_photons.push(light_photons.1);
@@ -148,33 +539,67 @@ impl PhotonScene {
     }
 
     pub fn photontrace(&self, photon: InFlightPhoton) -> Vec<Photon> {
-        let ray = photon.ray();
-        let Some(hit) = self.trace(&ray) else {
+        stats::count(Counter::PhotonRays);
+        let ray = photon.ray().of_kind(RayKind::Photon);
+        let hit = self.trace(&ray);
+
+        if let Some(volume_photon) = self.scatter_photon(&photon, &ray, hit.as_ref()) {
+            return vec![volume_photon];
+        }
+        let Some(hit) = hit else {
             return Vec::new();
         };
 
+        // objects that don't cast shadows are lit, but the photon carries on
+        // past them to whatever does
+        if !self.stops_photon(&ray, &hit) {
+            let mut landed = photon.landed(hit.position.clone());
+            landed.photon_type = absorbed_type(photon.photon_type);
+            let onwards = InFlightPhoton::new(
+                self.settings.offset(&hit.position, &photon.direction),
+                photon.direction,
+                photon.intensity,
+                photon.photon_type,
+            );
+            let mut photons = self.photontrace(onwards);
+            photons.push(landed);
+            return photons;
+        }
+
         let material = hit.material.photon_mapped();
+        let roughness = material.roughness();
 
         // pick absorb, diffuse or specular based on weights
-        let mut rng = rand::thread_rng();
-        let choice = [
-            PhotonBehaviour::Absorb,
-            PhotonBehaviour::Diffuse,
-            PhotonBehaviour::Specular,
-            PhotonBehaviour::ReflectOrRefract,
-        ]
-        .choose_weighted(&mut rng, |item| material.behaviour_weight(item))
-        .unwrap();
-
-        let (mut absorbed_photon, shadow_photons) = self.absorb_photon(photon, &hit);
+        let choice = random::with_rng(|rng| {
+            [
+                PhotonBehaviour::Absorb,
+                PhotonBehaviour::Diffuse,
+                PhotonBehaviour::Specular,
+                PhotonBehaviour::ReflectOrRefract,
+            ]
+            .choose_weighted(rng, |item| material.behaviour_weight(item))
+            .copied()
+            .unwrap()
+        });
+        let material = material.select(&choice);
+
+        let incoming_type = photon.photon_type;
+        let (absorbed_photon, shadow_photons) = self.absorb_photon(photon, &hit);
 
         let bounced_photons = match choice {
             PhotonBehaviour::Absorb => Vec::new(),
-            PhotonBehaviour::Diffuse => self.diffuse_photon(&absorbed_photon, &hit),
-            PhotonBehaviour::Specular => self.specular_photon(&absorbed_photon, &hit),
+            PhotonBehaviour::Diffuse => self.diffuse_photon(&absorbed_photon, &hit, material),
+            PhotonBehaviour::Specular => self.specular_photon(&absorbed_photon, &hit, material),
             PhotonBehaviour::ReflectOrRefract => {
-                absorbed_photon.photon_type = PhotonType::Caustic;
-                self.reflect_or_refract_photon(&absorbed_photon, &ray, &hit, material)
+                // light stays focused through any number of clean
+                // reflections and refractions, until it lands somewhere or
+                // something scatters it
+                let smooth = roughness < self.caustic_roughness;
+                let onward_type = match incoming_type {
+                    PhotonType::Direct | PhotonType::Caustic if smooth => PhotonType::Caustic,
+                    _ => PhotonType::Colour,
+                };
+                self.reflect_or_refract_photon(&absorbed_photon, onward_type, &ray, &hit, material)
             }
         };
 
@@ -185,13 +610,54 @@ impl PhotonScene {
         photons
     }
 
+    // photons can scatter in fog before reaching the surface. only single
+    // scattering is simulated, so they aren't traced any further.
+    fn scatter_photon(
+        &self,
+        photon: &InFlightPhoton,
+        ray: &Ray,
+        hit: Option<&Hit>,
+    ) -> Option<Photon> {
+        if !self.has_media() {
+            return None;
+        }
+
+        let end = hit.map_or(f32::INFINITY, |hit| hit.distance);
+        let spans = medium::spans(self.fog.as_ref(), &self.volumes, ray, end);
+        let distance = medium::scatter_distance(&spans)?;
+
+        let photon_type = match photon.photon_type {
+            PhotonType::Caustic => PhotonType::VolumeCaustic,
+            _ => PhotonType::Volume,
+        };
+        Some(Photon::new(
+            ray.position.clone() + ray.direction * distance,
+            photon.direction,
+            photon.intensity,
+            photon_type,
+        ))
+    }
+
+    // whether the photon stops at hit, rather than only landing on something
+    // that doesn't cast shadows
+    fn stops_photon(&self, ray: &Ray, hit: &Hit) -> bool {
+        if self.objects.iter().all(|object| object.casts_shadows()) {
+            return true;
+        }
+        let shadow_ray = Ray::new(ray.position.clone(), ray.direction)
+            .at_time(ray.time)
+            .of_kind(RayKind::Shadow);
+        self.trace(&shadow_ray)
+            .is_some_and(|blocker| blocker.distance <= hit.distance)
+    }
+
     fn absorb_photon(&self, photon: InFlightPhoton, hit: &Hit) -> (Photon, Vec<Photon>) {
         // store photon in kd tree
         let absorbed_photon = Photon::new(
             hit.position.clone(),
             photon.direction,
             photon.intensity,
-            PhotonType::Colour,
+            absorbed_type(photon.photon_type),
         );
 
         let shadow_photons = self.shadowphotontrace(&absorbed_photon);
@@ -201,18 +667,25 @@ impl PhotonScene {
 
     fn shadowphotontrace(&self, absorbed_photon: &Photon) -> Vec<Photon> {
         let ray = Ray::new(
-            absorbed_photon.position.clone() + absorbed_photon.incident * 0.0001,
+            self.settings
+                .offset(&absorbed_photon.position, &absorbed_photon.incident),
             absorbed_photon.incident,
-        );
+        )
+        .of_kind(RayKind::Shadow);
 
         let mut shadow_photons = Vec::new();
 
+        stats::count(Counter::ShadowRays);
+        stats::add(Counter::IntersectionTests, self.objects.len() as u64);
         for object in self.objects.iter() {
             let hits = object.intersect(&ray);
             for hit in hits {
                 if !hit.entering || hit.distance < 0.0 {
                     continue;
                 }
+                if hit.material.teleport(&ray, &hit).is_some() {
+                    continue;
+                }
 
                 let shadow_photon = Photon::new(
                     hit.position.clone(),
@@ -227,7 +700,12 @@ impl PhotonScene {
         shadow_photons
     }
 
-    fn diffuse_photon(&self, photon: &Photon, hit: &Hit) -> Vec<Photon> {
+    fn diffuse_photon(
+        &self,
+        photon: &Photon,
+        hit: &Hit,
+        material: &dyn PhotonMaterial,
+    ) -> Vec<Photon> {
         let mut direction = Vector::random();
 
         // flip direction if it's facing away from the normal
@@ -235,11 +713,7 @@ impl PhotonScene {
             direction.negate();
         }
 
-        let intensity = hit
-            .material
-            .photon_mapped()
-            .bounced_photon(photon, hit)
-            .unwrap();
+        let intensity = material.bounced_photon(photon, hit).unwrap();
         let photon = InFlightPhoton::new(
             hit.position.clone(),
             direction.normalised(),
@@ -250,14 +724,15 @@ impl PhotonScene {
         self.photontrace(photon)
     }
 
-    fn specular_photon(&self, photon: &Photon, hit: &Hit) -> Vec<Photon> {
+    fn specular_photon(
+        &self,
+        photon: &Photon,
+        hit: &Hit,
+        material: &dyn PhotonMaterial,
+    ) -> Vec<Photon> {
         let reflection = hit.normal.reflection(&photon.incident).normalised();
 
-        let intensity = hit
-            .material
-            .photon_mapped()
-            .bounced_photon(photon, hit)
-            .unwrap();
+        let intensity = material.bounced_photon(photon, hit).unwrap();
         let photon = InFlightPhoton::new(
             hit.position.clone(),
             reflection,
@@ -268,20 +743,23 @@ impl PhotonScene {
         self.photontrace(photon)
     }
 
+    // the reflected or refracted photon carries on as onward_type
     fn reflect_or_refract_photon(
         &self,
         photon: &Photon,
+        onward_type: PhotonType,
         ray: &Ray,
         hit: &Hit,
         material: &dyn PhotonMaterial,
     ) -> Vec<Photon> {
         let reflect_direction = hit.normal.reflection(&photon.incident).normalised();
+        let reflect_direction = material.scatter_direction(hit, reflect_direction);
         let reflected_photon = || {
             InFlightPhoton::new(
-                hit.position.clone() + reflect_direction * 0.0001,
+                self.settings.offset(&hit.position, &reflect_direction),
                 reflect_direction,
                 photon.intensity,
-                PhotonType::Caustic,
+                onward_type,
             )
         };
 
@@ -291,25 +769,60 @@ impl PhotonScene {
 
         // pick reflection or refraction
         let refract_chance = material.refract_chance(refract_result.kr);
-        let mut rng = rand::thread_rng();
-        let should_refract = rng.gen_bool(refract_chance as f64);
+        let should_refract = random::with_rng(|rng| rng.gen_bool(refract_chance as f64));
 
         if should_refract {
+            let ray = refract_result.ray;
+            let direction = material.scatter_direction(hit, ray.direction);
             self.photontrace(InFlightPhoton::new(
-                refract_result.ray.position,
-                refract_result.ray.direction,
+                self.settings.offset(&ray.position, &direction),
+                direction,
                 photon.intensity,
-                PhotonType::Caustic,
+                onward_type,
             ))
         } else {
             self.photontrace(reflected_photon())
         }
     }
 
-    fn vueontrace(&self, vueon: InFlightPhoton) -> RaytraceResult {
-        let ray = vueon.ray();
-        let Some(hit) = self.trace(&ray) else {
-            return RaytraceResult::none();
+    // light from the lights seen along a ray that misses everything
+    fn background(&self, ray: &Ray) -> Colour {
+        self.lights.iter().fold(Colour::black(), |acc, light| {
+            acc + light.background(&ray.direction)
+        })
+    }
+
+    // time is the time of the camera ray the vueon came from, and depth how
+    // many times it has been reflected or refracted
+    fn vueontrace(&self, vueon: InFlightPhoton, time: f32, depth: u8) -> RaytraceResult {
+        let kind = match depth {
+            0 => RayKind::Camera,
+            _ => RayKind::Secondary,
+        };
+        let ray = vueon.ray().at_time(time).of_kind(kind);
+        let summed_ahead = self.summed_ahead(&ray, depth);
+
+        let hit = self.trace_limit.clip(self.trace(&ray));
+
+        if let Some(view) = self.photon_view {
+            return self.photon_view_at(view, hit);
+        }
+
+        // light scattered towards the viewer by fog on the way
+        let end = hit.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+        let spans = medium::spans(self.fog.as_ref(), &self.volumes, &ray, end);
+        let (scattered, transmittance) = match spans.is_empty() {
+            true => (Colour::black(), 1.0),
+            false => medium::march(&spans, |distance| self.in_scatter(&ray, distance)),
+        };
+
+        let Some(hit) = hit else {
+            let mut result = RaytraceResult::none();
+            result.colour = self.background(&ray) * transmittance + scattered;
+            result.throughput = transmittance;
+            result.lighting.emission = self.background(&ray) * transmittance;
+            result.lighting.indirect = scattered;
+            return result;
         };
 
         let material = hit.material.photon_mapped();
@@ -319,104 +832,467 @@ impl PhotonScene {
             + material.behaviour_weight(&PhotonBehaviour::Diffuse)
             + material.behaviour_weight(&PhotonBehaviour::Specular);
         let mut surface_colour = Colour::black();
+        let mut surface_lighting = Lighting::default();
         if surface_weight > 0.0 {
-            if let Some(photon) = self.average_photon_at(&hit) {
-                surface_colour = material.render_vueon(&hit, &photon, -vueon.direction);
+            // the irradiance cache skips gathering, then the photons can't
+            // tell whether the lights are in view
+            let mut shadow = ShadowTest::Trace;
+            let cached = self.irradiance_cache.as_ref().and_then(|cache| {
+                let surface =
+                    |position: &Vertex, incident: &Vector| self.photon_surface(position, incident);
+                let gather = |position: &Vertex, normal: &Vector| {
+                    let regular = self.photon_sums_near(&self.regular_photon_map, position);
+                    let caustic = self.photon_sums_near(&self.caustic_photon_map, position);
+                    self.average_photon_at(position, normal, &regular, &caustic)
+                };
+                cache.get(&hit.position, &hit.normal, surface, gather)
+            });
+            let gathered = cached.or_else(|| {
+                let (regular, caustic) = summed_ahead.unwrap_or_else(|| {
+                    (
+                        self.photon_sums_near(&self.regular_photon_map, &hit.position),
+                        self.photon_sums_near(&self.caustic_photon_map, &hit.position),
+                    )
+                });
+                shadow = ShadowTest::from_sums(&regular);
+                self.average_photon_at(&hit.position, &hit.normal, &regular, &caustic)
+            });
+            if let Some(gathered) = gathered {
+                surface_colour = material.render_vueon(&hit, &gathered.photon, -vueon.direction);
+                surface_lighting = self.gathered_lighting(
+                    &hit,
+                    material,
+                    &gathered,
+                    -vueon.direction,
+                    surface_colour,
+                );
+            }
+            if self.direct_lighting {
+                let (direct, specular) =
+                    self.direct_light_at(&hit, material, -vueon.direction, time, shadow);
+                surface_colour += direct;
+                surface_lighting.diffuse += direct - specular;
+                surface_lighting.specular += specular;
             }
         }
 
+        // past max_depth, reflections and refractions come back black
+        let can_bounce = depth < self.settings.path_depth.max_depth;
+
         // calculate reflection colour
         let reflect_weight = material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract);
+        let reflect_direction = hit.normal.reflection(&vueon.direction).normalised();
         let reflect_vueon = InFlightPhoton::new(
-            hit.position.clone() + hit.normal * 0.0001,
-            hit.normal.reflection(&vueon.direction).normalised(),
+            self.settings.offset(&hit.position, &hit.normal),
+            material.scatter_direction(&hit, reflect_direction),
             vueon.intensity,
             PhotonType::Colour,
         );
         let mut reflect_colour = Colour::black();
-        if reflect_weight > 0.0 {
-            reflect_colour = self.vueontrace(reflect_vueon).colour * reflect_weight;
+        if reflect_weight > 0.0 && can_bounce {
+            stats::count(Counter::SecondaryRays);
+            let reflected = self.vueontrace(reflect_vueon, time, depth + 1).colour;
+            reflect_colour = self.settings.clamp_indirect(reflected) * reflect_weight;
         }
 
         // calculate refraction colour
         let refract_weight = material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract);
         let mut refract_colour = Colour::black();
-        if let Some(refract_result) = material.refracted_direction(&hit, ray.direction) {
+        let refract_result = material.refracted_direction(&hit, ray.direction);
+        if let Some(refract_result) = refract_result.filter(|_| can_bounce) {
+            let refract_ray = refract_result.ray;
+            let refract_direction = material.scatter_direction(&hit, refract_ray.direction);
             let refract_vueon = InFlightPhoton::new(
-                refract_result.ray.position + refract_result.ray.direction * 0.0001,
-                refract_result.ray.direction,
+                self.settings
+                    .offset(&refract_ray.position, &refract_direction),
+                refract_direction,
                 vueon.intensity,
                 PhotonType::Colour,
             );
-            refract_colour = self.vueontrace(refract_vueon).colour * refract_weight
+            stats::count(Counter::SecondaryRays);
+            let refracted = self.vueontrace(refract_vueon, time, depth + 1).colour;
+            refract_colour = self.settings.clamp_indirect(refracted) * refract_weight;
         }
 
+        let total_weight = surface_weight + reflect_weight + refract_weight;
         let mut colour = surface_colour + reflect_colour + refract_colour;
-        colour = colour / (surface_weight + reflect_weight + refract_weight);
+        colour = colour / total_weight;
+        colour = colour * transmittance + scattered;
+
+        surface_lighting.indirect += reflect_colour + refract_colour;
+        let mut lighting = surface_lighting.map(|part| part / total_weight * transmittance);
+        lighting.indirect += scattered;
 
         RaytraceResult {
-            colour,
+            colour: self.trace_limit.fade(colour, hit.distance),
             depth: hit.distance,
+            surface: Some(SurfaceInfo::from_hit(&hit)),
+            throughput: transmittance,
+            lighting: lighting.map(|part| self.trace_limit.fade(part, hit.distance)),
         }
     }
 
-    fn average_photon_at(&self, hit: &Hit) -> Option<Photon> {
-        let photon = self.average_photon_of_type_at(hit, false);
-        let Some((caustic_photon, caustic_photon_count)) =
-            self.average_photon_of_type_at(hit, true)
-        else {
-            return photon.map(|(photon, _)| photon);
-        };
-        let Some((mut photon, regular_photon_count)) = photon else {
-            return Some(caustic_photon);
+    // light arriving distance along the ray that fog could scatter towards
+    // the viewer. lights shine on it directly (unless something is in the
+    // way), and caustics through glass come from the volume photon map.
+    fn in_scatter(&self, ray: &Ray, distance: f32) -> Colour {
+        let position = ray.position.clone() + ray.direction * distance;
+
+        let mut arriving = Colour::black();
+        for light in self.lights.iter() {
+            let Some(ldir) = light.get_direction(&position) else {
+                continue;
+            };
+            if self.light_blocked(position.clone(), &ldir, light.as_ref(), ray.time) {
+                continue;
+            }
+            arriving += self.light_arriving(&position, &ldir, light.as_ref());
+        }
+
+        // like on surfaces, caustics count for the fraction of nearby volume
+        // photons that are caustic
+        let photons = self
+            .volume_photon_map
+            .as_ref()
+            .expect("Photon map not built")
+            .get_within_distance(&position, self.quality.gather_radius);
+        let caustic: Vec<_> = photons
+            .iter()
+            .filter(|photon| photon.photon_type == PhotonType::VolumeCaustic)
+            .collect();
+        if !caustic.is_empty() {
+            let mut intensity = Colour::black();
+            for photon in &caustic {
+                intensity += photon.intensity;
+            }
+            arriving += intensity / photons.len() as f32;
+        }
+
+        arriving
+    }
+
+    // whether something is between position and the light
+    fn light_blocked(&self, position: Vertex, ldir: &Vector, light: &dyn Light, time: f32) -> bool {
+        let limit = match light.distance(&position) {
+            0.0 => f32::MAX,
+            distance => distance,
         };
+        let shadow_ray = Ray::new(position, -ldir.normalised())
+            .at_time(time)
+            .of_kind(RayKind::Shadow);
+        stats::count(Counter::ShadowRays);
+        self.trace(&shadow_ray)
+            .is_some_and(|hit| hit.distance < limit)
+    }
+
+    // the light's intensity at position, dimmed by any fog on the way
+    fn light_arriving(&self, position: &Vertex, ldir: &Vector, light: &dyn Light) -> Colour {
+        let intensity = light
+            .get_intensity(position)
+            .expect("light.get_intensity() is None despite get_direction() being Some");
+        intensity
+            * medium::light_transmittance(self.fog.as_ref(), &self.volumes, position, ldir, light)
+    }
+
+    // light straight from the lights, for direct_lighting, and the highlight
+    // part of it. shadow tells whether the hit can see them, from the photons
+    // gathered around it.
+    fn direct_light_at(
+        &self,
+        hit: &Hit,
+        material: &dyn PhotonMaterial,
+        viewer: Vector,
+        time: f32,
+        shadow: ShadowTest,
+    ) -> (Colour, Colour) {
+        let mut colour = Colour::black();
+        let mut specular = Colour::black();
+        if shadow == ShadowTest::Shadowed {
+            return (colour, specular);
+        }
 
-        photon.intensity += caustic_photon.intensity
-            * (caustic_photon_count / (caustic_photon_count + regular_photon_count));
+        for light in self.lights.iter() {
+            let Some(ldir) = light.get_direction(&hit.position) else {
+                continue;
+            };
+            if shadow == ShadowTest::Trace {
+                let position = self.settings.offset(&hit.position, &-ldir.normalised());
+                if self.light_blocked(position, &ldir, light.as_ref(), time) {
+                    continue;
+                }
+            }
 
-        Some(photon)
+            let photon = Photon::new(
+                hit.position.clone(),
+                ldir.normalised(),
+                self.light_arriving(&hit.position, &ldir, light.as_ref()),
+                PhotonType::Direct,
+            );
+            colour += material.render_vueon(hit, &photon, viewer);
+            specular += material.specular_vueon(hit, &photon, viewer);
+        }
+        (colour, specular)
     }
 
-    fn average_photon_of_type_at(
+    // colour, lit by the photons gathered around hit, split into the light
+    // that came straight from the lights, through mirrors and glass, and the
+    // rest. render_vueon() scales with the photon's intensity, so each part
+    // is what its share of the intensity renders to.
+    fn gathered_lighting(
         &self,
         hit: &Hit,
-        caustic: bool,
-    ) -> Option<(Photon, f32 /* photon count at hit */)> {
-        let photon_map = if caustic {
-            &self.caustic_photon_map
-        } else {
-            &self.regular_photon_map
+        material: &dyn PhotonMaterial,
+        gathered: &Gathered,
+        viewer: Vector,
+        colour: Colour,
+    ) -> Lighting {
+        let photon = |intensity: Colour| {
+            Photon::new(
+                hit.position.clone(),
+                gathered.photon.incident,
+                intensity,
+                PhotonType::Colour,
+            )
+        };
+        let direct = photon(gathered.direct);
+        let direct_colour = material.render_vueon(hit, &direct, viewer);
+        let specular = material.specular_vueon(hit, &direct, viewer);
+        let caustic = material.render_vueon(hit, &photon(gathered.caustic), viewer);
+
+        Lighting {
+            diffuse: direct_colour - specular,
+            specular,
+            indirect: colour - direct_colour - caustic,
+            caustic,
+            ..Lighting::default()
+        }
+    }
+
+    // black where no photons are in reach, then on a log scale through blue
+    // (a few), red (around 30) and yellow (a few hundred) to white (1000 or
+    // more)
+    fn photon_view_at(&self, view: PhotonView, hit: Option<Hit>) -> RaytraceResult {
+        let Some(hit) = hit else {
+            return RaytraceResult::none();
+        };
+
+        let count = match view {
+            PhotonView::Density => self.photons_in_reach(
+                &[&self.regular_photon_map, &self.caustic_photon_map],
+                &hit.position,
+            ),
+            PhotonView::Caustic => {
+                self.photons_in_reach(&[&self.caustic_photon_map], &hit.position)
+            }
         };
-        let neighbour_photons = photon_map
+
+        let colour = heat_colour((count as f32 + 1.0).log10() / 3.0);
+        RaytraceResult {
+            colour,
+            depth: hit.distance,
+            surface: Some(SurfaceInfo::from_hit(&hit)),
+            throughput: 1.0,
+            lighting: Lighting {
+                diffuse: colour,
+                ..Lighting::default()
+            },
+        }
+    }
+
+    // how many photons in the maps are within the gather radius of position
+    fn photons_in_reach(&self, photon_maps: &[&Option<PhotonMap>], position: &Vertex) -> usize {
+        let radius = self.quality.gather_radius;
+        let mut count = 0;
+        for photon_map in photon_maps {
+            // the gather is a box, only count the sphere inside it
+            count += self
+                .photons_near(photon_map, position)
+                .iter()
+                .filter(|photon| {
+                    let offset = photon.position.vector() - position.vector();
+                    offset.len_sqrd() <= radius * radius
+                })
+                .count();
+        }
+        count
+    }
+
+    // photon power per unit area around a point, from both photon maps
+    fn irradiance_at(&self, position: &Vertex) -> Colour {
+        let radius = self.quality.gather_radius;
+        let mut total = Colour::black();
+        for photon_map in [&self.regular_photon_map, &self.caustic_photon_map] {
+            let photons = photon_map
+                .as_ref()
+                .expect("Photon map not built")
+                .get_within_distance(position, radius);
+
+            // the gather is a box, only count the sphere inside it
+            for photon in photons {
+                let offset = photon.position.vector() - position.vector();
+                if offset.len_sqrd() <= radius * radius {
+                    total += photon.intensity;
+                }
+            }
+        }
+        total / (std::f32::consts::PI * radius * radius)
+    }
+
+    pub fn bake_photons(&self, slice: &PhotonSlice) -> PhotonBake {
+        let (width, height) = slice.size();
+        let mut irradiance = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                irradiance.push(self.irradiance_at(&slice.texel_position(x, y)));
+            }
+        }
+
+        PhotonBake {
+            width,
+            height,
+            irradiance,
+        }
+    }
+
+    fn photons_near(&self, photon_map: &Option<PhotonMap>, position: &Vertex) -> Vec<Photon> {
+        photon_map
             .as_ref()
             .expect("Photon map not built")
-            .get_within_distance(&hit.position, 0.1);
-        let photons_in_radius = neighbour_photons.len();
-        if photons_in_radius == 0 {
+            .get_within_distance(position, self.quality.gather_radius)
+    }
+
+    // the photons around a camera ray's hit, if they were added up on the
+    // GPU before the camera raytraced it (see prepare_camera_rays())
+    #[cfg(feature = "gpu")]
+    fn summed_ahead(&self, ray: &Ray, depth: u8) -> Option<(PhotonSums, PhotonSums)> {
+        if depth > 0 || self.photon_gatherer.is_none() {
             return None;
         }
+        photon_gather::take_summed(ray)
+    }
 
-        let neighbour_photons_len = neighbour_photons.len() as f32;
+    #[cfg(not(feature = "gpu"))]
+    fn summed_ahead(&self, _ray: &Ray, _depth: u8) -> Option<(PhotonSums, PhotonSums)> {
+        None
+    }
 
-        let mut average_ldir = Vector::new(0.0, 0.0, 0.0);
-        let mut average_intensity = Colour::black();
+    // a gatherer for the photon maps on the GPU, or None (saying why) if
+    // they're gathered from on the CPU
+    #[cfg(feature = "gpu")]
+    fn prepare_photon_gatherer(&self) -> Option<PhotonGatherer> {
+        let why_not = if self.irradiance_cache.is_some() {
+            "the irradiance cache gathers on its own"
+        } else if self.photon_view.is_some() {
+            "the photons are shown instead"
+        } else {
+            match (&self.regular_photon_map, &self.caustic_photon_map) {
+                (Some(PhotonMap::HashGrid(regular)), Some(PhotonMap::HashGrid(caustic))) => {
+                    return PhotonGatherer::prepare(
+                        regular,
+                        caustic,
+                        self.quality.gather_radius,
+                        self.gather_filter,
+                        self.direct_lighting,
+                    );
+                }
+                _ => "only HashGrid photon maps can be gathered from on the GPU",
+            }
+        };
+        println!("Gathering photons on the CPU: {why_not}");
+        None
+    }
 
-        for ItemAndDistance { item: photon, .. } in neighbour_photons {
-            average_ldir += photon.incident.normalised();
-            average_intensity += photon.intensity;
+    // the photons around position in the photon map, added up
+    fn photon_sums_near(&self, photon_map: &Option<PhotonMap>, position: &Vertex) -> PhotonSums {
+        let radius = self.quality.gather_radius;
+        let mut sums = PhotonSums::none();
+        for photon in self.photons_near(photon_map, position) {
+            let offset = photon.position.vector() - position.vector();
+            let weight = self.gather_filter.weight(offset.len_sqrd(), radius);
+            sums.weight += weight;
+            sums.lit |= photon.photon_type == PhotonType::Direct;
+            sums.shadowed |= photon.photon_type == PhotonType::Shadow;
+
+            // direct_light_at() takes care of these, they only count towards
+            // how many photons there are
+            let direct = matches!(photon.photon_type, PhotonType::Direct | PhotonType::Shadow);
+            if self.direct_lighting && direct {
+                continue;
+            }
+            sums.incident += photon.incident.normalised() * weight;
+            sums.intensity += photon.intensity * weight;
+            if direct {
+                sums.direct += photon.intensity * weight;
+            }
         }
+        sums
+    }
 
+    // regular and caustic are the photons from the two photon maps around
+    // position, on a surface facing along normal
+    fn average_photon_at(
+        &self,
+        position: &Vertex,
+        normal: &Vector,
+        regular: &PhotonSums,
+        caustic: &PhotonSums,
+    ) -> Option<Gathered> {
+        let gathered = self.average_photon_of(position, normal, regular);
+        let Some((caustic, caustic_photon_count)) =
+            self.average_photon_of(position, normal, caustic)
+        else {
+            return gathered.map(|(gathered, _)| gathered);
+        };
+        let Some((mut gathered, regular_photon_count)) = gathered else {
+            let intensity = self.settings.clamp_indirect(caustic.photon.intensity);
+            let mut photon = caustic.photon;
+            photon.intensity = intensity;
+            return Some(Gathered {
+                photon,
+                direct: caustic.direct,
+                caustic: intensity,
+            });
+        };
+
+        gathered.caustic = self.settings.clamp_indirect(
+            caustic.photon.intensity
+                * (caustic_photon_count / (caustic_photon_count + regular_photon_count)),
+        );
+        gathered.photon.intensity += gathered.caustic;
+
+        Some(gathered)
+    }
+
+    // the photon count is weighted by the gather filter
+    fn average_photon_of(
+        &self,
+        position: &Vertex,
+        normal: &Vector,
+        sums: &PhotonSums,
+    ) -> Option<(Gathered, f32 /* photon count at hit */)> {
+        if sums.weight <= 0.0 {
+            return None;
+        }
+
+        let mut average_ldir = sums.incident;
+        if average_ldir.len_sqrd() == 0.0 {
+            average_ldir = -*normal;
+        }
         average_ldir.normalise();
-        average_intensity = average_intensity / neighbour_photons_len;
 
         let photon = Photon::new(
-            hit.position.clone(),
+            position.clone(),
             average_ldir,
-            average_intensity,
+            sums.intensity / sums.weight,
             PhotonType::Colour,
         );
 
-        Some((photon, neighbour_photons_len))
+        let gathered = Gathered {
+            photon,
+            direct: sums.direct / sums.weight,
+            caustic: Colour::black(),
+        };
+        Some((gathered, sums.weight))
     }
 }
 
@@ -430,8 +1306,17 @@ impl Environment for PhotonScene {
         self.lights.push(light);
     }
 
+    fn add_volume(&mut self, volume: Volume) {
+        self.volumes.push(volume);
+    }
+
     fn pre_render(&mut self) {
         self.build_photon_maps();
+        #[cfg(feature = "gpu")]
+        if self.gpu {
+            self.mesh_tracer = MeshTracer::prepare(&self.objects);
+            self.photon_gatherer = self.prepare_photon_gatherer();
+        }
     }
 
     fn raytrace(&self, ray: &Ray) -> RaytraceResult {
@@ -442,10 +1327,96 @@ impl Environment for PhotonScene {
             PhotonType::Vueon,
         );
 
-        self.vueontrace(vueon)
+        self.vueontrace(vueon, ray.time, 0)
     }
 
     fn objects(&self) -> &[Box<dyn Object>] {
         &self.objects
     }
+
+    fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    fn photon_bake(&self) -> Option<PhotonBake> {
+        let slice = self.bake.as_ref()?;
+        println!("Baking photons...");
+        Some(self.bake_photons(slice))
+    }
+
+    fn photon_density(&self) -> Option<PhotonBake> {
+        self.density_map.clone()
+    }
+
+    fn lights(&self) -> Vec<&dyn Light> {
+        self.lights
+            .iter()
+            .map(|light| light.as_ref() as &dyn Light)
+            .collect()
+    }
+
+    #[cfg(feature = "gpu")]
+    fn mesh_tracer(&self) -> Option<&MeshTracer> {
+        self.mesh_tracer.as_ref()
+    }
+
+    #[cfg(feature = "gpu")]
+    fn prepares_camera_rays(&self) -> bool {
+        self.photon_gatherer.is_some()
+    }
+
+    // finds where the camera rays hit, the same way vueontrace() will, and
+    // gathers the photons around those hits on the GPU
+    #[cfg(feature = "gpu")]
+    fn prepare_camera_rays(&self, rays: &[Ray], first_hits: &[FirstHit]) {
+        let Some(gatherer) = &self.photon_gatherer else {
+            return;
+        };
+        let hits: Vec<Option<Vertex>> = rays
+            .iter()
+            .enumerate()
+            .map(|(i, ray)| {
+                if let Some(first_hit) = first_hits.get(i) {
+                    mesh_tracer::expect_first_hit(*first_hit);
+                }
+                let ray = Ray::new(ray.position.clone(), ray.direction)
+                    .at_time(ray.time)
+                    .of_kind(RayKind::Camera);
+                let hit = self.trace_limit.clip(self.trace(&ray));
+                hit.map(|hit| hit.position)
+            })
+            .collect();
+        gatherer.gather_ahead(rays, &hits);
+    }
+}
+
+impl Integrator for PhotonScene {
+    fn raytrace(&self, ray: &Ray, depth: u8) -> RaytraceResult {
+        stats::count(Counter::SecondaryRays);
+        let vueon = InFlightPhoton::new(
+            ray.position.clone(),
+            ray.direction,
+            Colour::white(),
+            PhotonType::Vueon,
+        );
+        self.vueontrace(vueon, ray.time, depth)
+    }
+
+    fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f32) -> bool {
+        stats::count(Counter::ShadowRays);
+        self.trace(ray).is_some_and(|hit| hit.distance < distance)
+    }
+}
+
+// the type a photon is stored as where it lands
+fn absorbed_type(photon_type: PhotonType) -> PhotonType {
+    match photon_type {
+        PhotonType::Direct => PhotonType::Direct,
+        PhotonType::Caustic => PhotonType::Caustic,
+        _ => PhotonType::Colour,
+    }
 }
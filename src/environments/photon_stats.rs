@@ -0,0 +1,175 @@
+// What ended up in the photon maps: how many photons of each type were
+// stored, where, and how many a gather finds. Printed after the maps are
+// built, to tell whether a scene needs more photons or a bigger gather
+// radius without rendering it. Optionally also a top-down density map of
+// where the photons landed.
+
+use std::fmt;
+
+use crate::core::{
+    colour::Colour,
+    photon::{Photon, PhotonType},
+    vertex::Vertex,
+};
+
+use super::photon_bake::PhotonBake;
+
+// how many stored photons to gather around to estimate how many a gather
+// finds. spread evenly through the photons, so mostly where they're dense.
+pub const GATHER_SAMPLES: usize = 256;
+
+const TYPES: [PhotonType; 6] = [
+    PhotonType::Direct,
+    PhotonType::Colour,
+    PhotonType::Shadow,
+    PhotonType::Caustic,
+    PhotonType::Volume,
+    PhotonType::VolumeCaustic,
+];
+
+pub struct PhotonMapStats {
+    pub counts: [usize; TYPES.len()], // in the order of TYPES
+    pub bounds: Option<(Vertex, Vertex)>,
+    pub gather_radius: f32,
+    // the average number of photons within the gather radius of a stored
+    // photon, set once the maps are built
+    pub per_gather: Option<f32>,
+}
+
+impl PhotonMapStats {
+    pub fn of<'a>(photons: impl Iterator<Item = &'a Photon>, gather_radius: f32) -> Self {
+        let mut counts = [0; TYPES.len()];
+        let mut bounds: Option<(Vertex, Vertex)> = None;
+        for photon in photons {
+            if let Some(i) = TYPES.iter().position(|t| *t == photon.photon_type) {
+                counts[i] += 1;
+            }
+
+            let p = &photon.position;
+            bounds = Some(match bounds {
+                None => (p.clone(), p.clone()),
+                Some((min, max)) => (
+                    Vertex::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                    Vertex::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+                ),
+            });
+        }
+
+        Self {
+            counts,
+            bounds,
+            gather_radius,
+            per_gather: None,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    // where to gather around for per_gather, see GATHER_SAMPLES
+    pub fn gather_positions<'a>(photons: impl ExactSizeIterator<Item = &'a Photon>) -> Vec<Vertex> {
+        let step = (photons.len() / GATHER_SAMPLES).max(1);
+        photons
+            .step_by(step)
+            .take(GATHER_SAMPLES)
+            .map(|photon| photon.position.clone())
+            .collect()
+    }
+}
+
+impl fmt::Display for PhotonMapStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Photon maps: {} photons stored (", self.total())?;
+        let mut first = true;
+        for (photon_type, count) in TYPES.iter().zip(self.counts) {
+            if count == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{photon_type:?} {count}")?;
+            first = false;
+        }
+        writeln!(f, ")")?;
+
+        if let Some((min, max)) = &self.bounds {
+            writeln!(
+                f,
+                "  bounds ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+                min.x, min.y, min.z, max.x, max.y, max.z
+            )?;
+        }
+        match self.per_gather {
+            Some(per_gather) => write!(
+                f,
+                "  {per_gather:.1} photons within the gather radius ({}) of a stored photon, on average",
+                self.gather_radius
+            ),
+            None => write!(f, "  gather radius {}", self.gather_radius),
+        }
+    }
+}
+
+// where the surface photons landed, seen from above (down the y axis): the
+// photons per unit area in each texel of a grid over the photons' x and z
+// range, resolution texels across. shadow photons don't carry any light, so
+// they're left out.
+pub fn density_map<'a>(
+    photons: impl Iterator<Item = &'a Photon> + Clone,
+    resolution: u32,
+) -> Option<PhotonBake> {
+    let lit = |photon: &&Photon| photon.photon_type != PhotonType::Shadow;
+    let (min_x, max_x) = central_range(photons.clone().filter(lit).map(|p| p.position.x))?;
+    let (min_z, max_z) = central_range(photons.clone().filter(lit).map(|p| p.position.z))?;
+
+    let extent_x = (max_x - min_x).max(f32::EPSILON);
+    let extent_z = (max_z - min_z).max(f32::EPSILON);
+    let width = resolution.max(1);
+    let height = ((width as f32 * extent_z / extent_x).round() as u32).clamp(1, width * 16);
+    let texel_area = (extent_x / width as f32) * (extent_z / height as f32);
+
+    let mut counts = vec![0u32; (width * height) as usize];
+    for photon in photons.filter(lit) {
+        let fx = (photon.position.x - min_x) / extent_x;
+        let fz = (photon.position.z - min_z) / extent_z;
+        if !(0.0..=1.0).contains(&fx) || !(0.0..=1.0).contains(&fz) {
+            continue;
+        }
+        let x = ((fx * width as f32) as u32).min(width - 1);
+        // further away (higher z) at the top
+        let y = (((1.0 - fz) * height as f32) as u32).min(height - 1);
+        counts[(y * width + x) as usize] += 1;
+    }
+
+    let irradiance = counts
+        .into_iter()
+        .map(|count| {
+            let density = count as f32 / texel_area;
+            Colour::new(density, density, density)
+        })
+        .collect();
+    Some(PhotonBake {
+        width,
+        height,
+        irradiance,
+    })
+}
+
+// where the middle 98% of the values are. photons that land on big ground
+// planes far from everything else would otherwise squash the interesting
+// part into a few texels.
+fn central_range(values: impl Iterator<Item = f32>) -> Option<(f32, f32)> {
+    let mut values: Vec<f32> = values.collect();
+    if values.is_empty() {
+        return None;
+    }
+    let last = values.len() - 1;
+    let low = last / 100;
+    let high = last - low;
+    let (_, min, _) = values.select_nth_unstable_by(low, f32::total_cmp);
+    let min = *min;
+    let (_, max, _) = values.select_nth_unstable_by(high, f32::total_cmp);
+    Some((min, *max))
+}
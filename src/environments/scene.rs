@@ -1,13 +1,43 @@
+use std::ops::Range;
+
 use crate::core::hit::{Hit, HitVec};
 use crate::{lights::light::Light, objects::object::Object};
 
-use crate::core::{colour::Colour, ray::Ray};
+use crate::core::ray::{Ray, RayKind};
+use crate::core::stats::{self, Counter};
+
+use crate::core::colour::Colour;
+
+use super::ambient_occlusion::AmbientOcclusion;
+use super::environment::{
+    Environment, Integrator, Lighting, RaytraceResult, RenderSettings, SurfaceInfo, TraceLimit,
+};
+use super::medium::{self, Medium, Volume};
 
-use super::environment::{Environment, RaytraceResult};
+#[cfg(feature = "gpu")]
+use crate::gpu::mesh_tracer::MeshTracer;
 
 pub struct Scene {
     objects: Vec<Box<dyn Object>>,
     lights: Vec<Box<dyn Light>>,
+    volumes: Vec<Volume>,
+    pub fog: Option<Medium>,
+    pub trace_limit: TraceLimit,
+    pub path_window: Option<Range<f32>>,
+    pub settings: RenderSettings,
+    // darken ambient light where other objects are close by
+    pub ambient_occlusion: Option<AmbientOcclusion>,
+    // trace camera rays against the meshes on the GPU, see gpu::mesh_tracer
+    #[cfg(feature = "gpu")]
+    pub gpu: bool,
+    #[cfg(feature = "gpu")]
+    mesh_tracer: Option<MeshTracer>,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Scene {
@@ -15,12 +45,22 @@ impl Scene {
         Self {
             objects: Vec::new(),
             lights: Vec::new(),
+            volumes: Vec::new(),
+            fog: None,
+            trace_limit: TraceLimit::default(),
+            path_window: None,
+            settings: RenderSettings::default(),
+            ambient_occlusion: None,
+            #[cfg(feature = "gpu")]
+            gpu: false,
+            #[cfg(feature = "gpu")]
+            mesh_tracer: None,
         }
     }
 
     fn select_first_hit<'s>(&self, hits: HitVec<'s>) -> Option<Hit<'s>> {
         let mut min_hit: Option<Hit> = None;
-        let mut min_distance = std::f32::MAX;
+        let mut min_distance = f32::MAX;
 
         for hit in hits {
             if hit.distance < 0.0 {
@@ -39,58 +79,115 @@ impl Scene {
         min_hit
     }
 
-    fn trace(&self, ray: &Ray) -> Option<Hit> {
-        let mut min_hit: Option<Hit> = None;
-        let mut min_distance = std::f32::MAX;
+    fn trace(&self, ray: &Ray) -> Option<Hit<'_>> {
+        self.trace_limit.clip(Environment::trace(self, ray))
+    }
 
+    // raytrace a shadow ray.
+    // returns true if intersection found between 0 and limit along ray.
+    fn shadowtrace(&self, ray: &Ray, limit: f32) -> bool {
+        let limit = limit.min(self.trace_limit.max_distance);
+        stats::count(Counter::ShadowRays);
         for object in self.objects.iter() {
+            stats::count(Counter::IntersectionTests);
             let hits = object.intersect(ray);
-
             let hit = self.select_first_hit(hits);
             let Some(hit) = hit else {
                 continue;
             };
+            if hit.material.teleport(ray, &hit).is_some() {
+                continue; // portals and mirrors don't cast shadows
+            }
 
-            if hit.distance < min_distance {
-                min_distance = hit.distance;
-                min_hit = Some(hit);
+            if hit.distance > self.settings.epsilon && hit.distance < limit {
+                return true;
             }
         }
 
-        min_hit
+        false
     }
 
-    // raytrace a shadow ray.
-    // returns true if intersection found between 0 and limit along ray.
-    fn shadowtrace(&self, ray: &Ray, limit: f32) -> bool {
-        for object in self.objects.iter() {
-            let hits = object.intersect(ray);
-            let hit = self.select_first_hit(hits);
-            let Some(hit) = hit else {
+    // light from the scene's lights arriving distance along the ray, that
+    // fog there could scatter towards the viewer
+    fn in_scatter(&self, ray: &Ray, distance: f32) -> Colour {
+        let position = ray.position.clone() + ray.direction * distance;
+
+        let mut arriving = Colour::black();
+        for light in self.lights.iter() {
+            let Some(ldir) = light.get_direction(&position) else {
                 continue;
             };
 
-            if hit.distance > 0.0000001 && hit.distance < limit {
-                return true;
+            let path_length = ray.travelled + distance + light.distance(&position);
+            if !Integrator::in_path_window(self, path_length) {
+                continue;
+            }
+
+            let shadow_ray = Ray::new(position.clone(), -ldir.normalised())
+                .at_time(ray.time)
+                .of_kind(RayKind::Shadow);
+            let limit = match light.distance(&position) {
+                0.0 => f32::MAX,
+                distance => distance,
+            };
+            if self.shadowtrace(&shadow_ray, limit) {
+                continue;
             }
+
+            let intensity = light
+                .get_intensity(&position)
+                .expect("light.get_intensity() is None despite get_direction() being Some");
+            arriving += intensity * medium::light_transmittance(
+                self.fog.as_ref(),
+                &self.volumes,
+                &position,
+                &ldir,
+                light.as_ref(),
+            );
         }
+        arriving
+    }
 
-        false
+    // light from the lights seen along a ray that misses everything. it has
+    // come from infinitely far away, so never in a time-of-flight window.
+    fn background(&self, ray: &Ray) -> Colour {
+        if self.path_window.is_some() {
+            return Colour::black();
+        }
+        self.lights.iter().fold(Colour::black(), |acc, light| {
+            acc + light.background(&ray.direction)
+        })
     }
 
     // shoot a ray into the environment and get the colour and depth.
     // depth indicates the current recursion level.
     pub fn raytrace(&self, ray: &Ray, depth: u8) -> RaytraceResult {
         // first step, find the closest primitive
-        let Some(hit) = self.trace(ray) else {
-            return RaytraceResult {
-                colour: Colour::black(),
-                depth: 0.0,
-            };
+        let hit = self.trace(ray);
+
+        // then march through any fog on the way to it
+        let end = hit.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+        let spans = medium::spans(self.fog.as_ref(), &self.volumes, ray, end);
+        let (scattered, transmittance) = match spans.is_empty() {
+            true => (Colour::black(), 1.0),
+            false => medium::march(&spans, |distance| self.in_scatter(ray, distance)),
+        };
+
+        let Some(hit) = hit else {
+            let mut result = RaytraceResult::none();
+            result.colour = self.background(ray) * transmittance + scattered;
+            result.throughput = transmittance;
+            result.lighting.emission = self.background(ray) * transmittance;
+            result.lighting.indirect = scattered;
+            return result;
         };
 
         // next, compute the colour we should see
         let mut colour = hit.material.compute_once(self, ray, &hit, depth);
+        let mut lighting = Lighting {
+            indirect: colour,
+            ..Lighting::default()
+        };
 
         // then, compute the light contribution for every light in the scene
         for light in self.lights.iter() {
@@ -103,27 +200,53 @@ impl Scene {
 
             // shadow check
             if let Some(ldir) = lit {
-                let mut shadow_ray = Ray::new(hit.position.clone(), -ldir);
-
-                // add a small offset to the shadow ray origin to avoid self intersection
-                shadow_ray.position += shadow_ray.direction * 0.0001;
+                // start off the surface to avoid self intersection
+                let origin = self.settings.offset(&hit.position, &-ldir);
+                let shadow_ray = Ray::new(origin, -ldir)
+                    .at_time(ray.time)
+                    .of_kind(RayKind::Shadow);
 
                 if self.shadowtrace(&shadow_ray, ldir.length()) {
                     lit = None;
                 }
             }
 
+            // light from outside the time-of-flight window doesn't arrive
+            let path_length = ray.travelled + hit.distance + light.distance(&hit.position);
+            if !Integrator::in_path_window(self, path_length) {
+                lit = None;
+            }
+
             if let Some(ldir) = lit {
                 let intensity = light
                     .get_intensity(&hit.position)
                     .expect("light.get_intensity() is None despite get_direction() being Some");
-                colour += hit.material.compute_per_light(self, &viewer, &hit, &ldir) * intensity;
+                let intensity = intensity
+                    * medium::light_transmittance(
+                        self.fog.as_ref(),
+                        &self.volumes,
+                        &hit.position,
+                        &ldir,
+                        light.as_ref(),
+                    );
+                let lit = hit.material.compute_per_light(self, &viewer, &hit, &ldir) * intensity;
+                let specular =
+                    hit.material.specular_per_light(self, &viewer, &hit, &ldir) * intensity;
+                colour += lit;
+                lighting.diffuse += lit - specular;
+                lighting.specular += specular;
             }
         }
+        let colour = colour * transmittance + scattered;
+        let mut lighting = lighting.map(|part| part * transmittance);
+        lighting.indirect += scattered;
 
         RaytraceResult {
-            colour,
+            colour: self.trace_limit.fade(colour, hit.distance),
             depth: hit.distance,
+            surface: Some(SurfaceInfo::from_hit(&hit)),
+            throughput: transmittance,
+            lighting: lighting.map(|part| self.trace_limit.fade(part, hit.distance)),
         }
     }
 }
@@ -137,7 +260,21 @@ impl Environment for Scene {
         self.lights.push(light);
     }
 
-    fn pre_render(&mut self) {}
+    fn add_volume(&mut self, volume: Volume) {
+        self.volumes.push(volume);
+    }
+
+    fn pre_render(&mut self) {
+        #[cfg(feature = "gpu")]
+        if self.gpu {
+            self.mesh_tracer = MeshTracer::prepare(&self.objects);
+        }
+    }
+
+    fn set_path_window(&mut self, window: Option<Range<f32>>) -> bool {
+        self.path_window = window;
+        true
+    }
 
     fn raytrace(&self, ray: &Ray) -> RaytraceResult {
         Scene::raytrace(self, ray, 0)
@@ -146,4 +283,43 @@ impl Environment for Scene {
     fn objects(&self) -> &[Box<dyn Object>] {
         &self.objects
     }
+
+    fn lights(&self) -> Vec<&dyn Light> {
+        self.lights.iter().map(|light| light.as_ref()).collect()
+    }
+
+    fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    #[cfg(feature = "gpu")]
+    fn mesh_tracer(&self) -> Option<&MeshTracer> {
+        self.mesh_tracer.as_ref()
+    }
+}
+
+impl Integrator for Scene {
+    fn raytrace(&self, ray: &Ray, depth: u8) -> RaytraceResult {
+        stats::count(Counter::SecondaryRays);
+        Scene::raytrace(self, ray, depth)
+    }
+
+    fn settings(&self) -> RenderSettings {
+        self.settings
+    }
+
+    fn in_path_window(&self, path_length: f32) -> bool {
+        self.path_window
+            .as_ref()
+            .is_none_or(|window| window.contains(&path_length))
+    }
+
+    fn occluded(&self, ray: &Ray, distance: f32) -> bool {
+        self.shadowtrace(ray, distance)
+    }
+
+    fn ambient_visibility(&self, viewer: &Ray, hit: &Hit) -> f32 {
+        self.ambient_occlusion
+            .map_or(1.0, |occlusion| occlusion.unoccluded(self, viewer, hit))
+    }
 }
@@ -0,0 +1,179 @@
+// The GPU everything in gpu runs on, found once and shared by every scene,
+// and the few steps each of the compute shaders repeats: uploading storage
+// buffers, running a shader over them and reading its results back.
+
+use std::sync::{mpsc, OnceLock};
+
+use wgpu::util::DeviceExt;
+
+// threads in each of the shaders' workgroups, their @workgroup_size
+pub const WORKGROUP_SIZE: u32 = 64;
+
+pub struct Gpu {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+    pub name: String,
+}
+
+impl Gpu {
+    // the first time, looks for an adapter that can run compute shaders. a
+    // hardware one if there is one, but a software one (e.g. llvmpipe) still
+    // works. if there isn't one at all, says why not.
+    pub fn get() -> Result<&'static Self, String> {
+        static GPU: OnceLock<Result<Gpu, String>> = OnceLock::new();
+        GPU.get_or_init(|| pollster::block_on(Self::request()))
+            .as_ref()
+            .map_err(String::clone)
+    }
+
+    async fn request() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .ok_or("no GPU adapter found")?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return Err(format!(
+                "{} can't run compute shaders",
+                adapter.get_info().name
+            ));
+        }
+
+        // as big buffers as the adapter allows, for big meshes and photon maps
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    required_features: wgpu::Features::empty(),
+                    required_limits: adapter.limits(),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            device,
+            queue,
+            name: adapter.get_info().name,
+        })
+    }
+
+    pub fn pipeline(&self, shader: wgpu::ShaderModuleDescriptor) -> wgpu::ComputePipeline {
+        let module = self.device.create_shader_module(shader);
+        self.device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: None,
+                layout: None,
+                module: &module,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+    }
+
+    // a buffer for the shaders to read. storage buffers can't be empty, so
+    // an empty one is given a zeroed T instead.
+    pub fn storage<T: bytemuck::Pod>(&self, contents: &[T]) -> wgpu::Buffer {
+        let zeroed = [T::zeroed()];
+        let contents = if contents.is_empty() {
+            &zeroed
+        } else {
+            contents
+        };
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(contents),
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+    }
+
+    // the largest storage buffer the shaders can be given, in bytes
+    pub fn max_buffer_size(&self) -> u64 {
+        let limits = self.device.limits();
+        u64::from(limits.max_storage_buffer_binding_size).min(limits.max_buffer_size)
+    }
+
+    // runs the pipeline's shader once for each of count items, with
+    // buffers bound in order to group 0 (after which the shader writes count
+    // Ts), and reads back what it wrote
+    pub fn run<T: bytemuck::Pod>(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        buffers: &[&wgpu::Buffer],
+        count: usize,
+    ) -> Result<Vec<T>, String> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let workgroups = (count as u32).div_ceil(WORKGROUP_SIZE);
+        if workgroups > self.device.limits().max_compute_workgroups_per_dimension {
+            return Err(format!("{count} is too many to run at once"));
+        }
+
+        let size = (count * std::mem::size_of::<T>()) as u64;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let entries: Vec<_> = buffers
+            .iter()
+            .copied()
+            .chain([&output])
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        });
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, size);
+        let submission = self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        let _ = self
+            .device
+            .poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+        receiver
+            .recv()
+            .map_err(|_| "the GPU went away".to_string())?
+            .map_err(|e| e.to_string())?;
+
+        let results = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        readback.unmap();
+        Ok(results)
+    }
+}
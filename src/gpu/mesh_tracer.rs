@@ -0,0 +1,260 @@
+// Finding which mesh triangle each camera ray hits first on the GPU. Every
+// triangle of the scene's meshes is uploaded once, sorted into a bounding
+// volume hierarchy (see objects::bvh), and a compute shader sends a tile's
+// camera rays down it all at once. The CPU still does everything else: it
+// works out the hit on the triangle the GPU found, intersects the objects
+// that aren't meshes and shades the result.
+//
+// The camera finds a tile's first hits before raytracing any of its rays,
+// and hands each one over with expect_first_hit() just before raytracing
+// its ray. Environment::trace_nearest() picks it up, as long as it's for the
+// ray being traced.
+
+use std::{cell::Cell, sync::Once};
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    core::{
+        hit::{Hit, HitVec},
+        ray::Ray,
+        vector::Vector,
+    },
+    hitvec,
+    objects::{
+        bvh::{Aabb, Bvh},
+        object::Object,
+    },
+};
+
+use super::device::Gpu;
+
+const LEAF_SIZE: usize = 4;
+// what the shader writes for a ray that hits nothing
+const MISS: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuTriangle {
+    a: [f32; 4],
+    ab: [f32; 4],
+    ac: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuNode {
+    min: [f32; 3],
+    start: u32,
+    max: [f32; 3],
+    count: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuRay {
+    origin: [f32; 4],
+    direction: [f32; 4],
+}
+
+// the closest mesh triangle a camera ray hits, as its object's instance and
+// which of the object's triangles() it is, or None if it misses them all
+#[derive(Debug, Copy, Clone)]
+pub struct FirstHit {
+    position: Vector,
+    direction: Vector,
+    triangle: Option<(usize, usize)>,
+}
+
+impl FirstHit {
+    fn is_for(&self, ray: &Ray) -> bool {
+        let position = ray.position.vector();
+        let same = |a: &Vector, b: &Vector| a.x == b.x && a.y == b.y && a.z == b.z;
+        same(&self.position, &position) && same(&self.direction, &ray.direction)
+    }
+}
+
+thread_local! {
+    static NEXT: Cell<Option<FirstHit>> = const { Cell::new(None) };
+}
+
+// the first hit of the next camera ray raytraced on this thread
+pub fn expect_first_hit(first_hit: FirstHit) {
+    NEXT.with(|next| next.set(Some(first_hit)));
+}
+
+pub struct MeshTracer {
+    gpu: &'static Gpu,
+    pipeline: wgpu::ComputePipeline,
+    triangles: wgpu::Buffer,
+    nodes: wgpu::Buffer,
+    // which objects' triangles were uploaded, by instance
+    traced: Vec<bool>,
+    // the instance and index in it of each uploaded triangle, in the order
+    // the hierarchy sorted them into
+    owners: Vec<(usize, usize)>,
+}
+
+impl MeshTracer {
+    // a tracer for the objects made of triangles, or None (saying why) if
+    // there aren't any or the GPU can't be used, to trace them on the CPU
+    pub fn prepare(objects: &[Box<dyn Object>]) -> Option<Self> {
+        match Self::new(objects) {
+            Ok(tracer) => {
+                println!(
+                    "Tracing camera rays against {} triangles on {}",
+                    tracer.owners.len(),
+                    tracer.gpu.name
+                );
+                Some(tracer)
+            }
+            Err(e) => {
+                println!("Tracing on the CPU instead of the GPU: {e}");
+                None
+            }
+        }
+    }
+
+    fn new(objects: &[Box<dyn Object>]) -> Result<Self, String> {
+        let mut traced = vec![false; objects.len()];
+        let mut triangles = Vec::new();
+        for (instance, object) in objects.iter().enumerate() {
+            let Some(corners) = object.triangles() else {
+                continue;
+            };
+            traced[instance] = true;
+            triangles.extend(
+                corners
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, corners)| (instance, index, corners)),
+            );
+        }
+        if triangles.is_empty() {
+            return Err("there are no meshes".to_string());
+        }
+
+        let gpu = Gpu::get()?;
+        let size = (triangles.len() * std::mem::size_of::<GpuTriangle>()) as u64;
+        if size > gpu.max_buffer_size() {
+            return Err(format!("{} can't fit the meshes", gpu.name));
+        }
+
+        let bvh = Bvh::build(&mut triangles, LEAF_SIZE, |(_, _, corners)| {
+            Aabb::around(*corners)
+        });
+        let nodes: Vec<GpuNode> = bvh
+            .nodes()
+            .map(|(aabb, start, count)| GpuNode {
+                min: [aabb.min.x, aabb.min.y, aabb.min.z],
+                start: start as u32,
+                max: [aabb.max.x, aabb.max.y, aabb.max.z],
+                count: count as u32,
+            })
+            .collect();
+        let gpu_triangles: Vec<GpuTriangle> = triangles
+            .iter()
+            .map(|(_, _, [a, b, c])| GpuTriangle {
+                a: [a.x, a.y, a.z, 0.0],
+                ab: [b.x - a.x, b.y - a.y, b.z - a.z, 0.0],
+                ac: [c.x - a.x, c.y - a.y, c.z - a.z, 0.0],
+            })
+            .collect();
+
+        Ok(Self {
+            gpu,
+            pipeline: gpu.pipeline(wgpu::include_wgsl!("mesh_tracer.wgsl")),
+            triangles: gpu.storage(&gpu_triangles),
+            nodes: gpu.storage(&nodes),
+            traced,
+            owners: triangles
+                .into_iter()
+                .map(|(instance, index, _)| (instance, index))
+                .collect(),
+        })
+    }
+
+    // the first hits of the rays, in the same order. none if the GPU fails,
+    // so the rays are traced on the CPU.
+    pub fn first_hits(&self, rays: &[Ray]) -> Vec<FirstHit> {
+        let gpu_rays: Vec<GpuRay> = rays
+            .iter()
+            .map(|ray| GpuRay {
+                origin: [ray.position.x, ray.position.y, ray.position.z, 0.0],
+                direction: [ray.direction.x, ray.direction.y, ray.direction.z, 0.0],
+            })
+            .collect();
+        let buffers = [&self.triangles, &self.nodes, &self.gpu.storage(&gpu_rays)];
+        let hits: Vec<u32> = match self.gpu.run(&self.pipeline, &buffers, rays.len()) {
+            Ok(hits) => hits,
+            Err(e) => {
+                static FAILED: Once = Once::new();
+                FAILED.call_once(|| println!("Tracing on the CPU, the GPU failed: {e}"));
+                return Vec::new();
+            }
+        };
+
+        rays.iter()
+            .zip(hits)
+            .map(|(ray, hit)| FirstHit {
+                position: ray.position.vector(),
+                direction: ray.direction,
+                triangle: (hit != MISS).then(|| self.owners[hit as usize]),
+            })
+            .collect()
+    }
+
+    // the first hit expect_first_hit() was given, if it's for ray. it's only
+    // used once either way. objects are the ones the tracer was made for.
+    pub fn take_first_hit<'o>(
+        &self,
+        ray: &Ray,
+        objects: &'o [Box<dyn Object>],
+    ) -> Option<KnownHit<'_, 'o>> {
+        let first_hit = NEXT
+            .with(|next| next.take())
+            .filter(|first_hit| first_hit.is_for(ray))?;
+        let hit = match first_hit.triangle {
+            None => None,
+            // right at an edge, the shader and Triangle::intersect() can
+            // disagree on whether it's hit, and a triangle seen from behind
+            // doesn't hide what's past it (see select_first_hit()), so those
+            // rays are traced on the CPU
+            Some((instance, index)) => {
+                let hit = objects
+                    .get(instance)?
+                    .intersect_triangle(ray, index)
+                    .filter(|hit| hit.entering)?;
+                Some((instance, hit))
+            }
+        };
+        Some(KnownHit {
+            traced: &self.traced,
+            hit,
+        })
+    }
+}
+
+// a camera ray's first hit, worked out on the CPU, for Environment::trace_nearest()
+pub struct KnownHit<'t, 'o> {
+    traced: &'t [bool],
+    hit: Option<(usize, Hit<'o>)>,
+}
+
+impl<'o> KnownHit<'_, 'o> {
+    // the object's hits along the ray: the first hit if it's on the object,
+    // none if the object was uploaded but the hit is on another one, and all
+    // of them if the object wasn't uploaded
+    pub fn intersect(&mut self, instance: usize, object: &'o dyn Object, ray: &Ray) -> HitVec<'o> {
+        if !self.traced.get(instance).is_some_and(|traced| *traced) {
+            return object.intersect(ray);
+        }
+        match self
+            .hit
+            .take_if(|(hit_instance, _)| *hit_instance == instance)
+        {
+            Some((_, hit)) => hitvec![hit],
+            None => hitvec![],
+        }
+    }
+}
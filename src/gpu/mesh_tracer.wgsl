@@ -0,0 +1,114 @@
+// the closest triangle each ray hits, going down the bounding volume
+// hierarchy the triangles are sorted into. see mesh_tracer.rs.
+
+struct Triangle {
+    a: vec4<f32>,
+    ab: vec4<f32>,
+    ac: vec4<f32>,
+}
+
+// as Bvh's nodes: leaves have triangles[start..start + count], the others
+// have count 0, their first child straight after them and their second at
+// start
+struct Node {
+    min: vec3<f32>,
+    start: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+struct Ray {
+    origin: vec4<f32>,
+    direction: vec4<f32>,
+}
+
+@group(0) @binding(0) var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(1) var<storage, read> nodes: array<Node>;
+@group(0) @binding(2) var<storage, read> rays: array<Ray>;
+@group(0) @binding(3) var<storage, read_write> hits: array<u32>;
+
+const MISS: u32 = 0xffffffffu;
+// rays closer to parallel with a triangle than this miss it
+const EPSILON: f32 = 1e-8;
+const FAR: f32 = 3.4e38;
+// deeper than a hierarchy of billions of triangles needs
+const STACK_SIZE: u32 = 64u;
+
+// the slab test, as Aabb::hit_by()
+fn hit_node(node: Node, origin: vec3<f32>, inverse_direction: vec3<f32>, max_t: f32) -> bool {
+    let t0 = (node.min - origin) * inverse_direction;
+    let t1 = (node.max - origin) * inverse_direction;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+    let entry = max(max(near.x, near.y), near.z);
+    let exit = min(min(far.x, far.y), far.z);
+    return entry <= exit && exit >= 0.0 && entry <= max_t;
+}
+
+// the distance along the ray to the triangle (from either side), or -1 if it
+// misses. möller-trumbore, as TrianglePacket::intersect().
+fn hit_triangle(triangle: Triangle, origin: vec3<f32>, direction: vec3<f32>) -> f32 {
+    let ab = triangle.ab.xyz;
+    let ac = triangle.ac.xyz;
+    let p = cross(direction, ac);
+    let det = dot(ab, p);
+    if abs(det) <= EPSILON {
+        return -1.0;
+    }
+    let inverse_det = 1.0 / det;
+
+    let t = origin - triangle.a.xyz;
+    let u = dot(t, p) * inverse_det;
+    let q = cross(t, ab);
+    let v = dot(direction, q) * inverse_det;
+    let distance = dot(ac, q) * inverse_det;
+    if u < 0.0 || v < 0.0 || u + v > 1.0 || distance < 0.0 {
+        return -1.0;
+    }
+    return distance;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= arrayLength(&hits) {
+        return;
+    }
+    let origin = rays[index].origin.xyz;
+    let direction = rays[index].direction.xyz;
+    // dividing by zero isn't defined in shaders, so axis-aligned rays are
+    // tilted by a hair instead
+    let safe_direction = select(direction, vec3<f32>(1e-20), direction == vec3<f32>(0.0));
+    let inverse_direction = 1.0 / safe_direction;
+
+    var closest = FAR;
+    var hit = MISS;
+    var stack: array<u32, STACK_SIZE>;
+    var size = 1u;
+    stack[0] = 0u;
+    while size > 0u {
+        size -= 1u;
+        let node_index = stack[size];
+        let node = nodes[node_index];
+        if !hit_node(node, origin, inverse_direction, closest) {
+            continue;
+        }
+        if node.count == 0u {
+            if size + 2u > STACK_SIZE {
+                break;
+            }
+            stack[size] = node.start;
+            stack[size + 1u] = node_index + 1u;
+            size += 2u;
+            continue;
+        }
+        for (var i = node.start; i < node.start + node.count; i++) {
+            let distance = hit_triangle(triangles[i], origin, direction);
+            if distance >= 0.0 && distance < closest {
+                closest = distance;
+                hit = i;
+            }
+        }
+    }
+    hits[index] = hit;
+}
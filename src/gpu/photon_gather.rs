@@ -0,0 +1,261 @@
+// Gathering the photons around camera ray hits on the GPU. Both hash grid
+// photon maps (see core::photon_grid) are uploaded as they are, flat arrays
+// and all, and a compute shader adds up the photons around every hit of a
+// tile's camera rays at once, as PhotonScene::photon_sums_near() does. The
+// CPU still does everything else, including the shading with those sums.
+//
+// Before raytracing a tile, the camera has the scene trace its camera rays
+// with prepare_camera_rays(), which finds their hits on the CPU (so each is
+// found twice) and gathers around them on the GPU. The sums wait on this
+// thread until take_summed() picks them up for the ray being raytraced.
+
+use std::{cell::RefCell, collections::VecDeque, sync::Once};
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::{
+    core::{
+        colour::Colour,
+        photon::{PackedPhoton, PhotonType},
+        photon_grid::PhotonGrid,
+        ray::Ray,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::photon_scene::{GatherFilter, PhotonSums},
+};
+
+use super::device::Gpu;
+
+// a GpuPhoton's kind
+const DIRECT: u32 = 1;
+const SHADOW: u32 = 2;
+
+// the flags in GpuSums::intensity[3]
+const LIT: u32 = 1;
+const SHADOWED: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuPhoton {
+    position: [f32; 3],
+    kind: u32,
+    incident: u32,
+    intensity: [u32; 2], // half floats, two to a u32
+    padding: u32,
+}
+
+impl GpuPhoton {
+    fn new(position: &Vertex, photon: &PackedPhoton) -> Self {
+        let [u, v] = photon.incident.map(u32::from);
+        let [r, g, b] = photon.intensity.map(u32::from);
+        Self {
+            position: [position.x, position.y, position.z],
+            kind: match photon.photon_type {
+                PhotonType::Direct => DIRECT,
+                PhotonType::Shadow => SHADOW,
+                _ => 0,
+            },
+            incident: u | v << 8,
+            intensity: [r | g << 16, b],
+            padding: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuParams {
+    cell_size: f32,
+    radius: f32,
+    gather_filter: u32,
+    direct_lighting: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuSums {
+    incident: [f32; 4],  // and the weight
+    intensity: [f32; 4], // and LIT | SHADOWED
+    direct: [f32; 4],
+}
+
+impl GpuSums {
+    fn sums(&self) -> PhotonSums {
+        let [x, y, z, weight] = self.incident;
+        let [r, g, b, flags] = self.intensity;
+        let [direct_r, direct_g, direct_b, _] = self.direct;
+        let flags = flags as u32;
+        PhotonSums {
+            weight,
+            incident: Vector::new(x, y, z),
+            intensity: Colour::new(r, g, b),
+            direct: Colour::new(direct_r, direct_g, direct_b),
+            lit: flags & LIT != 0,
+            shadowed: flags & SHADOWED != 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuGathered {
+    regular: GpuSums,
+    caustic: GpuSums,
+}
+
+// the sums from the regular and caustic photon maps around a camera ray's
+// hit, or None if it hits nothing
+struct Summed {
+    position: Vector,
+    direction: Vector,
+    sums: Option<(PhotonSums, PhotonSums)>,
+}
+
+impl Summed {
+    fn is_for(&self, ray: &Ray) -> bool {
+        let position = ray.position.vector();
+        let same = |a: &Vector, b: &Vector| a.x == b.x && a.y == b.y && a.z == b.z;
+        same(&self.position, &position) && same(&self.direction, &ray.direction)
+    }
+}
+
+thread_local! {
+    // the tile's camera rays still to be raytraced on this thread, in order
+    static SUMMED: RefCell<VecDeque<Summed>> = const { RefCell::new(VecDeque::new()) };
+}
+
+// the sums for the next camera ray raytraced on this thread, if they're for
+// ray. they're only used once either way.
+pub fn take_summed(ray: &Ray) -> Option<(PhotonSums, PhotonSums)> {
+    SUMMED
+        .with_borrow_mut(|summed| summed.pop_front())
+        .filter(|summed| summed.is_for(ray))?
+        .sums
+}
+
+pub struct PhotonGatherer {
+    gpu: &'static Gpu,
+    pipeline: wgpu::ComputePipeline,
+    params: wgpu::Buffer,
+    // bucket starts and photons of each photon map
+    regular: [wgpu::Buffer; 2],
+    caustic: [wgpu::Buffer; 2],
+}
+
+impl PhotonGatherer {
+    // a gatherer for the two photon maps, or None (saying why) if the GPU
+    // can't be used, to gather on the CPU
+    pub fn prepare(
+        regular: &PhotonGrid,
+        caustic: &PhotonGrid,
+        radius: f32,
+        filter: GatherFilter,
+        direct_lighting: bool,
+    ) -> Option<Self> {
+        match Self::new(regular, caustic, radius, filter, direct_lighting) {
+            Ok(gatherer) => {
+                println!("Gathering photons on {}", gatherer.gpu.name);
+                Some(gatherer)
+            }
+            Err(e) => {
+                println!("Gathering photons on the CPU instead of the GPU: {e}");
+                None
+            }
+        }
+    }
+
+    fn new(
+        regular: &PhotonGrid,
+        caustic: &PhotonGrid,
+        radius: f32,
+        filter: GatherFilter,
+        direct_lighting: bool,
+    ) -> Result<Self, String> {
+        // the shader only looks through the 3 cells along each axis around
+        // a hit
+        let cell_size = regular.cell_size();
+        if radius * 2.0 > cell_size || caustic.cell_size() != cell_size {
+            return Err("the photon grids' cells are too small".to_string());
+        }
+
+        let gpu = Gpu::get()?;
+        let largest = regular.photons().len().max(caustic.photons().len());
+        if (largest * std::mem::size_of::<GpuPhoton>()) as u64 > gpu.max_buffer_size() {
+            return Err(format!("{} can't fit the photon maps", gpu.name));
+        }
+
+        let upload = |grid: &PhotonGrid| {
+            let photons: Vec<GpuPhoton> = grid
+                .positions()
+                .iter()
+                .zip(grid.photons())
+                .map(|(position, photon)| GpuPhoton::new(position, photon))
+                .collect();
+            [gpu.storage(grid.bucket_starts()), gpu.storage(&photons)]
+        };
+        let params = GpuParams {
+            cell_size,
+            radius,
+            gather_filter: match filter {
+                GatherFilter::Box => 0,
+                GatherFilter::Cone => 1,
+                GatherFilter::Gaussian => 2,
+            },
+            direct_lighting: direct_lighting as u32,
+        };
+
+        Ok(Self {
+            gpu,
+            pipeline: gpu.pipeline(wgpu::include_wgsl!("photon_gather.wgsl")),
+            params: gpu.storage(&[params]),
+            regular: upload(regular),
+            caustic: upload(caustic),
+        })
+    }
+
+    // gathers around the hits of the rays, None where they hit nothing, for
+    // take_summed() to pick up in the same order. if the GPU fails, there's
+    // nothing to pick up and the photons are gathered on the CPU.
+    pub fn gather_ahead(&self, rays: &[Ray], hits: &[Option<Vertex>]) {
+        let positions: Vec<[f32; 4]> = hits
+            .iter()
+            .flatten()
+            .map(|position| [position.x, position.y, position.z, 0.0])
+            .collect();
+        let buffers = [
+            &self.params,
+            &self.regular[0],
+            &self.regular[1],
+            &self.caustic[0],
+            &self.caustic[1],
+            &self.gpu.storage(&positions),
+        ];
+        let gathered: Result<Vec<GpuGathered>, _> =
+            self.gpu.run(&self.pipeline, &buffers, positions.len());
+
+        SUMMED.with_borrow_mut(|summed| {
+            summed.clear();
+            let mut gathered = match gathered {
+                Ok(gathered) => gathered.into_iter(),
+                Err(e) => {
+                    static FAILED: Once = Once::new();
+                    FAILED.call_once(|| {
+                        println!("Gathering photons on the CPU, the GPU failed: {e}")
+                    });
+                    return;
+                }
+            };
+            summed.extend(rays.iter().zip(hits).map(|(ray, hit)| {
+                Summed {
+                    position: ray.position.vector(),
+                    direction: ray.direction,
+                    sums: hit
+                        .as_ref()
+                        .and_then(|_| gathered.next())
+                        .map(|gathered| (gathered.regular.sums(), gathered.caustic.sums())),
+                }
+            }));
+        });
+    }
+}
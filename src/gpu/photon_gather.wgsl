@@ -0,0 +1,191 @@
+// the photons around each position in the two hash grid photon maps, added
+// up as PhotonScene::photon_sums_near() does. see photon_gather.rs.
+
+struct Params {
+    cell_size: f32,
+    radius: f32,
+    gather_filter: u32,   // BOX, CONE or GAUSSIAN
+    direct_lighting: u32, // 1 to leave out what direct_light_at() works out
+}
+
+// a PackedPhoton and its position
+struct Photon {
+    position: vec3<f32>,
+    kind: u32,     // DIRECT, SHADOW or 0 for the rest
+    incident: u32, // the octahedral encoded direction, a byte for each axis
+    rg: u32,       // the half float intensity, two to a u32
+    b: u32,
+    padding: u32,
+}
+
+// PhotonSums, with the weight in incident.w and LIT | SHADOWED in
+// intensity.w
+struct Sums {
+    incident: vec4<f32>,
+    intensity: vec4<f32>,
+    direct: vec4<f32>,
+}
+
+struct Gathered {
+    regular: Sums,
+    caustic: Sums,
+}
+
+@group(0) @binding(0) var<storage, read> params: Params;
+@group(0) @binding(1) var<storage, read> regular_buckets: array<u32>;
+@group(0) @binding(2) var<storage, read> regular_photons: array<Photon>;
+@group(0) @binding(3) var<storage, read> caustic_buckets: array<u32>;
+@group(0) @binding(4) var<storage, read> caustic_photons: array<Photon>;
+@group(0) @binding(5) var<storage, read> positions: array<vec4<f32>>;
+@group(0) @binding(6) var<storage, read_write> gathered: array<Gathered>;
+
+const REGULAR: u32 = 0u;
+const CAUSTIC: u32 = 1u;
+
+const BOX: u32 = 0u;
+const CONE: u32 = 1u;
+const GAUSSIAN: u32 = 2u;
+
+const DIRECT: u32 = 1u;
+const SHADOW: u32 = 2u;
+
+const LIT: u32 = 1u;
+const SHADOWED: u32 = 2u;
+
+// as GatherFilter::weight()
+const CONE_K: f32 = 1.1;
+const ALPHA: f32 = 0.918;
+const BETA: f32 = 1.953;
+
+// storage buffers can't be passed to functions, so the photon maps are
+// picked by number
+fn bucket_count(map: u32) -> u32 {
+    if map == REGULAR {
+        return arrayLength(&regular_buckets) - 1u;
+    }
+    return arrayLength(&caustic_buckets) - 1u;
+}
+
+fn bucket_start(map: u32, bucket: u32) -> u32 {
+    if map == REGULAR {
+        return regular_buckets[bucket];
+    }
+    return caustic_buckets[bucket];
+}
+
+fn photon(map: u32, index: u32) -> Photon {
+    if map == REGULAR {
+        return regular_photons[index];
+    }
+    return caustic_photons[index];
+}
+
+fn cell(position: vec3<f32>) -> vec3<i32> {
+    return vec3<i32>(floor(position / params.cell_size));
+}
+
+// as bucket() in photon_grid.rs
+fn bucket(cell: vec3<i32>, count: u32) -> u32 {
+    let c = bitcast<vec3<u32>>(cell);
+    let hash = (c.x * 73856093u) ^ (c.y * 19349663u) ^ (c.z * 83492791u);
+    return hash & (count - 1u);
+}
+
+fn weight(distance_sqrd: f32) -> f32 {
+    let radius = params.radius;
+    // the gather is a box, but the filters are round
+    if params.gather_filter != BOX && distance_sqrd > radius * radius {
+        return 0.0;
+    }
+    if params.gather_filter == CONE {
+        return 1.0 - sqrt(distance_sqrd) / (CONE_K * radius);
+    }
+    if params.gather_filter == GAUSSIAN {
+        let falloff = 1.0 - exp(-BETA * distance_sqrd / (2.0 * radius * radius));
+        return ALPHA * (1.0 - falloff / (1.0 - exp(-BETA)));
+    }
+    return 1.0;
+}
+
+// as decode_direction() in photon.rs
+fn decode_direction(encoded: u32) -> vec3<f32> {
+    let bytes = vec2<u32>(encoded & 0xffu, (encoded >> 8u) & 0xffu);
+    var uv = vec2<f32>(bytes) / 255.0 * 2.0 - 1.0;
+    let z = 1.0 - abs(uv.x) - abs(uv.y);
+    if z < 0.0 {
+        let signs = select(vec2<f32>(-1.0), vec2<f32>(1.0), uv >= vec2<f32>(0.0));
+        uv = (1.0 - abs(uv.yx)) * signs;
+    }
+    return normalize(vec3<f32>(uv, z));
+}
+
+fn sum_photons(map: u32, position: vec3<f32>) -> Sums {
+    let radius = params.radius;
+    let count = bucket_count(map);
+    let low = cell(position - radius);
+    let high = cell(position + radius);
+
+    var sums = Sums(vec4<f32>(0.0), vec4<f32>(0.0), vec4<f32>(0.0));
+    var flags = 0u;
+    // the radius is at most half a cell, so the box spans 3 cells along each
+    // axis at most. different cells can hash to the same bucket, so don't
+    // visit a bucket twice.
+    var visited: array<u32, 27>;
+    var visited_count = 0u;
+    for (var x = low.x; x <= min(high.x, low.x + 2); x++) {
+        for (var y = low.y; y <= min(high.y, low.y + 2); y++) {
+            for (var z = low.z; z <= min(high.z, low.z + 2); z++) {
+                let bucket = bucket(vec3<i32>(x, y, z), count);
+                var seen = false;
+                for (var i = 0u; i < visited_count; i++) {
+                    seen = seen || visited[i] == bucket;
+                }
+                if seen {
+                    continue;
+                }
+                visited[visited_count] = bucket;
+                visited_count += 1u;
+
+                let end = bucket_start(map, bucket + 1u);
+                for (var i = bucket_start(map, bucket); i < end; i++) {
+                    let photon = photon(map, i);
+                    let offset = photon.position - position;
+                    if any(abs(offset) > vec3<f32>(radius)) {
+                        continue;
+                    }
+                    let weight = weight(dot(offset, offset));
+                    sums.incident.w += weight;
+                    if photon.kind == DIRECT {
+                        flags |= LIT;
+                    }
+                    if photon.kind == SHADOW {
+                        flags |= SHADOWED;
+                    }
+
+                    let direct = photon.kind != 0u;
+                    if params.direct_lighting != 0u && direct {
+                        continue;
+                    }
+                    let intensity = vec3<f32>(unpack2x16float(photon.rg), unpack2x16float(photon.b).x);
+                    sums.incident += vec4<f32>(decode_direction(photon.incident) * weight, 0.0);
+                    sums.intensity += vec4<f32>(intensity * weight, 0.0);
+                    if direct {
+                        sums.direct += vec4<f32>(intensity * weight, 0.0);
+                    }
+                }
+            }
+        }
+    }
+    sums.intensity.w = f32(flags);
+    return sums;
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= arrayLength(&gathered) {
+        return;
+    }
+    let position = positions[index].xyz;
+    gathered[index] = Gathered(sum_photons(REGULAR, position), sum_photons(CAUSTIC, position));
+}
@@ -0,0 +1,164 @@
+// A raytracer and photon mapper.
+//
+// The quickest way in is to load a scene file:
+//
+//     let scene = SceneFile::from_path(&parse_path("scene.txt"), &[], 0.0)?;
+//     let mut environment = scene.environment;
+//     let framebuffer = scene.camera.render(environment.as_mut(), &mut |_| {});
+//
+// or build one in code with a SceneBuilder (see builder.rs).
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+pub mod core {
+    pub mod animation;
+    pub mod bloom;
+    pub mod cancel;
+    pub mod colour;
+    pub mod compare;
+    pub mod denoise;
+    pub mod depth_of_field;
+    pub mod error;
+    pub mod framebuffer;
+    pub mod hit;
+    pub mod onb;
+    pub mod photon;
+    pub mod photon_grid;
+    pub mod photon_tree;
+    pub mod platform;
+    pub mod random;
+    pub mod ray;
+    pub mod resolve;
+    pub mod sampler;
+    pub mod stats;
+    pub mod tex_coords;
+    pub mod transform;
+    pub mod vector;
+    pub mod vertex;
+}
+
+pub mod environments {
+    pub mod ambient_occlusion;
+    pub mod environment;
+    pub mod irradiance_cache;
+    pub mod medium;
+    pub mod photon_bake;
+    pub mod photon_scene;
+    pub mod photon_stats;
+    pub mod scene;
+}
+
+pub mod cameras {
+    pub mod camera;
+    pub mod full_camera;
+}
+
+pub mod materials {
+    pub mod compound_material;
+    pub mod depth_material;
+    pub mod falsecolour_material;
+    pub mod global_material;
+    pub mod hair_material;
+    pub mod material;
+    pub mod occlusion_material;
+    pub mod phong_material;
+    pub mod texture;
+    pub mod uv_checker_material;
+    pub mod vertex_colour_material;
+    pub mod weathered_material;
+    pub mod wireframe_material;
+}
+
+pub mod lights {
+    pub mod directional_light;
+    pub mod directional_point_light;
+    pub mod light;
+    pub mod point_light;
+    pub mod projector_light;
+    pub mod sun_sky;
+}
+
+pub mod objects {
+    pub mod animated_object;
+    pub mod bezier_object;
+    pub mod bvh;
+    pub mod csg_object;
+    pub mod cuboid_object;
+    pub mod curve_object;
+    pub mod fractal_object;
+    pub mod object;
+    pub mod plane_object;
+    pub mod point_cloud_object;
+    pub mod polymesh_object;
+    pub mod portal_object;
+    pub mod quadratic_object;
+    pub mod sphere_object;
+    pub mod triangle_object;
+    pub mod triangle_packet;
+    pub mod visibility_object;
+}
+
+#[cfg(feature = "gpu")]
+pub mod gpu {
+    pub mod device;
+    pub mod mesh_tracer;
+    pub mod photon_gather;
+}
+
+pub mod archive;
+pub mod builder;
+pub mod distributed;
+pub mod scene_file;
+
+pub use builder::SceneBuilder;
+pub use cameras::{camera::Camera, full_camera::FullCamera};
+pub use core::{colour::Colour, framebuffer::FrameBuffer, vector::Vector, vertex::Vertex};
+pub use environments::{environment::Environment, photon_scene::PhotonScene, scene::Scene};
+pub use lights::light::Light;
+pub use materials::material::Material;
+pub use objects::object::Object;
+pub use scene_file::SceneFile;
+
+// resolve a path relative to the working directory
+pub fn parse_path(path: &str) -> PathBuf {
+    if Path::new(path).is_absolute() {
+        return PathBuf::from(path);
+    }
+
+    let mut parsed = std::env::current_dir().unwrap();
+    for part in path.split('/') {
+        parsed.push(part);
+    }
+    parsed
+}
+
+// render the contents of a scene file straight to memory, as 8 bit RGBA rows
+// from the top. nothing touches the filesystem or ffmpeg, so with
+// --no-default-features (no threads) this works on wasm32, e.g. in a browser.
+// scenes with models or textures still need to load them from files.
+pub fn render_to_rgba(scene_text: &str, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let scene = SceneFile::from_contents(scene_text.to_string(), &[], 0.0)
+        .map_err(|e| format!("Failed to build scene!\n{e}"))?;
+
+    let mut camera = scene.camera;
+    camera.width = width;
+    camera.height = height;
+
+    let mut environment = scene.environment;
+    let mut framebuffer = camera.render(environment.as_mut(), &mut |_| {});
+    if camera.denoise_passes > 0 {
+        framebuffer = core::denoise::denoise(&framebuffer, camera.denoise_passes);
+    }
+    if let Some(depth_of_field) = &camera.depth_of_field {
+        framebuffer = depth_of_field.apply(&framebuffer);
+    }
+    if let Some(bloom) = &camera.bloom {
+        framebuffer = bloom.apply(&framebuffer);
+    }
+    if camera.show_gizmos {
+        camera.draw_gizmos(&mut framebuffer, environment.as_ref());
+    }
+    Ok(framebuffer.to_rgba(camera.colour_space))
+}
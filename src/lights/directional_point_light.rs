@@ -1,18 +1,21 @@
-use std::time::Instant;
-
-use rand::seq::SliceRandom;
+use std::ops::Range;
 
 use crate::{
     core::{
+        cancel,
         colour::Colour,
+        onb::Onb,
         photon::{InFlightPhoton, Photon, PhotonType},
+        platform::Instant,
+        random,
         vector::Vector,
         vertex::Vertex,
     },
     environments::photon_scene::PhotonScene,
+    objects::object::BoundingSphere,
 };
 
-use super::light::{Light, PhotonLight};
+use super::light::{cross_gizmo, towards_targets, Light, PhotonLight};
 
 pub struct DPLight {
     position: Vertex,
@@ -49,33 +52,80 @@ impl Light for DPLight {
         Some(self.intensity * dot)
     }
 
+    fn distance(&self, surface: &Vertex) -> f32 {
+        self.position.vector_to(surface).length()
+    }
+
+    fn gizmo(&self) -> Vec<(Vertex, Vertex)> {
+        let mut lines = cross_gizmo(&self.position, 0.2);
+
+        // arrow along the light direction
+        let direction = self.direction.normalised();
+        let tip = self.position.clone() + direction;
+        lines.push((self.position.clone(), tip.clone()));
+
+        // the light covers a hemisphere, so its cone is a circle around the
+        // arrow at 90 degrees. draw it with spokes from the tip
+        let Onb {
+            tangent: u,
+            bitangent: v,
+            ..
+        } = Onb::from_normal(&direction);
+
+        let segments = 16;
+        let point_at = |i: u32| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            self.position.clone() + (u * angle.cos() + v * angle.sin()) * 0.5
+        };
+        for i in 0..segments {
+            lines.push((point_at(i), point_at(i + 1)));
+            if i % 4 == 0 {
+                lines.push((tip.clone(), point_at(i)));
+            }
+        }
+
+        lines
+    }
+
     fn photon_light(self: Box<Self>) -> Box<dyn PhotonLight> {
         self
     }
 }
 
 impl PhotonLight for DPLight {
+    fn power(&self) -> f32 {
+        // only shoots photons into the hemisphere it faces
+        self.intensity.sum() * 2.0 * std::f32::consts::PI
+    }
+
     fn shoot_regular_photons(
         &self,
         scene: &PhotonScene,
-        num_photons: u32,
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon> {
-        let mut photons = Vec::with_capacity(num_photons as usize);
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
         let start = Instant::now();
 
-        for i in 0..num_photons {
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
             let direction = Vector::random_on_surface(self.direction);
 
             let photon = InFlightPhoton::new(
                 self.position.clone(),
                 direction.normalised(),
                 self.intensity,
-                PhotonType::Colour,
+                PhotonType::Direct,
             );
 
             let traced_photons = scene.photontrace(photon);
-            photons.extend(traced_photons);
+            shot_photons.extend(traced_photons);
 
             // print progress/ETA
             if first_thread && (i % 10000 == 0 || i == num_photons - 1) {
@@ -91,38 +141,43 @@ impl PhotonLight for DPLight {
             println!();
         }
 
-        photons
+        shot_photons
     }
 
     fn shoot_caustic_photons<'a>(
         &'a self,
         scene: &'a PhotonScene,
-        caustic_photons: &[Photon],
-        num_photons: u32,
+        targets: &[BoundingSphere],
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon> {
-        let mut photons = Vec::with_capacity(num_photons as usize);
-        let mut rng = rand::thread_rng();
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
         let start = Instant::now();
 
-        for i in 0..num_photons {
-            // pick a random existing caustic photon
-            let caustic_photon = caustic_photons.choose(&mut rng).unwrap();
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
 
-            // generate a random offset vector, of length 0.1
-            let offset = Vector::random() * 0.1;
-            let light_to_photon = self.position.vector_to(&caustic_photon.position);
-            let direction = light_to_photon + offset;
+            // only the hemisphere it faces is lit
+            let direction = towards_targets(&self.position, targets);
+            if direction.dot(&self.direction) <= 0.0 {
+                continue;
+            }
 
             let photon = InFlightPhoton::new(
                 self.position.clone(),
                 direction.normalised(),
                 self.intensity,
-                PhotonType::Caustic,
+                // not caustic until it reflects or refracts
+                PhotonType::Direct,
             );
 
             let traced_photons = scene.photontrace(photon);
-            photons.extend(traced_photons);
+            shot_photons.extend(traced_photons);
 
             // print progress/ETA
             if first_thread && i % 10000 == 0 {
@@ -138,6 +193,6 @@ impl PhotonLight for DPLight {
             println!();
         }
 
-        photons
+        shot_photons
     }
 }
@@ -1,6 +1,11 @@
+use std::ops::Range;
+
+use rand::seq::SliceRandom;
+
 use crate::{
-    core::{colour::Colour, photon::Photon, vector::Vector, vertex::Vertex},
+    core::{colour::Colour, photon::Photon, platform, random, vector::Vector, vertex::Vertex},
     environments::photon_scene::PhotonScene,
+    objects::object::BoundingSphere,
 };
 
 pub trait Light: Send + Sync {
@@ -11,6 +16,24 @@ pub trait Light: Send + Sync {
     // Get the intensity of the light in the direction of the surface
     fn get_intensity(&self, surface: &Vertex) -> Option<Colour>;
 
+    // how far light travels from the light to the surface, for time-of-flight
+    // rendering. lights without a position reach every surface at once.
+    fn distance(&self, _surface: &Vertex) -> f32 {
+        0.0
+    }
+
+    // line segments outlining the light, drawn over the image when debugging
+    // the scene layout. lights without a position have nothing to draw.
+    fn gizmo(&self) -> Vec<(Vertex, Vertex)> {
+        Vec::new()
+    }
+
+    // light seen along a ray that misses everything, e.g. from a sky. most
+    // lights can't be seen that way.
+    fn background(&self, _direction: &Vector) -> Colour {
+        Colour::black()
+    }
+
     // You will need additional light methods to support Photon-mapping.
 
     fn photon_light(self: Box<Self>) -> Box<dyn PhotonLight> {
@@ -19,71 +42,104 @@ pub trait Light: Send + Sync {
 }
 
 pub trait PhotonLight: Light {
+    // how much light it gives off in total (its intensity, over every
+    // direction it shoots photons in), for sharing photons between lights
+    fn power(&self) -> f32;
+
+    // stream tells this batch of photons apart from other lights' and other
+    // passes', for seeding (see core::random). with caustic_targets, only
+    // shoots towards them (see shoot_caustic_photons).
     fn shoot_photons_mt(
         &self,
         scene: &PhotonScene,
         num_photons: u32,
-        caustic_photons: Option<&[Photon]>,
+        caustic_targets: Option<&[BoundingSphere]>,
+        stream: u32,
     ) -> Vec<Vec<Photon>> {
-        let num_threads = std::thread::available_parallelism().map_or(4, |n| n.get()) as u32;
+        let num_threads = platform::num_threads() as u32;
         let photons_per_thread = num_photons / num_threads;
         let extra_photons = num_photons % num_threads;
         println!("Spawning {num_threads} threads to shoot {photons_per_thread} photons each... ({extra_photons} extra)");
 
-        std::thread::scope(|scope| {
-            let mut threads = Vec::new();
-
-            for thread_index in 0..num_threads {
-                let mut num_photons = photons_per_thread;
-                if thread_index == num_threads - 1 {
-                    num_photons += extra_photons;
-                }
-
-                let first_thread = thread_index == 0;
-                let thread_fn = move || {
-                    if let Some(caustic_photons) = caustic_photons {
-                        self.shoot_caustic_photons(
-                            scene,
-                            caustic_photons,
-                            num_photons,
-                            first_thread,
-                        )
-                    } else {
-                        self.shoot_regular_photons(scene, num_photons, first_thread)
-                    }
-                };
-
-                let thread = scope.spawn(thread_fn);
-                threads.push(thread);
-            }
+        let mut thread_photons = vec![photons_per_thread; num_threads as usize];
+        thread_photons[num_threads as usize - 1] += extra_photons;
 
-            let mut photons = Vec::new();
-
-            for (i, threads) in threads.into_iter().enumerate() {
-                photons.push(threads.join().unwrap());
-                print!(
-                    "{i}/{num_threads} threads finished shooting photons\r",
-                    i = i + 1
-                )
+        // each thread shoots the next range of photon indices
+        let jobs = thread_photons
+            .into_iter()
+            .scan(0, |start, num_photons| {
+                let range = *start..*start + num_photons;
+                *start += num_photons;
+                Some(range)
+            })
+            .enumerate()
+            .collect();
+        let photons = platform::map_parallel(jobs, |(thread_index, photons)| {
+            platform::pin_thread(thread_index);
+            let first_thread = thread_index == 0;
+            if let Some(targets) = caustic_targets {
+                self.shoot_caustic_photons(scene, targets, stream, photons, first_thread)
+            } else {
+                self.shoot_regular_photons(scene, stream, photons, first_thread)
             }
-            println!();
+        });
+        println!("{num_threads}/{num_threads} threads finished shooting photons");
 
-            photons
-        })
+        photons
     }
 
+    // implementations reseed with random::key(stream, index) before shooting
+    // each photon in the range, so results don't depend on the thread count
     fn shoot_regular_photons<'a>(
         &'a self,
         scene: &'a PhotonScene,
-        num_photons: u32,
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon>;
 
+    // like shoot_regular_photons, but only towards the targets: spheres
+    // around the mirrors and glass in the scene, never empty
     fn shoot_caustic_photons<'a>(
         &'a self,
         scene: &'a PhotonScene,
-        caustic_photons: &[Photon],
-        num_photons: u32,
+        targets: &[BoundingSphere],
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon>;
 }
+
+// a random direction from position towards one of the targets, each picked
+// by how much of the view it takes up. from inside one, any direction.
+pub fn towards_targets(position: &Vertex, targets: &[BoundingSphere]) -> Vector {
+    let extents: Vec<(&BoundingSphere, Option<f32>)> = targets
+        .iter()
+        .map(|target| (target, target.cos_extent(position)))
+        .collect();
+    // the solid angle of each, over 2 pi
+    let picked = random::with_rng(|rng| {
+        extents
+            .choose_weighted(rng, |(_, cos)| cos.map_or(2.0, |cos| 1.0 - cos))
+            .ok()
+    });
+
+    match picked {
+        Some((target, Some(cos))) => {
+            Vector::random_in_cone(position.vector_to(&target.centre).normalised(), *cos)
+        }
+        _ => Vector::random(),
+    }
+}
+
+// three short lines crossing at a point
+pub fn cross_gizmo(position: &Vertex, size: f32) -> Vec<(Vertex, Vertex)> {
+    [
+        Vector::new(size, 0.0, 0.0),
+        Vector::new(0.0, size, 0.0),
+        Vector::new(0.0, 0.0, size),
+    ]
+    .into_iter()
+    .map(|offset| (position.clone() - offset, position.clone() + offset))
+    .collect()
+}
@@ -1,18 +1,22 @@
-use std::time::Instant;
+use std::ops::Range;
 
-use rand::{distributions::Uniform, seq::SliceRandom, Rng};
+use rand::{distributions::Uniform, Rng};
 
 use crate::{
     core::{
+        cancel,
         colour::Colour,
         photon::{InFlightPhoton, Photon, PhotonType},
+        platform::Instant,
+        random,
         vector::Vector,
         vertex::Vertex,
     },
     environments::photon_scene::PhotonScene,
+    objects::object::BoundingSphere,
 };
 
-use super::light::{Light, PhotonLight};
+use super::light::{cross_gizmo, towards_targets, Light, PhotonLight};
 
 pub struct PointLight {
     position: Vertex,
@@ -38,27 +42,46 @@ impl Light for PointLight {
         Some(self.intensity)
     }
 
+    fn distance(&self, surface: &Vertex) -> f32 {
+        self.position.vector_to(surface).length()
+    }
+
+    fn gizmo(&self) -> Vec<(Vertex, Vertex)> {
+        cross_gizmo(&self.position, 0.2)
+    }
+
     fn photon_light(self: Box<Self>) -> Box<dyn PhotonLight> {
         self
     }
 }
 
 impl PhotonLight for PointLight {
+    fn power(&self) -> f32 {
+        // shines in every direction
+        self.intensity.sum() * 4.0 * std::f32::consts::PI
+    }
+
     fn shoot_regular_photons(
         &self,
         scene: &PhotonScene,
-        num_photons: u32,
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon> {
-        let mut photons = Vec::with_capacity(num_photons as usize);
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
 
-        let mut rng = rand::thread_rng();
         let distribution = Uniform::from(-1.0..1.0);
 
         let start = Instant::now();
 
-        for i in 0..num_photons {
-            let direction = loop {
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
+            let direction = random::with_rng(|rng| loop {
                 let direction = Vector::new(
                     rng.sample(distribution),
                     rng.sample(distribution),
@@ -67,17 +90,17 @@ impl PhotonLight for PointLight {
                 if direction.len_sqrd() <= 1.0 {
                     break direction;
                 }
-            };
+            });
 
             let photon = InFlightPhoton::new(
                 self.position.clone(),
                 direction.normalised(),
                 self.intensity,
-                PhotonType::Colour,
+                PhotonType::Direct,
             );
 
             let traced_photons = scene.photontrace(photon);
-            photons.extend(traced_photons);
+            shot_photons.extend(traced_photons);
 
             // print progress/ETA
             if first_thread && i % 10000 == 0 {
@@ -93,42 +116,40 @@ impl PhotonLight for PointLight {
             println!();
         }
 
-        photons
+        shot_photons
     }
 
     fn shoot_caustic_photons<'a>(
         &'a self,
         scene: &'a PhotonScene,
-        caustic_photons: &[Photon],
-        num_photons: u32,
+        targets: &[BoundingSphere],
+        stream: u32,
+        photons: Range<u32>,
         first_thread: bool,
     ) -> Vec<Photon> {
-        if caustic_photons.is_empty() {
-            return Vec::new();
-        }
-
-        let mut photons = Vec::with_capacity(num_photons as usize);
-        let mut rng = rand::thread_rng();
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
 
         let start = Instant::now();
 
-        for i in 0..num_photons {
-            // pick a random existing caustic photon
-            let caustic_photon = caustic_photons.choose(&mut rng).unwrap();
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
 
-            // generate a random offset vector, of length 0.1
-            let offset = Vector::random() * 0.1;
-            let direction = caustic_photon.incident + offset;
+            let direction = towards_targets(&self.position, targets);
 
             let photon = InFlightPhoton::new(
                 self.position.clone(),
                 direction.normalised(),
                 self.intensity,
-                PhotonType::Caustic,
+                // not caustic until it reflects or refracts
+                PhotonType::Direct,
             );
 
             let traced_photons = scene.photontrace(photon);
-            photons.extend(traced_photons);
+            shot_photons.extend(traced_photons);
 
             // print progress/ETA
             if first_thread && i % 10000 == 0 {
@@ -144,6 +165,6 @@ impl PhotonLight for PointLight {
             println!();
         }
 
-        photons
+        shot_photons
     }
 }
@@ -0,0 +1,244 @@
+use std::ops::Range;
+
+use rand::{distributions::Uniform, Rng};
+
+use crate::{
+    core::{
+        cancel,
+        colour::Colour,
+        photon::{InFlightPhoton, Photon, PhotonType},
+        platform::Instant,
+        random,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::photon_scene::PhotonScene,
+    materials::texture::Image,
+    objects::object::BoundingSphere,
+};
+
+use super::light::{cross_gizmo, towards_targets, Light, PhotonLight};
+
+// a light that projects an image, like a slide projector or sunlight
+// through a stained glass window. it only lights what's inside its frame,
+// with the colour of the image there.
+pub struct ProjectorLight {
+    position: Vertex,
+    forward: Vector,
+    right: Vector,
+    up: Vector,
+    // how far right and up the edges of the frame are, one unit forward
+    half_width: f32,
+    half_height: f32,
+    image: Image,
+    intensity: Colour,
+}
+
+impl ProjectorLight {
+    // fov is the angle across the image's width, in degrees. up is roughly
+    // which way is up in the image.
+    pub fn new(
+        position: Vertex,
+        direction: Vector,
+        up: Vector,
+        fov: f32,
+        image: Image,
+        intensity: Colour,
+    ) -> Box<Self> {
+        let forward = direction.normalised();
+        let right = up.cross(&forward).normalised();
+        let up = forward.cross(&right);
+        let half_width = (fov.to_radians() / 2.0).tan();
+        let half_height = half_width * image.aspect();
+
+        Box::new(Self {
+            position,
+            forward,
+            right,
+            up,
+            half_width,
+            half_height,
+            image,
+            intensity,
+        })
+    }
+
+    // where a direction from the light goes through the image, from 0 to 1
+    // across and down it, or None if it's outside the frame
+    fn project(&self, direction: &Vector) -> Option<(f32, f32)> {
+        let forward = direction.dot(&self.forward);
+        if forward <= 0.0 {
+            return None;
+        }
+        let x = direction.dot(&self.right) / forward / self.half_width;
+        let y = direction.dot(&self.up) / forward / self.half_height;
+        if x.abs() > 1.0 || y.abs() > 1.0 {
+            return None;
+        }
+        Some(((x + 1.0) / 2.0, (1.0 - y) / 2.0))
+    }
+
+    // the direction through a point in the image, from 0 to 1 across and
+    // down it
+    fn unproject(&self, x: f32, y: f32) -> Vector {
+        let right = self.right * ((x * 2.0 - 1.0) * self.half_width);
+        let up = self.up * ((1.0 - y * 2.0) * self.half_height);
+        (self.forward + right + up).normalised()
+    }
+
+    fn colour_towards(&self, direction: &Vector) -> Option<Colour> {
+        let (x, y) = self.project(direction)?;
+        Some(self.intensity * self.image.sample(x, y))
+    }
+}
+
+impl Light for ProjectorLight {
+    fn get_direction(&self, surface: &Vertex) -> Option<Vector> {
+        let direction = self.position.vector_to(surface);
+        self.project(&direction)?;
+        Some(direction.normalised())
+    }
+
+    fn get_intensity(&self, surface: &Vertex) -> Option<Colour> {
+        let direction = self.position.vector_to(surface);
+        Some(self.colour_towards(&direction).unwrap_or(Colour::black()))
+    }
+
+    fn distance(&self, surface: &Vertex) -> f32 {
+        self.position.vector_to(surface).length()
+    }
+
+    fn gizmo(&self) -> Vec<(Vertex, Vertex)> {
+        let mut lines = cross_gizmo(&self.position, 0.2);
+
+        // the frame one unit in front of the light, and lines out to it
+        let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| {
+            let direction = self.unproject(x, y);
+            self.position.clone() + direction * (1.0 / direction.dot(&self.forward))
+        });
+        for i in 0..4 {
+            lines.push((self.position.clone(), corners[i].clone()));
+            lines.push((corners[i].clone(), corners[(i + 1) % 4].clone()));
+        }
+
+        lines
+    }
+
+    fn photon_light(self: Box<Self>) -> Box<dyn PhotonLight> {
+        self
+    }
+}
+
+impl PhotonLight for ProjectorLight {
+    fn power(&self) -> f32 {
+        // the solid angle of the frame, lit as bright as the image is on
+        // average
+        let solid_angle =
+            4.0 * (self.half_width.atan().sin() * self.half_height.atan().sin()).asin();
+        (self.intensity * self.image.average()).sum() * solid_angle
+    }
+
+    fn shoot_regular_photons(
+        &self,
+        scene: &PhotonScene,
+        stream: u32,
+        photons: Range<u32>,
+        first_thread: bool,
+    ) -> Vec<Photon> {
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
+
+        let distribution = Uniform::from(0.0..1.0);
+
+        let start = Instant::now();
+
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
+            // somewhere in the frame, carrying the colour of the image there
+            let (x, y) =
+                random::with_rng(|rng| (rng.sample(distribution), rng.sample(distribution)));
+
+            let photon = InFlightPhoton::new(
+                self.position.clone(),
+                self.unproject(x, y),
+                self.intensity * self.image.sample(x, y),
+                PhotonType::Direct,
+            );
+
+            let traced_photons = scene.photontrace(photon);
+            shot_photons.extend(traced_photons);
+
+            // print progress/ETA
+            if first_thread && (i % 10000 == 0 || i == num_photons - 1) {
+                let progress = (i + 1) as f32 / num_photons as f32;
+                let elapsed = start.elapsed().as_secs_f32();
+                let eta = elapsed / progress - elapsed;
+                let percent = (progress * 100.0) as u32;
+                print!("{percent}% photons shot, elapsed {elapsed:.2}s, ETA {eta:.2}s\t\r");
+            }
+        }
+
+        if first_thread {
+            println!();
+        }
+
+        shot_photons
+    }
+
+    fn shoot_caustic_photons<'a>(
+        &'a self,
+        scene: &'a PhotonScene,
+        targets: &[BoundingSphere],
+        stream: u32,
+        photons: Range<u32>,
+        first_thread: bool,
+    ) -> Vec<Photon> {
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
+        let start = Instant::now();
+
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
+            let direction = towards_targets(&self.position, targets);
+
+            // the projector doesn't shine outside its frame
+            let Some(intensity) = self.colour_towards(&direction) else {
+                continue;
+            };
+
+            let photon = InFlightPhoton::new(
+                self.position.clone(),
+                direction.normalised(),
+                intensity,
+                // not caustic until it reflects or refracts
+                PhotonType::Direct,
+            );
+
+            let traced_photons = scene.photontrace(photon);
+            shot_photons.extend(traced_photons);
+
+            // print progress/ETA
+            if first_thread && i % 10000 == 0 {
+                let progress = i as f32 / num_photons as f32;
+                let elapsed = start.elapsed().as_secs_f32();
+                let eta = elapsed / progress - elapsed;
+                let percent = (progress * 100.0) as u32;
+                print!("{percent}% photons shot, elapsed {elapsed:.2}s, ETA {eta:.2}s\t\r");
+            }
+        }
+
+        if first_thread {
+            println!();
+        }
+
+        shot_photons
+    }
+}
@@ -0,0 +1,422 @@
+// The sun and sky light is a directional sun plus a sky around the scene,
+// seen wherever rays miss everything. The sky is Preetham et al.'s analytic
+// daylight model ("A Practical Analytic Model for Daylight", 1999), and the
+// sun is dimmed and reddened by the air it shines through. In photon scenes,
+// the sun and sky shoot their photons through windows (rectangles marking
+// where the outside can be seen from), so interiors lit from outside don't
+// waste photons on the outside of the walls.
+
+use std::{f32::consts::TAU, ops::Range};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{
+    core::{
+        cancel,
+        colour::Colour,
+        onb::Onb,
+        photon::{InFlightPhoton, Photon, PhotonType},
+        platform::Instant,
+        random,
+        sampler,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::photon_scene::PhotonScene,
+    objects::{object::BoundingSphere, portal_object::Quad},
+};
+
+use super::light::{Light, PhotonLight};
+
+// rgb wavelengths in micrometres, for how much air scatters each
+const WAVELENGTHS: [f32; 3] = [0.65, 0.55, 0.45];
+
+// the lowest the sky is worked out at, as the cosine of the angle from the
+// zenith. below it (and below the horizon) the sky stays the same colour.
+const MIN_COS_THETA: f32 = 0.01;
+
+// how many directions (up and around) the sky is sampled in, to work out
+// how much light comes in through a window
+const SKY_SAMPLES: (u32, u32) = (8, 32);
+
+pub struct SunSky {
+    towards_sun: Vector,
+    theta_sun: f32, // angle from the zenith to the sun
+    sun: Colour,
+    sky_intensity: f32, // brightness of the zenith
+    // perez distribution coefficients (A to E) for luminance and the x and
+    // y chromaticities, and their values at the zenith
+    perez: [[f32; 5]; 3],
+    zenith: [f32; 3],
+    windows: Vec<Window>,
+}
+
+// a window's normal points the way light comes in through it
+struct Window {
+    quad: Quad,
+    sun: Colour, // light from the sun on the window
+    sky: Colour, // and from the sky
+}
+
+impl Window {
+    fn power(&self) -> f32 {
+        (self.sun + self.sky).sum() * self.quad.width * self.quad.height
+    }
+
+    // how many of the photons through it come from the sun
+    fn sun_share(&self) -> f32 {
+        let total = (self.sun + self.sky).sum();
+        match total > 0.0 {
+            true => self.sun.sum() / total,
+            false => 0.0,
+        }
+    }
+
+    fn random_point(&self) -> Vertex {
+        let (u, v) = sampler::next_2d();
+        let (x, y) = (u - 0.5, v - 0.5);
+        self.quad.centre.clone()
+            + self.quad.right * (x * self.quad.width)
+            + self.quad.up * (y * self.quad.height)
+    }
+
+    // where a ray from position going along direction crosses the window, if
+    // it does
+    fn crossing(&self, position: &Vertex, direction: &Vector) -> Option<Vertex> {
+        let facing = self.quad.normal.dot(direction);
+        if facing == 0.0 {
+            return None;
+        }
+        let to_centre = position.vector_to(&self.quad.centre);
+        let t = self.quad.normal.dot(&to_centre) / facing;
+        let crossing = position.clone() + *direction * t;
+        let offset = self.quad.centre.vector_to(&crossing);
+        let inside = offset.dot(&self.quad.right).abs() <= self.quad.width / 2.0
+            && offset.dot(&self.quad.up).abs() <= self.quad.height / 2.0;
+        (t > 0.0 && inside).then_some(crossing)
+    }
+}
+
+impl SunSky {
+    // azimuth is measured around the y axis from +z towards +x, and
+    // elevation up from the horizon, both in degrees. turbidity is how hazy
+    // the air is, from about 2 (clear) to 10 (hazy).
+    pub fn new(
+        azimuth: f32,
+        elevation: f32,
+        turbidity: f32,
+        colour: Colour,
+        sky_intensity: f32,
+    ) -> Box<Self> {
+        let (azimuth, elevation) = (azimuth.to_radians(), elevation.to_radians());
+        let towards_sun = Vector::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            elevation.cos() * azimuth.cos(),
+        );
+        let theta_sun = std::f32::consts::FRAC_PI_2 - elevation;
+
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // the sky is scaled so its zenith is sky_intensity bright, so only
+        // the zenith's chromaticity is needed
+        let cubic = |[a, b, c, d]: [f32; 4]| {
+            a * theta_sun.powi(3) + b * theta_sun.powi(2) + c * theta_sun + d
+        };
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        Box::new(Self {
+            towards_sun,
+            theta_sun,
+            sun: colour * sun_transmittance(theta_sun, turbidity),
+            sky_intensity,
+            zenith: [1.0, zenith_x, zenith_y],
+            perez,
+            windows: Vec::new(),
+        })
+    }
+
+    // photon scenes shoot the sun's and sky's photons through windows, with
+    // their normals pointing inside
+    pub fn add_window(&mut self, quad: Quad) {
+        let sun = self.sun * (-self.towards_sun).dot(&quad.normal).max(0.0);
+
+        // the sky's light through the window, from every direction outside
+        // it (cos_theta from the window's normal, stratified)
+        let (ups, arounds) = SKY_SAMPLES;
+        let outside = Onb::from_normal(&-quad.normal);
+        let mut sky = Colour::black();
+        for i in 0..ups {
+            for j in 0..arounds {
+                let cos_theta = (i as f32 + 0.5) / ups as f32;
+                let phi = (j as f32 + 0.5) / arounds as f32 * TAU;
+                let r = (1.0 - cos_theta * cos_theta).sqrt();
+                let direction =
+                    outside.to_world(&Vector::new(r * phi.cos(), r * phi.sin(), cos_theta));
+                sky += self.sky_light(&direction) * cos_theta;
+            }
+        }
+        let sky = sky * (TAU / (ups * arounds) as f32);
+
+        self.windows.push(Window { quad, sun, sky });
+    }
+
+    fn sky(&self, direction: &Vector) -> Colour {
+        let direction = direction.normalised();
+        let cos_theta = direction.y.max(MIN_COS_THETA);
+        let gamma = direction.dot(&self.towards_sun).clamp(-1.0, 1.0).acos();
+
+        // each of luminance, x and y relative to the zenith
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let perez = &self.perez[i];
+            self.zenith[i] * perez_function(perez, cos_theta, gamma)
+                / perez_function(perez, 1.0, self.theta_sun)
+        });
+        xyy_to_rgb(x, y, luminance * self.sky_intensity)
+    }
+}
+
+impl Light for SunSky {
+    fn get_direction(&self, _surface: &Vertex) -> Option<Vector> {
+        Some(-self.towards_sun)
+    }
+
+    fn get_intensity(&self, _surface: &Vertex) -> Option<Colour> {
+        Some(self.sun)
+    }
+
+    fn background(&self, direction: &Vector) -> Colour {
+        self.sky(direction)
+    }
+
+    fn photon_light(self: Box<Self>) -> Box<dyn PhotonLight> {
+        assert!(
+            !self.windows.is_empty(),
+            "SunSky needs a window to shoot photons through"
+        );
+        self
+    }
+}
+
+impl SunSky {
+    // the sky lighting the scene from a direction. below the horizon is the
+    // ground, which (unlike the background there) gives off nothing.
+    fn sky_light(&self, direction: &Vector) -> Colour {
+        match direction.y > 0.0 {
+            true => self.sky(direction),
+            false => Colour::black(),
+        }
+    }
+
+    fn pick_window(&self) -> &Window {
+        random::with_rng(|rng| {
+            self.windows
+                .choose_weighted(rng, |window| window.power())
+                .unwrap_or(&self.windows[0])
+        })
+    }
+
+    fn sun_photon(&self, position: Vertex) -> InFlightPhoton {
+        InFlightPhoton::new(position, -self.towards_sun, self.sun, PhotonType::Direct)
+    }
+
+    // the sky seen looking back out along direction. the directions are
+    // spread evenly over the hemisphere, so the light along each is weighted
+    // by 2 pi cos to average out to the sky's light through the window.
+    fn sky_photon(&self, window: &Window, position: Vertex, direction: Vector) -> InFlightPhoton {
+        let cos_theta = direction.dot(&window.quad.normal);
+        let intensity = self.sky_light(&-direction) * (TAU * cos_theta);
+        InFlightPhoton::new(position, direction, intensity, PhotonType::Colour)
+    }
+}
+
+impl PhotonLight for SunSky {
+    fn power(&self) -> f32 {
+        self.windows.iter().map(Window::power).sum()
+    }
+
+    fn shoot_regular_photons(
+        &self,
+        scene: &PhotonScene,
+        stream: u32,
+        photons: Range<u32>,
+        first_thread: bool,
+    ) -> Vec<Photon> {
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
+        let start = Instant::now();
+
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
+            // from somewhere on a window, either from the sun or the sky
+            let window = self.pick_window();
+            let position = scene
+                .settings
+                .offset(&window.random_point(), &window.quad.normal);
+            let from_sun = random::with_rng(|rng| rng.gen::<f32>() < window.sun_share());
+            let photon = match from_sun {
+                true => self.sun_photon(position),
+                false => {
+                    let direction = Vector::random_on_surface(window.quad.normal);
+                    self.sky_photon(window, position, direction)
+                }
+            };
+
+            let traced_photons = scene.photontrace(photon);
+            shot_photons.extend(traced_photons);
+
+            // print progress/ETA
+            if first_thread && (i % 10000 == 0 || i == num_photons - 1) {
+                let progress = (i + 1) as f32 / num_photons as f32;
+                let elapsed = start.elapsed().as_secs_f32();
+                let eta = elapsed / progress - elapsed;
+                let percent = (progress * 100.0) as u32;
+                print!("{percent}% photons shot, elapsed {elapsed:.2}s, ETA {eta:.2}s\t\r");
+            }
+        }
+
+        if first_thread {
+            println!();
+        }
+
+        shot_photons
+    }
+
+    fn shoot_caustic_photons<'a>(
+        &'a self,
+        scene: &'a PhotonScene,
+        targets: &[BoundingSphere],
+        stream: u32,
+        photons: Range<u32>,
+        first_thread: bool,
+    ) -> Vec<Photon> {
+        let num_photons = photons.len() as u32;
+        let mut shot_photons = Vec::with_capacity(num_photons as usize);
+        let start = Instant::now();
+
+        for (i, index) in (0..num_photons).zip(photons) {
+            if cancel::cancelled() {
+                break;
+            }
+            random::reseed(random::key(stream, index));
+
+            // a random point in one of the targets, the bigger ones more
+            // often (by how big they look from far away)
+            let target = random::with_rng(|rng| {
+                targets
+                    .choose_weighted(rng, |target| target.radius * target.radius)
+                    .ok()
+                    .cloned()
+            });
+            let Some(target) = target else {
+                continue;
+            };
+            let target = target.random_point();
+
+            // sunlight can only come in from where the sun is, so it's aimed
+            // by following it back to a window. skylight can come from any
+            // point on a window facing the target.
+            let window = self.pick_window();
+            let from_sun = random::with_rng(|rng| rng.gen::<f32>() < window.sun_share());
+            let photon = if from_sun {
+                let Some(crossing) = window.crossing(&target, &self.towards_sun) else {
+                    continue;
+                };
+                let position = scene.settings.offset(&crossing, &window.quad.normal);
+                self.sun_photon(position)
+            } else {
+                let position = window.random_point();
+                let direction = position.vector_to(&target).normalised();
+                if direction.dot(&window.quad.normal) <= 0.0 {
+                    continue;
+                }
+                let position = scene.settings.offset(&position, &window.quad.normal);
+                self.sky_photon(window, position, direction)
+            };
+
+            let traced_photons = scene.photontrace(photon);
+            shot_photons.extend(traced_photons);
+
+            // print progress/ETA
+            if first_thread && i % 10000 == 0 {
+                let progress = i as f32 / num_photons as f32;
+                let elapsed = start.elapsed().as_secs_f32();
+                let eta = elapsed / progress - elapsed;
+                let percent = (progress * 100.0) as u32;
+                print!("{percent}% photons shot, elapsed {elapsed:.2}s, ETA {eta:.2}s\t\r");
+            }
+        }
+
+        if first_thread {
+            println!();
+        }
+
+        shot_photons
+    }
+}
+
+// how the sky's brightness (or colour) varies with the angle from the zenith
+// (theta) and from the sun (gamma)
+fn perez_function([a, b, c, d, e]: &[f32; 5], cos_theta: f32, gamma: f32) -> f32 {
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// how much sunlight makes it through the air, theta_sun from the zenith.
+// rayleigh scattering takes the blue out of it, and haze (aerosols, more
+// the more turbid the air is) a bit of everything.
+fn sun_transmittance(theta_sun: f32, turbidity: f32) -> Colour {
+    // how much air the light goes through, compared with straight down
+    let relative_mass =
+        1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+
+    let [r, g, b] = WAVELENGTHS.map(|wavelength| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-relative_mass * (rayleigh + aerosol)).exp()
+    });
+    Colour::new(r, g, b)
+}
+
+// CIE xyY to linear sRGB
+fn xyy_to_rgb(x: f32, y: f32, luminance: f32) -> Colour {
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Colour::new(
+        (3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z).max(0.0),
+    )
+}
@@ -1,132 +1,572 @@
-#![allow(dead_code)]
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::Instant,
+};
 
-use std::{path::PathBuf, process::Command, time::Instant};
+use cli::{Options, VideoFormat};
 
-use environments::environment::Environment;
+use rust_raytracer::{
+    archive,
+    core::{
+        animation::Animation,
+        cancel,
+        colour::ColourSpace,
+        compare::{self, Threshold},
+        denoise::denoise,
+        error::RenderError,
+        framebuffer::{Aov, DepthFormat, LightingPass},
+        platform,
+        stats::{self, RenderStats},
+    },
+    distributed::{self, Job},
+    environments::{environment::Environment, photon_bake::PhotonBake},
+    materials::texture,
+    parse_path,
+    scene_file::{LoadedScene, ParseError, ParseErrors},
+    Camera, FrameBuffer, FullCamera, SceneFile,
+};
 
-use scene_file::{ParseError, SceneFile};
+mod cli;
 
-use crate::cameras::{camera::Camera, full_camera::FullCamera};
+#[cfg(feature = "preview")]
+mod preview;
 
-mod core {
-    pub mod colour;
-    pub mod framebuffer;
-    pub mod hit;
-    pub mod photon;
-    pub mod photon_tree;
-    pub mod ray;
-    pub mod tex_coords;
-    pub mod transform;
-    pub mod vector;
-    pub mod vertex;
-}
-
-mod environments {
-    pub mod environment;
-    pub mod photon_scene;
-    pub mod scene;
-}
-
-mod cameras {
-    pub mod camera;
-    pub mod full_camera;
-}
-
-mod materials {
-    pub mod compound_material;
-    pub mod falsecolour_material;
-    pub mod global_material;
-    pub mod material;
-    pub mod phong_material;
-    pub mod texture;
-}
+// the live preview window, if built with the "preview" feature
+#[cfg(feature = "preview")]
+type Preview = Option<preview::PreviewWindow>;
+#[cfg(not(feature = "preview"))]
+#[derive(Default)]
+struct Preview {}
 
-mod lights {
-    pub mod directional_light;
-    pub mod directional_point_light;
-    pub mod light;
-    pub mod point_light;
-}
-
-mod objects {
-    pub mod csg_object;
-    pub mod cuboid_object;
-    pub mod object;
-    pub mod plane_object;
-    pub mod polymesh_object;
-    pub mod quadratic_object;
-    pub mod sphere_object;
-    pub mod triangle_object;
-}
+fn main() {
+    let mut options = Options::from_env();
 
-mod scene_file;
+    if options.worker {
+        if let Err(e) = distributed::serve(&options.listen, options.threads) {
+            eprintln!("Failed to start worker! {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    handle_ctrl_c();
 
-fn parse_path(path: &str) -> PathBuf {
-    let mut parsed = std::env::current_dir().unwrap();
-    for part in path.split('/') {
-        parsed.push(part);
+    if let Some((scene, archive)) = &options.pack {
+        match archive::pack(Path::new(scene), archive) {
+            Ok(count) => println!("Packed {count} files into {}", archive.display()),
+            Err(e) => {
+                eprintln!("Failed to pack scene! {e}");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    // like diff(1), 1 if the images are too far apart and 2 if they can't
+    // be compared
+    if let Some((reference, image)) = &options.diff {
+        match diff(reference, image, &options.output, &options.thresholds) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Failed to compare images! {e}");
+                std::process::exit(2);
+            }
+        }
+        return;
     }
-    parsed
-}
 
-fn main() {
-    // get txt filename from first argv
-    let scene_filename = std::env::args()
-        .nth(1)
-        .unwrap_or("assets/scenes/scene2.txt".to_string());
+    // archives are unpacked and rendered from a temporary folder, so keep
+    // track of the file itself to watch for changes
+    let watched = parse_path(&options.scene_filename);
+    let is_archive = archive::is_archive(&watched);
+    if is_archive {
+        options.output = std::env::current_dir().unwrap().join(&options.output);
+        options.scene_filename = archive::SCENE_FILENAME.to_string();
+    }
+    let scene_filename = watched.display();
 
-    // when assets/scene.txt changes, re-render
+    // when the scene file changes, re-render. None while it's missing, e.g.
+    // when an editor saves by replacing it, which waits for it to come back.
     let get_last_modified = || {
-        std::fs::metadata(&scene_filename)
-            .expect("Failed to get metadata for scene file")
-            .modified()
-            .expect("Failed to get modified time for scene file")
+        std::fs::metadata(&watched)
+            .and_then(|metadata| metadata.modified())
+            .ok()
     };
 
-    loop {
+    let read_scene = || std::fs::read_to_string(&watched).unwrap_or_default();
+
+    let mut preview = Preview::default();
+
+    // the last still image's scene, kept while watching so it can be
+    // rendered again without being rebuilt if only its textures change
+    let mut kept_scene = None;
+    'watch: loop {
         let last_modified = get_last_modified();
+        let contents = read_scene();
 
-        render(&scene_filename);
-        println!("Waiting for changes to {scene_filename}...");
+        kept_scene = match kept_scene.take() {
+            Some(scene) => Some(rerender(&options, &mut preview, scene)),
+            None => {
+                // an archive that can't be unpacked fails like a broken scene
+                let unpacked = match is_archive {
+                    true => archive::unpack(&watched)
+                        .and_then(|folder| {
+                            std::env::set_current_dir(folder).map_err(|e| e.to_string())
+                        })
+                        .map_err(|e| format!("Failed to unpack scene archive! {e}")),
+                    false => Ok(()),
+                };
+                match unpacked.and_then(|()| render(&options, &mut preview)) {
+                    Ok(scene) => scene,
+                    Err(e) => {
+                        println!("{e}");
+                        // without watching there's nothing left to do, so
+                        // say it failed
+                        if !options.watch {
+                            std::process::exit(1);
+                        }
+                        None
+                    }
+                }
+            }
+        };
+        if !options.watch || cancel::cancelled() {
+            break;
+        }
+        println!("Waiting for changes to {scene_filename} or its textures...");
 
         loop {
             if get_last_modified() > last_modified {
+                // the scene only needs building again if it's different
+                if is_archive || !SceneFile::same_scene(&contents, &read_scene()) {
+                    kept_scene = None;
+                }
                 break;
             }
+            if kept_scene.is_some() && texture::images_changed() {
+                break;
+            }
+            if cancel::cancelled() {
+                break 'watch;
+            }
 
+            idle_preview(&mut preview);
             std::thread::sleep(std::time::Duration::from_millis(50));
         }
     }
+
+    if cancel::cancelled() {
+        std::process::exit(130);
+    }
+}
+
+// print how far image is from reference and write where they differ.
+// returns whether they're within every threshold.
+fn diff(
+    reference: &Path,
+    image: &Path,
+    output: &Path,
+    thresholds: &[Threshold],
+) -> Result<bool, String> {
+    let reference = compare::load(reference)?;
+    let image = compare::load(image)?;
+    if (reference.width, reference.height) != (image.width, image.height) {
+        return Err(format!(
+            "The images are different sizes, {}x{} and {}x{}",
+            reference.width, reference.height, image.width, image.height
+        ));
+    }
+
+    let measurements = compare::measure(&reference, &image);
+    println!("{measurements}");
+    std::fs::create_dir_all(output).map_err(|e| RenderError::io(output, e))?;
+    let filename = output.join("diff.ppm");
+    // false colour, not light
+    compare::error_map(&reference, &image).write_rgb_file(&filename, ColourSpace::Linear)?;
+    ffmpeg_ppm_to_png(&filename);
+    println!(
+        "Differences written to {}",
+        filename.with_extension("png").display()
+    );
+
+    let mut passed = true;
+    for threshold in thresholds.iter().filter(|t| !t.passes(&measurements)) {
+        let measured = measurements.get(threshold.metric);
+        println!("Failed {threshold}: {measured:.5}");
+        passed = false;
+    }
+    Ok(passed)
+}
+
+// the first Ctrl-C stops the render and writes what it finished, a second
+// one quits straight away
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_ctrl_c() {
+    // only async-signal-safe calls in here
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        if cancel::cancelled() {
+            unsafe { libc::_exit(130) };
+        }
+        cancel::cancel();
+    }
+
+    let handler = on_interrupt as extern "C" fn(libc::c_int);
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+// when watching, invalid paragraphs are skipped so the rest of the scene
+// still renders while it is being edited
+fn build_scene(options: &Options, time: f32) -> Result<LoadedScene, ParseErrors> {
+    let path = parse_path(&options.scene_filename);
+    let mut scene = if options.watch {
+        SceneFile::from_path_recovering(&path, &options.overrides, time)?
+    } else {
+        SceneFile::from_path(&path, &options.overrides, time)?
+    };
+
+    for camera in std::iter::once(&mut scene.camera).chain(&mut scene.other_cameras) {
+        if let Some(width) = options.width {
+            camera.width = width;
+        }
+        if let Some(height) = options.height {
+            camera.height = height;
+        }
+        if let Some(samples) = options.samples {
+            camera.samples = samples;
+        }
+        if options.threads.is_some() {
+            camera.threads = options.threads;
+        }
+    }
+
+    // the command line wins over the scene paragraph's thread settings
+    if options.threads.is_some() {
+        platform::set_num_threads(options.threads);
+    }
+    if options.reserve_core {
+        platform::set_reserve_core(true);
+    }
+    if options.pin_threads {
+        platform::set_pin_threads(true);
+    }
+
+    Ok(scene)
 }
 
-fn build_scene(
-    scene_filename: &str,
-) -> Result<(Box<dyn Environment>, Box<FullCamera>), ParseError> {
-    SceneFile::from_path(&parse_path(scene_filename))
+// the frames to render, from the scene's animation paragraph and the command
+// line. None if this is a still image.
+fn get_animation(options: &Options, animation: Option<Animation>) -> Option<Animation> {
+    let fps = options
+        .fps
+        .or(animation.as_ref().map(|a| a.fps))
+        .unwrap_or(24.0);
+    let frames = options.frames.clone().or(animation.map(|a| a.frames))?;
+    Some(Animation { fps, frames })
 }
 
-fn render(scene_filename: &str) {
+// returns the scene of a still image once it's rendered, already prepared
+// for rerender(), or why nothing could be rendered
+fn render(options: &Options, preview: &mut Preview) -> Result<Option<LoadedScene>, String> {
+    if !options.compare.is_empty() {
+        render_comparison(options, preview)?;
+        return Ok(None);
+    }
     let start = Instant::now();
 
-    let (mut scene, camera) = match build_scene(scene_filename) {
-        Ok(scene) => scene,
-        Err(e) => {
-            println!("Failed to build scene! {:?}", e);
+    let mut scene =
+        build_scene(options, 0.0).map_err(|e| format!("Failed to build scene!\n{e}"))?;
+    // every frame skips the same paragraphs, so only report them once
+    let errors = std::mem::take(&mut scene.errors);
+
+    std::fs::create_dir_all(&options.output)
+        .map_err(|e| format!("Failed to create output directory: {e}"))?;
+
+    if let Some(frames) = options.turntable {
+        render_turntable(options, preview, scene, frames, start);
+        print_skipped(&errors);
+        return Ok(None);
+    }
+    let Some(animation) = get_animation(options, scene.animation.clone()) else {
+        render_frame(options, preview, &mut scene, None, 0.0, start, true);
+        print_skipped(&errors);
+        return Ok(Some(scene));
+    };
+    let directories = frame_directories(options, &scene);
+    drop(scene);
+
+    for frame in animation.frames.clone() {
+        let start = Instant::now();
+        let time = animation.time_of(frame);
+        println!(
+            "Rendering frame {frame} of {}..{} ({time:.2}s)",
+            animation.frames.start, animation.frames.end
+        );
+
+        let mut scene =
+            build_scene(options, time).map_err(|e| format!("Failed to build scene!\n{e}"))?;
+        render_frame(options, preview, &mut scene, Some(frame), time, start, true);
+        if cancel::cancelled() {
+            println!("Stopped after frame {frame}");
+            break;
+        }
+    }
+
+    if !cancel::cancelled() {
+        for directory in &directories {
+            assemble_video(directory, animation.frames.start, animation.fps, options.video);
+        }
+    }
+    print_skipped(&errors);
+    Ok(None)
+}
+
+// render the scene from its camera as it is (a) and with the --compare
+// overrides (b), then write both, the two side by side (a on the left) and
+// how far apart they are
+fn render_comparison(options: &Options, preview: &mut Preview) -> Result<(), String> {
+    std::fs::create_dir_all(&options.output)
+        .map_err(|e| format!("Failed to create output directory: {e}"))?;
+    let mut compared = options.clone();
+    compared.overrides.extend(options.compare.iter().cloned());
+
+    let mut renders = Vec::new();
+    for (name, options) in [("a", options), ("b", &compared)] {
+        println!("Rendering {name}...");
+        let mut scene =
+            build_scene(options, 0.0).map_err(|e| format!("Failed to build scene {name}!\n{e}"))?;
+        print_skipped(&scene.errors);
+
+        let camera = &scene.camera;
+        open_preview(preview, camera.width, camera.height);
+        let mut on_update =
+            |framebuffer: &FrameBuffer| update_preview(preview, framebuffer, camera.colour_space);
+        let framebuffer = camera.render(scene.environment.as_mut(), &mut on_update);
+        if cancel::cancelled() {
+            return Ok(());
+        }
+        renders.push((name, framebuffer, camera.colour_space));
+    }
+
+    let (a, b) = (&renders[0].1, &renders[1].1);
+    if (a.width, a.height) != (b.width, b.height) {
+        return Err(format!(
+            "Can't compare renders of different sizes, {}x{} and {}x{}",
+            a.width, a.height, b.width, b.height
+        ));
+    }
+    // the difference is data, like the heat maps
+    let images = [
+        ("compare", compare::split(a, b), renders[0].2),
+        ("difference", compare::difference(a, b), ColourSpace::Linear),
+    ];
+    for (name, framebuffer, colour_space) in renders.iter().cloned().chain(images) {
+        let filename = output_filename(&options.output, name, None);
+        if written(framebuffer.write_rgb_file(&filename, colour_space)) {
+            ffmpeg_ppm_to_png(&filename);
+        }
+    }
+    println!("RMS difference between a and b: {:.5}", compare::rmse(a, b));
+    Ok(())
+}
+
+// the camera goes once around the middle of the scene over frames frames.
+// nothing else moves, so the scene is only built and prepared once.
+fn render_turntable(
+    options: &Options,
+    preview: &mut Preview,
+    mut scene: LoadedScene,
+    frames: u32,
+    mut start: Instant,
+) {
+    let Some((min, max)) = scene.environment.bounds() else {
+        println!("Nothing in the scene has bounds to turn around");
+        return;
+    };
+    let centre = min.clone() + min.vector_to(&max) * 0.5;
+    let step = std::f32::consts::TAU / frames as f32;
+
+    for frame in 0..frames {
+        println!("Rendering turntable frame {frame} of {frames}");
+        render_frame(options, preview, &mut scene, Some(frame), 0.0, start, frame == 0);
+        if cancel::cancelled() {
+            println!("Stopped after frame {frame}");
             return;
         }
+
+        for camera in std::iter::once(&mut scene.camera).chain(&mut scene.other_cameras) {
+            camera.orbit(&centre, step);
+        }
+        start = Instant::now();
+    }
+
+    let fps = options.fps.unwrap_or(24.0);
+    for directory in frame_directories(options, &scene) {
+        assemble_video(&directory, 0, fps, options.video);
+    }
+}
+
+// where each of the scene's cameras writes its frames, see render_frame()
+fn frame_directories(options: &Options, scene: &LoadedScene) -> Vec<PathBuf> {
+    if scene.other_cameras.is_empty() {
+        return vec![options.output.clone()];
+    }
+    std::iter::once(&scene.camera)
+        .chain(&scene.other_cameras)
+        .map(|camera| options.output.join(camera.name.as_deref().unwrap_or_default()))
+        .collect()
+}
+
+// put the frames in directory, numbered from first_frame, together into a
+// video next to them
+fn assemble_video(directory: &Path, first_frame: u32, fps: f32, format: VideoFormat) {
+    let video_filename = directory.join("rgb").with_extension(format.extension());
+    println!("Assembling the frames into {}...", video_filename.display());
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-y")
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("warning")
+        .arg("-framerate")
+        .arg(fps.to_string())
+        .arg("-start_number")
+        .arg(first_frame.to_string())
+        .arg("-i")
+        .arg(directory.join("rgb_%04d.ppm"));
+    match format {
+        // most players only play 4:2:0 video, which needs even sizes
+        VideoFormat::Mp4 => command
+            .arg("-vf")
+            .arg("pad=ceil(iw/2)*2:ceil(ih/2)*2")
+            .arg("-pix_fmt")
+            .arg("yuv420p"),
+        // a palette made from the frames looks much better than the default
+        VideoFormat::Gif => command
+            .arg("-vf")
+            .arg("split[a][b];[a]palettegen[p];[b][p]paletteuse"),
     };
+
+    match command.arg(&video_filename).output() {
+        Ok(output) if !output.status.success() => println!(
+            "Failed to assemble the video! {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(_) => {}
+        Err(e) => println!("Failed to run FFmpeg! {e}"),
+    }
+}
+
+// render the scene from render() again after only its textures changed:
+// they're reloaded in place, and everything else (e.g. the photon maps) is
+// kept as it was
+fn rerender(options: &Options, preview: &mut Preview, mut scene: LoadedScene) -> LoadedScene {
+    let start = Instant::now();
+    println!("Reusing the scene, reloading just the textures that changed...");
+    texture::reload_changed();
+    render_frame(options, preview, &mut scene, None, 0.0, start, false);
+    scene
+}
+
+// printed after the render, so they don't scroll away
+fn print_skipped(errors: &[ParseError]) {
+    if errors.is_empty() {
+        return;
+    }
+    println!("Skipped invalid paragraphs, {} error(s):", errors.len());
+    for e in errors {
+        for line in e.to_string().lines() {
+            println!("  {line}");
+        }
+    }
+}
+
+// frame is None for still images, which don't get numbered filenames, and
+// time places it in the animation. the scene is only prepared (e.g. its
+// photon maps built) once, then rendered from each of its cameras. prepare
+// is false if it already has been.
+fn render_frame(
+    options: &Options,
+    preview: &mut Preview,
+    scene: &mut LoadedScene,
+    frame: Option<u32>,
+    time: f32,
+    start: Instant,
+    prepare: bool,
+) {
+    let LoadedScene {
+        environment: scene,
+        camera,
+        other_cameras,
+        ..
+    } = scene;
     let build_scene_end = Instant::now();
+    stats::record_phase("build scene", (build_scene_end - start).as_secs_f32());
 
-    let framebuffer = camera.render(scene.as_mut());
+    if prepare {
+        scene.pre_render();
+    }
+    let job = distributed_job(options, time);
+    let job = job.as_ref();
+    let mut png_filenames = Vec::new();
+    let output = options.output.as_path();
+    if other_cameras.is_empty() {
+        png_filenames = render_view(
+            options,
+            preview,
+            camera,
+            scene.as_mut(),
+            output,
+            frame,
+            job,
+        );
+    } else {
+        // each camera gets its own directory, e.g. render/top/rgb.ppm
+        for camera in std::iter::once(&*camera).chain(other_cameras.iter()) {
+            if cancel::cancelled() {
+                break;
+            }
+            let name = camera.name.as_deref().unwrap_or_default();
+            println!("Rendering camera {name}...");
+            let output = output.join(name);
+            if let Err(e) = std::fs::create_dir_all(&output) {
+                println!("Failed to create output directory: {e}");
+                continue;
+            }
+            let filenames = render_view(
+                options,
+                preview,
+                camera,
+                scene.as_mut(),
+                &output,
+                frame,
+                job,
+            );
+            png_filenames.extend(filenames);
+        }
+    }
     let render_end = Instant::now();
 
-    let rgb_outpath = parse_path("render/rgb.ppm");
-    framebuffer.write_rgb_file(&rgb_outpath);
-    framebuffer.write_depth_file(&parse_path("render/depth.ppm"));
+    let bake_filename = scene
+        .photon_bake()
+        .and_then(|bake| write_photon_bake(&bake, "photons", output, frame));
+    let density_filename = scene
+        .photon_density()
+        .and_then(|density| write_photon_bake(&density, "photon_density", output, frame));
+    // taken even if they aren't written, so the next frame starts from zero
+    let stats = stats::take();
+    if options.stats {
+        println!("{stats}");
+        png_filenames.extend(write_stats(&stats, output, frame));
+    }
     let write_end = Instant::now();
 
     println!("Running FFmpeg...");
-    ffmpeg_ppm_to_png(rgb_outpath);
+    let baked = bake_filename.iter().chain(&density_filename);
+    for png_filename in png_filenames.iter().chain(baked) {
+        ffmpeg_ppm_to_png(png_filename);
+    }
     let ffmpeg_end = Instant::now();
 
     println!(
@@ -139,9 +579,329 @@ fn render(scene_filename: &str) {
     );
 }
 
-fn ffmpeg_ppm_to_png(ppm_filename: PathBuf) {
+// what --workers render, None without them
+fn distributed_job(options: &Options, time: f32) -> Option<Job> {
+    if options.workers.is_empty() {
+        return None;
+    }
+    let filename = parse_path(&options.scene_filename);
+    let scene = match std::fs::read_to_string(&filename) {
+        Ok(scene) => scene,
+        Err(e) => {
+            println!("Failed to read scene for workers, rendering alone: {e}");
+            return None;
+        }
+    };
+    Some(Job {
+        filename,
+        scene,
+        overrides: options.overrides.iter().map(|o| o.to_string()).collect(),
+        time,
+    })
+}
+
+// renders the already prepared scene from one camera and writes its images,
+// returning the filenames of the ones to convert to PNG. job is given to
+// share the rendering with --workers.
+fn render_view(
+    options: &Options,
+    preview: &mut Preview,
+    camera: &FullCamera,
+    scene: &mut dyn Environment,
+    output: &Path,
+    frame: Option<u32>,
+    job: Option<&Job>,
+) -> Vec<PathBuf> {
+    let rgb_filename = output_filename(output, "rgb", frame);
+
+    // nothing but the colours, the rest needs the whole image at once
+    if options.stream && job.is_none() {
+        println!(
+            "Streaming the render, skipping depth, AOVs, post-processing, gizmos and brackets"
+        );
+        if let Err(e) = camera.render_streamed(scene, &rgb_filename) {
+            println!("Failed to stream the render! {e}");
+        }
+        return vec![rgb_filename];
+    }
+
+    open_preview(preview, camera.width, camera.height);
+    let mut on_update =
+        |framebuffer: &FrameBuffer| update_preview(preview, framebuffer, camera.colour_space);
+
+    let mut framebuffer = if let Some(job) = job {
+        distributed::render(&options.workers, job, camera, scene, &mut on_update)
+    } else if camera.progressive {
+        let on_pass = |framebuffer: &FrameBuffer| {
+            if !write_framebuffer(framebuffer, camera, output, frame) {
+                return;
+            }
+            ffmpeg_ppm_to_png(&rgb_filename);
+            println!(
+                "Preview written to {}",
+                rgb_filename.with_extension("png").display()
+            );
+        };
+        camera.render_progressive(scene, on_pass, &mut on_update)
+    } else {
+        camera.render_prepared(scene, &mut on_update)
+    };
+    if cancel::cancelled() {
+        println!("Render stopped, writing the tiles that were finished (Ctrl-C again to quit)...");
+    }
+    if camera.denoise_passes > 0 {
+        println!("Denoising...");
+        framebuffer = denoise(&framebuffer, camera.denoise_passes);
+    }
+    if let Some(depth_of_field) = &camera.depth_of_field {
+        println!("Adding depth of field...");
+        framebuffer = depth_of_field.apply(&framebuffer);
+    }
+    if let Some(bloom) = &camera.bloom {
+        println!("Adding bloom...");
+        framebuffer = bloom.apply(&framebuffer);
+    }
+    if camera.show_gizmos {
+        camera.draw_gizmos(&mut framebuffer, scene);
+    }
+    let transient_filenames = render_transient(camera, scene, output, frame);
+
+    let mut filenames = Vec::new();
+    if write_framebuffer(&framebuffer, camera, output, frame) {
+        filenames.push(rgb_filename);
+    }
+    if camera.write_aovs {
+        filenames.extend(write_aovs(&framebuffer, camera.colour_space, output, frame));
+    }
+    if camera.write_lighting_passes {
+        filenames.extend(write_lighting_passes(
+            &framebuffer,
+            camera.colour_space,
+            output,
+            frame,
+        ));
+    }
+    if let Some(ev) = options.bracket {
+        filenames.extend(write_brackets(&framebuffer, ev, camera.colour_space, output, frame));
+    }
+    filenames.extend(transient_filenames);
+    filenames
+}
+
+#[cfg(feature = "preview")]
+fn open_preview(preview: &mut Preview, width: u32, height: u32) {
+    let is_usable = preview
+        .as_ref()
+        .is_some_and(|p| p.is_open() && p.size() == (width, height));
+    if is_usable {
+        return;
+    }
+
+    *preview = match preview::PreviewWindow::new(width, height) {
+        Ok(window) => Some(window),
+        Err(e) => {
+            println!("Failed to open preview window: {e}");
+            None
+        }
+    };
+}
+
+#[cfg(feature = "preview")]
+fn update_preview(preview: &mut Preview, framebuffer: &FrameBuffer, colour_space: ColourSpace) {
+    if let Some(window) = preview {
+        window.update(framebuffer, colour_space);
+    }
+}
+
+#[cfg(feature = "preview")]
+fn idle_preview(preview: &mut Preview) {
+    if let Some(window) = preview {
+        window.idle();
+    }
+}
+
+#[cfg(not(feature = "preview"))]
+fn open_preview(_preview: &mut Preview, _width: u32, _height: u32) {}
+
+#[cfg(not(feature = "preview"))]
+fn update_preview(_preview: &mut Preview, _framebuffer: &FrameBuffer, _: ColourSpace) {}
+
+#[cfg(not(feature = "preview"))]
+fn idle_preview(_preview: &mut Preview) {}
+
+// e.g. render/rgb.ppm, or render/rgb_0042.ppm for frame 42 of an animation
+fn output_filename(output: &Path, name: &str, frame: Option<u32>) -> PathBuf {
+    match frame {
+        Some(frame) => output.join(format!("{name}_{frame:04}.ppm")),
+        None => output.join(format!("{name}.ppm")),
+    }
+}
+
+// print why an image couldn't be written, returning whether it was
+fn written(result: Result<(), RenderError>) -> bool {
+    if let Err(e) = &result {
+        println!("Failed to write image! {e}");
+    }
+    result.is_ok()
+}
+
+// returns whether the rgb image was written
+fn write_framebuffer(
+    framebuffer: &FrameBuffer,
+    camera: &FullCamera,
+    output: &Path,
+    frame: Option<u32>,
+) -> bool {
+    let rgb_written = written(framebuffer.write_rgb_file(
+        &output_filename(output, "rgb", frame),
+        camera.colour_space,
+    ));
+    let depth_filename = output_filename(output, "depth", frame);
+    let depth_filename = match camera.depth_output.format {
+        DepthFormat::Float => depth_filename.with_extension("pfm"),
+        _ => depth_filename,
+    };
+    written(framebuffer.write_depth_file(&depth_filename, camera.miss_depth, &camera.depth_output));
+    rgb_written
+}
+
+// writes each AOV image, e.g. render/normal.pfm and render/albedo.ppm,
+// returning the filenames of the ones to convert to PNG
+fn write_aovs(
+    framebuffer: &FrameBuffer,
+    colour_space: ColourSpace,
+    output: &Path,
+    frame: Option<u32>,
+) -> Vec<PathBuf> {
+    let mut filenames = Vec::new();
+    for aov in Aov::ALL {
+        let image = framebuffer.aov(aov);
+        let filename = output_filename(output, aov.name(), frame);
+        if aov.is_float() {
+            written(image.write_pfm_file(&filename.with_extension("pfm")));
+        } else if written(image.write_rgb_file(&filename, colour_space)) {
+            filenames.push(filename);
+        }
+    }
+    filenames
+}
+
+// writes each part of the lighting twice, e.g. render/diffuse.ppm to look at
+// and render/diffuse.pfm (unclamped) to recombine, returning the filenames of
+// the ones to convert to PNG
+fn write_lighting_passes(
+    framebuffer: &FrameBuffer,
+    colour_space: ColourSpace,
+    output: &Path,
+    frame: Option<u32>,
+) -> Vec<PathBuf> {
+    let mut filenames = Vec::new();
+    for pass in LightingPass::ALL {
+        let image = framebuffer.lighting_pass(pass);
+        let filename = output_filename(output, pass.name(), frame);
+        written(image.write_pfm_file(&filename.with_extension("pfm")));
+        if written(image.write_rgb_file(&filename, colour_space)) {
+            filenames.push(filename);
+        }
+    }
+    filenames
+}
+
+// writes the image ev stops darker and brighter, e.g. render/rgb_-2ev.ppm
+// and render/rgb_+2ev.ppm, returning their filenames
+fn write_brackets(
+    framebuffer: &FrameBuffer,
+    ev: f32,
+    colour_space: ColourSpace,
+    output: &Path,
+    frame: Option<u32>,
+) -> Vec<PathBuf> {
+    [-ev, ev]
+        .into_iter()
+        .filter_map(|ev| {
+            let filename = output_filename(output, &format!("rgb_{ev:+}ev"), frame);
+            let image = framebuffer.exposed(ev);
+            written(image.write_rgb_file(&filename, colour_space)).then_some(filename)
+        })
+        .collect()
+}
+
+// writes the heat map and raw values, returning the heat map's filename
+fn write_photon_bake(
+    bake: &PhotonBake,
+    name: &str,
+    output: &Path,
+    frame: Option<u32>,
+) -> Option<PathBuf> {
+    let heat_map_filename = output_filename(output, name, frame);
+    // the heat map's colours are picked to be looked at as they are
+    let heat_map = bake.heat_map();
+    if !written(heat_map.write_rgb_file(&heat_map_filename, ColourSpace::Linear)) {
+        return None;
+    }
+
+    let values_filename = heat_map_filename.with_extension("pfm");
+    if let Err(e) = bake.write_pfm_file(&values_filename) {
+        println!("Failed to write {name}: {e}");
+        return None;
+    }
+
+    println!(
+        "Photon heat map written to {} (peak {:.4}, values in {})",
+        heat_map_filename.with_extension("png").display(),
+        bake.peak(),
+        values_filename.display()
+    );
+    Some(heat_map_filename)
+}
+
+// writes the stats as JSON and the per-tile render times as a heat map,
+// returning the heat map's filename
+fn write_stats(stats: &RenderStats, output: &Path, frame: Option<u32>) -> Option<PathBuf> {
+    let json_filename = output_filename(output, "stats", frame).with_extension("json");
+    if let Err(e) = std::fs::write(&json_filename, stats.to_json()) {
+        println!("Failed to write stats: {e}");
+    }
+
+    let tile_times = stats.tile_times.as_ref()?;
+    let heat_map_filename = output_filename(output, "tiles", frame);
+    let heat_map = tile_times.heat_map();
+    if !written(heat_map.write_rgb_file(&heat_map_filename, ColourSpace::Linear)) {
+        return None;
+    }
+    println!(
+        "Stats written to {}, tile render times to {}",
+        json_filename.display(),
+        heat_map_filename.with_extension("png").display()
+    );
+    Some(heat_map_filename)
+}
+
+// renders and writes the camera's time-of-flight bins, if it has any,
+// returning their filenames. the scene must already have been rendered.
+fn render_transient(
+    camera: &FullCamera,
+    scene: &mut dyn Environment,
+    output: &Path,
+    frame: Option<u32>,
+) -> Vec<PathBuf> {
+    let mut filenames = Vec::new();
+    let on_bin = |bin: u32, framebuffer: &FrameBuffer| {
+        let filename = output_filename(output, &format!("transient_{bin:04}"), frame);
+        if written(framebuffer.write_rgb_file(&filename, camera.colour_space)) {
+            filenames.push(filename);
+        }
+    };
+
+    if let Err(e) = camera.render_transient(scene, on_bin, &mut |_| {}) {
+        println!("Failed to render transient bins! {e}");
+    }
+    filenames
+}
+
+fn ffmpeg_ppm_to_png(ppm_filename: &Path) {
     let png_filename = ppm_filename.with_extension("png");
-    Command::new("ffmpeg")
+    let result = Command::new("ffmpeg")
         .arg("-y")
         .arg("-hide_banner")
         .arg("-loglevel")
@@ -149,6 +909,8 @@ fn ffmpeg_ppm_to_png(ppm_filename: PathBuf) {
         .arg("-i")
         .arg(ppm_filename)
         .arg(png_filename)
-        .output()
-        .expect("Failed to execute ffmpeg");
+        .output();
+    if let Err(e) = result {
+        println!("Failed to run FFmpeg! {e}");
+    }
 }
@@ -1,10 +1,19 @@
 use std::sync::Arc;
 
+use rand::seq::SliceRandom;
+
 use crate::{
     core::{
-        colour::Colour, hit::Hit, photon::Photon, ray::Ray, tex_coords::TexCoords, vector::Vector,
+        colour::{Colour, ColourSpace},
+        error::RenderError,
+        hit::Hit,
+        photon::Photon,
+        random,
+        ray::Ray,
+        tex_coords::TexCoords,
+        vector::Vector,
     },
-    environments::scene::Scene,
+    environments::environment::Integrator,
 };
 
 use super::{
@@ -15,25 +24,167 @@ use super::{
 };
 
 pub struct CompoundMaterial {
-    materials: Vec<Arc<dyn Material>>,
+    layers: Vec<Layer>,
+    blend: Blend,
+}
+
+struct Layer {
+    material: Arc<dyn Material>,
+    // None shares equally with the other layers (1/N), or takes all of its
+    // side of a Fresnel blend
+    weight: Option<f32>,
+    coat: bool,
+}
+
+// how the layers' weights are worked out
+pub enum Blend {
+    // each layer counts for its own weight
+    Weighted,
+    // a smooth coat (e.g. varnish) over the other layers: the coat layers
+    // count for how much light a coat with this index of refraction reflects
+    // at the angle it's seen from (Schlick's approximation), and the layers
+    // under it for the rest
+    Fresnel { ior: f32 },
+}
+
+impl Default for CompoundMaterial {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CompoundMaterial {
     pub fn new() -> Self {
         Self {
-            materials: Vec::new(),
+            layers: Vec::new(),
+            blend: Blend::Weighted,
         }
     }
 
+    pub fn set_blend(&mut self, blend: Blend) {
+        self.blend = blend;
+    }
+
     pub fn add_material(&mut self, material: Arc<impl Material + 'static>) {
-        // self.materials.push(material);
-        self.materials.push(material);
+        self.add_layer(material, None, false);
+    }
+
+    // a layer with a weight of its own instead of an equal share. coat
+    // layers are the ones on top when blending by Fresnel.
+    pub fn add_layer(&mut self, material: Arc<dyn Material>, weight: Option<f32>, coat: bool) {
+        self.layers.push(Layer {
+            material,
+            weight,
+            coat,
+        });
+    }
+
+    // how much a layer counts towards the surface seen at cos_theta (the
+    // cosine of the angle to the normal). without an angle, e.g. for
+    // photons coming from anywhere, the Fresnel split is averaged over them.
+    fn layer_weight(&self, layer: &Layer, cos_theta: Option<f32>) -> f32 {
+        match self.blend {
+            Blend::Weighted => layer.weight.unwrap_or(1.0 / self.layers.len() as f32),
+            Blend::Fresnel { ior } => {
+                let weight = layer.weight.unwrap_or(1.0);
+                let reflectance = schlick(ior, cos_theta);
+                match layer.coat {
+                    true => weight * reflectance,
+                    false => weight * (1.0 - reflectance),
+                }
+            }
+        }
+    }
+
+    // the layers with their weights, as seen at cos_theta
+    fn weighted_layers(
+        &self,
+        cos_theta: Option<f32>,
+    ) -> impl Iterator<Item = (&dyn PhotonMaterial, f32)> {
+        self.layers.iter().map(move |layer| {
+            let weight = self.layer_weight(layer, cos_theta);
+            (layer.material.photon_mapped(), weight)
+        })
     }
 
     fn photon_materials(&self) -> impl Iterator<Item = &dyn PhotonMaterial> {
-        self.materials
+        self.layers
             .iter()
-            .map(|material| material.photon_mapped())
+            .map(|layer| layer.material.photon_mapped())
+    }
+
+    // pick one of the layers at random, with probability proportional to
+    // its weight times weight(). None if every layer has a weight of 0.
+    fn pick(&self, weight: impl Fn(&dyn PhotonMaterial) -> f32) -> Option<&dyn PhotonMaterial> {
+        let layers: Vec<(&dyn PhotonMaterial, f32)> = self.weighted_layers(None).collect();
+        random::with_rng(|rng| {
+            layers
+                .choose_weighted(rng, |(material, layer_weight)| {
+                    weight(*material) * layer_weight
+                })
+                .ok()
+                .map(|(material, _)| *material)
+        })
+    }
+
+    // average of f() over the layers, weighted by weight() and their own
+    // weights
+    fn weighted_average(
+        &self,
+        weight: impl Fn(&dyn PhotonMaterial) -> f32,
+        f: impl Fn(&dyn PhotonMaterial) -> f32,
+    ) -> f32 {
+        let (total, total_weight) = self.weighted_layers(None).fold(
+            (0.0, 0.0),
+            |(total, total_weight), (material, layer_weight)| {
+                let weight = weight(material) * layer_weight;
+                (total + f(material) * weight, total_weight + weight)
+            },
+        );
+
+        if total_weight == 0.0 {
+            return 0.0;
+        }
+        total / total_weight
+    }
+
+    // the layers' shade() of a light reaching the surface, weighted by how
+    // they're seen from viewer
+    fn blend_per_light(
+        &self,
+        viewer: &Vector,
+        hit: &Hit,
+        shade: impl Fn(&dyn Material) -> Colour,
+    ) -> Colour {
+        let cos_theta = Some(viewer.normalised().dot(&hit.normal).abs());
+        self.layers.iter().fold(Colour::black(), |acc, layer| {
+            let weight = self.layer_weight(layer, cos_theta);
+            match weight > 0.0 {
+                true => acc + shade(layer.material.as_ref()) * weight,
+                false => acc,
+            }
+        })
+    }
+
+    // the surface layers' shade() of a vueon, weighted like bounced photons
+    fn blend_vueon(&self, shade: impl Fn(&dyn PhotonMaterial) -> Colour) -> Colour {
+        let (colour, total_weight) = self.weighted_layers(None).fold(
+            (Colour::black(), 0.0),
+            |(colour, total_weight), (material, layer_weight)| {
+                let weight = surface_weight(material) * layer_weight;
+                if weight == 0.0 {
+                    return (colour, total_weight);
+                }
+
+                let layer_colour = shade(material) * weight;
+                (colour + layer_colour, total_weight + weight)
+            },
+        );
+
+        if total_weight == 0.0 {
+            return Colour::black();
+        }
+        colour / total_weight
     }
 
     pub fn new_simple(colour: Colour, reflectiveness: f32, shininess: f32) -> Arc<Self> {
@@ -64,41 +215,79 @@ impl CompoundMaterial {
         Arc::new(compound)
     }
 
-    pub fn new_textured(texture: String, scale: f32, transparency: f32) -> Arc<Self> {
-        let texture = Texture::import(texture.to_string(), scale, 0.1, 1000000.0);
+    pub fn new_textured(
+        texture: String,
+        scale: f32,
+        transparency: f32,
+    ) -> Result<Arc<Self>, RenderError> {
+        let texture =
+            Texture::import(texture.to_string(), scale, 0.1, 1000000.0, ColourSpace::Srgb)?;
         // let texture = Arc::new(FalseColour::new());
         let global = GlobalMaterial::new(transparency, transparency, 1.0);
 
         let mut compound = Self::new();
         compound.add_material(texture);
         compound.add_material(global);
-        Arc::new(compound)
+        Ok(Arc::new(compound))
     }
 }
 
 impl Material for CompoundMaterial {
-    fn compute_once(&self, scene: &Scene, viewer: &Ray, hit: &Hit, depth: u8) -> Colour {
-        self.materials
-            .iter()
-            .fold(Colour::black(), |acc, material| {
-                acc + material.compute_once(scene, viewer, hit, depth)
-            })
-            / self.materials.len() as f32
+    fn compute_once(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        depth: u8,
+    ) -> Colour {
+        let cos_theta = Some(viewer.direction.dot(&hit.normal).abs());
+        self.layers.iter().fold(Colour::black(), |acc, layer| {
+            let weight = self.layer_weight(layer, cos_theta);
+            match weight > 0.0 {
+                true => acc + layer.material.compute_once(integrator, viewer, hit, depth) * weight,
+                false => acc,
+            }
+        })
     }
 
     fn compute_per_light(
         &self,
-        scene: &Scene,
+        integrator: &dyn Integrator,
         viewer: &Vector,
         hit: &Hit,
         ldir: &Vector,
     ) -> Colour {
-        self.materials
-            .iter()
-            .fold(Colour::black(), |acc, material| {
-                acc + material.compute_per_light(scene, viewer, hit, ldir)
-            })
-            / self.materials.len() as f32
+        self.blend_per_light(viewer, hit, |material| {
+            material.compute_per_light(integrator, viewer, hit, ldir)
+        })
+    }
+
+    fn specular_per_light(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Vector,
+        hit: &Hit,
+        ldir: &Vector,
+    ) -> Colour {
+        self.blend_per_light(viewer, hit, |material| {
+            material.specular_per_light(integrator, viewer, hit, ldir)
+        })
+    }
+
+    fn albedo(&self, hit: &Hit) -> Option<Colour> {
+        // the surface layers' colours, weighted like bounced photons are
+        let (albedo, total_weight) = self.weighted_layers(None).fold(
+            (Colour::black(), 0.0),
+            |(albedo, total_weight), (material, layer_weight)| match material.albedo(hit) {
+                Some(layer_albedo) => {
+                    let weight = surface_weight(material) * layer_weight;
+                    (albedo + layer_albedo * weight, total_weight + weight)
+                }
+                None => (albedo, total_weight),
+            },
+        );
+
+        (total_weight > 0.0).then(|| albedo / total_weight)
     }
 
     fn normal(&self, tex_coords: &TexCoords) -> Option<Vector> {
@@ -122,47 +311,68 @@ impl Material for CompoundMaterial {
 
 impl PhotonMaterial for CompoundMaterial {
     fn behaviour_weight(&self, behaviour: &PhotonBehaviour) -> f32 {
-        self.photon_materials().fold(0.0, |acc, material| {
-            acc + material.behaviour_weight(behaviour)
-        }) / self.materials.len() as f32
+        self.weighted_layers(None)
+            .fold(0.0, |acc, (material, layer_weight)| {
+                acc + material.behaviour_weight(behaviour) * layer_weight
+            })
     }
 
     fn bounced_photon(&self, photon: &Photon, hit: &Hit) -> Option<Colour> {
-        self.photon_materials()
-            .fold(None, |acc, material| match acc {
-                Some(colour) => match material.bounced_photon(photon, hit) {
-                    Some(new_colour) => Some(colour + new_colour),
-                    None => Some(colour),
-                },
-                None => material.bounced_photon(photon, hit),
-            })
+        // only the layers that actually bounce photons get a say
+        self.pick(surface_weight)?.bounced_photon(photon, hit)
     }
 
     fn render_vueon(&self, hit: &Hit, photon: &Photon, viewer: Vector) -> Colour {
-        self.photon_materials()
-            .fold(Colour::black(), |acc, material| {
-                acc + material.render_vueon(hit, photon, viewer)
-            })
+        self.blend_vueon(|material| material.render_vueon(hit, photon, viewer))
+    }
+
+    fn specular_vueon(&self, hit: &Hit, photon: &Photon, viewer: Vector) -> Colour {
+        self.blend_vueon(|material| material.specular_vueon(hit, photon, viewer))
     }
 
     fn refract_chance(&self, kr: f32) -> f32 {
-        self.photon_materials()
-            .fold(0.0, |acc, material| acc + material.refract_chance(kr))
-            / self.materials.len() as f32
+        // a texture layer can't refract, so it mustn't drag the chance down
+        self.weighted_average(
+            |material| material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract),
+            |material| material.refract_chance(kr),
+        )
     }
 
     fn refracted_direction(&self, hit: &Hit, viewer: Vector) -> Option<RefractionResult> {
-        // return the first non-None result
-        // this is fine for now because only one of our materials has
-        // refraction. ideally we would randomly pick using the refract weight
-        // of each material.
+        self.pick(|material| material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract))?
+            .refracted_direction(hit, viewer)
+    }
 
-        for material in self.photon_materials() {
-            if let Some(result) = material.refracted_direction(hit, viewer) {
-                return Some(result);
-            }
+    fn scatter_direction(&self, hit: &Hit, direction: Vector) -> Vector {
+        match self.pick(|material| material.behaviour_weight(&PhotonBehaviour::ReflectOrRefract)) {
+            Some(material) => material.scatter_direction(hit, direction),
+            None => direction,
         }
+    }
 
-        None
+    fn select(&self, behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        match self.pick(|material| material.behaviour_weight(behaviour)) {
+            Some(material) => material.select(behaviour),
+            None => self,
+        }
     }
 }
+
+// how much light a smooth surface with this index of refraction reflects,
+// seen at cos_theta, or on average over every direction without one
+fn schlick(ior: f32, cos_theta: Option<f32>) -> f32 {
+    let r0 = ((1.0 - ior) / (1.0 + ior)).powi(2);
+    match cos_theta {
+        Some(cos_theta) => r0 + (1.0 - r0) * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5),
+        // the cosine weighted average of (1 - cos)^5 is 1/21
+        None => r0 + (1.0 - r0) / 21.0,
+    }
+}
+
+// how much a material absorbs or bounces photons off its surface,
+// as opposed to reflecting or refracting them
+fn surface_weight(material: &dyn PhotonMaterial) -> f32 {
+    material.behaviour_weight(&PhotonBehaviour::Absorb)
+        + material.behaviour_weight(&PhotonBehaviour::Diffuse)
+        + material.behaviour_weight(&PhotonBehaviour::Specular)
+}
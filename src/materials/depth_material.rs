@@ -0,0 +1,68 @@
+// This Material class shades by distance from the camera, white at near
+// fading to black at far, ignoring the lights. A useful debug tool for
+// checking scale and placement. In photon scenes, where vueons don't know
+// how far they've come, it's the distance from the last bounce.
+
+use crate::{
+    core::{colour::Colour, hit::Hit, photon::Photon, ray::Ray, vector::Vector},
+    environments::environment::Integrator,
+};
+
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
+
+pub struct DepthGradient {
+    near: f32,
+    far: f32,
+}
+
+impl DepthGradient {
+    pub fn new(near: f32, far: f32) -> Self {
+        Self { near, far }
+    }
+
+    pub fn colour_at(&self, distance: f32) -> Colour {
+        let t = ((distance - self.near) / (self.far - self.near)).clamp(0.0, 1.0);
+        Colour::grey(1.0 - t)
+    }
+}
+
+impl Material for DepthGradient {
+    fn compute_once(
+        &self,
+        _integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
+        // including the way to a mirror or through glass
+        self.colour_at(viewer.travelled + hit.distance)
+    }
+
+    fn compute_per_light(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Vector,
+        _hit: &Hit,
+        _ldir: &Vector,
+    ) -> Colour {
+        Colour::black()
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
+
+impl PhotonMaterial for DepthGradient {
+    fn bounced_photon(&self, photon: &Photon, _hit: &Hit) -> Option<Colour> {
+        Some(photon.intensity)
+    }
+
+    fn render_vueon(&self, hit: &Hit, photon: &Photon, _viewer: Vector) -> Colour {
+        self.colour_at(hit.distance) * photon.intensity
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
+}
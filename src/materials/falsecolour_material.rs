@@ -3,13 +3,19 @@
 
 use crate::{
     core::{colour::Colour, hit::Hit, ray::Ray, vector::Vector},
-    environments::scene::Scene,
+    environments::environment::Integrator,
 };
 
-use super::material::{Material, PhotonMaterial};
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
 
 pub struct FalseColour {}
 
+impl Default for FalseColour {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FalseColour {
     pub fn new() -> Self {
         Self {}
@@ -25,13 +31,19 @@ impl FalseColour {
 }
 
 impl Material for FalseColour {
-    fn compute_once(&self, _scene: &Scene, _viewer: &Ray, hit: &Hit, _depth: u8) -> Colour {
+    fn compute_once(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
         self.colour_at_hit(hit)
     }
 
     fn compute_per_light(
         &self,
-        _scene: &Scene,
+        _integrator: &dyn Integrator,
         _viewer: &Vector,
         _hit: &Hit,
         _ldir: &Vector,
@@ -57,4 +69,8 @@ impl PhotonMaterial for FalseColour {
     ) -> Colour {
         self.colour_at_hit(hit) * photon.intensity
     }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
 }
@@ -1,29 +1,70 @@
-// The global material generates a reflection/refraction layer
+// The global material generates a reflection/refraction layer. Rough ones
+// scatter their reflections and refractions, for brushed metal and frosted
+// glass.
 
-use std::sync::Arc;
+use std::{f32::consts::FRAC_PI_2, sync::Arc};
 
 use crate::{
     core::{colour::Colour, hit::Hit, photon::Photon, ray::Ray, vector::Vector},
-    environments::scene::Scene,
+    environments::environment::Integrator,
 };
 
 use super::material::{Material, PhotonBehaviour, PhotonMaterial, RefractionResult};
 
+// how many rays rough reflections and refractions are averaged over, when
+// seen straight from the camera. after that, one ray each.
+const ROUGH_SAMPLES: u32 = 8;
+
 pub struct GlobalMaterial {
     reflect_weight: f32,
     refract_weight: f32,
-    ior: f32, // index of refraction
+    ior: f32,       // index of refraction
+    roughness: f32, // 0 for a perfect mirror or clear glass, up to 1
 }
 
 impl GlobalMaterial {
     pub fn new(reflect_weight: f32, refract_weight: f32, ior: f32) -> Arc<Self> {
+        Self::new_rough(reflect_weight, refract_weight, ior, 0.0)
+    }
+
+    pub fn new_rough(
+        reflect_weight: f32,
+        refract_weight: f32,
+        ior: f32,
+        roughness: f32,
+    ) -> Arc<Self> {
         Arc::new(Self {
             reflect_weight,
             refract_weight,
             ior,
+            roughness,
         })
     }
 
+    // somewhere in a cone around the ideal direction, from none at all for
+    // smooth surfaces to the whole hemisphere at roughness 1. directions
+    // that end up on the wrong side of the surface are mirrored back.
+    fn scattered(&self, hit: &Hit, direction: Vector) -> Vector {
+        if self.roughness == 0.0 {
+            return direction;
+        }
+
+        let cos_max = (self.roughness * FRAC_PI_2).cos();
+        let scattered = Vector::random_in_cone(direction, cos_max);
+        let past_surface = hit.normal.dot(&scattered);
+        if past_surface * hit.normal.dot(&direction) < 0.0 {
+            return scattered - hit.normal * (2.0 * past_surface);
+        }
+        scattered
+    }
+
+    fn samples(&self, depth: u8) -> u32 {
+        match self.roughness > 0.0 && depth == 0 {
+            true => ROUGH_SAMPLES,
+            false => 1,
+        }
+    }
+
     #[allow(non_snake_case)]
     fn refraction(&self, hit: &Hit, incoming: Vector) -> Option<RefractionResult> {
         if self.refract_weight == 0.0 {
@@ -57,8 +98,7 @@ impl GlobalMaterial {
         let kr = (r_par.powi(2) + r_per.powi(2)) / 2.0; // reflectance coefficient
 
         // raytrace the refracted ray
-        let refract_origin = hit.position.clone() + T * 0.0001;
-        let refract_ray = Ray::new(refract_origin, T);
+        let refract_ray = Ray::new(hit.position.clone(), T);
 
         Some(RefractionResult {
             ray: refract_ray,
@@ -68,33 +108,72 @@ impl GlobalMaterial {
 }
 
 impl Material for GlobalMaterial {
-    fn compute_once(&self, scene: &Scene, viewer: &Ray, hit: &Hit, depth: u8) -> Colour {
-        if depth >= 5 {
-            return Colour::black();
-        }
+    fn compute_once(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        depth: u8,
+    ) -> Colour {
+        // work out the fresnel split first, to know how much each ray carries
+        let refraction = self.refraction(hit, viewer.direction);
+        let kr = refraction.as_ref().map_or(0.0, |refraction| refraction.kr);
+        let settings = integrator.settings();
+        let samples = self.samples(depth);
 
         // reflection
         let mut reflection_colour = None;
         if self.reflect_weight > 0.0 {
-            // spawn a reflection ray at the hit point
-            let reflection_direction = hit.normal.reflection(&viewer.direction).normalised();
-            let reflection_origin = hit.position.clone() + reflection_direction * 0.0001;
-            let reflection_ray = Ray::new(reflection_origin, reflection_direction);
-
-            reflection_colour =
-                Some(scene.raytrace(&reflection_ray, depth + 1).colour * self.reflect_weight);
+            let ideal_direction = hit.normal.reflection(&viewer.direction).normalised();
+            let weight = match refraction {
+                Some(_) => self.reflect_weight * kr,
+                None => self.reflect_weight,
+            };
+
+            let mut total = Colour::black();
+            for _ in 0..samples {
+                // spawn a reflection ray at the hit point
+                let reflection_direction = self.scattered(hit, ideal_direction);
+                let reflection_origin = settings.offset(&hit.position, &reflection_direction);
+                let reflection_ray = Ray::new(reflection_origin, reflection_direction)
+                    .continued(viewer, hit.distance)
+                    .weighted(weight);
+
+                let survival = settings
+                    .path_depth
+                    .survival(depth, reflection_ray.throughput);
+                total += match survival {
+                    Some(chance) => settings.clamp_indirect(
+                        integrator.raytrace(&reflection_ray, depth + 1).colour
+                            * (self.reflect_weight / chance),
+                    ),
+                    None => Colour::black(),
+                };
+            }
+            reflection_colour = Some(total / samples as f32);
         }
 
         // refraction
         let mut refraction_colour = None;
-        let mut kr = 0.0;
-        if self.refract_weight > 0.0 {
-            if let Some(refract_result) = self.refraction(hit, viewer.direction) {
-                kr = refract_result.kr;
-
-                let raytraced_colour = scene.raytrace(&refract_result.ray, depth + 1).colour;
-                refraction_colour = Some(raytraced_colour * self.refract_weight);
+        if let Some(refraction) = refraction {
+            let mut total = Colour::black();
+            for _ in 0..samples {
+                let refract_direction = self.scattered(hit, refraction.ray.direction);
+                let refract_origin = settings.offset(&hit.position, &refract_direction);
+                let refract_ray = Ray::new(refract_origin, refract_direction)
+                    .continued(viewer, hit.distance)
+                    .weighted(self.refract_weight * (1.0 - kr));
+
+                let survival = settings.path_depth.survival(depth, refract_ray.throughput);
+                total += match survival {
+                    Some(chance) => settings.clamp_indirect(
+                        integrator.raytrace(&refract_ray, depth + 1).colour
+                            * (self.refract_weight / chance),
+                    ),
+                    None => Colour::black(),
+                };
             }
+            refraction_colour = Some(total / samples as f32);
         }
 
         match (reflection_colour, refraction_colour) {
@@ -134,4 +213,16 @@ impl PhotonMaterial for GlobalMaterial {
     fn refracted_direction(&self, hit: &Hit, incoming: Vector) -> Option<RefractionResult> {
         self.refraction(hit, incoming)
     }
+
+    fn roughness(&self) -> f32 {
+        self.roughness
+    }
+
+    fn scatter_direction(&self, hit: &Hit, direction: Vector) -> Vector {
+        self.scattered(hit, direction)
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
 }
@@ -0,0 +1,79 @@
+// A Phong material for hair and fur, lit along the fibres instead of by the
+// normal, from Kajiya and Kay 1989, "Rendering Fur with Three Dimensional
+// Textures". Fibres are thin cylinders, so a light lights the whole ring
+// around them, and they shine in a band across the strands rather than at a
+// point. Like in Scheuermann 2004, "Practical Real-Time Hair Rendering and
+// Shading", there are two highlights: a white one off the surface, and one
+// in the hair's colour that went through it, each shifted along the strand
+// the opposite way. Hits that don't know their fibre's direction are shaded
+// like any Phong material.
+
+use std::sync::Arc;
+
+use crate::core::{colour::Colour, hit::Hit, vector::Vector};
+
+use super::{material::PhotonMaterial, phong_material::Phong};
+
+pub struct Hair {
+    colour: Colour,
+    ambient_strength: f32,
+    shininess: f32,
+    shift: f32, // how far the highlights move along the strand, tilting it
+}
+
+impl Hair {
+    pub fn new(colour: Colour, ambient_strength: f32, shininess: f32, shift: f32) -> Arc<Self> {
+        Arc::new(Self {
+            colour,
+            ambient_strength,
+            shininess,
+            shift,
+        })
+    }
+
+    // the highlight from a strand tilted towards the normal by shift, as in
+    // the sine of the angle between it and the half vector
+    fn highlight(tangent: Vector, normal: Vector, half: Vector, shift: f32, shininess: f32) -> f32 {
+        let tangent = (tangent + normal * shift).normalised();
+        let cos = tangent.dot(&half);
+        (1.0 - cos * cos).max(0.0).sqrt().powf(shininess)
+    }
+}
+
+impl Phong for Hair {
+    fn colour_at_hit(&self, _hit: &Hit) -> Colour {
+        self.colour
+    }
+
+    fn ambient_strength(&self) -> f32 {
+        self.ambient_strength
+    }
+
+    fn shininess(&self) -> f32 {
+        self.shininess
+    }
+
+    fn diffuse(&self, hit: &Hit, ldir: &Vector) -> Colour {
+        let Some(tangent) = hit.tangent else {
+            return self.colour * -hit.normal.dot(ldir);
+        };
+        let cos = tangent.dot(ldir);
+        self.colour * (1.0 - cos * cos).max(0.0).sqrt()
+    }
+
+    fn specular(&self, hit: &Hit, ldir: &Vector, viewer: &Vector) -> Colour {
+        let Some(tangent) = hit.tangent else {
+            let reflection = hit.normal.reflection(ldir).normalised();
+            return Colour::white() * viewer.dot(&reflection).max(0.0).powf(self.shininess);
+        };
+        let half = (*viewer - *ldir).normalised();
+        let primary = Self::highlight(tangent, hit.normal, half, -self.shift, self.shininess);
+        let secondary =
+            Self::highlight(tangent, hit.normal, half, self.shift, self.shininess / 2.0);
+        Colour::white() * (primary * 0.5) + self.colour * secondary
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
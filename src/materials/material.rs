@@ -4,17 +4,35 @@ use crate::{
     core::{
         colour::Colour, hit::Hit, photon::Photon, ray::Ray, tex_coords::TexCoords, vector::Vector,
     },
-    environments::scene::Scene,
+    environments::environment::Integrator,
 };
 
 pub trait Material: Send + Sync {
     // called once per intersection
-    fn compute_once(&self, scene: &Scene, viewer: &Ray, hit: &Hit, depth: u8) -> Colour;
+    fn compute_once(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        depth: u8,
+    ) -> Colour;
 
     // called for each light that reaches a surface
     fn compute_per_light(
         &self,
-        scene: &Scene,
+        integrator: &dyn Integrator,
+        viewer: &Vector,
+        hit: &Hit,
+        ldir: &Vector,
+    ) -> Colour {
+        Colour::black()
+    }
+
+    // the highlight part of compute_per_light, to split direct light into
+    // the diffuse and specular lighting passes
+    fn specular_per_light(
+        &self,
+        integrator: &dyn Integrator,
         viewer: &Vector,
         hit: &Hit,
         ldir: &Vector,
@@ -22,11 +40,23 @@ pub trait Material: Send + Sync {
         Colour::black()
     }
 
+    // the surface's base colour, before any lighting. None for materials
+    // that only reflect or refract.
+    fn albedo(&self, hit: &Hit) -> Option<Colour> {
+        None
+    }
+
     // materials that support bump/normal maps should implement this
     fn normal(&self, tex_coords: &TexCoords) -> Option<Vector> {
         None
     }
 
+    // surfaces that send rays somewhere else rather than being shaded
+    // (portals and mirrors) return the ray to carry on tracing with
+    fn teleport(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        None
+    }
+
     // You will need additional material methods to support Photon-mapping.
 
     // assert this is a photon mapped material and return a reference to it
@@ -60,18 +90,51 @@ pub trait PhotonMaterial: Material {
         }
     }
 
+    // how much the surface scatters photons rather than reflecting or
+    // refracting them cleanly, 0 for a perfect mirror or glass
+    fn roughness(&self) -> f32 {
+        let smooth = self.behaviour_weight(&PhotonBehaviour::ReflectOrRefract);
+        let total = smooth
+            + self.behaviour_weight(&PhotonBehaviour::Absorb)
+            + self.behaviour_weight(&PhotonBehaviour::Diffuse)
+            + self.behaviour_weight(&PhotonBehaviour::Specular);
+        if total > 0.0 {
+            1.0 - smooth / total
+        } else {
+            1.0
+        }
+    }
+
     // these return None if the absorb, diffuse and specular weights are all 0
     // i.e. the object is transparent or mirror
     fn bounced_photon(&self, photon: &Photon, hit: &Hit) -> Option<Colour>;
+    // materials that don't shade photons themselves show them as the light
+    // they would bounce, so caustics and the rest still show up on them
     fn render_vueon(&self, hit: &Hit, photon: &Photon, viewer: Vector) -> Colour {
+        self.bounced_photon(photon, hit).unwrap_or(Colour::black())
+    }
+    // the highlight part of render_vueon, like specular_per_light
+    fn specular_vueon(&self, hit: &Hit, photon: &Photon, viewer: Vector) -> Colour {
         Colour::black()
     }
 
-    // these return None/0 if the object is neither reflective or transparent
+    // these return None/0 if the object is neither reflective or transparent.
+    // the refracted ray starts on the surface, see RenderSettings::offset()
     fn refract_chance(&self, kr: f32) -> f32 {
         0.0
     }
     fn refracted_direction(&self, hit: &Hit, viewer_incoming: Vector) -> Option<RefractionResult> {
         None
     }
+
+    // a reflected or refracted direction, scattered by how rough the surface
+    // is. perfectly smooth surfaces leave it as it is.
+    fn scatter_direction(&self, hit: &Hit, direction: Vector) -> Vector {
+        direction
+    }
+
+    // the material that should handle a photon once a behaviour has been picked.
+    // single materials return themselves, compound materials pick one of their
+    // layers at random, weighted by how much each layer does that behaviour.
+    fn select(&self, behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial;
 }
@@ -0,0 +1,61 @@
+// This Material class shades everything white, darkened by ambient
+// occlusion, and ignores the lights. A useful debug tool for seeing where
+// objects crowd each other. Photon scenes don't trace occlusion rays, so
+// there it's plain white.
+
+use crate::{
+    core::{colour::Colour, hit::Hit, photon::Photon, ray::Ray, vector::Vector},
+    environments::{ambient_occlusion::AmbientOcclusion, environment::Integrator},
+};
+
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
+
+pub struct Occlusion {
+    ambient_occlusion: AmbientOcclusion,
+}
+
+impl Occlusion {
+    pub fn new(ambient_occlusion: AmbientOcclusion) -> Self {
+        Self { ambient_occlusion }
+    }
+}
+
+impl Material for Occlusion {
+    fn compute_once(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
+        Colour::white() * self.ambient_occlusion.unoccluded(integrator, viewer, hit)
+    }
+
+    fn compute_per_light(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Vector,
+        _hit: &Hit,
+        _ldir: &Vector,
+    ) -> Colour {
+        Colour::black()
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
+
+impl PhotonMaterial for Occlusion {
+    fn bounced_photon(&self, photon: &Photon, _hit: &Hit) -> Option<Colour> {
+        Some(photon.intensity)
+    }
+
+    fn render_vueon(&self, _hit: &Hit, photon: &Photon, _viewer: Vector) -> Colour {
+        photon.intensity
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
+}
@@ -4,10 +4,10 @@ use crate::{
     core::{
         colour::Colour, hit::Hit, photon::Photon, ray::Ray, tex_coords::TexCoords, vector::Vector,
     },
-    environments::scene::Scene,
+    environments::environment::Integrator,
 };
 
-use super::material::{Material, PhotonMaterial};
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
 
 pub trait Phong: Send + Sync {
     fn colour_at_hit(&self, hit: &Hit) -> Colour;
@@ -36,13 +36,24 @@ pub trait Phong: Send + Sync {
 
 // impl material for PhongT
 impl<T: Phong> Material for T {
-    fn compute_once(&self, _scene: &Scene, _viewer: &Ray, hit: &Hit, _depth: u8) -> Colour {
-        self.ambient(hit)
+    fn compute_once(
+        &self,
+        integrator: &dyn Integrator,
+        viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
+        // ambient light doesn't come from anywhere, so it arrives along with
+        // the ray that sees it
+        if !integrator.in_path_window(viewer.travelled + hit.distance) {
+            return Colour::black();
+        }
+        self.ambient(hit) * integrator.ambient_visibility(viewer, hit)
     }
 
     fn compute_per_light(
         &self,
-        _scene: &Scene,
+        _integrator: &dyn Integrator,
         viewer: &Vector,
         hit: &Hit,
         ldir: &Vector,
@@ -50,6 +61,20 @@ impl<T: Phong> Material for T {
         self.diffuse(hit, ldir) + self.specular(hit, ldir, viewer)
     }
 
+    fn specular_per_light(
+        &self,
+        _integrator: &dyn Integrator,
+        viewer: &Vector,
+        hit: &Hit,
+        ldir: &Vector,
+    ) -> Colour {
+        self.specular(hit, ldir, viewer)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Option<Colour> {
+        Some(self.colour_at_hit(hit))
+    }
+
     fn normal(&self, tex_coords: &TexCoords) -> Option<Vector> {
         self.normal(tex_coords)
     }
@@ -63,21 +88,44 @@ pub struct Monochrome {
     colour: Colour,
     ambient_strength: f32,
     shininess: f32,
+
+    // how far the hue of each object instance is randomly shifted, as a
+    // fraction of the colour wheel. the seed picks a different set of shifts.
+    hue_variation: f32,
+    seed: u32,
 }
 
 impl Monochrome {
     pub fn new(colour: Colour, ambient_strength: f32, shininess: f32) -> Arc<Self> {
+        Self::new_varied(colour, ambient_strength, shininess, 0.0, 0)
+    }
+
+    pub fn new_varied(
+        colour: Colour,
+        ambient_strength: f32,
+        shininess: f32,
+        hue_variation: f32,
+        seed: u32,
+    ) -> Arc<Self> {
         Arc::new(Self {
             colour,
             ambient_strength,
             shininess,
+            hue_variation,
+            seed,
         })
     }
 }
 
 impl Phong for Monochrome {
-    fn colour_at_hit(&self, _hit: &Hit) -> Colour {
-        self.colour
+    fn colour_at_hit(&self, hit: &Hit) -> Colour {
+        if self.hue_variation == 0.0 {
+            return self.colour;
+        }
+
+        let random = hit.random_per_instance(self.seed) - 0.5; // -0.5 <= random < 0.5
+        let angle = random * self.hue_variation * std::f32::consts::TAU;
+        self.colour.hue_rotated(angle)
     }
 
     fn ambient_strength(&self) -> f32 {
@@ -103,4 +151,12 @@ impl<T: Phong> PhotonMaterial for T {
             self.diffuse(hit, &photon.incident) + self.specular(hit, &photon.incident, &viewer);
         colour * photon.intensity
     }
+
+    fn specular_vueon(&self, hit: &Hit, photon: &Photon, viewer: Vector) -> Colour {
+        self.specular(hit, &photon.incident, &viewer) * photon.intensity
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
 }
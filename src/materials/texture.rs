@@ -1,12 +1,20 @@
 use std::{
-    io::{BufRead, Read},
-    path::PathBuf,
+    io::BufRead,
+    path::{Path, PathBuf},
     process::Command,
-    sync::Arc,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::SystemTime,
 };
 
 use crate::{
-    core::{colour::Colour, hit::Hit, tex_coords::TexCoords, vector::Vector},
+    core::{
+        colour::{Colour, ColourSpace},
+        error::RenderError,
+        framebuffer::FrameBuffer,
+        hit::Hit,
+        tex_coords::TexCoords,
+        vector::Vector,
+    },
     parse_path,
 };
 
@@ -19,29 +27,31 @@ pub struct Image {
 }
 
 impl Image {
-    pub fn from_image(path: PathBuf) -> Result<Self, String> {
+    // colour_space is what the image's values are encoded in. they're
+    // decoded to linear as the image is loaded.
+    pub fn from_image(path: PathBuf, colour_space: ColourSpace) -> Result<Self, RenderError> {
         let ppm_path = path.with_extension("ppm");
-        if !ppm_path.exists() {
-            // convert to ppm
-            Command::new("ffmpeg")
-                .arg("-y")
-                .arg("-hide_banner")
-                .arg("-loglevel")
-                .arg("warning")
-                .arg("-i")
-                .arg(path)
-                .arg(ppm_path.clone())
-                .output()
-                .map_err(|e| e.to_string())?;
+        if is_stale(&ppm_path, &path) {
+            if let Err(e) = convert_to_ppm(&path, &ppm_path) {
+                // an old conversion is better than none
+                if !ppm_path.exists() {
+                    return Err(e);
+                }
+                println!("{e}, using the ppm converted before");
+            }
         }
 
-        Self::from_ppm(ppm_path)
+        Self::from_ppm(ppm_path, colour_space)
     }
 
-    pub fn from_ppm(path: PathBuf) -> Result<Self, String> {
-        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    pub fn from_ppm(path: PathBuf, colour_space: ColourSpace) -> Result<Self, RenderError> {
+        let file = std::fs::File::open(&path).map_err(|e| RenderError::io(&path, e))?;
         let mut reader = std::io::BufReader::new(file);
+        Self::read_ppm(&mut reader, colour_space)
+            .map_err(|message| RenderError::Texture { path, message })
+    }
 
+    fn read_ppm(reader: &mut impl BufRead, colour_space: ColourSpace) -> Result<Self, String> {
         fn read_until_whitespace(reader: &mut impl BufRead) -> Result<String, String> {
             let mut result = String::new();
             loop {
@@ -60,18 +70,23 @@ impl Image {
                 .map_err(|e| e.to_string())
         }
 
-        if read_until_whitespace(&mut reader)? != "P6" {
+        if read_until_whitespace(reader)? != "P6" {
             return Err("Invalid PPM file: expected P6".to_string());
         }
 
-        let width = read_u32(&mut reader)?;
-        let height = read_u32(&mut reader)?;
-        let max_value = read_u32(&mut reader)?;
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let max_value = read_u32(reader)?;
+        // lookups wrap around the image, which needs at least one pixel
+        if width == 0 || height == 0 || max_value == 0 {
+            return Err("Invalid PPM file: empty image".to_string());
+        }
 
         let bytes_per_sample = if max_value <= 255 { 1 } else { 2 };
 
-        let mut pixels: Vec<Colour> = Vec::with_capacity((width * height) as usize);
-        for _ in 0..(width * height) {
+        let num_pixels = width as usize * height as usize;
+        let mut pixels: Vec<Colour> = Vec::with_capacity(num_pixels);
+        for _ in 0..num_pixels {
             let mut buf = [0; 6];
             let buf = &mut buf[0..(bytes_per_sample * 3)];
 
@@ -92,7 +107,7 @@ impl Image {
                 (b as f32) / (max_value as f32),
             );
 
-            pixels.push(Colour::new(r, g, b));
+            pixels.push(colour_space.decode(Colour::new(r, g, b)));
         }
 
         Ok(Self {
@@ -118,37 +133,215 @@ impl Image {
         self.get_xy(x, y)
     }
 
-    fn get(&self, tex_coords: impl Into<TexCoords>) -> Colour {
+    pub fn get(&self, tex_coords: impl Into<TexCoords>) -> Colour {
         let tex_coords = tex_coords.into();
         self.get_uv(tex_coords.u, tex_coords.v)
     }
+
+    // the displacement.jpg next to a texture's other images, for models.
+    // heights rather than colours, so not decoded.
+    pub fn import_displacement(name: &str) -> Result<Self, RenderError> {
+        let folder = parse_path(&format!("assets/textures/{}", name));
+        Self::from_image(folder.join("displacement.jpg"), ColourSpace::Linear)
+    }
+
+    // a texture's diffuse.jpg on its own, e.g. for a projector's slide
+    pub fn import_diffuse(name: &str) -> Result<Self, RenderError> {
+        let folder = parse_path(&format!("assets/textures/{}", name));
+        Self::from_image(folder.join("diffuse.jpg"), ColourSpace::Srgb)
+    }
+
+    // the mean of every pixel
+    pub fn average(&self) -> Colour {
+        let total = self
+            .pixels
+            .iter()
+            .fold(Colour::black(), |acc, pixel| acc + *pixel);
+        total / self.pixels.len().max(1) as f32
+    }
+
+    // the pixels as they are, e.g. to compare renders
+    pub fn to_framebuffer(&self) -> FrameBuffer {
+        let mut framebuffer = FrameBuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                framebuffer.plot_pixel(x, y, &self.get_xy(x, y));
+            }
+        }
+        framebuffer
+    }
+
+    // height over width
+    pub fn aspect(&self) -> f32 {
+        self.height as f32 / self.width as f32
+    }
+
+    // the pixel at x and y from 0 to 1 across the image, from its top left
+    // corner. unlike get(), nothing is flipped or repeated.
+    pub fn sample(&self, x: f32, y: f32) -> Colour {
+        let x = (x.clamp(0.0, 1.0) * (self.width - 1) as f32).round() as u32;
+        let y = (y.clamp(0.0, 1.0) * (self.height - 1) as f32).round() as u32;
+        self.get_xy(x, y)
+    }
+}
+
+fn convert_to_ppm(path: &Path, ppm_path: &Path) -> Result<(), RenderError> {
+    let output = Command::new("ffmpeg")
+        .arg("-y")
+        .arg("-hide_banner")
+        .arg("-loglevel")
+        .arg("warning")
+        .arg("-i")
+        .arg(path)
+        .arg(ppm_path)
+        .output()
+        .map_err(|e| RenderError::Texture {
+            path: path.to_path_buf(),
+            message: format!("Could not run FFmpeg to convert it: {e}"),
+        })?;
+    if !output.status.success() {
+        return Err(RenderError::Texture {
+            path: path.to_path_buf(),
+            message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+// whether the ppm converted from source is missing or older than it
+fn is_stale(ppm_path: &Path, source: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(ppm_path), modified(source)) {
+        (None, _) => true,
+        (Some(ppm), Some(source)) => source > ppm,
+        (Some(_), None) => false,
+    }
+}
+
+// the images textures are drawn from, shared by every material using the same
+// file. when watching a scene, only these are reloaded if just their files
+// changed, instead of building the whole scene again (see reload_changed()).
+static CACHE: Mutex<Vec<Weak<CachedImage>>> = Mutex::new(Vec::new());
+
+pub struct CachedImage {
+    path: PathBuf,
+    colour_space: ColourSpace,
+    modified: Mutex<Option<SystemTime>>, // of the file when it was loaded
+    image: RwLock<Image>,
+}
+
+impl CachedImage {
+    // the image at path, from the cache if it's already loaded and its file
+    // hasn't changed since
+    pub fn load(path: PathBuf, colour_space: ColourSpace) -> Result<Arc<Self>, RenderError> {
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|image| image.strong_count() > 0);
+        let cached = cache
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|image| image.path == path && image.colour_space == colour_space);
+        if let Some(image) = cached {
+            if image.changed() {
+                image.reload()?;
+            }
+            return Ok(image);
+        }
+
+        let modified = Self::modified_now(&path);
+        let image = Arc::new(Self {
+            image: RwLock::new(Image::from_image(path.clone(), colour_space)?),
+            path,
+            colour_space,
+            modified: Mutex::new(modified),
+        });
+        cache.push(Arc::downgrade(&image));
+        Ok(image)
+    }
+
+    pub fn get(&self, tex_coords: impl Into<TexCoords>) -> Colour {
+        self.image.read().unwrap().get(tex_coords)
+    }
+
+    // the image's file, or the ppm it was converted to if it's gone
+    fn modified_now(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .or_else(|_| std::fs::metadata(path.with_extension("ppm")))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    fn changed(&self) -> bool {
+        Self::modified_now(&self.path) != *self.modified.lock().unwrap()
+    }
+
+    // swap in the file's current pixels. on error the old ones stay.
+    fn reload(&self) -> Result<(), RenderError> {
+        // set first, so a broken file is only reported once
+        *self.modified.lock().unwrap() = Self::modified_now(&self.path);
+        let image = Image::from_image(self.path.clone(), self.colour_space)?;
+        *self.image.write().unwrap() = image;
+        Ok(())
+    }
+}
+
+// whether the file of any texture image still in use has changed since it was
+// loaded
+pub fn images_changed() -> bool {
+    let cache = CACHE.lock().unwrap();
+    cache
+        .iter()
+        .filter_map(Weak::upgrade)
+        .any(|image| image.changed())
+}
+
+// reload every texture image still in use whose file has changed, in place,
+// so the scenes using them see the new pixels without being rebuilt
+pub fn reload_changed() {
+    let cache = CACHE.lock().unwrap();
+    for image in cache.iter().filter_map(Weak::upgrade) {
+        if !image.changed() {
+            continue;
+        }
+        match image.reload() {
+            Ok(()) => println!("Reloaded texture {}", image.path.display()),
+            Err(e) => println!("Failed to reload texture, keeping the old one! {e}"),
+        }
+    }
 }
 
 pub struct Texture {
-    pub diffuse: Image,
-    pub normal: Option<Image>,
-    pub roughness: Option<Image>,
+    pub diffuse: Arc<CachedImage>,
+    pub normal: Option<Arc<CachedImage>>,
+    pub roughness: Option<Arc<CachedImage>>,
     scale: f32,
     ambient_strength: f32,
     shininess: f32,
 }
 
 impl Texture {
-    pub fn import(name: String, scale: f32, ambient_strength: f32, shininess: f32) -> Arc<Self> {
+    // the normal and roughness maps are optional, the diffuse image isn't.
+    // colour_space is the diffuse image's, the others are always data.
+    pub fn import(
+        name: String,
+        scale: f32,
+        ambient_strength: f32,
+        shininess: f32,
+        colour_space: ColourSpace,
+    ) -> Result<Arc<Self>, RenderError> {
         let folder = parse_path(&format!("assets/textures/{}", name));
 
-        let diffuse = Image::from_image(folder.join("diffuse.jpg")).unwrap();
-        let normal = Image::from_image(folder.join("normal.jpg")).ok();
-        let roughness = Image::from_image(folder.join("roughness.jpg")).ok();
+        let diffuse = CachedImage::load(folder.join("diffuse.jpg"), colour_space)?;
+        let normal = CachedImage::load(folder.join("normal.jpg"), ColourSpace::Linear).ok();
+        let roughness = CachedImage::load(folder.join("roughness.jpg"), ColourSpace::Linear).ok();
 
-        Arc::new(Self {
+        Ok(Arc::new(Self {
             diffuse,
             normal,
             roughness,
             scale,
             ambient_strength,
             shininess,
-        })
+        }))
     }
 }
 
@@ -172,9 +365,7 @@ impl Phong for Texture {
 
     fn normal(&self, tex_coords: &TexCoords) -> Option<Vector> {
         // return None; // tmp
-        let Some(normal) = self.normal.as_ref() else {
-            return None;
-        };
+        let normal = self.normal.as_ref()?;
         let normal = normal.get(tex_coords.clone() * self.scale);
         let normal = Vector::new(normal.r, normal.g, normal.b);
         let normal: Vector = normal * 2.0 - Vector::new(1.0, 1.0, 1.0);
@@ -0,0 +1,78 @@
+// This Material class paints a checkerboard in texture space, ignoring the
+// lights. A useful debug tool for finding stretched or flipped uvs and
+// seams. Objects without texture coordinates show up magenta.
+
+use crate::{
+    core::{colour::Colour, hit::Hit, photon::Photon, ray::Ray, vector::Vector},
+    environments::environment::Integrator,
+};
+
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
+
+pub struct UvChecker {
+    squares: f32, // along each side of the 0 to 1 uv square
+}
+
+impl UvChecker {
+    pub fn new(squares: f32) -> Self {
+        Self { squares }
+    }
+
+    pub fn colour_at_hit(&self, hit: &Hit) -> Colour {
+        let Some(tex_coords) = &hit.tex_coords else {
+            return Colour::new(1.0, 0.0, 1.0);
+        };
+        let u = (tex_coords.u * self.squares).floor() as i64;
+        let v = (tex_coords.v * self.squares).floor() as i64;
+
+        // the squares shade from red to green along u and black to blue
+        // along v, so which way the uvs run can be read off them too
+        let shade = match (u + v).rem_euclid(2) {
+            0 => 1.0,
+            _ => 0.3,
+        };
+        let across = tex_coords.u.rem_euclid(1.0);
+        let down = tex_coords.v.rem_euclid(1.0);
+        Colour::new(1.0 - across, across, down) * shade
+    }
+}
+
+impl Material for UvChecker {
+    fn compute_once(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
+        self.colour_at_hit(hit)
+    }
+
+    fn compute_per_light(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Vector,
+        _hit: &Hit,
+        _ldir: &Vector,
+    ) -> Colour {
+        Colour::black()
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
+
+impl PhotonMaterial for UvChecker {
+    fn bounced_photon(&self, photon: &Photon, hit: &Hit) -> Option<Colour> {
+        Some(self.colour_at_hit(hit) * photon.intensity)
+    }
+
+    fn render_vueon(&self, hit: &Hit, photon: &Photon, _viewer: Vector) -> Colour {
+        self.colour_at_hit(hit) * photon.intensity
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
+}
@@ -0,0 +1,43 @@
+// A Phong material coloured by the mesh's vertex colours, e.g. for meshes
+// made from coloured point clouds. Hits without vertex colours use the
+// fallback colour instead.
+
+use std::sync::Arc;
+
+use crate::core::{colour::Colour, hit::Hit};
+
+use super::{material::PhotonMaterial, phong_material::Phong};
+
+pub struct VertexColour {
+    fallback: Colour,
+    ambient_strength: f32,
+    shininess: f32,
+}
+
+impl VertexColour {
+    pub fn new(fallback: Colour, ambient_strength: f32, shininess: f32) -> Arc<Self> {
+        Arc::new(Self {
+            fallback,
+            ambient_strength,
+            shininess,
+        })
+    }
+}
+
+impl Phong for VertexColour {
+    fn colour_at_hit(&self, hit: &Hit) -> Colour {
+        hit.colour.unwrap_or(self.fallback)
+    }
+
+    fn ambient_strength(&self) -> f32 {
+        self.ambient_strength
+    }
+
+    fn shininess(&self) -> f32 {
+        self.shininess
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
@@ -0,0 +1,86 @@
+// A Phong material whose colour is worn away on convex edges and dirtied in
+// concave cavities, based on the curvature at the hit.
+
+use std::sync::Arc;
+
+use crate::core::{colour::Colour, hit::Hit};
+
+use super::{material::PhotonMaterial, phong_material::Phong};
+
+pub struct Weathered {
+    colour: Colour,
+    wear_colour: Colour,
+    dirt_colour: Colour,
+    wear_curvature: f32, // curvature at which an edge is fully worn
+    dirt_curvature: f32, // (negative) curvature at which a cavity is fully dirty
+    ambient_strength: f32,
+    shininess: f32,
+}
+
+impl Weathered {
+    pub fn new(
+        colour: Colour,
+        wear_colour: Colour,
+        dirt_colour: Colour,
+        wear_curvature: f32,
+        dirt_curvature: f32,
+        ambient_strength: f32,
+        shininess: f32,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            colour,
+            wear_colour,
+            dirt_colour,
+            wear_curvature,
+            dirt_curvature,
+            ambient_strength,
+            shininess,
+        })
+    }
+}
+
+// 0 on flat surfaces, rising to 1 on edges at least as sharp as full_curvature
+pub fn edge_mask(hit: &Hit, full_curvature: f32) -> f32 {
+    smoothstep(hit.curvature / full_curvature)
+}
+
+// same as edge_mask(), but for cavities
+pub fn cavity_mask(hit: &Hit, full_curvature: f32) -> f32 {
+    smoothstep(-hit.curvature / full_curvature)
+}
+
+fn smoothstep(x: f32) -> f32 {
+    let x = x.clamp(0.0, 1.0);
+    x * x * (3.0 - 2.0 * x)
+}
+
+fn mix(a: Colour, b: Colour, amount: f32) -> Colour {
+    a * (1.0 - amount) + b * amount
+}
+
+impl Phong for Weathered {
+    fn colour_at_hit(&self, hit: &Hit) -> Colour {
+        let worn = mix(
+            self.colour,
+            self.wear_colour,
+            edge_mask(hit, self.wear_curvature),
+        );
+        mix(
+            worn,
+            self.dirt_colour,
+            cavity_mask(hit, self.dirt_curvature),
+        )
+    }
+
+    fn ambient_strength(&self) -> f32 {
+        self.ambient_strength
+    }
+
+    fn shininess(&self) -> f32 {
+        self.shininess
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
@@ -0,0 +1,75 @@
+// This Material class draws the edges of a mesh's triangles over a flat
+// fill, ignoring the lights. A useful debug tool for seeing how a mesh is
+// built. Objects that aren't made of triangles are all fill.
+
+use crate::{
+    core::{colour::Colour, hit::Hit, photon::Photon, ray::Ray, vector::Vector},
+    environments::environment::Integrator,
+};
+
+use super::material::{Material, PhotonBehaviour, PhotonMaterial};
+
+pub struct Wireframe {
+    edge: Colour,
+    fill: Colour,
+    // how close to an edge a hit has to be to count, as the smallest of its
+    // barycentric coordinates, so thin triangles get thinner lines
+    width: f32,
+}
+
+impl Wireframe {
+    pub fn new(edge: Colour, fill: Colour, width: f32) -> Self {
+        Self { edge, fill, width }
+    }
+
+    pub fn colour_at_hit(&self, hit: &Hit) -> Colour {
+        let Some(barycentric) = hit.barycentric else {
+            return self.fill;
+        };
+        let nearest_edge = barycentric.into_iter().fold(f32::INFINITY, f32::min);
+        match nearest_edge < self.width {
+            true => self.edge,
+            false => self.fill,
+        }
+    }
+}
+
+impl Material for Wireframe {
+    fn compute_once(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Ray,
+        hit: &Hit,
+        _depth: u8,
+    ) -> Colour {
+        self.colour_at_hit(hit)
+    }
+
+    fn compute_per_light(
+        &self,
+        _integrator: &dyn Integrator,
+        _viewer: &Vector,
+        _hit: &Hit,
+        _ldir: &Vector,
+    ) -> Colour {
+        Colour::black()
+    }
+
+    fn photon_mapped(&self) -> &dyn PhotonMaterial {
+        self
+    }
+}
+
+impl PhotonMaterial for Wireframe {
+    fn bounced_photon(&self, photon: &Photon, hit: &Hit) -> Option<Colour> {
+        Some(self.colour_at_hit(hit) * photon.intensity)
+    }
+
+    fn render_vueon(&self, hit: &Hit, photon: &Photon, _viewer: Vector) -> Colour {
+        self.colour_at_hit(hit) * photon.intensity
+    }
+
+    fn select(&self, _behaviour: &PhotonBehaviour) -> &dyn PhotonMaterial {
+        self
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    core::{
+        animation::{Keyframes, Motion},
+        hit::HitVec,
+        ray::Ray,
+        transform::Transform,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::object::{BoundingSphere, Object};
+
+// an object that moves along its keyframes while the camera shutter is open.
+// the object itself is already placed where it is at frame_time, so rays
+// only need shifting by how far it moves after that.
+pub struct Animated {
+    object: Box<dyn Object>,
+    keyframes: Keyframes,
+    frame_time: f32,
+}
+
+impl Animated {
+    pub fn new(object: Box<dyn Object>, keyframes: Keyframes, frame_time: f32) -> Box<Self> {
+        Box::new(Self {
+            object,
+            keyframes,
+            frame_time,
+        })
+    }
+}
+
+impl Object for Animated {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let delta = self.keyframes.delta(self.frame_time, ray.time);
+        if delta.len_sqrd() == 0.0 {
+            return self.object.intersect(ray);
+        }
+
+        // moving the object by delta is the same as moving the ray by -delta
+        let local_ray = Ray::new(ray.position.clone() - delta, ray.direction)
+            .at_time(ray.time)
+            .of_kind(ray.kind);
+        let mut hits = self.object.intersect(&local_ray);
+        for hit in hits.iter_mut() {
+            hit.position += delta;
+        }
+        hits
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        self.object.apply_transform(transform);
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.object.bounding_sphere()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        self.object.material()
+    }
+}
+
+// a rigid object that moves, turns and scales while the camera shutter is
+// open, from its motion's start to its end. the object itself is where it
+// is without any motion, and each ray is taken into its space at the ray's
+// time, hits are brought back out.
+pub struct Moving {
+    object: Box<dyn Object>,
+    motion: Motion,
+    placed: Transform, // applied after the motion
+    // the object to world and back when the shutter opens, which is every
+    // ray without motion blur. None if it's squashed flat.
+    start: Option<(Transform, Transform)>,
+}
+
+impl Moving {
+    pub fn new(object: Box<dyn Object>, motion: Motion) -> Box<Self> {
+        let mut moving = Box::new(Self {
+            object,
+            motion,
+            placed: Transform::identity(),
+            start: None,
+        });
+        moving.start = moving.transforms_at(0.0);
+        moving
+    }
+
+    fn transforms_at(&self, time: f32) -> Option<(Transform, Transform)> {
+        let to_world = self.placed.clone() * self.motion.at(time).transform();
+        let to_local = to_world.inverse().ok()?;
+        Some((to_world, to_local))
+    }
+}
+
+impl Object for Moving {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let transforms = if ray.time == 0.0 {
+            self.start.clone()
+        } else {
+            self.transforms_at(ray.time)
+        };
+        let Some((to_world, to_local)) = transforms else {
+            return hitvec![];
+        };
+
+        // objects expect a normalised direction, so distances along the
+        // local ray are stretched by the transform
+        let mut position = ray.position.clone();
+        position.apply_transform(&to_local);
+        let mut direction = ray.direction;
+        direction.apply_transform(&to_local);
+        let stretch = direction.length();
+        let local_ray = Ray::new(position, direction.normalised())
+            .at_time(ray.time)
+            .of_kind(ray.kind);
+
+        let mut hits = self.object.intersect(&local_ray);
+        let normal_to_world = to_local.transposed();
+        for hit in hits.iter_mut() {
+            if hit.distance.is_infinite() {
+                continue;
+            }
+            hit.distance /= stretch;
+            hit.position.apply_transform(&to_world);
+            hit.normal.apply_transform(&normal_to_world);
+            hit.normal.normalise();
+            if let Some(tangent) = &mut hit.tangent {
+                tangent.apply_transform(&to_world);
+                tangent.normalise();
+            }
+        }
+        hits
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        self.placed = transform.clone() * self.placed.clone();
+        self.start = self.transforms_at(0.0);
+    }
+
+    // where it is when the shutter opens
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let (to_world, _) = self.start.as_ref()?;
+        Some(self.object.bounding_sphere()?.transformed(to_world))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        self.object.material()
+    }
+}
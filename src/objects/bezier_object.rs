@@ -0,0 +1,414 @@
+// Smooth surfaces made of bicubic Bézier patches, intersected directly
+// instead of being split into triangles. Each patch is subdivided into a tree
+// of smaller patches, whose control points bound them. Rays that reach a leaf
+// refine a hit on it with Newton's method, as in Martin et al. 2000,
+// "Practical Ray Tracing of Trimmed NURBS Surfaces".
+//
+// Patches can be loaded from .bpt files, the format the Utah teapot is usually
+// shared in:
+//     <number of patches>
+//     3 3                 (the degree in u and v, only bicubic is supported)
+//     x y z               (16 control points, 4 rows of 4 along u)
+//     ...
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    core::{
+        error::RenderError,
+        hit::{Hit, HitVec},
+        ray::Ray,
+        tex_coords::TexCoords,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::{
+    bvh::Aabb,
+    object::{BoundingSphere, Object},
+};
+
+// each level splits a patch in four, so the leaves are 1/16 of it each way
+const TREE_DEPTH: u32 = 4;
+const NEWTON_STEPS: u32 = 8;
+// how close Newton's method has to get to the ray, in world units
+const TOLERANCE: f32 = 1e-5;
+
+type ControlPoints = [[Vector; 4]; 4]; // [v][u]
+
+// the cubic Bernstein polynomials at t, and their derivatives
+pub(crate) fn bernstein(t: f32) -> ([f32; 4], [f32; 4]) {
+    let s = 1.0 - t;
+    (
+        [s * s * s, 3.0 * t * s * s, 3.0 * t * t * s, t * t * t],
+        [
+            -3.0 * s * s,
+            3.0 * s * s - 6.0 * t * s,
+            6.0 * t * s - 3.0 * t * t,
+            3.0 * t * t,
+        ],
+    )
+}
+
+// splits a cubic curve in half with de Casteljau's algorithm
+pub(crate) fn split_curve(p: [Vector; 4]) -> ([Vector; 4], [Vector; 4]) {
+    let mid = |a: Vector, b: Vector| (a + b) * 0.5;
+    let ab = mid(p[0], p[1]);
+    let bc = mid(p[1], p[2]);
+    let cd = mid(p[2], p[3]);
+    let abc = mid(ab, bc);
+    let bcd = mid(bc, cd);
+    let centre = mid(abc, bcd);
+    ([p[0], ab, abc, centre], [centre, bcd, cd, p[3]])
+}
+
+// the four quarters of a patch, in the order (low u, low v), (high u, low v),
+// (low u, high v), (high u, high v)
+fn split_patch(points: &ControlPoints) -> [ControlPoints; 4] {
+    let mut low_u = [[Vector::zero(); 4]; 4];
+    let mut high_u = [[Vector::zero(); 4]; 4];
+    for v in 0..4 {
+        (low_u[v], high_u[v]) = split_curve(points[v]);
+    }
+
+    let split_v = |half: &ControlPoints| {
+        let mut low_v = [[Vector::zero(); 4]; 4];
+        let mut high_v = [[Vector::zero(); 4]; 4];
+        for u in 0..4 {
+            let (low, high) = split_curve([half[0][u], half[1][u], half[2][u], half[3][u]]);
+            for v in 0..4 {
+                low_v[v][u] = low[v];
+                high_v[v][u] = high[v];
+            }
+        }
+        (low_v, high_v)
+    };
+    let (low_u_low_v, low_u_high_v) = split_v(&low_u);
+    let (high_u_low_v, high_u_high_v) = split_v(&high_u);
+    [low_u_low_v, high_u_low_v, low_u_high_v, high_u_high_v]
+}
+
+struct Node {
+    // around the control points, which the patch stays inside
+    aabb: Aabb,
+    uv: [f32; 4],       // u0, u1, v0, v1
+    first_child: usize, // 0 for leaves, the others follow it
+}
+
+impl Node {
+    fn new(points: &ControlPoints, uv: [f32; 4]) -> Self {
+        Self {
+            // so flat patches still have some thickness
+            aabb: Aabb::around(points.iter().flatten().copied()).padded(TOLERANCE),
+            uv,
+            first_child: 0,
+        }
+    }
+}
+
+struct Patch {
+    points: ControlPoints,
+    nodes: Vec<Node>, // the root first
+}
+
+impl Patch {
+    fn new(points: ControlPoints) -> Self {
+        let mut patch = Self {
+            points,
+            nodes: Vec::new(),
+        };
+        patch.build();
+        patch
+    }
+
+    fn build(&mut self) {
+        self.nodes.clear();
+        self.nodes
+            .push(Node::new(&self.points, [0.0, 1.0, 0.0, 1.0]));
+        let mut queue = vec![(0, self.points, 0)];
+        while let Some((index, points, depth)) = queue.pop() {
+            if depth == TREE_DEPTH {
+                continue;
+            }
+            let [u0, u1, v0, v1] = self.nodes[index].uv;
+            let (um, vm) = ((u0 + u1) / 2.0, (v0 + v1) / 2.0);
+            let quarter_uvs = [
+                [u0, um, v0, vm],
+                [um, u1, v0, vm],
+                [u0, um, vm, v1],
+                [um, u1, vm, v1],
+            ];
+
+            self.nodes[index].first_child = self.nodes.len();
+            for (quarter, uv) in split_patch(&points).into_iter().zip(quarter_uvs) {
+                queue.push((self.nodes.len(), quarter, depth + 1));
+                self.nodes.push(Node::new(&quarter, uv));
+            }
+        }
+    }
+
+    // the point at (u, v) and the derivatives along u and v
+    fn evaluate(&self, u: f32, v: f32) -> (Vector, Vector, Vector) {
+        let (bu, dbu) = bernstein(u);
+        let (bv, dbv) = bernstein(v);
+        let mut point = Vector::zero();
+        let mut du = Vector::zero();
+        let mut dv = Vector::zero();
+        for (j, row) in self.points.iter().enumerate() {
+            for (i, control) in row.iter().enumerate() {
+                point += *control * (bu[i] * bv[j]);
+                du += *control * (dbu[i] * bv[j]);
+                dv += *control * (bu[i] * dbv[j]);
+            }
+        }
+        (point, du, dv)
+    }
+
+    // the closest hit in front of the ray and nearer than max_t, as (t, u, v)
+    fn intersect(&self, ray: &RayPlanes, max_t: f32) -> Option<(f32, f32, f32)> {
+        let mut closest: Option<(f32, f32, f32)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let max_t = closest.map_or(max_t, |(t, _, _)| t);
+            if !node.aabb.hit_by(&ray.origin, &ray.inverse_direction, max_t) {
+                continue;
+            }
+            if node.first_child != 0 {
+                stack.extend(node.first_child..node.first_child + 4);
+                continue;
+            }
+
+            if let Some((t, u, v)) = self.refine(ray, node.uv) {
+                if t > 0.0 && t < max_t {
+                    closest = Some((t, u, v));
+                }
+            }
+        }
+        closest
+    }
+
+    // Newton's method from the middle of a leaf, towards where the patch
+    // meets both planes through the ray
+    fn refine(&self, ray: &RayPlanes, [u0, u1, v0, v1]: [f32; 4]) -> Option<(f32, f32, f32)> {
+        let mut u = (u0 + u1) / 2.0;
+        let mut v = (v0 + v1) / 2.0;
+        for _ in 0..NEWTON_STEPS {
+            let (point, du, dv) = self.evaluate(u, v);
+            let f = (
+                ray.normals[0].dot(&point) + ray.offsets[0],
+                ray.normals[1].dot(&point) + ray.offsets[1],
+            );
+            if f.0.abs() < TOLERANCE && f.1.abs() < TOLERANCE {
+                // a little past the leaf's edges, so there are no cracks
+                // between leaves
+                let margin = (u1 - u0) * 0.01;
+                let inside = (u0 - margin..=u1 + margin).contains(&u)
+                    && (v0 - margin..=v1 + margin).contains(&v)
+                    && (0.0..=1.0).contains(&u)
+                    && (0.0..=1.0).contains(&v);
+                let t = (point - ray.origin).dot(&ray.direction);
+                return inside.then_some((t, u, v));
+            }
+
+            let (a, b) = (ray.normals[0].dot(&du), ray.normals[0].dot(&dv));
+            let (c, d) = (ray.normals[1].dot(&du), ray.normals[1].dot(&dv));
+            let determinant = a * d - b * c;
+            if determinant == 0.0 {
+                return None;
+            }
+            u -= (d * f.0 - b * f.1) / determinant;
+            v -= (a * f.1 - c * f.0) / determinant;
+        }
+        None
+    }
+
+    // the surface normal at (u, v), following du x dv
+    fn normal(&self, u: f32, v: f32) -> Vector {
+        let (_, du, dv) = self.evaluate(u, v);
+        let normal = du.cross(&dv);
+        if normal.len_sqrd() > 1e-12 {
+            return normal.normalised();
+        }
+        // a corner pulled together into a point (e.g. the top of the teapot),
+        // so look from just inside it
+        let (_, du, dv) = self.evaluate(0.5 + (u - 0.5) * 0.999, 0.5 + (v - 0.5) * 0.999);
+        du.cross(&dv).normalised()
+    }
+}
+
+// a ray as the two planes it's where they meet, for Newton's method
+struct RayPlanes {
+    origin: Vector,
+    direction: Vector, // normalised
+    inverse_direction: Vector,
+    normals: [Vector; 2],
+    offsets: [f32; 2],
+}
+
+impl RayPlanes {
+    fn new(ray: &Ray) -> Self {
+        let origin = ray.position.vector();
+        let direction = ray.direction.normalised();
+        let d = direction;
+        let first = if d.x.abs() > d.y.abs() && d.x.abs() > d.z.abs() {
+            Vector::new(d.y, -d.x, 0.0)
+        } else {
+            Vector::new(0.0, d.z, -d.y)
+        }
+        .normalised();
+        let second = first.cross(&d).normalised();
+        Self {
+            origin,
+            direction,
+            inverse_direction: Vector::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z),
+            normals: [first, second],
+            offsets: [-first.dot(&origin), -second.dot(&origin)],
+        }
+    }
+}
+
+pub struct BezierSurface {
+    patches: Vec<Patch>,
+    material: Arc<dyn Material>,
+}
+
+impl BezierSurface {
+    // each patch is 16 control points, 4 rows of 4 along u
+    pub fn new(patches: Vec<[[Vector; 4]; 4]>, material: Arc<dyn Material>) -> Box<Self> {
+        let patches = patches.into_iter().map(Patch::new).collect();
+        Box::new(Self { patches, material })
+    }
+
+    pub fn from_bpt_file(
+        path: PathBuf,
+        material: Arc<dyn Material>,
+    ) -> Result<Box<Self>, RenderError> {
+        let contents = std::fs::read_to_string(&path).map_err(|e| RenderError::io(&path, e))?;
+        let patches = parse_bpt(&contents).map_err(|(line, message)| RenderError::Patches {
+            path,
+            line,
+            message,
+        })?;
+        Ok(Self::new(patches, material))
+    }
+
+    // around every patch's control points, None if there are no patches
+    fn aabb(&self) -> Option<Aabb> {
+        self.patches
+            .iter()
+            .map(|patch| patch.nodes[0].aabb)
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
+// the patches in a .bpt file, or the line (from 1) that's wrong
+fn parse_bpt(contents: &str) -> Result<Vec<[[Vector; 4]; 4]>, (usize, String)> {
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split_whitespace().collect::<Vec<_>>()))
+        .filter(|(_, words)| !words.is_empty());
+    let mut next_line = |expected: &str| {
+        lines
+            .next()
+            .ok_or((contents.lines().count(), format!("Missing {expected}")))
+    };
+    let number = |line: usize, word: &str| {
+        word.parse::<f32>()
+            .map_err(|_| (line, format!("Invalid number: {word}")))
+    };
+
+    let (line, words) = next_line("number of patches")?;
+    let count = match words[..] {
+        [count] => count
+            .parse::<usize>()
+            .map_err(|_| (line, format!("Invalid number of patches: {count}")))?,
+        _ => return Err((line, "Expected the number of patches".to_string())),
+    };
+
+    let mut patches = Vec::new();
+    for _ in 0..count {
+        let (line, words) = next_line("patch degrees")?;
+        if words[..] != ["3", "3"] {
+            return Err((line, "Only bicubic (3 3) patches are supported".to_string()));
+        }
+        let mut points = [[Vector::zero(); 4]; 4];
+        for point in points.iter_mut().flatten() {
+            let (line, words) = next_line("control point")?;
+            let [x, y, z] = words[..] else {
+                return Err((line, "Expected x y z".to_string()));
+            };
+            *point = Vector::new(number(line, x)?, number(line, y)?, number(line, z)?);
+        }
+        patches.push(points);
+    }
+    Ok(patches)
+}
+
+impl Object for BezierSurface {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let planes = RayPlanes::new(ray);
+        let mut closest: Option<(f32, &Patch, f32, f32)> = None;
+        for patch in &self.patches {
+            let max_t = closest.map_or(f32::INFINITY, |(t, ..)| t);
+            if let Some((t, u, v)) = patch.intersect(&planes, max_t) {
+                closest = Some((t, patch, u, v));
+            }
+        }
+        let Some((t, patch, u, v)) = closest else {
+            return hitvec![];
+        };
+
+        // a surface, not a solid, so both sides are the front
+        let distance = t / ray.direction.length();
+        let mut normal = patch.normal(u, v);
+        if normal.dot(&ray.direction) > 0.0 {
+            normal.negate();
+        }
+        let position = ray.position.clone() + ray.direction * distance;
+        let tex_coords = TexCoords::new(u, v);
+        if let Some(normal_map) = self.material.normal(&tex_coords) {
+            let (_, du, _) = patch.evaluate(u, v);
+            normal = normal_map.to_tangent_space(&du, &normal);
+        }
+        hitvec![Hit::new(
+            self,
+            true,
+            distance,
+            position,
+            normal,
+            self.material.as_ref(),
+            Some(tex_coords),
+        )]
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        // the surface is an affine combination of its control points, so
+        // moving them moves it
+        for patch in &mut self.patches {
+            for point in patch.points.iter_mut().flatten() {
+                let mut vertex = Vertex::from(*point);
+                vertex.apply_transform(transform);
+                *point = vertex.vector();
+            }
+            patch.build();
+        }
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(self.aabb()?.bounding_sphere())
+    }
+
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        Some(self.aabb()?.bounds())
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+}
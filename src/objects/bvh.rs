@@ -0,0 +1,193 @@
+// Axis-aligned bounding boxes, and bounding volume hierarchies of them over
+// lists of things too many to test one by one, like the splats of a point
+// cloud, the strands of fur or the triangles of the meshes traced on the
+// GPU. The hierarchy keeps its own nodes and sorts the things into the order
+// of its leaves, so a leaf is a range of them.
+
+use std::ops::Range;
+
+use crate::core::{vector::Vector, vertex::Vertex};
+
+use super::object::BoundingSphere;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl Aabb {
+    // around nothing, so anything unioned with it is itself
+    pub fn empty() -> Self {
+        Self {
+            min: Vector::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            max: Vector::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn around(points: impl IntoIterator<Item = Vector>) -> Self {
+        points
+            .into_iter()
+            .fold(Self::empty(), |Self { min, max }, p| Self {
+                min: Vector::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                max: Vector::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self::around([self.min, self.max, other.min, other.max])
+    }
+
+    // grown by padding on every side
+    pub fn padded(&self, padding: f32) -> Self {
+        let padding = Vector::new(padding, padding, padding);
+        Self {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
+    }
+
+    pub fn size(&self) -> Vector {
+        self.max - self.min
+    }
+
+    // the smallest and largest corners, as Object::bounds gives them
+    pub fn bounds(&self) -> (Vertex, Vertex) {
+        (self.min.into(), self.max.into())
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            centre: Vertex::from((self.min + self.max) * 0.5),
+            radius: self.size().length() / 2.0,
+        }
+    }
+
+    // whether the ray passes through the box before max_t, with the slab
+    // test. inverse_direction is 1 / each part of the ray's direction.
+    pub fn hit_by(&self, origin: &Vector, inverse_direction: &Vector, max_t: f32) -> bool {
+        let slab = |min: f32, max: f32, origin: f32, inverse: f32| {
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            (t0.min(t1), t0.max(t1))
+        };
+        let (x0, x1) = slab(self.min.x, self.max.x, origin.x, inverse_direction.x);
+        let (y0, y1) = slab(self.min.y, self.max.y, origin.y, inverse_direction.y);
+        let (z0, z1) = slab(self.min.z, self.max.z, origin.z, inverse_direction.z);
+        let near = x0.max(y0).max(z0);
+        let far = x1.min(y1).min(z1);
+        near <= far && far >= 0.0 && near <= max_t
+    }
+}
+
+struct Node {
+    aabb: Aabb,
+    // leaves have items[start..start + count], the others have count 0,
+    // their first child straight after them and their second at start
+    start: usize,
+    count: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>, // the root first
+}
+
+impl Bvh {
+    // sorts items into the order of the leaves, each with at most leaf_size.
+    // aabb is the box around an item.
+    pub fn build<T>(items: &mut [T], leaf_size: usize, aabb: impl Fn(&T) -> Aabb) -> Self {
+        let mut bvh = Self { nodes: Vec::new() };
+        if !items.is_empty() {
+            bvh.build_node(items, 0, leaf_size.max(1), &aabb);
+        }
+        bvh
+    }
+
+    // splits items (which start at start in the whole list) in half along
+    // their longest side, until there are few enough for a leaf
+    fn build_node<T>(
+        &mut self,
+        items: &mut [T],
+        start: usize,
+        leaf_size: usize,
+        item_aabb: &impl Fn(&T) -> Aabb,
+    ) {
+        let aabb = items
+            .iter()
+            .fold(Aabb::empty(), |aabb, item| aabb.union(&item_aabb(item)));
+
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            aabb,
+            start,
+            count: items.len(),
+        });
+        if items.len() <= leaf_size {
+            return;
+        }
+
+        let size = aabb.size();
+        let axis = |item: &T| {
+            let aabb = item_aabb(item);
+            let p = aabb.min + aabb.max; // twice the centre
+            if size.x >= size.y && size.x >= size.z {
+                p.x
+            } else if size.y >= size.z {
+                p.y
+            } else {
+                p.z
+            }
+        };
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |a, b| axis(a).total_cmp(&axis(b)));
+
+        let (first, second) = items.split_at_mut(middle);
+        self.build_node(first, start, leaf_size, item_aabb);
+        let second_index = self.nodes.len();
+        self.build_node(second, start + middle, leaf_size, item_aabb);
+        self.nodes[index].start = second_index;
+        self.nodes[index].count = 0;
+    }
+
+    // around everything, None if it's empty
+    pub fn aabb(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.aabb)
+    }
+
+    // each node's box, start and count, root first, see Node. for copying
+    // the hierarchy somewhere else, e.g. to the GPU.
+    pub fn nodes(&self) -> impl Iterator<Item = (Aabb, usize, usize)> + '_ {
+        self.nodes
+            .iter()
+            .map(|node| (node.aabb, node.start, node.count))
+    }
+
+    // calls visit with the range of items in every leaf the ray passes
+    // through before max_t. visit returns the distance of a closer hit it
+    // found, which leaves further away than are then skipped.
+    pub fn traverse(
+        &self,
+        origin: &Vector,
+        inverse_direction: &Vector,
+        mut max_t: f32,
+        mut visit: impl FnMut(Range<usize>) -> Option<f32>,
+    ) {
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let Some(node) = self.nodes.get(index) else {
+                continue;
+            };
+            if !node.aabb.hit_by(origin, inverse_direction, max_t) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            if let Some(t) = visit(node.start..node.start + node.count) {
+                max_t = max_t.min(t);
+            }
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use crate::{
     core::{hit::HitVec, ray::Ray, transform::Transform},
     hitvec,
+    materials::material::Material,
 };
 
-use super::object::Object;
+use super::object::{BoundingSphere, Object};
 
 pub enum CsgMode {
     Union,
@@ -11,6 +12,17 @@ pub enum CsgMode {
     Difference,
 }
 
+impl CsgMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Union" => Some(Self::Union),
+            "Intersection" => Some(Self::Intersection),
+            "Difference" => Some(Self::Difference),
+            _ => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub enum CsgAction {
     AEnter, // ray enters A
@@ -36,7 +48,7 @@ impl Csg {
 }
 
 impl Object for Csg {
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
         let actions = match self.mode {
             CsgMode::Union => [ADrop, BDrop, AExit, BDrop, ADrop, BExit, AEnter, BEnter],
             CsgMode::Intersection => [AExit, BExit, ADrop, BEnter, AEnter, BDrop, ADrop, BDrop],
@@ -48,10 +60,7 @@ impl Object for Csg {
 
         let mut hit_vec = hitvec![];
 
-        loop {
-            let Some(left_hit) = left_hits.peek() else {
-                break;
-            };
+        while let Some(left_hit) = left_hits.peek() {
             let Some(right_hit) = right_hits.peek() else {
                 break;
             };
@@ -111,4 +120,16 @@ impl Object for Csg {
         self.left.apply_transform(transform);
         self.right.apply_transform(transform);
     }
+
+    // whatever the operation, the result is inside both together
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let left = self.left.bounding_sphere()?;
+        let right = self.right.bounding_sphere()?;
+        Some(left.union(&right))
+    }
+
+    // the outside is mostly the left object's surface
+    fn material(&self) -> Option<&dyn Material> {
+        self.left.material()
+    }
 }
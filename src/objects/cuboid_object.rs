@@ -12,7 +12,10 @@ use crate::{
     materials::material::Material,
 };
 
-use super::{object::Object, plane_object::Plane};
+use super::{
+    object::{BoundingSphere, Object},
+    plane_object::Plane,
+};
 
 pub struct CuboidPlanes {
     left: Plane,
@@ -39,7 +42,9 @@ impl CuboidPlanes {
 
 pub struct Cuboid {
     pub corner: Vertex, // bottom left corner
-    pub size: Vector,
+    // from the corner along its width, height and depth, which stay edges of
+    // a parallelepiped under any transform
+    pub edges: [Vector; 3],
     material: Arc<dyn Material>,
 
     planes: OnceLock<CuboidPlanes>,
@@ -49,7 +54,11 @@ impl Cuboid {
     pub fn new(corner: Vertex, size: Vector, material: Arc<dyn Material>) -> Box<Self> {
         Box::new(Self {
             corner,
-            size,
+            edges: [
+                Vector::new(size.x, 0.0, 0.0),
+                Vector::new(0.0, size.y, 0.0),
+                Vector::new(0.0, 0.0, size.z),
+            ],
             material,
             planes: OnceLock::new(),
         })
@@ -58,24 +67,28 @@ impl Cuboid {
     fn get_planes(&self) -> &CuboidPlanes {
         self.planes.get_or_init(|| {
             let corner = self.corner.clone();
-            let Vector {
-                x: width,
-                y: height,
-                z: depth,
-            } = self.size;
+            let [width, height, depth] = self.edges;
 
             let fdl = corner.clone(); // front down left
-            let ful = corner.clone() + Vector::new(0.0, height, 0.0);
-            let bdl = corner.clone() + Vector::new(0.0, 0.0, depth);
-            let bdr = corner.clone() + Vector::new(width, 0.0, depth);
-
-            // vectors
-            let up = Vector::new(0.0, 1.0, 0.0);
-            let down = Vector::new(0.0, -1.0, 0.0);
-            let left = Vector::new(-1.0, 0.0, 0.0);
-            let right = Vector::new(1.0, 0.0, 0.0);
-            let forwards = Vector::new(0.0, 0.0, 1.0);
-            let backwards = Vector::new(0.0, 0.0, -1.0);
+            let ful = corner.clone() + height;
+            let bdl = corner.clone() + depth;
+            let bdr = corner.clone() + width + depth;
+
+            // vectors, the normals pointing out of the faces
+            let outwards = |a: Vector, b: Vector, away: Vector| {
+                let normal = a.cross(&b).normalised();
+                if normal.dot(&away) < 0.0 {
+                    normal.negated()
+                } else {
+                    normal
+                }
+            };
+            let up = outwards(depth, width, height);
+            let down = up.negated();
+            let right = outwards(height, depth, width);
+            let left = right.negated();
+            let forwards = outwards(width, height, depth);
+            let backwards = forwards.negated();
 
             let m = &self.material;
             CuboidPlanes {
@@ -91,7 +104,7 @@ impl Cuboid {
 }
 
 impl Object for Cuboid {
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
         let planes = self.get_planes();
         let mut first_hit = None::<Hit>;
         let mut back_hit = None::<Hit>;
@@ -100,16 +113,11 @@ impl Object for Cuboid {
             let hits = plane.intersect(ray);
 
             for hit in hits {
-                // check if hit position is inside the cube
-                let hit_position = &hit.position;
-                let corner = &self.corner;
-                let size = self.size;
-                let inside = hit_position.x >= corner.x - 0.0001
-                    && hit_position.x <= corner.x + size.x + 0.0001
-                    && hit_position.y >= corner.y - 0.0001
-                    && hit_position.y <= corner.y + size.y + 0.0001
-                    && hit_position.z >= corner.z - 0.0001
-                    && hit_position.z <= corner.z + size.z + 0.0001;
+                // check if hit position is inside the cube, behind all of
+                // its faces
+                let inside = planes
+                    .iter()
+                    .all(|plane| plane.signed_distance(&hit.position) <= 0.0001);
                 if !inside {
                     continue;
                 }
@@ -149,13 +157,25 @@ impl Object for Cuboid {
 
     fn apply_transform(&mut self, transform: &Transform) {
         self.corner.apply_transform(transform);
+        for edge in &mut self.edges {
+            edge.apply_transform(transform);
+        }
         self.planes = OnceLock::new();
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let [width, height, depth] = self.edges;
+        let centre = self.corner.clone() + (width + height + depth) * 0.5;
+        let radius = (width + height + depth).length() / 2.0;
+        // a sheared cuboid's longest diagonal could be any of the four
+        let radius = [width + height - depth, width - height + depth, height + depth - width]
+            .iter()
+            .map(|diagonal| diagonal.length() / 2.0)
+            .fold(radius, f32::max);
+        Some(BoundingSphere { centre, radius })
+    }
 
-        // note: only supports raw scaling, not scaling + rotation
-        self.size = Vector::new(
-            self.size.x * transform[0][0],
-            self.size.y * transform[1][1],
-            self.size.z * transform[2][2],
-        );
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
     }
 }
@@ -0,0 +1,320 @@
+// Thin curves with a radius, for hair, fur and grass. Each one is a cubic
+// Bézier curve, drawn as a ribbon that always turns to face the ray, shaded
+// as if it were round. It's intersected like in pbrt (Pharr et al.,
+// "Physically Based Rendering", section 3.7): the curve is moved so the ray
+// runs along z from the origin, and split in half until the pieces are
+// nearly straight, skipping halves whose bounds don't reach the ray. Hits
+// have the curve's direction as their tangent, for `material Hair`.
+//
+// There are a lot of strands on a head or a coat, so they're kept in a
+// bounding volume hierarchy, like point clouds.
+
+use std::sync::Arc;
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+use crate::{
+    core::{
+        hit::{Hit, HitVec},
+        onb::Onb,
+        ray::Ray,
+        tex_coords::TexCoords,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::{
+    bezier_object::{bernstein, split_curve},
+    bvh::{Aabb, Bvh},
+    object::{BoundingSphere, Object},
+};
+
+// the most curves in a leaf of the hierarchy
+const LEAF_SIZE: usize = 4;
+
+pub struct Curve {
+    points: [Vector; 4],
+    radius: [f32; 2], // at the start and the end, in between is in between
+    depth: u32,       // how many times to split it before it's straight enough
+}
+
+impl Curve {
+    pub fn bezier(points: [Vector; 4], radius: [f32; 2]) -> Self {
+        let mut curve = Self {
+            points,
+            radius,
+            depth: 0,
+        };
+        curve.update_depth();
+        curve
+    }
+
+    // a curve through all the points, as one Bézier curve between each
+    // pair. the radius goes evenly from the first point to the last.
+    pub fn catmull_rom(points: &[Vector], radius: [f32; 2]) -> Vec<Self> {
+        let segments = points.len().saturating_sub(1);
+        let point = |i: isize| points[i.clamp(0, points.len() as isize - 1) as usize];
+        let radius_at = |i: usize| {
+            let t = i as f32 / segments as f32;
+            radius[0] * (1.0 - t) + radius[1] * t
+        };
+        (0..segments)
+            .map(|i| {
+                let j = i as isize;
+                let control = [
+                    point(j),
+                    point(j) + (point(j + 1) - point(j - 1)) * (1.0 / 6.0),
+                    point(j + 1) - (point(j + 2) - point(j)) * (1.0 / 6.0),
+                    point(j + 1),
+                ];
+                Self::bezier(control, [radius_at(i), radius_at(i + 1)])
+            })
+            .collect()
+    }
+
+    // from pbrt, enough that the pieces are within 5% of the radius of
+    // straight lines
+    fn update_depth(&mut self) {
+        let p = &self.points;
+        let bend = |i: usize| {
+            let d = p[i] - p[i + 1] * 2.0 + p[i + 2];
+            d.x.abs().max(d.y.abs()).max(d.z.abs())
+        };
+        let bend = bend(0).max(bend(1));
+        let epsilon = self.radius[0].max(self.radius[1]) * 0.05;
+        let depth = (std::f32::consts::SQRT_2 * 6.0 * bend / (8.0 * epsilon)).log2() / 2.0;
+        self.depth = if depth.is_finite() {
+            depth.clamp(0.0, 10.0) as u32
+        } else {
+            0
+        };
+    }
+
+    fn radius_at(&self, u: f32) -> f32 {
+        self.radius[0] * (1.0 - u) + self.radius[1] * u
+    }
+
+    // the point at u and the direction there
+    fn evaluate(points: &[Vector; 4], u: f32) -> (Vector, Vector) {
+        let (b, db) = bernstein(u);
+        let mut point = Vector::zero();
+        let mut tangent = Vector::zero();
+        for i in 0..4 {
+            point += points[i] * b[i];
+            tangent += points[i] * db[i];
+        }
+        (point, tangent)
+    }
+
+    // the curve stays inside its control points
+    fn aabb(&self) -> Aabb {
+        Aabb::around(self.points).padded(self.radius[0].max(self.radius[1]))
+    }
+
+    // where along the ray the ribbon is hit, nearer than max_z, and u there.
+    // points are in ray space: from the ray's origin with z along it.
+    fn intersect(
+        &self,
+        points: [Vector; 4],
+        u_range: (f32, f32),
+        depth: u32,
+        max_z: f32,
+    ) -> Option<(f32, f32)> {
+        let (u0, u1) = u_range;
+        let radius = self.radius_at(u0).max(self.radius_at(u1));
+        let Aabb { min, max } = Aabb::around(points);
+        if min.x - radius > 0.0
+            || max.x + radius < 0.0
+            || min.y - radius > 0.0
+            || max.y + radius < 0.0
+            || max.z + radius < 0.0
+            || min.z - radius > max_z
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let middle = (u0 + u1) / 2.0;
+            let (first, second) = split_curve(points);
+            let first = self.intersect(first, (u0, middle), depth - 1, max_z);
+            let max_z = first.map_or(max_z, |(z, _)| z);
+            return self
+                .intersect(second, (middle, u1), depth - 1, max_z)
+                .or(first);
+        }
+
+        // nearly straight, so the closest point to the ray on the line
+        // between the ends
+        let (x, y) = (points[3].x - points[0].x, points[3].y - points[0].y);
+        let length_sqrd = x * x + y * y;
+        if length_sqrd == 0.0 {
+            return None;
+        }
+        let w = -(points[0].x * x + points[0].y * y) / length_sqrd;
+        if !(0.0..=1.0).contains(&w) {
+            return None;
+        }
+        let u = u0 + (u1 - u0) * w;
+        let (point, _) = Self::evaluate(&points, w);
+        let radius = self.radius_at(u);
+        if point.x * point.x + point.y * point.y > radius * radius {
+            return None;
+        }
+        // rays leaving a strand (e.g. shadows) start on its ribbon, which
+        // turns to face them, so they'd hit it again straight away
+        if point.len_sqrd() <= 4.0 * radius * radius {
+            return None;
+        }
+        (point.z >= 0.0 && point.z < max_z).then_some((point.z, u))
+    }
+}
+
+pub struct Curves {
+    curves: Vec<Curve>, // in the order of the hierarchy's leaves
+    bvh: Bvh,
+    material: Arc<dyn Material>,
+}
+
+impl Curves {
+    pub fn new(mut curves: Vec<Curve>, material: Arc<dyn Material>) -> Box<Self> {
+        let bvh = Bvh::build(&mut curves, LEAF_SIZE, Curve::aabb);
+        Box::new(Self {
+            curves,
+            bvh,
+            material,
+        })
+    }
+}
+
+impl Object for Curves {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let origin = ray.position.vector();
+        let d = ray.direction;
+        let inverse_direction = Vector::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+        let stretch = d.length();
+        let ray_space = Onb::from_normal(&d);
+
+        // distances here are along the normalised direction
+        let mut closest: Option<(f32, &Curve, f32)> = None;
+        self.bvh
+            .traverse(&origin, &inverse_direction, f32::INFINITY, |leaf| {
+                for curve in &self.curves[leaf] {
+                    let points = curve.points.map(|p| ray_space.to_local(&(p - origin)));
+                    let max_z = closest.map_or(f32::INFINITY, |(z, ..)| z);
+                    if let Some((z, u)) = curve.intersect(points, (0.0, 1.0), curve.depth, max_z) {
+                        closest = Some((z, curve, u));
+                    }
+                }
+                closest.map(|(z, ..)| z / stretch)
+            });
+        let Some((z, curve, u)) = closest else {
+            return hitvec![];
+        };
+
+        // shade it like a cylinder, turning the normal around the strand by
+        // how far across the ribbon the hit is
+        let distance = z / stretch;
+        let position = ray.position.clone() + d * distance;
+        let (centre, tangent) = Curve::evaluate(&curve.points, u);
+        let tangent = tangent.normalised();
+        let direction = d.normalised();
+        let mut facing = (tangent * tangent.dot(&direction) - direction).normalised();
+        if !facing.len_sqrd().is_normal() {
+            facing = direction.negated(); // looking straight along it
+        }
+        let across = tangent.cross(&facing);
+        let offset =
+            ((position.vector() - centre).dot(&across) / curve.radius_at(u)).clamp(-1.0, 1.0);
+        let normal = facing * (1.0 - offset * offset).sqrt() + across * offset;
+
+        // a surface seen from every side, so always entering
+        let mut hit = Hit::new(
+            self,
+            true,
+            distance,
+            position,
+            normal,
+            self.material.as_ref(),
+            Some(TexCoords::new(u, (offset + 1.0) / 2.0)),
+        );
+        hit.tangent = Some(tangent);
+        hitvec![hit]
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        // strands keep their thickness when they're stretched, like
+        // point cloud splats
+        let column = |i: usize| Vector::new(transform[0][i], transform[1][i], transform[2][i]);
+        let (x, y, z) = (column(0), column(1), column(2));
+        let scale = x.dot(&y.cross(&z)).abs().cbrt();
+
+        for curve in &mut self.curves {
+            for point in &mut curve.points {
+                let mut vertex = Vertex::from(*point);
+                vertex.apply_transform(transform);
+                *point = vertex.vector();
+            }
+            curve.radius = curve.radius.map(|r| r * scale);
+            curve.update_depth();
+        }
+        self.bvh = Bvh::build(&mut self.curves, LEAF_SIZE, Curve::aabb);
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(self.bvh.aabb()?.bounding_sphere())
+    }
+
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        Some(self.bvh.aabb()?.bounds())
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+}
+
+// strands of the given length growing from each root, along the normal
+// there, drooping by gravity (how far the tips fall, as a fraction of the
+// length) and with randomness in their direction and length. the same seed
+// grows the same fur.
+pub fn grow_fur(
+    roots: &[(Vertex, Vector)],
+    length: f32,
+    gravity: f32,
+    randomness: f32,
+    radius: [f32; 2],
+    seed: u64,
+) -> Vec<Curve> {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut random_vector = || loop {
+        let v = Vector::new(
+            rng.gen::<f32>() * 2.0 - 1.0,
+            rng.gen::<f32>() * 2.0 - 1.0,
+            rng.gen::<f32>() * 2.0 - 1.0,
+        );
+        if v.len_sqrd() <= 1.0 {
+            return v;
+        }
+    };
+
+    let down = Vector::new(0.0, -gravity, 0.0);
+    roots
+        .iter()
+        .map(|(root, normal)| {
+            let shorter = random_vector().length() * randomness * 0.5;
+            let step = length * (1.0 - shorter) / 3.0;
+            let middle = (*normal + down * 0.5 + random_vector() * randomness).normalised();
+            let tip = (*normal + down + random_vector() * randomness).normalised();
+
+            let p0 = root.vector();
+            let p1 = p0 + *normal * step;
+            let p2 = p1 + middle * step;
+            let p3 = p2 + tip * step;
+            Curve::bezier([p0, p1, p2, p3], radius)
+        })
+        .collect()
+}
@@ -0,0 +1,331 @@
+// Fractals that have no surface to intersect, only a distance estimate (how
+// far a point is from the surface, at most). Rays are sphere traced: they step
+// forwards by the estimate until they're within the detail of the surface.
+// Normals come from how the estimate changes around the hit, and the
+// estimate near the surface gives an ambient occlusion term, which is the
+// hit's colour for `material VertexColour`.
+
+use std::sync::Arc;
+
+use crate::{
+    core::{
+        colour::Colour,
+        hit::{Hit, HitVec},
+        ray::Ray,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::object::{BoundingSphere, Object};
+
+// steps per ray before giving up, e.g. rays grazing the surface
+const MAX_STEPS: u32 = 512;
+
+pub enum FractalKind {
+    // the 3D Mandelbrot set in spherical coordinates, with its axis along y
+    Mandelbulb { power: f32, iterations: u32 },
+    // a slice through a quaternion Julia set, c = (real, i, j, k)
+    Julia { c: [f32; 4], iterations: u32 },
+    // a cube with the middle of each face and the centre taken out, again
+    // for each smaller cube
+    Menger { iterations: u32 },
+}
+
+impl FractalKind {
+    // radius of a sphere around the fractal, where marching starts
+    fn bounds(&self) -> f32 {
+        match self {
+            // anything further out escapes
+            Self::Mandelbulb { .. } | Self::Julia { .. } => 2.0,
+            Self::Menger { .. } => 3f32.sqrt(),
+        }
+    }
+
+    // a lower bound on the distance to the surface, negative inside
+    fn distance(&self, p: Vector) -> f32 {
+        match *self {
+            Self::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+            Self::Julia { c, iterations } => julia(p, c, iterations),
+            Self::Menger { iterations } => menger(p, iterations),
+        }
+    }
+}
+
+// from https://www.skytopia.com/project/fractal/2mandelbulb.html, with the
+// derivative for the estimate
+fn mandelbulb(p: Vector, power: f32, iterations: u32) -> f32 {
+    // y up
+    let c = Vector::new(p.x, p.z, p.y);
+    let mut z = c;
+    let mut dr = 1.0;
+    let mut r = z.length();
+    for _ in 0..iterations {
+        if r > 2.0 {
+            break;
+        }
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        let zr = r.powf(power);
+        z = Vector::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        ) * zr
+            + c;
+        r = z.length();
+    }
+    0.5 * r.ln() * r / dr
+}
+
+fn julia(p: Vector, c: [f32; 4], iterations: u32) -> f32 {
+    let mut q = [p.x, p.y, p.z, 0.0];
+    let mut dq = [1.0, 0.0, 0.0, 0.0];
+    for _ in 0..iterations {
+        dq = quaternion_mul(q, dq).map(|x| 2.0 * x);
+        q = quaternion_mul(q, q);
+        for (q, c) in q.iter_mut().zip(c) {
+            *q += c;
+        }
+        if quaternion_len(q) > 4.0 {
+            break;
+        }
+    }
+    let r = quaternion_len(q);
+    0.5 * r * r.ln() / quaternion_len(dq)
+}
+
+fn quaternion_mul(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn quaternion_len(q: [f32; 4]) -> f32 {
+    q.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+// exact, from https://iquilezles.org/articles/menger/
+fn menger(p: Vector, iterations: u32) -> f32 {
+    // the cube from -1 to 1
+    let q = Vector::new(p.x.abs() - 1.0, p.y.abs() - 1.0, p.z.abs() - 1.0);
+    let outside = Vector::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+    let mut distance = outside + q.x.max(q.y.max(q.z)).min(0.0);
+
+    let mut scale = 1.0;
+    for _ in 0..iterations {
+        let fold = |x: f32| 1.0 - 3.0 * ((x * scale).rem_euclid(2.0) - 1.0).abs();
+        let r = Vector::new(fold(p.x).abs(), fold(p.y).abs(), fold(p.z).abs());
+        scale *= 3.0;
+        let cross = r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x));
+        distance = distance.max((cross - 1.0) / scale);
+    }
+    distance
+}
+
+pub struct Fractal {
+    kind: FractalKind,
+    material: Arc<dyn Material>,
+    // how close counts as on the surface, in the fractal's own units (they
+    // are a few units across)
+    detail: f32,
+    colour: Colour, // darkened by the occlusion
+    to_world: Transform,
+    to_local: Transform,
+    flattened: bool, // transformed by something that squashes it flat
+}
+
+impl Fractal {
+    // centred on centre, size times as big as its own units
+    pub fn new(
+        kind: FractalKind,
+        centre: Vector,
+        size: f32,
+        detail: f32,
+        colour: Colour,
+        material: Arc<dyn Material>,
+    ) -> Box<Self> {
+        let to_world = Transform::from_translation(centre)
+            * Transform::from_matrix([
+                [size, 0.0, 0.0, 0.0],
+                [0.0, size, 0.0, 0.0],
+                [0.0, 0.0, size, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]);
+        let mut fractal = Box::new(Self {
+            kind,
+            material,
+            detail,
+            colour,
+            to_world: Transform::identity(),
+            to_local: Transform::identity(),
+            flattened: false,
+        });
+        fractal.apply_transform(&to_world);
+        fractal
+    }
+
+    // the distance along the ray to where it next crosses the surface, going
+    // in or out
+    fn march(&self, origin: Vector, direction: Vector, mut t: f32, t_far: f32) -> Option<f32> {
+        // rays leaving the surface (e.g. shadows) get off it first
+        let mut leaving = true;
+        for _ in 0..MAX_STEPS {
+            let distance = self.kind.distance(origin + direction * t).abs();
+            if distance >= self.detail {
+                leaving = false;
+            } else if !leaving {
+                return Some(t);
+            }
+            t += distance.max(self.detail);
+            if t > t_far {
+                return None;
+            }
+        }
+        None
+    }
+
+    // the gradient of the distance, from four samples around p, see
+    // https://iquilezles.org/articles/normalsSDF/
+    fn normal(&self, p: Vector) -> Vector {
+        let h = self.detail;
+        [
+            Vector::new(1.0, -1.0, -1.0),
+            Vector::new(-1.0, -1.0, 1.0),
+            Vector::new(-1.0, 1.0, -1.0),
+            Vector::new(1.0, 1.0, 1.0),
+        ]
+        .into_iter()
+        .fold(Vector::zero(), |normal, k| {
+            normal + k * self.kind.distance(p + k * h)
+        })
+        .normalised()
+    }
+
+    // 1 out in the open, less where the surface around p crowds in
+    fn occlusion(&self, p: Vector, normal: Vector) -> f32 {
+        let mut occlusion = 0.0;
+        let mut weight = 1.0;
+        for i in 0..5 {
+            let h = 0.01 + 0.03 * i as f32;
+            occlusion += (h - self.kind.distance(p + normal * h)) * weight;
+            weight *= 0.95;
+        }
+        (1.0 - 3.0 * occlusion).clamp(0.0, 1.0)
+    }
+
+    fn create_hit(&self, ray: &Ray, local: (Vector, Vector), t: f32, stretch: f32) -> Hit<'_> {
+        let (origin, direction) = local;
+        let distance = t / stretch;
+        let p = origin + direction * t;
+        let local_normal = self.normal(p);
+
+        // normals go back by the inverse transpose
+        let mut normal = local_normal;
+        normal.apply_transform(&self.to_local.transposed());
+        let mut normal = normal.normalised();
+        let entering = normal.dot(&ray.direction) < 0.0;
+        if !entering {
+            normal.negate();
+        }
+
+        let mut hit = Hit::new(
+            self,
+            entering,
+            distance,
+            ray.position.clone() + ray.direction * distance,
+            normal,
+            self.material.as_ref(),
+            None,
+        );
+        hit.colour = Some(self.colour * self.occlusion(p, local_normal));
+        hit
+    }
+}
+
+impl Object for Fractal {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        if self.flattened {
+            return hitvec![];
+        }
+
+        let mut origin = ray.position.clone();
+        origin.apply_transform(&self.to_local);
+        let origin = origin.vector();
+        let mut direction = ray.direction;
+        direction.apply_transform(&self.to_local);
+        let stretch = direction.length();
+        let direction = direction.normalised();
+
+        // only march inside the bounds
+        let radius = self.kind.bounds();
+        let b = origin.dot(&direction);
+        let c = origin.dot(&origin) - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return hitvec![];
+        }
+        let t_near = -b - discriminant.sqrt();
+        let t_far = -b + discriminant.sqrt();
+        if t_far < 0.0 {
+            return hitvec![];
+        }
+        let t_start = t_near.max(0.0);
+
+        let material = self.material.as_ref();
+        let Some(t) = self.march(origin, direction, t_start, t_far) else {
+            if self.kind.distance(origin + direction * t_start) < 0.0 {
+                // all the way inside
+                return hitvec![
+                    Hit::infinity(self, true, f32::NEG_INFINITY, material),
+                    Hit::infinity(self, false, f32::INFINITY, material)
+                ];
+            }
+            return hitvec![];
+        };
+        let first = self.create_hit(ray, (origin, direction), t, stretch);
+        if !first.entering {
+            // the ray started inside
+            let enter = Hit::infinity(self, true, f32::NEG_INFINITY, material);
+            return hitvec![enter, first];
+        }
+
+        let exit = match self.march(origin, direction, t, t_far) {
+            Some(t) => self.create_hit(ray, (origin, direction), t, stretch),
+            None => Hit::infinity(self, false, f32::INFINITY, material),
+        };
+        hitvec![first, exit]
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        let to_world = transform.clone() * self.to_world.clone();
+        let Ok(to_local) = to_world.inverse() else {
+            self.flattened = true;
+            return;
+        };
+        self.to_world = to_world;
+        self.to_local = to_local;
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        if self.flattened {
+            return None;
+        }
+        let sphere = BoundingSphere {
+            centre: Vertex::zero(),
+            radius: self.kind.bounds(),
+        };
+        Some(sphere.transformed(&self.to_world))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+}
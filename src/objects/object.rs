@@ -1,7 +1,146 @@
-use crate::core::{hit::HitVec, ray::Ray, transform::Transform};
+use rand::Rng;
+
+use crate::{
+    core::{
+        hit::{Hit, HitVec},
+        random,
+        ray::Ray,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    materials::material::Material,
+};
 
 pub trait Object: Send + Sync {
-    fn intersect(&self, ray: &Ray) -> HitVec;
+    fn intersect(&self, ray: &Ray) -> HitVec<'_>;
     fn apply_transform(&mut self, transform: &Transform);
+
+    // whether the object blocks light. photons still land on it if not.
+    fn casts_shadows(&self) -> bool {
+        true
+    }
+
+    // a sphere the whole object fits in, None if it doesn't fit in one
+    // (e.g. planes) or doesn't say
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        None
+    }
+
+    // the smallest and largest corners of a box the whole object fits in,
+    // None if it doesn't fit in one
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        let sphere = self.bounding_sphere()?;
+        let extent = Vector::new(sphere.radius, sphere.radius, sphere.radius);
+        Some((sphere.centre.clone() - extent, sphere.centre + extent))
+    }
+
+    // the material the object is drawn with, if it's the same all over
+    fn material(&self) -> Option<&dyn Material> {
+        None
+    }
+
+    // the corners of the triangles the object is made of, for finding which
+    // one a ray hits somewhere else (e.g. on the GPU). None if it isn't only
+    // triangles that stay put.
+    fn triangles(&self) -> Option<Vec<[Vector; 3]>> {
+        None
+    }
+
+    // the hit on the index'th of triangles(), None if the ray misses it
+    fn intersect_triangle(&self, _ray: &Ray, _index: usize) -> Option<Hit<'_>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BoundingSphere {
+    pub centre: Vertex,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    // around the box the points fit in. None if there aren't any.
+    pub fn around(points: impl Iterator<Item = Vertex> + Clone) -> Option<Self> {
+        let (min, max) = bounds_of(points.clone())?;
+        let centre = min.clone() + min.vector_to(&max) * 0.5;
+        let radius = points.map(|p| centre.distance(&p)).fold(0.0, f32::max);
+        Some(Self { centre, radius })
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let between = self.centre.vector_to(&other.centre);
+        let distance = between.length();
+        if distance + other.radius <= self.radius {
+            return self.clone();
+        }
+        if distance + self.radius <= other.radius {
+            return other.clone();
+        }
+
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        let centre = self.centre.clone() + between * ((radius - self.radius) / distance);
+        Self { centre, radius }
+    }
+
+    // still around the object after it's been transformed, growing by the
+    // most the transform stretches anything
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        let mut centre = self.centre.clone();
+        centre.apply_transform(transform);
+        Self {
+            centre,
+            radius: self.radius * max_stretch(transform),
+        }
+    }
+
+    // a random point inside it, uniformly over its volume
+    pub fn random_point(&self) -> Vertex {
+        let depth = random::with_rng(|rng| rng.gen::<f32>()).cbrt();
+        self.centre.clone() + Vector::random() * (self.radius * depth)
+    }
+
+    // how much of the view from position the sphere takes up, as the cosine
+    // of the angle from its centre to its edge. None from inside it.
+    pub fn cos_extent(&self, position: &Vertex) -> Option<f32> {
+        let distance = position.distance(&self.centre);
+        if distance <= self.radius {
+            return None;
+        }
+        let sin = self.radius / distance;
+        Some((1.0 - sin * sin).sqrt())
+    }
+}
+
+// the smallest and largest corners of the box the points fit in, None if
+// there aren't any
+pub fn bounds_of(points: impl Iterator<Item = Vertex>) -> Option<(Vertex, Vertex)> {
+    points.fold(None, |bounds, p| {
+        Some(match bounds {
+            None => (p.clone(), p),
+            Some((min, max)) => (
+                Vertex::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z)),
+                Vertex::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z)),
+            ),
+        })
+    })
 }
 
+// the most the transform lengthens any direction, found by power iteration
+// on its transpose times itself
+fn max_stretch(transform: &Transform) -> f32 {
+    let row = |i: usize| Vector::new(transform[i][0], transform[i][1], transform[i][2]);
+    let column = |i: usize| Vector::new(transform[0][i], transform[1][i], transform[2][i]);
+    let stretched = |v: &Vector| Vector::new(row(0).dot(v), row(1).dot(v), row(2).dot(v));
+    let back = |v: &Vector| Vector::new(column(0).dot(v), column(1).dot(v), column(2).dot(v));
+
+    let mut direction = Vector::new(1.0, 1.0, 1.0).normalised();
+    for _ in 0..16 {
+        let next = back(&stretched(&direction));
+        if next.len_sqrd() == 0.0 {
+            return 0.0;
+        }
+        direction = next.normalised();
+    }
+    stretched(&direction).length()
+}
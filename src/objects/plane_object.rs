@@ -47,11 +47,17 @@ impl Plane {
     ) -> Box<Self> {
         Box::new(Self::new_raw(point, up, normal, material))
     }
+
+    // how far in front of the plane a point is, in units of the normal's
+    // length
+    pub fn signed_distance(&self, point: &Vertex) -> f32 {
+        self.normal.dot(&point.vector()) + self.d
+    }
 }
 
 impl Object for Plane {
     #[allow(non_snake_case)]
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
         let material = self.material.as_ref();
 
         let U = self.normal.dot(&ray.position.vector()) + self.d;
@@ -100,7 +106,7 @@ impl Object for Plane {
             let tex_coords = TexCoords::new(u, v);
 
             if let Some(normal_map) = self.material.normal(&tex_coords) {
-                let right = self.normal.cross(&self.up).normalised();
+                let right = self.normal.cross(&self.up);
                 normal = normal_map.to_tangent_space(&right, &normal);
             }
 
@@ -0,0 +1,476 @@
+// Point clouds, e.g. from a 3D scanner, drawn as a small splat at every
+// point: a disc facing along the point's normal (only seen from that side),
+// or a sphere for points without one. Each point can have its own colour, which is the hit's colour
+// for `material VertexColour`. Scans have millions of points, so they're
+// kept in a bounding volume hierarchy of their own. Like texture images,
+// the colours in the files are sRGB, and are decoded to linear as they're
+// loaded.
+//
+// Points can be loaded from:
+//     .ply files, ascii or binary, with x y z and optionally nx ny nz and
+//         red green blue properties on their vertex element
+//     .xyz files, a point per line: x y z, optionally followed by r g b from
+//         0 to 255, optionally followed by nx ny nz
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{
+    core::{
+        colour::{Colour, ColourSpace},
+        error::RenderError,
+        hit::{Hit, HitVec},
+        ray::Ray,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::{
+    bvh::{Aabb, Bvh},
+    object::{BoundingSphere, Object},
+};
+
+// the most points in a leaf of the hierarchy
+const LEAF_SIZE: usize = 4;
+
+pub struct Point {
+    pub position: Vector,
+    pub normal: Option<Vector>, // a disc facing along it, or a sphere if None
+    pub colour: Option<Colour>,
+}
+
+pub struct PointCloud {
+    points: Vec<Point>, // in the order of the hierarchy's leaves
+    bvh: Bvh,
+    radius: f32,
+    material: Arc<dyn Material>,
+}
+
+impl PointCloud {
+    pub fn new(mut points: Vec<Point>, radius: f32, material: Arc<dyn Material>) -> Box<Self> {
+        let bvh = Self::build(&mut points, radius);
+        Box::new(Self {
+            points,
+            bvh,
+            radius,
+            material,
+        })
+    }
+
+    pub fn from_file(
+        path: PathBuf,
+        radius: f32,
+        material: Arc<dyn Material>,
+    ) -> Result<Box<Self>, RenderError> {
+        let bytes = std::fs::read(&path).map_err(|e| RenderError::io(&path, e))?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let points = match extension.to_lowercase().as_str() {
+            "ply" => parse_ply(&bytes),
+            "xyz" => parse_xyz(&String::from_utf8_lossy(&bytes)),
+            _ => Err("Point clouds must be .ply or .xyz files".to_string()),
+        };
+        let points = points.map_err(|message| RenderError::PointCloud {
+            path: path.clone(),
+            message,
+        })?;
+        if points.is_empty() {
+            return Err(RenderError::PointCloud {
+                path,
+                message: "No points in the file".to_string(),
+            });
+        }
+        Ok(Self::new(points, radius, material))
+    }
+
+    // around the splats, not just their centres
+    fn build(points: &mut [Point], radius: f32) -> Bvh {
+        Bvh::build(points, LEAF_SIZE, |point| {
+            Aabb::around([point.position]).padded(radius)
+        })
+    }
+
+    // where the ray hits the point's splat, as the distance along it and
+    // the normal facing back along the ray
+    fn intersect_point(&self, point: &Point, ray: &Ray) -> Option<(f32, Vector)> {
+        let origin = ray.position.vector();
+        let direction = ray.direction;
+        match point.normal {
+            Some(normal) => {
+                // discs are only seen from the front, where the scanner
+                // was. neighbouring discs overlap, and rays leaving one
+                // would otherwise hit the back of the next.
+                let denominator = direction.dot(&normal);
+                if denominator >= 0.0 {
+                    return None;
+                }
+                let t = (point.position - origin).dot(&normal) / denominator;
+                let on_plane = origin + direction * t;
+                if t < 0.0 || (on_plane - point.position).len_sqrd() > self.radius * self.radius {
+                    return None;
+                }
+                Some((t, normal))
+            }
+            None => {
+                let offset = origin - point.position;
+                let a = direction.len_sqrd();
+                let b = offset.dot(&direction);
+                let c = offset.len_sqrd() - self.radius * self.radius;
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let near = (-b - discriminant.sqrt()) / a;
+                let far = (-b + discriminant.sqrt()) / a;
+                let t = if near >= 0.0 { near } else { far };
+                if t < 0.0 {
+                    return None;
+                }
+                let mut normal = (origin + direction * t - point.position).normalised();
+                if normal.dot(&direction) > 0.0 {
+                    normal.negate();
+                }
+                Some((t, normal))
+            }
+        }
+    }
+}
+
+impl Object for PointCloud {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let origin = ray.position.vector();
+        let d = ray.direction;
+        let inverse_direction = Vector::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+
+        let mut closest: Option<(f32, Vector, &Point)> = None;
+        self.bvh
+            .traverse(&origin, &inverse_direction, f32::INFINITY, |leaf| {
+                for point in &self.points[leaf] {
+                    if let Some((t, normal)) = self.intersect_point(point, ray) {
+                        if closest.is_none_or(|(closest, ..)| t < closest) {
+                            closest = Some((t, normal, point));
+                        }
+                    }
+                }
+                closest.map(|(t, ..)| t)
+            });
+
+        let Some((distance, normal, point)) = closest else {
+            return hitvec![];
+        };
+        // splats are surfaces, not solids, so every hit is the front
+        let mut hit = Hit::new(
+            self,
+            true,
+            distance,
+            ray.position.clone() + ray.direction * distance,
+            normal,
+            self.material.as_ref(),
+            None,
+        );
+        if point.normal.is_none() {
+            hit.curvature = 1.0 / self.radius;
+        }
+        hit.colour = point.colour;
+        hitvec![hit]
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        // normals go by the cofactors of the transform, which is its inverse
+        // transpose scaled, but still works when it squashes space flat
+        let column = |i: usize| Vector::new(transform[0][i], transform[1][i], transform[2][i]);
+        let (x, y, z) = (column(0), column(1), column(2));
+        let cofactors = [y.cross(&z), z.cross(&x), x.cross(&y)];
+
+        for point in &mut self.points {
+            let mut position = Vertex::from(point.position);
+            position.apply_transform(transform);
+            point.position = position.vector();
+            if let Some(normal) = &mut point.normal {
+                *normal =
+                    (cofactors[0] * normal.x + cofactors[1] * normal.y + cofactors[2] * normal.z)
+                        .normalised();
+            }
+        }
+        // the splats can't stretch, so they keep their volume
+        self.radius *= x.dot(&cofactors[0]).abs().cbrt();
+        self.bvh = Self::build(&mut self.points, self.radius);
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(self.bvh.aabb()?.bounding_sphere())
+    }
+
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        Some(self.bvh.aabb()?.bounds())
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+}
+
+// scans without normals sometimes write zeros instead
+fn unit_normal(normal: Vector) -> Option<Vector> {
+    (normal.len_sqrd() > 0.0).then(|| normal.normalised())
+}
+
+fn parse_xyz(contents: &str) -> Result<Vec<Point>, String> {
+    let mut points = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let numbers = line
+            .split_whitespace()
+            .map(|word| word.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("line {}: Invalid number", index + 1))?;
+        let (colour, normal) = match numbers.len() {
+            3 => (None, None),
+            6 => (Some(&numbers[3..6]), None),
+            9 => (Some(&numbers[3..6]), Some(&numbers[6..9])),
+            _ => {
+                return Err(format!(
+                    "line {}: Expected x y z, then optionally r g b and nx ny nz",
+                    index + 1
+                ))
+            }
+        };
+        points.push(Point {
+            position: Vector::new(numbers[0], numbers[1], numbers[2]),
+            normal: normal.and_then(|n| unit_normal(Vector::new(n[0], n[1], n[2]))),
+            colour: colour.map(|c| {
+                ColourSpace::Srgb.decode(Colour::new(c[0] / 255.0, c[1] / 255.0, c[2] / 255.0))
+            }),
+        });
+    }
+    Ok(points)
+}
+
+#[derive(Clone, Copy)]
+enum PlyType {
+    Int(usize), // the size in bytes
+    Uint(usize),
+    Float,
+    Double,
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "char" | "int8" => Self::Int(1),
+            "uchar" | "uint8" => Self::Uint(1),
+            "short" | "int16" => Self::Int(2),
+            "ushort" | "uint16" => Self::Uint(2),
+            "int" | "int32" => Self::Int(4),
+            "uint" | "uint32" => Self::Uint(4),
+            "float" | "float32" => Self::Float,
+            "double" | "float64" => Self::Double,
+            _ => return Err(format!("Unknown property type: {name}")),
+        })
+    }
+
+    // in bytes, in a binary file
+    fn size(self) -> usize {
+        match self {
+            Self::Int(size) | Self::Uint(size) => size,
+            Self::Float => 4,
+            Self::Double => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    kind: PlyType,
+    list_count: Option<PlyType>, // the type of the length, for lists
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+// the values after the header, as words or bytes
+enum PlyBody<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary { bytes: &'a [u8], big_endian: bool },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, kind: PlyType) -> Result<f64, String> {
+        let truncated = || "The file ends before all the points".to_string();
+        match self {
+            Self::Ascii(words) => {
+                let word = words.next().ok_or_else(truncated)?;
+                word.parse::<f64>()
+                    .map_err(|_| format!("Invalid number: {word}"))
+            }
+            Self::Binary { bytes, big_endian } => {
+                let size = kind.size();
+                if bytes.len() < size {
+                    return Err(truncated());
+                }
+                let (value, rest) = bytes.split_at(size);
+                *bytes = rest;
+                let mut buffer = [0; 8];
+                if *big_endian {
+                    buffer[8 - size..].copy_from_slice(value);
+                    buffer.reverse();
+                } else {
+                    buffer[..size].copy_from_slice(value);
+                }
+                let unsigned = u64::from_le_bytes(buffer);
+                Ok(match kind {
+                    // shifted up and back down again to carry the sign
+                    PlyType::Int(size) => {
+                        let shift = 64 - 8 * size as u32;
+                        ((unsigned << shift) as i64 >> shift) as f64
+                    }
+                    PlyType::Uint(_) => unsigned as f64,
+                    PlyType::Float => f32::from_bits(unsigned as u32) as f64,
+                    PlyType::Double => f64::from_bits(unsigned),
+                })
+            }
+        }
+    }
+}
+
+fn parse_ply(bytes: &[u8]) -> Result<Vec<Point>, String> {
+    // the header is always text, ending with its own line
+    let header_end = bytes
+        .windows(b"end_header".len())
+        .position(|window| window == b"end_header")
+        .ok_or("Missing end_header")?;
+    let body_start = bytes[header_end..]
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .map_or(bytes.len(), |newline| header_end + newline + 1);
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("Not a PLY file".to_string());
+    }
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    for line in lines {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words[..] {
+            ["format", name, _] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("Invalid element count: {count}"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, kind, name] => {
+                let element = elements.last_mut().ok_or("Property before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::from_name(kind)?,
+                    list_count: Some(PlyType::from_name(count)?),
+                });
+            }
+            ["property", kind, name] => {
+                let element = elements.last_mut().ok_or("Property before any element")?;
+                element.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::from_name(kind)?,
+                    list_count: None,
+                });
+            }
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(format!("Invalid header line: {line}")),
+        }
+    }
+
+    let body = &bytes[body_start..];
+    let body_len = body.len();
+    let mut body = match format.as_deref() {
+        Some("ascii") => PlyBody::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|_| "Invalid text after the header")?
+                .split_whitespace(),
+        ),
+        Some("binary_little_endian") => PlyBody::Binary {
+            bytes: body,
+            big_endian: false,
+        },
+        Some("binary_big_endian") => PlyBody::Binary {
+            bytes: body,
+            big_endian: true,
+        },
+        _ => return Err("Missing or unknown format".to_string()),
+    };
+
+    for element in &elements {
+        if element.name != "vertex" {
+            // read past the ones before the vertices
+            for _ in 0..element.count {
+                for property in &element.properties {
+                    let count = match property.list_count {
+                        Some(count) => body.read(count)? as usize,
+                        None => 1,
+                    };
+                    for _ in 0..count {
+                        body.read(property.kind)?;
+                    }
+                }
+            }
+            continue;
+        }
+
+        let find = |name: &str| element.properties.iter().position(|p| p.name == name);
+        let (Some(x), Some(y), Some(z)) = (find("x"), find("y"), find("z")) else {
+            return Err("Vertices need x, y and z properties".to_string());
+        };
+        let normal = find("nx").zip(find("ny")).zip(find("nz"));
+        let colour = find("red").zip(find("green")).zip(find("blue"));
+        if element.properties.iter().any(|p| p.list_count.is_some()) {
+            return Err("Vertices can't have list properties".to_string());
+        }
+
+        // a broken header can claim more points than the file has room for,
+        // so don't reserve more than that. a value in an ascii file takes at
+        // least a digit and a space.
+        let row_size: usize = element
+            .properties
+            .iter()
+            .map(|property| match body {
+                PlyBody::Ascii(_) => 2,
+                PlyBody::Binary { .. } => property.kind.size(),
+            })
+            .sum();
+        let mut points = Vec::with_capacity(element.count.min(body_len / row_size.max(1)));
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                *value = body.read(property.kind)? as f32;
+                // colours are 0 to 255 unless they're floats
+                if let PlyType::Int(_) | PlyType::Uint(_) = property.kind {
+                    if ["red", "green", "blue"].contains(&property.name.as_str()) {
+                        *value /= 255.0;
+                    }
+                }
+            }
+            points.push(Point {
+                position: Vector::new(values[x], values[y], values[z]),
+                normal: normal.and_then(|((nx, ny), nz)| {
+                    unit_normal(Vector::new(values[nx], values[ny], values[nz]))
+                }),
+                colour: colour.map(|((r, g), b)| {
+                    ColourSpace::Srgb.decode(Colour::new(values[r], values[g], values[b]))
+                }),
+            });
+        }
+        return Ok(points);
+    }
+    Err("No vertex element".to_string())
+}
@@ -1,33 +1,241 @@
 use std::{
     collections::HashMap,
     fs::File,
-    io::{BufRead, BufReader},
-    sync::Arc,
+    io::{BufReader, Read, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
 };
 
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
 use crate::{
     core::{
+        colour::{Colour, ColourSpace},
+        error::RenderError,
         hit::{Hit, HitVec},
+        platform,
         ray::Ray,
+        tex_coords::TexCoords,
         transform::Transform,
         vector::Vector,
         vertex::{RichVertex, Vertex},
     },
     hitvec,
-    materials::material::Material,
+    materials::{material::Material, texture::Image},
 };
 
-use super::{object::Object, triangle_object::Triangle};
+use super::{
+    object::{bounds_of, BoundingSphere, Object},
+    triangle_object::Triangle,
+    triangle_packet::PacketBvh,
+};
 
 pub struct PolyMesh {
     vertices: Vec<RichVertex>,
     triangles: Vec<Triangle>,
     normals: Vec<Vector>,
+    tex_coords: Vec<TexCoords>,
     smooth: bool,
     material: Arc<dyn Material>,
 
     // map from vertex index to indexes of adjacent triangles
     vertex_to_triangles: HashMap<usize, Vec<usize>>,
+
+    deformation: Option<Deformation>,
+
+    // the triangles eight at a time, for the "simd" feature
+    packets: OnceLock<PacketBvh>,
+}
+
+// where the mesh's triangles have moved to end_time seconds after the
+// shutter opens. rays in between see the triangles part way there.
+struct Deformation {
+    triangles: Vec<Triangle>, // in the same order as the mesh's
+    end_time: f32,
+}
+
+// (vertex index, optional[tex coords index], optional[normal index])
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+// a triangle's (vertex index, tex coords) corners, while reshaping the mesh
+type Face = [(usize, Option<TexCoords>); 3];
+
+// the records parsed from a contiguous range of lines of an OBJ file
+#[derive(Default)]
+struct ObjChunk {
+    vertices: Vec<RichVertex>,
+    normals: Vec<Vector>,
+    tex_coords: Vec<TexCoords>,
+    faces: Vec<(usize, Vec<FaceVertex>)>, // with their line numbers
+}
+
+// what's wrong with a line of an OBJ file, and which line it is
+type ObjError = (usize, String);
+
+// how many lines each thread parses between progress updates
+const PROGRESS_LINES: usize = 1 << 16;
+
+impl ObjChunk {
+    // split the file into one chunk of lines per thread and parse them all at once.
+    // OBJ indices are absolute, so stitching the chunks back together in order
+    // keeps every face pointing at the right vertices.
+    fn parse_parallel(contents: &str) -> Result<Self, ObjError> {
+        let lines: Vec<&str> = contents.lines().collect();
+
+        let num_threads = platform::num_threads();
+        let lines_per_chunk = lines.len().div_ceil(num_threads).max(1);
+
+        // every thread adds the lines it has parsed, a few at a time
+        let total = lines.len();
+        let parsed = AtomicUsize::new(0);
+        print_parse_progress(0, total);
+        let chunks = lines.chunks(lines_per_chunk).enumerate().collect();
+        let chunks = platform::map_parallel(chunks, |(index, lines)| {
+            let mut chunk = Self::default();
+            let first_line = index * lines_per_chunk + 1;
+            for (part, lines) in lines.chunks(PROGRESS_LINES).enumerate() {
+                chunk.parse(lines, first_line + part * PROGRESS_LINES)?;
+                let done = parsed.fetch_add(lines.len(), Ordering::Relaxed) + lines.len();
+                print_parse_progress(done, total);
+            }
+            Ok(chunk)
+        });
+        println!();
+
+        let mut this = Self::default();
+        for chunk in chunks {
+            let chunk = chunk?;
+            this.vertices.extend(chunk.vertices);
+            this.normals.extend(chunk.normals);
+            this.tex_coords.extend(chunk.tex_coords);
+            this.faces.extend(chunk.faces);
+        }
+
+        Ok(this)
+    }
+
+    // adds the records in lines to the chunk. first_line is the line number
+    // of lines[0].
+    fn parse(&mut self, lines: &[&str], first_line: usize) -> Result<(), ObjError> {
+        for (line_number, line) in (first_line..).zip(lines) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let number = |index: usize, what: &str| -> Result<f32, ObjError> {
+                words
+                    .get(index)
+                    .and_then(|word| word.parse::<f32>().ok())
+                    .ok_or_else(|| (line_number, format!("Could not parse {what}")))
+            };
+
+            match words[0] {
+                "#" => continue,
+                "v" => {
+                    let x = number(1, "vertex x coordinate")?;
+                    let y = number(2, "vertex y coordinate")?;
+                    let z = number(3, "vertex z coordinate")?;
+                    let mut vertex: RichVertex = Vertex::new(x, y, z).into();
+
+                    // some tools (e.g. for scans) put a colour after the
+                    // position, sRGB like a texture's
+                    if words.len() >= 7 {
+                        let colour = Colour::new(
+                            number(4, "vertex colour")?,
+                            number(5, "vertex colour")?,
+                            number(6, "vertex colour")?,
+                        );
+                        vertex.colour = Some(ColourSpace::Srgb.decode(colour));
+                    }
+                    self.vertices.push(vertex);
+                }
+                "vn" => {
+                    let x = number(1, "normal x coordinate")?;
+                    let y = number(2, "normal y coordinate")?;
+                    let z = number(3, "normal z coordinate")?;
+                    self.normals.push(Vector::new(x, y, z));
+                }
+                "vt" => {
+                    let u = number(1, "texture u coordinate")?;
+                    let v = number(2, "texture v coordinate")?;
+                    self.tex_coords.push(TexCoords::new(u, v));
+                }
+                "f" => {
+                    let face = Self::parse_face(&words).map_err(|e| (line_number, e))?;
+                    self.faces.push((line_number, face));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_face(words: &[&str]) -> Result<Vec<FaceVertex>, String> {
+        // the line is of the form:
+        // f 1/2/3 4/5/6 7/8/9 [10/11/12]
+
+        // OBJ indices start at 1
+        let index = |word: &str, what: &str| -> Result<usize, String> {
+            word.parse::<usize>()
+                .ok()
+                .and_then(|index| index.checked_sub(1))
+                .ok_or_else(|| format!("Could not parse {what} index"))
+        };
+
+        let mut indices_in_obj = Vec::new();
+
+        for vertex_info in words.iter().skip(1) {
+            let numbers: Vec<&str> = vertex_info.split('/').collect();
+
+            let vertex_index = index(numbers[0], "vertex")?;
+            // the tex coords index can be empty, e.g. "f 1//3"
+            let tex_coords_index = match numbers.get(1).filter(|t| !t.is_empty()) {
+                Some(t) => Some(index(t, "tex coords")?),
+                None => None,
+            };
+            let normal_index = match numbers.get(2) {
+                Some(n) => Some(index(n, "normal")?),
+                None => None,
+            };
+
+            indices_in_obj.push((vertex_index, tex_coords_index, normal_index));
+        }
+
+        if indices_in_obj.len() < 3 {
+            return Err("Faces need at least 3 vertices".to_string());
+        }
+        Ok(indices_in_obj)
+    }
+
+    // whether a face's indices all point at something that was parsed
+    fn check_face(&self, face: &[FaceVertex]) -> Result<(), String> {
+        for &(vertex, tex_coords, normal) in face {
+            if vertex >= self.vertices.len() {
+                return Err(format!("No vertex {}", vertex + 1));
+            }
+            if tex_coords.is_some_and(|t| t >= self.tex_coords.len()) {
+                return Err(format!("No tex coords {}", tex_coords.unwrap() + 1));
+            }
+            if normal.is_some_and(|n| n >= self.normals.len()) {
+                return Err(format!("No normal {}", normal.unwrap() + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+// like cameras::camera::print_progress(), but for lines of an OBJ file
+fn print_parse_progress(done: usize, total: usize) {
+    let percent = done * 100 / total.max(1);
+    let mut stdout = std::io::stdout().lock();
+    let _ = write!(
+        stdout,
+        "\rParsing OBJ file... {percent}% ({done}/{total} lines)"
+    );
+    let _ = stdout.flush();
 }
 
 impl PolyMesh {
@@ -35,171 +243,385 @@ impl PolyMesh {
         path: std::path::PathBuf,
         material: Arc<dyn Material>,
         smooth: bool,
-    ) -> Self {
-        let obj_file = File::open(path.clone()).unwrap_or_else(|e| {
-            panic!(
-                "Could not open OBJ file at path {} (cwd: {:?})\n{}",
-                path.to_str().unwrap(),
-                std::env::current_dir().unwrap(),
-                e
-            )
-        });
+    ) -> Result<Self, RenderError> {
+        let obj_file = File::open(&path).map_err(|e| RenderError::io(&path, e))?;
 
         let mut this = Self {
             vertices: Vec::new(),
             triangles: Vec::new(),
             normals: Vec::new(),
+            tex_coords: Vec::new(),
             smooth,
             material,
             vertex_to_triangles: HashMap::new(),
+            deformation: None,
+            packets: OnceLock::new(),
         };
 
-        let reader = BufReader::new(obj_file);
-        for line in reader.lines() {
-            let line = line.expect("Could not read next line from OBJ file");
+        let mut contents = String::new();
+        BufReader::new(obj_file)
+            .read_to_string(&mut contents)
+            .map_err(|e| RenderError::io(&path, e))?;
 
-            if line.is_empty() {
-                continue;
-            }
+        let obj_error = |(line, message): ObjError| RenderError::Obj {
+            path: path.clone(),
+            line,
+            message,
+        };
+        let chunk = ObjChunk::parse_parallel(&contents).map_err(obj_error)?;
+        for (line, face) in &chunk.faces {
+            chunk
+                .check_face(face)
+                .map_err(|message| obj_error((*line, message)))?;
+        }
 
-            let words: Vec<&str> = line.split_whitespace().collect();
-            match words[0] {
-                "#" => continue,
-                "v" => {
-                    let x = words[1]
-                        .parse::<f32>()
-                        .expect("Could not parse vertex x coordinate");
-                    let y = words[2]
-                        .parse::<f32>()
-                        .expect("Could not parse vertex y coordinate");
-                    let z = words[3]
-                        .parse::<f32>()
-                        .expect("Could not parse vertex z coordinate");
-                    this.vertices.push(Vertex::new(x, y, z).into());
-                }
-                "vn" => {
-                    let x = words[1]
-                        .parse::<f32>()
-                        .expect("Could not parse normal x coordinate");
-                    let y = words[2]
-                        .parse::<f32>()
-                        .expect("Could not parse normal y coordinate");
-                    let z = words[3]
-                        .parse::<f32>()
-                        .expect("Could not parse normal z coordinate");
-                    this.normals.push(Vector::new(x, y, z));
-                }
-                "f" => {
-                    this.parse_face(words);
-                }
-                _ => {}
-            }
+        this.vertices = chunk.vertices;
+        this.normals = chunk.normals;
+        this.tex_coords = chunk.tex_coords;
+
+        // the triangles only read what was parsed, so they're built on all
+        // the threads at once
+        let faces: Vec<Vec<FaceVertex>> = chunk.faces.into_iter().map(|(_, face)| face).collect();
+        let triangles = map_split(&faces, |faces| {
+            faces
+                .iter()
+                .flat_map(|face| this.face_triangles(face))
+                .collect()
+        });
+
+        // a vertex keeps the normal of the last face that uses it
+        for &(vertex, _, normal) in faces.iter().flatten() {
+            this.vertices[vertex].normal = normal.map(|normal| this.normals[normal]);
+        }
+        for triangle in triangles {
+            this.add_triangle(triangle);
         }
 
         // at this point, all the faces have been parsed. go through them again
         // and calculate any missing vertex normals.
         if smooth {
-            for vertex_index in 0..this.vertices.len() {
-                let vertex = &this.vertices[vertex_index];
-                if vertex.normal.is_some() {
-                    continue;
-                }
-
-                this.calculate_normal(vertex_index);
+            this.fill_missing_normals();
+        }
 
-                // go to all the triangles that have this vertex to give them
-                // the new vertex normal
-                for triangle_index in this.vertex_to_triangles[&vertex_index].iter() {
-                    // triangle_index is the index of the triangle that has
-                    // this vertex as one of its points
+        Ok(this)
+    }
 
-                    let vertex = &this.vertices[vertex_index];
-                    let triangle = &mut this.triangles[*triangle_index];
-                    let (a_index, b_index, c_index) = triangle.vertex_indices;
+    fn fill_missing_normals(&mut self) {
+        // the vertices of faces that have no normal. averaging only reads
+        // the triangles, so it's split between the threads.
+        let missing: Vec<usize> = (0..self.vertices.len())
+            .filter(|index| {
+                self.vertices[*index].normal.is_none()
+                    && self.vertex_to_triangles.contains_key(index)
+            })
+            .collect();
+        let normals = map_split(&missing, |indices| {
+            indices
+                .iter()
+                .map(|index| self.average_normal(*index))
+                .collect()
+        });
 
-                    if a_index == vertex_index {
-                        triangle.a.normal = vertex.normal;
-                    } else if b_index == vertex_index {
-                        triangle.b.normal = vertex.normal;
-                    } else if c_index == vertex_index {
-                        triangle.c.normal = vertex.normal;
-                    }
+        for (vertex_index, normal) in missing.into_iter().zip(normals) {
+            self.vertices[vertex_index].normal = Some(normal);
+
+            // go to all the triangles that have this vertex to give them
+            // the new vertex normal
+            for triangle_index in self.vertex_to_triangles[&vertex_index].iter() {
+                // triangle_index is the index of the triangle that has
+                // this vertex as one of its points
+
+                let vertex = &self.vertices[vertex_index];
+                let triangle = &mut self.triangles[*triangle_index];
+                let (a_index, b_index, c_index) = triangle.vertex_indices;
+
+                if a_index == vertex_index {
+                    triangle.a.normal = vertex.normal;
+                } else if b_index == vertex_index {
+                    triangle.b.normal = vertex.normal;
+                } else if c_index == vertex_index {
+                    triangle.c.normal = vertex.normal;
                 }
             }
         }
+    }
+
+    // count points spread evenly over the surface, with the normal there
+    // (smooth if the mesh is), e.g. to grow fur from. the same seed picks
+    // the same points.
+    pub fn sample_surface(&self, count: usize, seed: u64) -> Vec<(Vertex, Vector)> {
+        let mut total_area = 0.0;
+        let cumulative_areas = self
+            .triangles
+            .iter()
+            .map(|triangle| {
+                total_area += triangle.ab.cross(&triangle.bc).length() / 2.0;
+                total_area
+            })
+            .collect::<Vec<_>>();
+        if self.triangles.is_empty() || total_area <= 0.0 {
+            return Vec::new();
+        }
 
-        this
+        let mut rng = SmallRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| {
+                let area = rng.gen::<f32>() * total_area;
+                let index = cumulative_areas
+                    .partition_point(|&cumulative| cumulative < area)
+                    .min(self.triangles.len() - 1);
+                let triangle = &self.triangles[index];
+
+                // uniform over the triangle, from Osada et al. 2002,
+                // "Shape Distributions"
+                let (r1, r2) = (rng.gen::<f32>().sqrt(), rng.gen::<f32>());
+                let weights = [1.0 - r1, r1 * (1.0 - r2), r1 * r2];
+                let position = triangle.a.vector() * weights[0]
+                    + triangle.b.vector() * weights[1]
+                    + triangle.c.vector() * weights[2];
+                let normal = match (triangle.a.normal, triangle.b.normal, triangle.c.normal) {
+                    (Some(a), Some(b), Some(c)) if self.smooth => {
+                        (a * weights[0] + b * weights[1] + c * weights[2]).normalised()
+                    }
+                    _ => triangle.get_plane_normal(),
+                };
+                (Vertex::from(position), normal)
+            })
+            .collect()
     }
 
-    fn parse_face(&mut self, words: Vec<&str>) {
-        // the line is of the form:
-        // f 1/2/3 4/5/6 7/8/9 [10/11/12]
+    // loop subdivision: split every triangle into 4, levels times, moving
+    // the old and new vertices towards a smooth surface through the original
+    // (control) mesh. the mesh is rendered smooth afterwards.
+    pub fn subdivide(&mut self, levels: u32) {
+        let mut vertices = self.vertices.clone();
+        let mut faces = self.faces();
 
-        // vec of (vertex index, optional[normal index])
-        let mut indices_in_obj: Vec<(usize, Option<usize>)> = Vec::new();
+        for _ in 0..levels {
+            (vertices, faces) = loop_subdivide(&vertices, &faces);
+        }
 
-        for vertex_info in words.iter().skip(1) {
-            let numbers: Vec<&str> = vertex_info.split('/').collect();
+        self.smooth = true;
+        self.rebuild(vertices, faces);
+    }
+
+    // displacement mapping: split every triangle into 4, subdivisions times,
+    // then push each vertex out along its normal by the brightness of the
+    // displacement image at its tex coords, times scale. vertices shared
+    // between faces only move once, so the mesh doesn't crack open along
+    // tex coord seams. vertices without tex coords stay where they are.
+    pub fn displace(&mut self, displacement: &Image, scale: f32, subdivisions: u32) {
+        // every vertex needs a normal to be pushed along
+        for vertex_index in 0..self.vertices.len() {
+            if self.vertex_to_triangles.contains_key(&vertex_index) {
+                self.calculate_normal(vertex_index);
+            }
+        }
+
+        let mut vertices = self.vertices.clone();
+        let mut faces = self.faces();
+
+        for _ in 0..subdivisions {
+            faces = split_faces(&faces, &mut vertices, |vertices, p, q| {
+                lerp_vertex(&vertices[p], &vertices[q], 0.5)
+            });
+        }
+
+        let mut displaced = vec![false; vertices.len()];
+        for (index, tex_coords) in faces.iter().flatten() {
+            let Some(tex_coords) = tex_coords else {
+                continue;
+            };
+            let vertex = &mut vertices[*index];
+            let Some(normal) = vertex.normal else {
+                continue;
+            };
+            if displaced[*index] {
+                continue;
+            }
+
+            let height = displacement.get(tex_coords.clone());
+            let height = (height.r + height.g + height.b) / 3.0;
+            vertex.vertex += normal.normalised() * (height * scale);
+            displaced[*index] = true;
+        }
+
+        self.rebuild(vertices, faces);
+    }
+
+    fn faces(&self) -> Vec<Face> {
+        self.triangles
+            .iter()
+            .map(|triangle| {
+                let (a, b, c) = triangle.vertex_indices;
+                [
+                    (a, triangle.a.tex_coords.clone()),
+                    (b, triangle.b.tex_coords.clone()),
+                    (c, triangle.c.tex_coords.clone()),
+                ]
+            })
+            .collect()
+    }
 
-            let vertex_index = numbers[0]
-                .parse::<usize>()
-                .expect("Could not parse vertex index")
-                - 1;
-            let normal_index = numbers
-                .get(2)
-                .map(|n| n.parse::<usize>().expect("Could not parse normal index") - 1);
+    // replace the mesh's triangles after reshaping it
+    fn rebuild(&mut self, mut vertices: Vec<RichVertex>, faces: Vec<Face>) {
+        // the old normals point the wrong way on the new surface
+        for vertex in &mut vertices {
+            vertex.normal = None;
+        }
+        self.vertices = vertices;
+        self.triangles.clear();
+        self.vertex_to_triangles.clear();
+        self.packets = OnceLock::new();
+
+        for face in faces {
+            let [a, b, c] = face.map(|(index, tex_coords)| {
+                let mut vertex = self.vertices[index].clone();
+                vertex.tex_coords = tex_coords;
+                (index, vertex)
+            });
+            let indices = (a.0, b.0, c.0);
+
+            self.add_triangle(Triangle::new(
+                a.1,
+                b.1,
+                c.1,
+                indices,
+                self.material.clone(),
+                self.smooth,
+            ));
+        }
 
-            indices_in_obj.push((vertex_index, normal_index));
+        if self.smooth {
+            self.fill_missing_normals();
         }
+    }
 
-        // function to create, process and store a triangle
-        let mut create_triangle = |i: usize, j: usize, k: usize| {
-            // i, j, k are the indices of indices_in_obj
+    // blur between this mesh and end, e.g. the next frame of an animation
+    // exported as OBJ files. both must have the same faces in the same order.
+    pub fn deform_to(&mut self, end: PolyMesh, end_time: f32) -> Result<(), String> {
+        if end.triangles.len() != self.triangles.len() {
+            return Err(format!(
+                "Deformed mesh has {} triangles, expected {}",
+                end.triangles.len(),
+                self.triangles.len()
+            ));
+        }
 
-            // (index in vertices, index in normals)
-            let (av, an) = indices_in_obj[i];
-            let (bv, bn) = indices_in_obj[j];
-            let (cv, cn) = indices_in_obj[k];
+        self.deformation = Some(Deformation {
+            triangles: end.triangles,
+            end_time,
+        });
+        Ok(())
+    }
 
-            // set normals
-            let get_normal = |index: usize| self.normals[index];
-            self.vertices[av].normal = an.map(get_normal);
-            self.vertices[bv].normal = bn.map(get_normal);
-            self.vertices[cv].normal = cn.map(get_normal);
+    // the closest hit on the mesh as it is t of the way to its deformed state
+    fn intersect_deformed(&self, ray: &Ray, deformation: &Deformation, t: f32) -> Option<Hit<'_>> {
+        let mut closest_hit: Option<Hit> = None;
 
+        for (start, end) in self.triangles.iter().zip(&deformation.triangles) {
             let triangle = Triangle::new(
-                self.vertices[av].clone(),
-                self.vertices[bv].clone(),
-                self.vertices[cv].clone(),
-                (av, bv, cv),
+                lerp_vertex(&start.a, &end.a, t),
+                lerp_vertex(&start.b, &end.b, t),
+                lerp_vertex(&start.c, &end.c, t),
+                start.vertex_indices,
                 self.material.clone(),
                 self.smooth,
             );
-            self.triangles.push(triangle);
 
-            // add the triangle to the vertex_to_triangles map
-            for index in [i, j, k] {
-                let entry = self.vertex_to_triangles.entry(index).or_default();
-                entry.push(self.triangles.len() - 1);
+            for hit in triangle.intersect(ray) {
+                let is_closer = closest_hit
+                    .as_ref()
+                    .is_none_or(|closest| hit.distance < closest.distance);
+                if hit.distance < 0.0 || !is_closer {
+                    continue;
+                }
+
+                // the in between triangle only lives for this loop, so
+                // point the hit at the undeformed one instead
+                let mut mesh_hit = Hit::new(
+                    start,
+                    hit.entering,
+                    hit.distance,
+                    hit.position.clone(),
+                    hit.normal,
+                    self.material.as_ref(),
+                    hit.tex_coords.clone(),
+                );
+                mesh_hit.curvature = hit.curvature;
+                mesh_hit.colour = hit.colour;
+                mesh_hit.barycentric = hit.barycentric;
+                closest_hit = Some(mesh_hit);
             }
-        };
+        }
 
-        // create first triangle
-        create_triangle(0, 1, 2);
+        closest_hit
+    }
+
+    // find the closest triangle eight at a time, then work out the hit on
+    // just that one. None if no triangle is hit or the hit doesn't agree
+    // with the exact maths (right on the edge of a triangle), to fall back
+    // to checking every triangle.
+    fn intersect_packed(&self, ray: &Ray) -> Option<HitVec<'_>> {
+        let packets = self
+            .packets
+            .get_or_init(|| PacketBvh::build(&self.triangles));
+        let (_, index) = packets.closest(ray)?;
+        let hit = self.intersect_triangle(ray, index)?;
+        Some(hitvec![hit])
+    }
+
+    // the triangles of a face from an OBJ file, two if it's a quad, with the
+    // face's own normals and tex coords at their corners
+    fn face_triangles(&self, face: &[FaceVertex]) -> Vec<Triangle> {
+        let corner = |i: usize| {
+            // (index in vertices, index in tex coords, index in normals)
+            let (vertex, tex_coords, normal) = face[i];
+            let mut corner = self.vertices[vertex].clone();
+            corner.normal = normal.map(|normal| self.normals[normal]);
+            // tex coords belong to the face, not the shared vertex,
+            // so only set them on the triangle's own copy
+            corner.tex_coords = tex_coords.map(|index| self.tex_coords[index].clone());
+            (vertex, corner)
+        };
+        let triangle = |i: usize, j: usize, k: usize| {
+            let [(a, a_corner), (b, b_corner), (c, c_corner)] = [corner(i), corner(j), corner(k)];
+            Triangle::new(
+                a_corner,
+                b_corner,
+                c_corner,
+                (a, b, c),
+                self.material.clone(),
+                self.smooth,
+            )
+        };
 
+        let mut triangles = vec![triangle(0, 1, 2)];
         // if there's a fourth vertex, create second triangle
-        if indices_in_obj.len() == 4 {
-            create_triangle(0, 2, 3);
+        if face.len() == 4 {
+            triangles.push(triangle(0, 2, 3));
+        }
+        triangles
+    }
+
+    // store a triangle, and note it in the vertex_to_triangles map
+    fn add_triangle(&mut self, triangle: Triangle) {
+        let (a, b, c) = triangle.vertex_indices;
+        self.triangles.push(triangle);
+        for index in [a, b, c] {
+            let entry = self.vertex_to_triangles.entry(index).or_default();
+            entry.push(self.triangles.len() - 1);
         }
     }
 
     fn calculate_normal(&mut self, vertex_index: usize) {
-        let vertex = &mut self.vertices[vertex_index];
-        if vertex.normal.is_some() {
-            return;
+        if self.vertices[vertex_index].normal.is_none() {
+            self.vertices[vertex_index].normal = Some(self.average_normal(vertex_index));
         }
+    }
 
-        // average the normals of the triangles adjacent to this vertex
+    // average the normals of the triangles adjacent to a vertex
+    fn average_normal(&self, vertex_index: usize) -> Vector {
         let mut average_normal = Vector::zero();
         for triangle_index in self.vertex_to_triangles[&vertex_index].iter() {
             let triangle = &self.triangles[*triangle_index];
@@ -208,12 +630,144 @@ impl PolyMesh {
         }
 
         average_normal.normalise();
-        vertex.normal = Some(average_normal);
+        average_normal
+    }
+}
+
+// f over items, split evenly between the threads, with the results of all
+// the splits in order
+fn map_split<I: Sync, T: Send>(items: &[I], f: impl Fn(&[I]) -> Vec<T> + Sync) -> Vec<T> {
+    let per_thread = items.len().div_ceil(platform::num_threads()).max(1);
+    platform::map_parallel(items.chunks(per_thread).collect(), f)
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// split every face into 4 through the middle of its edges. midpoint makes
+// the new vertex between two existing ones, which neighbouring faces share.
+fn split_faces(
+    faces: &[Face],
+    vertices: &mut Vec<RichVertex>,
+    midpoint: impl Fn(&[RichVertex], usize, usize) -> RichVertex,
+) -> Vec<Face> {
+    let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut subdivided = Vec::with_capacity(faces.len() * 4);
+
+    for face in faces {
+        let mut split = |i: usize, j: usize| {
+            let ((p, p_tex), (q, q_tex)) = (&face[i], &face[j]);
+            let index = *midpoints.entry((*p.min(q), *p.max(q))).or_insert_with(|| {
+                vertices.push(midpoint(vertices, *p, *q));
+                vertices.len() - 1
+            });
+            let tex_coords = match (p_tex, q_tex) {
+                (Some(p), Some(q)) => Some(TexCoords::new((p.u + q.u) / 2.0, (p.v + q.v) / 2.0)),
+                _ => None,
+            };
+            (index, tex_coords)
+        };
+        let ab = split(0, 1);
+        let bc = split(1, 2);
+        let ca = split(2, 0);
+
+        let [a, b, c] = face.clone();
+        subdivided.push([a, ab.clone(), ca.clone()]);
+        subdivided.push([ab.clone(), b, bc.clone()]);
+        subdivided.push([ca.clone(), bc.clone(), c]);
+        subdivided.push([ab, bc, ca]);
     }
+
+    subdivided
+}
+
+// one level of loop subdivision, returning the new vertices and faces.
+// the weights are from Charles Loop's 1987 thesis, with open edges kept
+// as curves of their own.
+fn loop_subdivide(vertices: &[RichVertex], faces: &[Face]) -> (Vec<RichVertex>, Vec<Face>) {
+    // the vertices opposite each edge, one per face the edge is part of
+    let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for face in faces {
+        for i in 0..3 {
+            let (p, q, opposite) = (face[i].0, face[(i + 1) % 3].0, face[(i + 2) % 3].0);
+            edges.entry((p.min(q), p.max(q))).or_default().push(opposite);
+        }
+    }
+
+    // every vertex's neighbours, and the ones along an open edge
+    let mut neighbours = vec![Vec::new(); vertices.len()];
+    let mut open_neighbours = vec![Vec::new(); vertices.len()];
+    for (&(p, q), opposite) in &edges {
+        neighbours[p].push(q);
+        neighbours[q].push(p);
+        if opposite.len() == 1 {
+            open_neighbours[p].push(q);
+            open_neighbours[q].push(p);
+        }
+    }
+
+    let position = |index: usize| vertices[index].vertex.vector();
+    let average = |indices: &[usize]| {
+        let total = indices
+            .iter()
+            .fold(Vector::zero(), |total, index| total + position(*index));
+        total * (1.0 / indices.len() as f32)
+    };
+
+    // move the existing vertices towards their neighbours
+    let mut subdivided: Vec<RichVertex> = (0..vertices.len())
+        .map(|index| {
+            let moved = match (neighbours[index].len(), open_neighbours[index].len()) {
+                (0, _) => position(index),
+                (valence, 0) => {
+                    let n = valence as f32;
+                    let cos = (std::f32::consts::TAU / n).cos();
+                    let beta = (0.625 - (0.375 + 0.25 * cos).powi(2)) / n;
+                    position(index) * (1.0 - n * beta) + average(&neighbours[index]) * (n * beta)
+                }
+                (_, 2) => position(index) * 0.75 + average(&open_neighbours[index]) * 0.25,
+                _ => position(index), // corners stay where they are
+            };
+            let mut vertex = RichVertex::new(Vertex::from(moved), None, None);
+            vertex.colour = vertices[index].colour;
+            vertex
+        })
+        .collect();
+
+    // and add new ones in the middle of the edges, pulled towards the
+    // vertices on either side
+    let faces = split_faces(faces, &mut subdivided, |_, p, q| {
+        let ends = position(p) + position(q);
+        let middle = match edges[&(p.min(q), p.max(q))].as_slice() {
+            [c, d] => ends * 0.375 + (position(*c) + position(*d)) * 0.125,
+            _ => ends * 0.5,
+        };
+        let mut vertex = RichVertex::new(Vertex::from(middle), None, None);
+        vertex.colour = lerp_vertex(&vertices[p], &vertices[q], 0.5).colour;
+        vertex
+    });
+
+    (subdivided, faces)
 }
 
 impl Object for PolyMesh {
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        if let Some(deformation) = &self.deformation {
+            let t = (ray.time / deformation.end_time).clamp(0.0, 1.0);
+            if t > 0.0 {
+                return match self.intersect_deformed(ray, deformation, t) {
+                    None => hitvec![],
+                    Some(hit) => hitvec![hit],
+                };
+            }
+        }
+
+        if cfg!(feature = "simd") {
+            if let Some(hits) = self.intersect_packed(ray) {
+                return hits;
+            }
+        }
+
         let mut closest_hit: Option<Hit> = None;
 
         for triangle in self.triangles.iter() {
@@ -249,5 +803,59 @@ impl Object for PolyMesh {
         for triangle in self.triangles.iter_mut() {
             triangle.apply_transform(transform);
         }
+        self.packets = OnceLock::new();
+
+        if let Some(deformation) = &mut self.deformation {
+            for triangle in deformation.triangles.iter_mut() {
+                triangle.apply_transform(transform);
+            }
+        }
     }
+
+    // where the mesh is when the shutter opens
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        BoundingSphere::around(self.vertices.iter().map(|vertex| vertex.vertex.clone()))
+    }
+
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        bounds_of(self.vertices.iter().map(|vertex| vertex.vertex.clone()))
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
+
+    fn triangles(&self) -> Option<Vec<[Vector; 3]>> {
+        if self.deformation.is_some() {
+            return None;
+        }
+        let corners = |triangle: &Triangle| {
+            [&triangle.a, &triangle.b, &triangle.c].map(|corner| corner.vector())
+        };
+        Some(self.triangles.iter().map(corners).collect())
+    }
+
+    fn intersect_triangle(&self, ray: &Ray, index: usize) -> Option<Hit<'_>> {
+        self.triangles
+            .get(index)?
+            .intersect(ray)
+            .into_iter()
+            .filter(|hit| hit.distance >= 0.0)
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+// t of the way from start to end, keeping start's tex coords
+fn lerp_vertex(start: &RichVertex, end: &RichVertex, t: f32) -> RichVertex {
+    let vertex = start.vertex.clone() + start.vector_to(&end.vertex) * t;
+    let normal = match (start.normal, end.normal) {
+        (Some(start), Some(end)) => Some((start * (1.0 - t) + end * t).normalised()),
+        (normal, _) => normal,
+    };
+    let mut lerped = RichVertex::new(vertex, normal, start.tex_coords.clone());
+    lerped.colour = match (start.colour, end.colour) {
+        (Some(start), Some(end)) => Some(start * (1.0 - t) + end * t),
+        (colour, _) => colour,
+    };
+    lerped
 }
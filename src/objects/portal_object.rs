@@ -0,0 +1,237 @@
+// Portals and mirrors: flat rectangles that rays pass through to somewhere
+// else instead of being shaded. A portal joins two rectangles, so anything
+// seen through one is what's in front of the other. A mirror sends rays back
+// the way they came, a perfect reflection without any material weights.
+//
+// Rays (and photons) follow these themselves, see Environment::trace(). Shadow
+// rays go straight through them.
+
+use crate::{
+    core::{
+        colour::Colour,
+        hit::{Hit, HitVec},
+        ray::Ray,
+        transform::Transform,
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::environment::Integrator,
+    hitvec,
+    materials::material::Material,
+};
+
+use super::object::Object;
+
+#[derive(Clone)]
+pub struct Quad {
+    pub centre: Vertex,
+    pub normal: Vector,
+    pub up: Vector,
+    pub right: Vector,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Quad {
+    // up doesn't need to be exactly perpendicular to normal
+    pub fn new(centre: Vertex, normal: Vector, up: Vector, width: f32, height: f32) -> Self {
+        let mut quad = Self {
+            centre,
+            normal,
+            up,
+            right: Vector::zero(),
+            width,
+            height,
+        };
+        quad.orthonormalise();
+        quad
+    }
+
+    fn orthonormalise(&mut self) {
+        self.normal.normalise();
+        self.up = (self.up - self.normal * self.normal.dot(&self.up)).normalised();
+        self.right = self.up.cross(&self.normal);
+    }
+
+    // distance along the ray to where it crosses the quad
+    fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let facing = self.normal.dot(&ray.direction);
+        if facing == 0.0 {
+            return None;
+        }
+
+        let t = self
+            .normal
+            .dot(&(self.centre.vector() - ray.position.vector()))
+            / facing;
+        if t <= 0.0 {
+            return None;
+        }
+
+        let (x, y, _) = self.to_local(&(ray.position.clone() + ray.direction * t).vector());
+        if x.abs() > self.width / 2.0 || y.abs() > self.height / 2.0 {
+            return None;
+        }
+        Some(t)
+    }
+
+    // (right, up, normal) coordinates relative to the centre
+    fn to_local(&self, point: &Vector) -> (f32, f32, f32) {
+        let offset = *point - self.centre.vector();
+        (
+            offset.dot(&self.right),
+            offset.dot(&self.up),
+            offset.dot(&self.normal),
+        )
+    }
+
+    fn hit<'s>(
+        &'s self,
+        ray: &Ray,
+        what: &'s dyn Object,
+        material: &'s dyn Material,
+    ) -> HitVec<'s> {
+        let Some(t) = self.intersect(ray) else {
+            return hitvec![];
+        };
+
+        // both sides are the front
+        let normal = if self.normal.dot(&ray.direction) > 0.0 {
+            self.normal.negated()
+        } else {
+            self.normal
+        };
+        let position = ray.position.clone() + ray.direction * t;
+        hitvec![Hit::new(what, true, t, position, normal, material, None)]
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        // transform the edges to pick up any scaling
+        let mut right_edge = self.right * (self.width / 2.0);
+        let mut up_edge = self.up * (self.height / 2.0);
+        right_edge.apply_transform(transform);
+        up_edge.apply_transform(transform);
+
+        self.centre.apply_transform(transform);
+        self.normal.apply_transform(transform);
+        self.up = up_edge;
+        self.width = right_edge.length() * 2.0;
+        self.height = up_edge.length() * 2.0;
+        self.orthonormalise();
+    }
+}
+
+// one side of a portal, rays going in the entrance come out of the exit
+struct Doorway {
+    entrance: Quad,
+    exit: Quad,
+}
+
+impl Object for Doorway {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        self.entrance.hit(ray, self, self)
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        self.entrance.apply_transform(transform);
+        self.exit.apply_transform(transform);
+    }
+}
+
+impl Material for Doorway {
+    fn compute_once(&self, _: &dyn Integrator, _: &Ray, _: &Hit, _: u8) -> Colour {
+        Colour::black() // never shaded, rays are teleported instead
+    }
+
+    // going into the front of the entrance means coming out of the front of
+    // the exit, so turn around (180 degrees about up) on the way through.
+    // positions are scaled if the two quads are different sizes.
+    fn teleport(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        let (x, y, _) = self.entrance.to_local(&hit.position.vector());
+        let x = x * self.exit.width / self.entrance.width;
+        let y = y * self.exit.height / self.entrance.height;
+        let position = self.exit.centre.clone() + self.exit.right * -x + self.exit.up * y;
+
+        let direction = ray.direction;
+        let direction = self.exit.right * -direction.dot(&self.entrance.right)
+            + self.exit.up * direction.dot(&self.entrance.up)
+            + self.exit.normal * -direction.dot(&self.entrance.normal);
+
+        Some(
+            Ray::new(position, direction)
+                .at_time(ray.time)
+                .of_kind(ray.kind),
+        )
+    }
+}
+
+pub struct Portal {
+    a: Doorway,
+    b: Doorway,
+}
+
+impl Portal {
+    pub fn new(a: Quad, b: Quad) -> Box<Self> {
+        Box::new(Self {
+            a: Doorway {
+                entrance: a.clone(),
+                exit: b.clone(),
+            },
+            b: Doorway {
+                entrance: b,
+                exit: a,
+            },
+        })
+    }
+}
+
+impl Object for Portal {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        let mut hits = self.a.intersect(ray);
+        for hit in self.b.intersect(ray) {
+            hits.push(hit);
+        }
+        hits
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        // each quad is stored twice
+        self.a.apply_transform(transform);
+        self.b.apply_transform(transform);
+    }
+}
+
+pub struct Mirror {
+    quad: Quad,
+}
+
+impl Mirror {
+    pub fn new(quad: Quad) -> Box<Self> {
+        Box::new(Self { quad })
+    }
+}
+
+impl Object for Mirror {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        self.quad.hit(ray, self, self)
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        self.quad.apply_transform(transform);
+    }
+}
+
+impl Material for Mirror {
+    fn compute_once(&self, _: &dyn Integrator, _: &Ray, _: &Hit, _: u8) -> Colour {
+        Colour::black() // never shaded, rays are teleported instead
+    }
+
+    fn teleport(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+        let direction = hit.normal.reflection(&ray.direction).normalised();
+        Some(
+            Ray::new(hit.position.clone(), direction)
+                .at_time(ray.time)
+                .of_kind(ray.kind.bounced()),
+        )
+    }
+}
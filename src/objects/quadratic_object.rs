@@ -18,6 +18,8 @@ pub struct Quadratic {
     // ax^2 + 2bxy + 2cxz + 2dx + ey^2 + 2fyz + 2gy + hz^2 + 2iz + j = 0
     variables: (f32, f32, f32, f32, f32, f32, f32, f32, f32, f32),
     material: Arc<dyn Material>,
+    // squashed flat by a transform (e.g. scaled by 0), leaving nothing to hit
+    flattened: bool,
 }
 
 impl Quadratic {
@@ -28,6 +30,7 @@ impl Quadratic {
         Box::new(Self {
             variables,
             material,
+            flattened: false,
         })
     }
 
@@ -38,9 +41,36 @@ impl Quadratic {
     }
 }
 
+impl Quadratic {
+    // mean curvature of the surface, relative to the gradient direction.
+    // this is half the divergence of the unit normal, which for a quadric is
+    // (|g|^2 trace(H) - g.Hg) / 2|g|^3, where H is the (constant) hessian.
+    // (the factors of 2 in the gradient and hessian cancel out)
+    fn curvature(&self, gradient: &Vector) -> f32 {
+        let (a, b, c, _, e, f, _, h, _, _) = self.variables;
+        let g = gradient;
+
+        let hg = Vector::new(
+            a * g.x + b * g.y + c * g.z,
+            b * g.x + e * g.y + f * g.z,
+            c * g.x + f * g.y + h * g.z,
+        );
+        let len_sqrd = g.len_sqrd();
+        if len_sqrd == 0.0 {
+            return 0.0;
+        }
+
+        (len_sqrd * (a + e + h) - g.dot(&hg)) / (2.0 * len_sqrd * len_sqrd.sqrt())
+    }
+}
+
 impl Object for Quadratic {
     #[allow(non_snake_case)]
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        if self.flattened {
+            return hitvec![];
+        }
+
         let P = ray.position.clone();
         let D = ray.direction;
         let (a, b, c, d, e, f, g, h, i, j) = self.variables;
@@ -96,17 +126,19 @@ impl Object for Quadratic {
                 z: zi,
             } = position.vector();
 
-            let mut normal = Vector::new(
+            let gradient = Vector::new(
                 a * xi + b * yi + c * zi + d,
                 b * xi + e * yi + f * zi + g,
                 c * xi + f * yi + h * zi + i,
-            )
-            .normalised();
+            );
+            let mut normal = gradient.normalised();
+            let mut curvature = self.curvature(&gradient);
             if normal.dot(&D) > 0.0 {
                 normal.negate();
+                curvature = -curvature;
             }
 
-            Hit::new(
+            let mut hit = Hit::new(
                 self,
                 entering,
                 t,
@@ -114,7 +146,9 @@ impl Object for Quadratic {
                 normal,
                 self.material.as_ref(),
                 None,
-            )
+            );
+            hit.curvature = curvature;
+            hit
         };
 
         let hit1 = create_hit(t0, true);
@@ -128,7 +162,10 @@ impl Object for Quadratic {
         let (a, b, c, d, e, f, g, h, i, j) = self.variables;
         let Q = Transform::from_matrix([[a, b, c, d], [b, e, f, g], [c, f, h, i], [d, g, i, j]]);
 
-        let T = T.clone().inverse();
+        let Ok(T) = T.inverse() else {
+            self.flattened = true;
+            return;
+        };
         let T_T = T.transposed();
 
         let Q = T_T * Q * T;
@@ -3,6 +3,7 @@ use std::{f32::consts::PI, sync::Arc};
 use crate::{
     core::{
         hit::{Hit, HitVec},
+        onb::Onb,
         ray::Ray,
         tex_coords::TexCoords,
         transform::Transform,
@@ -13,12 +14,58 @@ use crate::{
     materials::material::Material,
 };
 
-use super::object::Object;
+use super::object::{BoundingSphere, Object};
 
 pub struct Sphere {
+    // in the sphere's own space if it's been squashed into an ellipsoid
     pub centre: Vertex,
     pub radius: f32,
     material: Arc<dyn Material>,
+    // where the texture's poles (normal) and middle (tangent) point, or None
+    // for the world's y and z axes
+    uv_axes: Option<Onb>,
+    // for spheres transformed by more than moving, turning and uniformly
+    // scaling, which are intersected in their own space instead
+    local_space: Option<LocalSpace>,
+    flattened: bool, // transformed by something that squashes it flat
+}
+
+struct LocalSpace {
+    to_world: Transform,
+    to_local: Transform,
+    // normals go back by the inverse transpose
+    normal_to_world: Transform,
+    // how much bigger it got on average, for the curvature
+    scale: f32,
+}
+
+impl LocalSpace {
+    fn new(to_world: Transform) -> Option<Self> {
+        let to_local = to_world.inverse().ok()?;
+        let column = |i: usize| Vector::new(to_world[0][i], to_world[1][i], to_world[2][i]);
+        let volume = column(0).dot(&column(1).cross(&column(2)));
+        Some(Self {
+            normal_to_world: to_local.transposed(),
+            scale: volume.abs().cbrt(),
+            to_world,
+            to_local,
+        })
+    }
+}
+
+// how much a transform scales every direction by, if it only moves, turns
+// and uniformly scales (so a sphere stays a sphere)
+fn uniform_scale(transform: &Transform) -> Option<f32> {
+    let column = |i: usize| Vector::new(transform[0][i], transform[1][i], transform[2][i]);
+    let (x, y, z) = (column(0), column(1), column(2));
+    let scale = x.length();
+    let close = |a: f32, b: f32| (a - b).abs() <= 1e-5 * scale * scale;
+    let uniform = close(x.len_sqrd(), y.len_sqrd())
+        && close(x.len_sqrd(), z.len_sqrd())
+        && close(x.dot(&y), 0.0)
+        && close(x.dot(&z), 0.0)
+        && close(y.dot(&z), 0.0);
+    (uniform && scale > 0.0).then_some(scale)
 }
 
 impl Sphere {
@@ -27,18 +74,46 @@ impl Sphere {
             centre,
             radius,
             material,
+            uv_axes: None,
+            local_space: None,
+            flattened: false,
         })
     }
+
+    // turns the texture so its poles are along pole, then by rotation
+    // radians around it
+    pub fn orient_texture(&mut self, pole: Vector, rotation: f32) {
+        let middle = Onb::from_normal_and_tangent(&pole, &Vector::new(0.0, 0.0, 1.0));
+        let tangent = middle.tangent * rotation.cos() + middle.bitangent * rotation.sin();
+        self.uv_axes = Some(Onb::from_normal_and_tangent(&pole, &tangent));
+    }
 }
 
 impl Object for Sphere {
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        if self.flattened {
+            return hitvec![];
+        }
+
+        // the ray in the sphere's own space, with distances along it
+        // stretched by the transform
+        let (origin, direction, stretch) = match &self.local_space {
+            Some(space) => {
+                let mut origin = ray.position.clone();
+                origin.apply_transform(&space.to_local);
+                let mut direction = ray.direction;
+                direction.apply_transform(&space.to_local);
+                (origin, direction.normalised(), direction.length())
+            }
+            None => (ray.position.clone(), ray.direction, 1.0),
+        };
+
         // offset ray by sphere position
         // equivalent to transforming ray into local sphere space
-        let ro = ray.position.vector() - self.centre.vector();
+        let ro = origin.vector() - self.centre.vector();
 
-        let a = ray.direction.dot(&ray.direction);
-        let b = 2.0 * ray.direction.dot(&ro);
+        let a = direction.dot(&direction);
+        let b = 2.0 * direction.dot(&ro);
         let c = ro.dot(&ro) - self.radius * self.radius;
 
         let discriminant = b * b - 4.0 * a * c;
@@ -52,15 +127,40 @@ impl Object for Sphere {
         let t0 = (-b - ds) / 2.0;
         let t1 = (-b + ds) / 2.0;
 
-        let create_hit = |distance, entering| {
+        let create_hit = |local_distance: f32, entering| {
+            let distance = local_distance / stretch;
             let position = ray.position.clone() + ray.direction * distance;
-            let mut normal = (position.vector() - self.centre.vector()).normalised();
+            let local_position = origin.clone() + direction * local_distance;
+            let offset = local_position.vector() - self.centre.vector();
+            let mut local_normal = offset.normalised();
+            let mut normal = match &self.local_space {
+                Some(space) => {
+                    let mut normal = local_normal;
+                    normal.apply_transform(&space.normal_to_world);
+                    normal.normalised()
+                }
+                None => local_normal,
+            };
+            let scale = self.local_space.as_ref().map_or(1.0, |space| space.scale);
+            let mut curvature = 1.0 / (self.radius * scale);
             if normal.dot(&ray.direction) > 0.0 {
+                // seen from the inside, the sphere is concave
                 normal.negate();
+                local_normal.negate();
+                curvature = -curvature;
             }
 
-            let theta = (normal.x.atan2(normal.z)) + PI; // longitude
-            let phi = (-normal.y).acos(); // latitude
+            // x across the middle of the texture, y up to its pole, z out of
+            // its middle
+            let (uv_normal, across) = match &self.uv_axes {
+                Some(axes) => {
+                    let local = axes.to_local(&local_normal);
+                    (Vector::new(local.y, local.z, local.x), axes.bitangent)
+                }
+                None => (local_normal, Vector::new(1.0, 0.0, 0.0)),
+            };
+            let theta = (uv_normal.x.atan2(uv_normal.z)) + PI; // longitude
+            let phi = (-uv_normal.y).acos(); // latitude
             let u = theta / (2.0 * PI);
             let v = (PI - phi) / PI;
             let tex_coords = TexCoords::new(u, v);
@@ -68,15 +168,17 @@ impl Object for Sphere {
             if let Some(mut normal_map) = self.material.normal(&tex_coords) {
                 // rotate the normal map
                 // maths from https://computergraphics.stackexchange.com/a/5499
-                let a = Vector::new(1.0, 0.0, 0.0);
-                let tangent = a
-                    .cross(&(position.clone() - self.centre.vector()).vector())
-                    .normalised();
+                // (degenerate where the normal points across the texture, in
+                // which case any tangent will do)
+                let mut tangent = across.cross(&offset);
+                if let Some(space) = &self.local_space {
+                    tangent.apply_transform(&space.to_world);
+                }
                 normal_map = normal_map.to_tangent_space(&tangent, &normal);
                 normal = normal_map.normalised();
             }
 
-            Hit::new(
+            let mut hit = Hit::new(
                 self,
                 entering,
                 distance,
@@ -84,18 +186,53 @@ impl Object for Sphere {
                 normal,
                 self.material.as_ref(),
                 Some(tex_coords),
-            )
+            );
+            hit.curvature = curvature;
+            hit
         };
 
         hitvec![create_hit(t0, true), create_hit(t1, false)]
     }
 
     fn apply_transform(&mut self, transform: &Transform) {
-        self.centre.apply_transform(transform);
+        if self.flattened {
+            return;
+        }
+        if self.local_space.is_none() {
+            if let Some(scale) = uniform_scale(transform) {
+                self.centre.apply_transform(transform);
+                if let Some(axes) = &mut self.uv_axes {
+                    axes.normal.apply_transform(transform);
+                    axes.tangent.apply_transform(transform);
+                    *axes = Onb::from_normal_and_tangent(&axes.normal, &axes.tangent);
+                }
+                self.radius *= scale;
+                return;
+            }
+        }
 
-        // only support uniform scaling for now
-        if transform[0][0] == transform[1][1] && transform[1][1] == transform[2][2] {
-            self.radius *= transform[0][0];
+        // squashed or sheared into an ellipsoid, so keep the sphere (and its
+        // texture) where it is and transform rays into its space instead
+        let to_world = match self.local_space.take() {
+            Some(space) => transform.clone() * space.to_world,
+            None => transform.clone(),
+        };
+        self.local_space = LocalSpace::new(to_world);
+        self.flattened = self.local_space.is_none();
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let sphere = BoundingSphere {
+            centre: self.centre.clone(),
+            radius: self.radius,
+        };
+        match &self.local_space {
+            Some(space) => Some(sphere.transformed(&space.to_world)),
+            None => Some(sphere),
         }
     }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
+    }
 }
@@ -4,15 +4,19 @@ use crate::{
     core::{
         hit::{Hit, HitVec},
         ray::Ray,
+        tex_coords::TexCoords,
         transform::Transform,
         vector::Vector,
-        vertex::RichVertex,
+        vertex::{RichVertex, Vertex},
     },
     hitvec,
     materials::material::Material,
 };
 
-use super::{object::Object, plane_object::Plane};
+use super::{
+    object::{bounds_of, BoundingSphere, Object},
+    plane_object::Plane,
+};
 
 struct Barycentric {
     // alpha is the weight of vertex a, etc.
@@ -34,6 +38,8 @@ pub struct Triangle {
 
     plane: OnceLock<Plane>,
     plane_normal: OnceLock<Vector>,
+    tangent: OnceLock<Option<Vector>>,
+    curvature: OnceLock<f32>,
 
     // the index of a, b, c in the polymesh's vertex list
     pub vertex_indices: (usize, usize, usize),
@@ -63,6 +69,8 @@ impl Triangle {
             material,
             plane: OnceLock::new(),
             plane_normal: OnceLock::new(),
+            tangent: OnceLock::new(),
+            curvature: OnceLock::new(),
             vertex_indices,
         }
     }
@@ -95,6 +103,55 @@ impl Triangle {
         })
     }
 
+    // direction of increasing u across the triangle, used to orient normal maps.
+    // None if the triangle doesn't have tex coords on all three vertices.
+    fn get_tangent(&self) -> Option<Vector> {
+        *self.tangent.get_or_init(|| {
+            let (a, b, c) = (
+                self.a.tex_coords.as_ref()?,
+                self.b.tex_coords.as_ref()?,
+                self.c.tex_coords.as_ref()?,
+            );
+
+            let ac = -self.ca;
+            let (du1, dv1) = (b.u - a.u, b.v - a.v);
+            let (du2, dv2) = (c.u - a.u, c.v - a.v);
+
+            let det = du1 * dv2 - du2 * dv1;
+            if det == 0.0 {
+                // degenerate uv mapping, fall back to an edge of the triangle
+                return Some(self.ab.normalised());
+            }
+
+            Some(((self.ab * dv2 - ac * dv1) * (1.0 / det)).normalised())
+        })
+    }
+
+    // estimate the mean curvature from how much the vertex normals turn along
+    // each edge. for a triangle on a sphere of radius r this gives 1/r.
+    // flat shaded triangles have no vertex normals, so are flat.
+    fn get_curvature(&self) -> f32 {
+        *self.curvature.get_or_init(|| {
+            let (Some(an), Some(bn), Some(cn)) = (self.a.normal, self.b.normal, self.c.normal)
+            else {
+                return 0.0;
+            };
+
+            let edge_curvature = |n1: Vector, n2: Vector, edge: Vector| {
+                let len_sqrd = edge.len_sqrd();
+                if len_sqrd == 0.0 {
+                    return 0.0;
+                }
+                (n2 - n1).dot(&edge) / len_sqrd
+            };
+
+            (edge_curvature(an, bn, self.ab)
+                + edge_curvature(bn, cn, self.bc)
+                + edge_curvature(cn, an, self.ca))
+                / 3.0
+        })
+    }
+
     fn get_barycentric(&self, ap: &Vector, bp: &Vector, cp: &Vector) -> Barycentric {
         // note: these are not actually the area, to get it we would divide by 2.
         // but since we're normalising the hit normal anyway we can skip it.
@@ -123,10 +180,64 @@ impl Triangle {
         let normal = an * barycentric.alpha + bn * barycentric.beta + cn * barycentric.gamma;
         hit.normal = normal.normalised();
     }
+
+    // interpolate the vertex colours at the hit, if all three have one
+    fn colour_hit(&self, hit: &mut Hit, ai: &Vector, bi: &Vector, ci: &Vector) {
+        let (Some(ac), Some(bc), Some(cc)) = (self.a.colour, self.b.colour, self.c.colour) else {
+            return;
+        };
+
+        let barycentric = self.get_barycentric(ai, bi, ci);
+        let total = barycentric.alpha + barycentric.beta + barycentric.gamma;
+        let colour = ac * barycentric.alpha + bc * barycentric.beta + cc * barycentric.gamma;
+        hit.colour = Some(colour / total);
+    }
+
+    // interpolate the vertex tex coords at the hit, and apply the material's
+    // normal map in the tangent frame given by the uv layout
+    fn texture_hit(&self, hit: &mut Hit, ai: &Vector, bi: &Vector, ci: &Vector) {
+        let Some(tangent) = self.get_tangent() else {
+            return; // keep the plane's tex coords
+        };
+        let (Some(at), Some(bt), Some(ct)) =
+            (&self.a.tex_coords, &self.b.tex_coords, &self.c.tex_coords)
+        else {
+            return;
+        };
+
+        let barycentric = self.get_barycentric(ai, bi, ci);
+        let total = barycentric.alpha + barycentric.beta + barycentric.gamma;
+        let (alpha, beta, gamma) = (
+            barycentric.alpha / total,
+            barycentric.beta / total,
+            barycentric.gamma / total,
+        );
+        let tex_coords = TexCoords::new(
+            at.u * alpha + bt.u * beta + ct.u * gamma,
+            at.v * alpha + bt.v * beta + ct.v * gamma,
+        );
+
+        // the plane may have applied a planar normal map, start again from
+        // the geometric (or smoothed) normal
+        if !self.smooth {
+            hit.normal = if hit.entering {
+                self.get_plane_normal()
+            } else {
+                self.get_plane_normal().negated()
+            };
+        }
+
+        if let Some(normal_map) = self.material.normal(&tex_coords) {
+            // the tangent is made perpendicular to the (possibly smoothed) normal
+            hit.normal = normal_map.to_tangent_space(&tangent, &hit.normal);
+        }
+
+        hit.tex_coords = Some(tex_coords);
+    }
 }
 
 impl Object for Triangle {
-    fn intersect(&self, ray: &Ray) -> HitVec {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
         let plane = self.get_plane();
         let plane_hits = plane.intersect(ray);
         let mut triangle_hits = hitvec![];
@@ -152,6 +263,21 @@ impl Object for Triangle {
             if self.smooth {
                 self.smoothen_hit(&mut plane_hit, &ai, &bi, &ci);
             }
+            self.texture_hit(&mut plane_hit, &ai, &bi, &ci);
+            self.colour_hit(&mut plane_hit, &ai, &bi, &ci);
+
+            let barycentric = self.get_barycentric(&ai, &bi, &ci);
+            let total = barycentric.alpha + barycentric.beta + barycentric.gamma;
+            plane_hit.barycentric = Some([
+                barycentric.alpha / total,
+                barycentric.beta / total,
+                barycentric.gamma / total,
+            ]);
+
+            plane_hit.curvature = self.get_curvature();
+            if plane_hit.normal.dot(&ray.direction) > 0.0 {
+                plane_hit.curvature = -plane_hit.curvature; // seen from the inside
+            }
 
             triangle_hits.push(plane_hit);
         }
@@ -170,5 +296,20 @@ impl Object for Triangle {
 
         self.plane_normal = OnceLock::new();
         self.plane = OnceLock::new();
+        self.tangent = OnceLock::new();
+        self.curvature = OnceLock::new();
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        let corners = [&self.a, &self.b, &self.c].map(|corner| corner.vertex.clone());
+        BoundingSphere::around(corners.into_iter())
+    }
+
+    fn bounds(&self) -> Option<(Vertex, Vertex)> {
+        bounds_of([&self.a, &self.b, &self.c].map(|corner| corner.vertex.clone()).into_iter())
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        Some(self.material.as_ref())
     }
 }
@@ -0,0 +1,163 @@
+// Intersecting a ray with eight triangles at once. The triangles are stored
+// component by component (structure of arrays), so the same maths runs
+// across all eight lanes and the compiler can turn each step into SIMD
+// instructions, without needing nightly std::simd or another dependency.
+//
+// A mesh's triangles are grouped into packets of neighbours, and the packets
+// into a bounding volume hierarchy (see objects::bvh), so a ray only tests
+// the packets whose boxes it passes through.
+
+use crate::core::{ray::Ray, vector::Vector};
+
+use super::{
+    bvh::{Aabb, Bvh},
+    triangle_object::Triangle,
+};
+
+pub const LANES: usize = 8;
+type Lanes = [f32; LANES];
+
+// rays closer to parallel with a triangle than this miss it. it's the
+// cosine of the angle between the ray and the triangle's normal, so it
+// means the same for big and small triangles.
+const EPSILON: f32 = 1e-6;
+
+pub struct TrianglePacket {
+    a: [Lanes; 3],
+    ab: [Lanes; 3],
+    ac: [Lanes; 3],
+    // EPSILON times each triangle's area (doubled), below which the
+    // determinant counts as zero
+    min_det: Lanes,
+    // which of the mesh's triangles each lane is
+    indices: [usize; LANES],
+    aabb: Aabb,
+}
+
+impl TrianglePacket {
+    // the triangles at indices, at most eight of them. lanes past the last
+    // one are left as triangles with no area, which are never hit.
+    fn pack(triangles: &[Triangle], indices: &[usize]) -> Self {
+        let mut packet = Self {
+            a: [[0.0; LANES]; 3],
+            ab: [[0.0; LANES]; 3],
+            ac: [[0.0; LANES]; 3],
+            min_det: [0.0; LANES],
+            indices: [0; LANES],
+            aabb: Aabb::empty(),
+        };
+        for (lane, &index) in indices.iter().enumerate() {
+            let triangle = &triangles[index];
+            let (ab, ac) = (triangle.ab, -triangle.ca);
+            for (axis, a) in triangle.a.xyz().into_iter().enumerate() {
+                packet.a[axis][lane] = a;
+            }
+            for (axis, (ab, ac)) in [(ab.x, ac.x), (ab.y, ac.y), (ab.z, ac.z)]
+                .into_iter()
+                .enumerate()
+            {
+                packet.ab[axis][lane] = ab;
+                packet.ac[axis][lane] = ac;
+            }
+            packet.min_det[lane] = EPSILON * ab.cross(&ac).length();
+            packet.indices[lane] = index;
+            packet.aabb = packet.aabb.union(&aabb_of(triangle));
+        }
+        packet
+    }
+
+    // the distance along the ray to each triangle (from either side),
+    // infinite where the ray misses. möller-trumbore, one step at a time
+    // across all the lanes.
+    fn intersect(&self, ray: &Ray) -> Lanes {
+        let [dx, dy, dz] = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let [ox, oy, oz] = [ray.position.x, ray.position.y, ray.position.z];
+        let [abx, aby, abz] = &self.ab;
+        let [acx, acy, acz] = &self.ac;
+        let [ax, ay, az] = &self.a;
+        let direction_length = ray.direction.length();
+
+        let mut distances = [f32::INFINITY; LANES];
+        for lane in 0..LANES {
+            // p = direction x ac
+            let px = dy * acz[lane] - dz * acy[lane];
+            let py = dz * acx[lane] - dx * acz[lane];
+            let pz = dx * acy[lane] - dy * acx[lane];
+            let det = abx[lane] * px + aby[lane] * py + abz[lane] * pz;
+            let inv_det = 1.0 / det;
+
+            // t = origin - a
+            let tx = ox - ax[lane];
+            let ty = oy - ay[lane];
+            let tz = oz - az[lane];
+            let u = (tx * px + ty * py + tz * pz) * inv_det;
+
+            // q = t x ab
+            let qx = ty * abz[lane] - tz * aby[lane];
+            let qy = tz * abx[lane] - tx * abz[lane];
+            let qz = tx * aby[lane] - ty * abx[lane];
+            let v = (dx * qx + dy * qy + dz * qz) * inv_det;
+            let distance = (acx[lane] * qx + acy[lane] * qy + acz[lane] * qz) * inv_det;
+
+            // & rather than && so there are no branches to stop vectorising
+            let hit = (det.abs() > self.min_det[lane] * direction_length)
+                & (u >= 0.0)
+                & (v >= 0.0)
+                & (u + v <= 1.0)
+                & (distance >= 0.0);
+            distances[lane] = if hit { distance } else { f32::INFINITY };
+        }
+        distances
+    }
+}
+
+pub struct PacketBvh {
+    packets: Vec<TrianglePacket>,
+    bvh: Bvh, // a packet in each leaf
+}
+
+impl PacketBvh {
+    pub fn build(triangles: &[Triangle]) -> Self {
+        // the leaves of a hierarchy over the triangles are the packets
+        let mut indices: Vec<usize> = (0..triangles.len()).collect();
+        let leaves = Bvh::build(&mut indices, LANES, |index| aabb_of(&triangles[*index]));
+        let mut packets: Vec<TrianglePacket> = leaves
+            .nodes()
+            .filter(|(_, _, count)| *count > 0)
+            .map(|(_, start, count)| {
+                TrianglePacket::pack(triangles, &indices[start..start + count])
+            })
+            .collect();
+
+        let bvh = Bvh::build(&mut packets, 1, |packet| packet.aabb);
+        Self { packets, bvh }
+    }
+
+    // the distance to the closest triangle the ray hits, and its index in
+    // the mesh, or None if it hits none
+    pub fn closest(&self, ray: &Ray) -> Option<(f32, usize)> {
+        let origin = ray.position.vector();
+        let d = ray.direction;
+        let inverse_direction = Vector::new(1.0 / d.x, 1.0 / d.y, 1.0 / d.z);
+
+        let mut closest: Option<(f32, usize)> = None;
+        self.bvh
+            .traverse(&origin, &inverse_direction, f32::INFINITY, |leaf| {
+                for packet in &self.packets[leaf] {
+                    for (lane, distance) in packet.intersect(ray).into_iter().enumerate() {
+                        if closest.is_none_or(|(closest, _)| distance < closest)
+                            && distance.is_finite()
+                        {
+                            closest = Some((distance, packet.indices[lane]));
+                        }
+                    }
+                }
+                closest.map(|(distance, _)| distance)
+            });
+        closest
+    }
+}
+
+fn aabb_of(triangle: &Triangle) -> Aabb {
+    Aabb::around([&triangle.a, &triangle.b, &triangle.c].map(|corner| corner.vector()))
+}
@@ -0,0 +1,77 @@
+use crate::{
+    core::{
+        hit::HitVec,
+        ray::{Ray, RayKind},
+        transform::Transform,
+    },
+    hitvec,
+    materials::material::Material,
+};
+
+use super::object::{BoundingSphere, Object};
+
+// which rays can see an object. turning them off allows the usual tricks,
+// like a ground plane that only catches shadows, or a light's bulb that
+// doesn't block its own light.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Visibility {
+    pub camera: bool,
+    pub reflections: bool,
+    pub shadows: bool,
+}
+
+impl Visibility {
+    pub const ALL: Self = Self {
+        camera: true,
+        reflections: true,
+        shadows: true,
+    };
+
+    fn sees(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Secondary => self.reflections,
+            RayKind::Shadow => self.shadows,
+            // photons still land on objects that don't cast shadows, they
+            // just don't stop there
+            RayKind::Photon => true,
+        }
+    }
+}
+
+// an object that some kinds of rays go straight through
+pub struct PartlyVisible {
+    object: Box<dyn Object>,
+    visibility: Visibility,
+}
+
+impl PartlyVisible {
+    pub fn new(object: Box<dyn Object>, visibility: Visibility) -> Box<Self> {
+        Box::new(Self { object, visibility })
+    }
+}
+
+impl Object for PartlyVisible {
+    fn intersect(&self, ray: &Ray) -> HitVec<'_> {
+        if !self.visibility.sees(ray.kind) {
+            return hitvec![];
+        }
+        self.object.intersect(ray)
+    }
+
+    fn apply_transform(&mut self, transform: &Transform) {
+        self.object.apply_transform(transform);
+    }
+
+    fn casts_shadows(&self) -> bool {
+        self.visibility.shadows
+    }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.object.bounding_sphere()
+    }
+
+    fn material(&self) -> Option<&dyn Material> {
+        self.object.material()
+    }
+}
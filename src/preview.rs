@@ -0,0 +1,64 @@
+// Window that shows the framebuffer while it's being rendered.
+// Only built with the "preview" feature.
+
+use minifb::{Window, WindowOptions};
+
+use crate::core::{colour::ColourSpace, framebuffer::FrameBuffer};
+
+pub struct PreviewWindow {
+    window: Window,
+    buffer: Vec<u32>,
+    width: u32,
+    height: u32,
+}
+
+impl PreviewWindow {
+    pub fn new(width: u32, height: u32) -> Result<Self, String> {
+        let window = Window::new(
+            "rust-raytracer",
+            width as usize,
+            height as usize,
+            WindowOptions::default(),
+        )
+        .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            window,
+            buffer: vec![0; (width * height) as usize],
+            width,
+            height,
+        })
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn update(&mut self, framebuffer: &FrameBuffer, colour_space: ColourSpace) {
+        assert!(framebuffer.width == self.width && framebuffer.height == self.height);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let colour = colour_space.encode(framebuffer.get_colour(x, y));
+                let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u32;
+
+                let pixel = to_byte(colour.r) << 16 | to_byte(colour.g) << 8 | to_byte(colour.b);
+                self.buffer[(y * self.width + x) as usize] = pixel;
+            }
+        }
+
+        // errors here just mean the window was closed
+        let _ =
+            self.window
+                .update_with_buffer(&self.buffer, self.width as usize, self.height as usize);
+    }
+
+    // keep the window responsive while waiting for the next render
+    pub fn idle(&mut self) {
+        self.window.update();
+    }
+}
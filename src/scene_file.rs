@@ -1,26 +1,68 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock, RwLock},
+};
 
 use crate::{
-    cameras::full_camera::FullCamera,
-    core::{colour::Colour, transform::Transform, vector::Vector, vertex::Vertex},
-    environments::{environment::Environment, photon_scene::PhotonScene, scene::Scene},
+    cameras::full_camera::{FullCamera, TransientBins},
+    core::{
+        animation::{Animation, Keyframes, Motion, Pose},
+        bloom::Bloom,
+        colour::{Colour, ColourSpace},
+        depth_of_field::DepthOfField,
+        framebuffer::{DepthFormat, DepthOutput, MissDepth},
+        platform, random,
+        resolve::ResolvePolicy,
+        sampler::Sampler,
+        transform::{Transform, UpAxis},
+        vector::Vector,
+        vertex::Vertex,
+    },
+    environments::{
+        ambient_occlusion::AmbientOcclusion,
+        environment::{Environment, RenderSettings, TraceLimit},
+        irradiance_cache::IrradianceCache,
+        medium::{Medium, Volume},
+        photon_bake::PhotonSlice,
+        photon_scene::{GatherFilter, GatherMethod, PhotonQuality, PhotonScene, PhotonView},
+        scene::Scene,
+    },
     lights::{
         directional_light::DirectionalLight, directional_point_light::DPLight, light::Light,
-        point_light::PointLight,
+        point_light::PointLight, projector_light::ProjectorLight, sun_sky::SunSky,
     },
     materials::{
-        compound_material::CompoundMaterial, falsecolour_material::FalseColour,
-        global_material::GlobalMaterial, material::Material, phong_material::Monochrome,
-        texture::Texture,
+        compound_material::{Blend, CompoundMaterial},
+        depth_material::DepthGradient,
+        falsecolour_material::FalseColour,
+        global_material::GlobalMaterial,
+        hair_material::Hair,
+        material::Material,
+        occlusion_material::Occlusion,
+        phong_material::Monochrome,
+        texture::{Image, Texture},
+        uv_checker_material::UvChecker,
+        vertex_colour_material::VertexColour,
+        weathered_material::Weathered,
+        wireframe_material::Wireframe,
     },
     objects::{
+        animated_object::{Animated, Moving},
+        bezier_object::BezierSurface,
         csg_object::{Csg, CsgMode},
         cuboid_object::Cuboid,
+        curve_object::{grow_fur, Curve, Curves},
+        fractal_object::{Fractal, FractalKind},
         object::Object,
         plane_object::Plane,
+        point_cloud_object::PointCloud,
         polymesh_object::PolyMesh,
+        portal_object::{Mirror, Portal, Quad},
         quadratic_object::Quadratic,
         sphere_object::Sphere,
+        visibility_object::{PartlyVisible, Visibility},
     },
 };
 
@@ -30,16 +72,25 @@ type LineNumber = u32;
 pub struct ParseError {
     message: String,
     line: LineNumber,
+    word: Option<String>,    // the part of the line it's about, see at()
+    snippet: Option<String>, // the line itself, once located
+    column: usize,           // counting from 1, 0 if not located
 }
 
 type Result<T> = std::result::Result<T, ParseError>;
 
+// a paragraph, or everything wrong with it
+type ParagraphResult = std::result::Result<Paragraph, Vec<ParseError>>;
+
 // err!(line_number, "...", ...)
 macro_rules! err {
     ($line:expr, $($arg:tt)*) => {
         ParseError {
             message: format!($($arg)*),
             line: $line,
+            word: None,
+            snippet: None,
+            column: 0,
         }
     };
 }
@@ -51,52 +102,408 @@ macro_rules! bail {
     };
 }
 
+mod colour_names;
+#[cfg(any(feature = "json", feature = "yaml"))]
+mod structured;
+
+impl ParseError {
+    // point at a word on the line, e.g. the bad number in a vector, rather
+    // than the start of it
+    fn at(mut self, word: &str) -> Self {
+        self.word = Some(word.to_string());
+        self
+    }
+
+    // find the line in the file, to show it along with the message. errors
+    // from overrides are on line 0, which isn't in the file.
+    fn locate(&mut self, contents: &str) {
+        let Some(snippet) = (self.line as usize)
+            .checked_sub(1)
+            .and_then(|index| contents.lines().nth(index))
+        else {
+            return;
+        };
+
+        // the first whole word that matches, e.g. not the "1" in "x1 1"
+        let is_whole_word = |start: usize, word: &str| {
+            let before = snippet[..start].chars().next_back();
+            let after = snippet[start + word.len()..].chars().next();
+            before.is_none_or(char::is_whitespace) && after.is_none_or(char::is_whitespace)
+        };
+        let indentation = snippet.len() - snippet.trim_start().len();
+        let found = self.word.as_deref().and_then(|word| {
+            snippet
+                .match_indices(word)
+                .map(|(start, _)| start)
+                .find(|&start| is_whole_word(start, word))
+        });
+        if found.is_none() {
+            self.word = None;
+        }
+        let start = found.unwrap_or(indentation);
+        self.column = snippet[..start].chars().count() + 1;
+        self.snippet = Some(snippet.to_string());
+    }
+}
+
+// e.g.
+//     line 12, column 12: Invalid float
+//        12 |     radius 1.o
+//           |            ^^^
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some(snippet) = &self.snippet else {
+            if self.line == 0 {
+                return write!(f, "{}", self.message);
+            }
+            return write!(f, "line {}: {}", self.line, self.message);
+        };
+        writeln!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )?;
+
+        let gutter = " ".repeat(self.line.to_string().len());
+        // keep tabs so the marker lines up under the snippet
+        let padding: String = snippet
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.word.as_ref().map_or(1, |word| word.chars().count());
+        writeln!(f, "  {} | {snippet}", self.line)?;
+        write!(f, "  {gutter} | {padding}{}", "^".repeat(width.max(1)))
+    }
+}
+
+// every error found in a scene file, in the order they appear in it
+#[derive(Debug)]
+pub struct ParseErrors(pub Vec<ParseError>);
+
+impl From<ParseError> for ParseErrors {
+    fn from(error: ParseError) -> Self {
+        Self(vec![error])
+    }
+}
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "\n\n")?;
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
 pub struct SceneFile {
     contents: String,
 }
 
+pub struct LoadedScene {
+    pub environment: Box<dyn Environment>,
+    pub camera: Box<FullCamera>,
+    // more named cameras to render the same scene from, see FullCamera::name
+    pub other_cameras: Vec<Box<FullCamera>>,
+    pub animation: Option<Animation>,
+    pub errors: Vec<ParseError>, // paragraphs skipped by from_*_recovering()
+}
+
+impl LoadedScene {
+    // for the loaders that don't recover, all the errors if there were any
+    fn without_errors(self) -> std::result::Result<Self, ParseErrors> {
+        if !self.errors.is_empty() {
+            return Err(ParseErrors(self.errors));
+        }
+        Ok(self)
+    }
+
+    // move the cameras with auto_frame back until the whole scene fits in
+    // their view
+    pub fn frame_cameras(&mut self) {
+        let cameras = std::iter::once(&mut self.camera).chain(&mut self.other_cameras);
+        let cameras: Vec<_> = cameras.filter(|camera| camera.auto_frame).collect();
+        if cameras.is_empty() {
+            return;
+        }
+
+        let Some((min, max)) = self.environment.bounds() else {
+            println!("Nothing in the scene has bounds, not framing the camera");
+            return;
+        };
+        println!(
+            "Scene bounds ({:.2}, {:.2}, {:.2}) to ({:.2}, {:.2}, {:.2})",
+            min.x, min.y, min.z, max.x, max.y, max.z
+        );
+        for camera in cameras {
+            camera.frame(&min, &max);
+            let position = &camera.position;
+            println!(
+                "Camera framed from ({:.2}, {:.2}, {:.2})",
+                position.x, position.y, position.z
+            );
+        }
+    }
+
+    // points the errors at their place in the text the scene came from
+    fn located(result: Result<Self>, contents: &str) -> Result<Self> {
+        match result {
+            Ok(mut scene) => {
+                for error in &mut scene.errors {
+                    error.locate(contents);
+                }
+                Ok(scene)
+            }
+            Err(mut error) => {
+                error.locate(contents);
+                Err(error)
+            }
+        }
+    }
+}
+
+// an error instead of a panic, e.g. for editors that save by replacing the
+// file, so it's briefly missing while watching it
+fn read_scene_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| err!(0, "Failed to read scene file {}: {}", path.display(), e))
+}
+
 impl SceneFile {
-    pub fn from_path(path: &PathBuf) -> Result<(Box<dyn Environment>, Box<FullCamera>)> {
-        let contents = std::fs::read_to_string(path).expect("Failed to read scene file");
-        Self::from_contents(contents)
+    // time is in seconds, and places keyframed objects and cameras
+    pub fn from_path(
+        path: &Path,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> std::result::Result<LoadedScene, ParseErrors> {
+        Self::from_path_recovering(path, overrides, time)?.without_errors()
+    }
+
+    // the models and texture folders a scene file refers to, relative to the
+    // working directory
+    pub fn referenced_assets(path: &Path) -> Result<Vec<PathBuf>> {
+        let contents = read_scene_file(path)?;
+        let (paragraphs, mut errors) = Paragraph::parse_file(path, &contents);
+        if !errors.is_empty() {
+            return Err(errors.remove(0));
+        }
+
+        let mut assets = Vec::new();
+        for paragraph in &paragraphs {
+            paragraph.collect_assets(&mut assets)?;
+        }
+        Ok(assets)
+    }
+
+    // whether two versions of a scene file describe the same scene, i.e. they
+    // only differ in comments, blank lines and trailing spaces
+    pub fn same_scene(old: &str, new: &str) -> bool {
+        fn significant(contents: &str) -> impl Iterator<Item = &str> {
+            let mut previous_blank = true;
+            contents
+                .lines()
+                .map(str::trim_end)
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .filter(move |line| {
+                    let blank = line.is_empty();
+                    let keep = !(blank && previous_blank);
+                    previous_blank = blank;
+                    keep
+                })
+        }
+        significant(old.trim_end()).eq(significant(new.trim_end()))
+    }
+
+    pub fn from_contents(
+        contents: String,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> std::result::Result<LoadedScene, ParseErrors> {
+        Self::from_contents_recovering(contents, overrides, time)?.without_errors()
+    }
+
+    // the same scenes as from_contents(), written as JSON (see structured.rs)
+    #[cfg(feature = "json")]
+    pub fn from_json(
+        contents: String,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> std::result::Result<LoadedScene, ParseErrors> {
+        let (paragraphs, errors) = structured::parse_json(&contents);
+        Self::from_paragraphs_recovering(paragraphs, errors, overrides, time)?.without_errors()
+    }
+
+    // the same scenes as from_contents(), written as YAML (see structured.rs)
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(
+        contents: String,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> std::result::Result<LoadedScene, ParseErrors> {
+        let (paragraphs, errors) = structured::parse_yaml(&contents);
+        Self::from_paragraphs_recovering(paragraphs, errors, overrides, time)?.without_errors()
+    }
+
+    // like from_path(), but invalid paragraphs are skipped and their errors
+    // collected in the scene's errors, so the rest of it can still render
+    pub fn from_path_recovering(
+        path: &Path,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> Result<LoadedScene> {
+        let contents = read_scene_file(path)?;
+        Self::from_named_contents_recovering(path, contents, overrides, time)
+    }
+
+    // like from_path_recovering(), with the file already read (e.g. sent
+    // over the network). the filename only decides the format.
+    pub fn from_named_contents_recovering(
+        filename: &Path,
+        contents: String,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> Result<LoadedScene> {
+        let (paragraphs, errors) = Paragraph::parse_file(filename, &contents);
+        let scene = Self::from_paragraphs_recovering(paragraphs, errors, overrides, time);
+        if Paragraph::is_structured(filename) {
+            // their line numbers count paragraphs, not lines of the file
+            return scene;
+        }
+        LoadedScene::located(scene, &contents)
+    }
+
+    // only errors that don't belong to a paragraph, e.g. from overrides,
+    // are returned as Err
+    pub fn from_contents_recovering(
+        contents: String,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> Result<LoadedScene> {
+        let (paragraphs, errors) = Paragraph::parse_whole_file_recovering(&contents);
+        let scene = Self::from_paragraphs_recovering(paragraphs, errors, overrides, time);
+        LoadedScene::located(scene, &contents)
     }
 
-    pub fn from_contents(contents: String) -> Result<(Box<dyn Environment>, Box<FullCamera>)> {
-        let paragraphs = Paragraph::parse_whole_file(contents)?;
+    fn from_paragraphs_recovering(
+        mut paragraphs: Vec<Paragraph>,
+        mut errors: Vec<ParseError>,
+        overrides: &[SceneOverride],
+        time: f32,
+    ) -> Result<LoadedScene> {
+        for scene_override in overrides {
+            scene_override.apply(&mut paragraphs)?;
+        }
+
+        // the scene's axis is the default up axis for the models it imports
+        let mut axis = UpAxis::default();
+        for paragraph in paragraphs.iter_mut().filter(|p| p.is_scene()) {
+            if let Some(attribute) = paragraph.attributes.remove("axis") {
+                match attribute.as_up_axis() {
+                    Ok(up_axis) => axis = up_axis,
+                    Err(e) => errors.push(e),
+                }
+            }
+        }
+        for paragraph in &mut paragraphs {
+            paragraph.axis = axis;
+        }
+
+        // named materials are merged into whatever uses them up front, so
+        // everything after only sees ordinary material paragraphs
+        let materials = NamedMaterials::take(&mut paragraphs, &mut errors);
+        let paragraphs: Vec<Paragraph> = paragraphs
+            .into_iter()
+            .filter_map(|paragraph| match materials.resolve(paragraph, &mut Vec::new()) {
+                Ok(paragraph) => Some(paragraph),
+                Err(e) => {
+                    errors.push(e);
+                    None
+                }
+            })
+            .collect();
+
+        // named objects can be referenced from any other paragraph, so
+        // collect them all before building anything. hidden ones are only
+        // there to be referenced.
+        let mut named = HashMap::new();
+        let mut visible = Vec::new();
+        for mut paragraph in paragraphs {
+            if paragraph.kind != "object" {
+                visible.push(paragraph);
+                continue;
+            }
+            match paragraph.take_name(&named) {
+                Ok((name, hidden)) => {
+                    if let Some(name) = name {
+                        named.insert(name, paragraph.clone());
+                    }
+                    if !hidden {
+                        visible.push(paragraph);
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        let names = Names {
+            paragraphs: &named,
+            resolving: Vec::new(),
+        };
 
         let (scenes, paragraphs): (Vec<_>, Vec<_>) =
-            paragraphs.into_iter().partition(|p| p.is_scene());
+            visible.into_iter().partition(|p| p.is_scene());
 
         let mut scenes = scenes.into_iter();
-        let mut scene = match scenes.next() {
-            Some(scene) => {
-                let ParagraphItem::Env(scene) = scene.into_item()? else {
-                    panic!("is_scene() is true but into_item() is not Env")
-                };
-                scene
+        let scene = match scenes.next().map(|scene| scene.into_item(time, &names)) {
+            Some(Ok(ParagraphItem::Env(scene))) => Some(scene),
+            Some(Ok(_)) => panic!("is_scene() is true but into_item() is not Env"),
+            Some(Err(e)) => {
+                errors.push(e);
+                None
             }
-            None => Box::new(Scene::new()),
+            None => None,
         };
+        let mut scene = scene.unwrap_or_else(|| {
+            random::set_seed(None);
+            platform::set_num_threads(None);
+            platform::set_reserve_core(false);
+            platform::set_pin_threads(false);
+            Box::new(Scene::new())
+        });
 
-        if let Some(paragraph) = scenes.next() {
-            bail!(paragraph.start_line, "Multiple scenes in file");
+        for paragraph in scenes {
+            errors.push(err!(paragraph.start_line, "Multiple scenes in file"));
         }
 
-        let mut camera = None;
+        let mut cameras: Vec<(LineNumber, Box<FullCamera>)> = Vec::new();
+        let mut inactive_cameras = Vec::new();
+        let mut animation = None;
         for paragraph in paragraphs {
             let start_line = paragraph.start_line;
-            let item = paragraph.into_item()?;
+            let item = match paragraph.into_item(time, &names) {
+                Ok(item) => item,
+                Err(e) => {
+                    errors.push(e);
+                    continue;
+                }
+            };
             match item {
                 ParagraphItem::Light(light) => scene.add_light(light),
                 ParagraphItem::Object(object) => scene.add_object(object),
-                ParagraphItem::Camera(c) => {
-                    if camera.is_some() {
-                        bail!(start_line, "Multiple cameras in file")
+                ParagraphItem::Volume(volume) => scene.add_volume(volume),
+                ParagraphItem::Camera(c) => cameras.push((start_line, c)),
+                ParagraphItem::InactiveCamera(c) => inactive_cameras.push(*c),
+                ParagraphItem::Animation(a) => {
+                    if animation.is_some() {
+                        errors.push(err!(start_line, "Multiple animations in file"));
+                        continue;
                     }
-                    camera = Some(c)
+                    animation = Some(a)
                 }
                 ParagraphItem::Material(_) => {
-                    bail!(start_line, "Cannot add material to scene on its own")
+                    errors.push(err!(start_line, "Cannot add material to scene on its own"))
                 }
                 ParagraphItem::Env(_) => {
                     panic!("is_scene() is false but into_item() is Env")
@@ -104,7 +511,31 @@ impl SceneFile {
             }
         }
 
-        let camera = camera.unwrap_or_else(|| {
+        // several cameras can only be rendered if they're named, so their
+        // images don't overwrite each other
+        let all_named = cameras.iter().all(|(_, camera)| camera.name.is_some());
+        let mut names = Vec::new();
+        let mut cameras = cameras.into_iter().filter_map(|(start_line, camera)| {
+            if names.is_empty() {
+                names.push(camera.name.clone());
+                return Some(camera);
+            }
+            if !all_named {
+                errors.push(err!(start_line, "Multiple cameras in file, name them to render from each"));
+                return None;
+            }
+            if names.contains(&camera.name) {
+                let name = camera.name.as_deref().unwrap_or_default();
+                errors.push(err!(start_line, "Duplicate camera name: {}", name));
+                return None;
+            }
+            names.push(camera.name.clone());
+            Some(camera)
+        });
+        let camera = cameras.next();
+        let other_cameras = cameras.collect();
+
+        let mut camera = camera.unwrap_or_else(|| {
             let width = 1024;
             let height = 1024;
 
@@ -114,68 +545,155 @@ impl SceneFile {
             let up = Vector::new(0.0, lookat.z, -lookat.y);
             let fov = 40f32.to_radians();
 
-            let camera = FullCamera::new(width, height, fov, position, lookat, up);
+            // so there's something to see whatever the scene holds
+            let mut camera = FullCamera::new(width, height, fov, position, lookat, up);
+            camera.auto_frame = true;
             Box::new(camera)
         });
-        Ok((scene, camera))
+        camera.inactive_cameras = inactive_cameras;
+
+        // in file order, wherever they were found
+        errors.sort_by_key(|error| error.line);
+        let mut loaded = LoadedScene {
+            environment: scene,
+            camera,
+            other_cameras,
+            animation,
+            errors,
+        };
+        loaded.frame_cameras();
+        Ok(loaded)
     }
 }
 
+#[derive(Clone)]
 struct Paragraph {
     kind: String,
     class: String,
     attributes: HashMap<String, Attribute>,
     start_line: LineNumber,
+    axis: UpAxis,           // the scene's up axis
+    offset: Option<Vector>, // where a repeat paragraph moved this copy to
 }
 
 impl Paragraph {
     fn parse_whole_file(contents: String) -> Result<Vec<Self>> {
-        let mut paragraphs = Vec::new();
+        let (paragraphs, mut errors) = Self::parse_whole_file_recovering(&contents);
+        if !errors.is_empty() {
+            return Err(errors.remove(0));
+        }
+        Ok(paragraphs)
+    }
+
+    // like parse_whole_file_recovering(), in the format the file's
+    // extension says
+    fn parse_file(path: &Path, contents: &str) -> (Vec<Self>, Vec<ParseError>) {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "json")]
+            Some("json") => structured::parse_json(contents),
+            #[cfg(not(feature = "json"))]
+            Some("json") => (
+                Vec::new(),
+                vec![err!(0, "JSON scenes need the json feature")],
+            ),
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => structured::parse_yaml(contents),
+            #[cfg(not(feature = "yaml"))]
+            Some("yaml" | "yml") => (
+                Vec::new(),
+                vec![err!(0, "YAML scenes need the yaml feature")],
+            ),
+            _ => Self::parse_whole_file_recovering(contents),
+        }
+    }
+
+    // whether parse_file() reads the file as JSON or YAML
+    fn is_structured(path: &Path) -> bool {
+        matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("json" | "yaml" | "yml")
+        )
+    }
+
+    // the paragraphs that parsed, and the errors from the ones that didn't
+    fn parse_whole_file_recovering(contents: &str) -> (Vec<Self>, Vec<ParseError>) {
+        // in file order, so the first error is the first one in the file
+        let mut results = Vec::new();
 
         let mut lines = contents.lines().enumerate();
         let mut lines_in_paragraph = vec![];
         let mut paragraph_start_line: LineNumber = 0;
+        let mut skipping = false; // indented lines with no paragraph header
 
-        let mut process_paragraph =
-            |lines_in_paragraph: &mut Vec<&str>, line_number: LineNumber| -> Result<()> {
-                if lines_in_paragraph.is_empty() {
-                    return Ok(());
-                }
+        let process_paragraph = |results: &mut Vec<ParagraphResult>,
+                                 lines_in_paragraph: &mut Vec<&str>,
+                                 line_number: LineNumber| {
+            if lines_in_paragraph.is_empty() {
+                return;
+            }
 
-                let lines_in_paragraph = std::mem::take(lines_in_paragraph);
-                let paragraph = Paragraph::parse(lines_in_paragraph, line_number)?;
-                paragraphs.push(paragraph);
-                Ok(())
-            };
+            let lines_in_paragraph = std::mem::take(lines_in_paragraph);
+            results.push(Paragraph::parse(lines_in_paragraph, line_number));
+        };
 
         loop {
             let next_line = lines.next();
             let Some((line_number, next_line)) = next_line else {
-                process_paragraph(&mut lines_in_paragraph, paragraph_start_line)?;
+                process_paragraph(&mut results, &mut lines_in_paragraph, paragraph_start_line);
                 break;
             };
             let line_number = line_number as LineNumber + 1;
 
             if next_line.trim().is_empty() || next_line.starts_with('#') {
-                process_paragraph(&mut lines_in_paragraph, paragraph_start_line)?;
+                process_paragraph(&mut results, &mut lines_in_paragraph, paragraph_start_line);
+                skipping = false;
                 continue;
             }
 
             let indentation = next_line.chars().take_while(|c| c.is_whitespace()).count();
-            if lines_in_paragraph.is_empty() && indentation == 0 {
+            if indentation == 0 {
+                if !lines_in_paragraph.is_empty() {
+                    // carry on as if the newline was there
+                    process_paragraph(&mut results, &mut lines_in_paragraph, paragraph_start_line);
+                    let e = err!(line_number, "Started next paragraph without empty newline");
+                    results.push(Err(vec![e]));
+                }
                 lines_in_paragraph.push(next_line);
                 paragraph_start_line = line_number;
-            } else if !lines_in_paragraph.is_empty() && indentation > 0 {
+                skipping = false;
+            } else if !lines_in_paragraph.is_empty() {
                 lines_in_paragraph.push(next_line);
-            } else {
-                bail!(line_number, "Started next paragraph without empty newline");
+            } else if !skipping {
+                let e = err!(line_number, "Indented line outside of a paragraph");
+                results.push(Err(vec![e]));
+                skipping = true;
             }
         }
 
-        Ok(paragraphs)
+        Self::expand_all(results)
+    }
+
+    // the paragraphs, with repeat paragraphs expanded into their copies, and
+    // the errors in place of the ones that failed
+    fn expand_all(results: Vec<ParagraphResult>) -> (Vec<Self>, Vec<ParseError>) {
+        let mut paragraphs = Vec::new();
+        let mut errors = Vec::new();
+        for result in results {
+            match result {
+                Ok(paragraph) if paragraph.kind == "repeat" => match paragraph.expand_repeat() {
+                    Ok(copies) => paragraphs.extend(copies),
+                    Err(e) => errors.push(e),
+                },
+                Ok(paragraph) => paragraphs.push(paragraph),
+                Err(e) => errors.extend(e),
+            }
+        }
+        (paragraphs, errors)
     }
 
-    fn parse(lines: Vec<&str>, first_line_number: LineNumber) -> Result<Self> {
+    // every bad attribute is reported, not just the first, but the header
+    // has to be right to make sense of the rest
+    fn parse(lines: Vec<&str>, first_line_number: LineNumber) -> ParagraphResult {
         let first_line = lines[0];
 
         let get_indentation = |s: &str| s.chars().take_while(|c| c.is_whitespace()).count();
@@ -184,27 +702,36 @@ impl Paragraph {
         let mut words = first_line.split_whitespace();
         let kind = words
             .next()
-            .ok_or_else(|| err!(first_line_number, "Empty paragraph"))?
+            .ok_or_else(|| vec![err!(first_line_number, "Empty paragraph")])?
             .to_string();
         let class = words
             .next()
-            .ok_or_else(|| err!(first_line_number, "Missing paragraph class"))?
+            .ok_or_else(|| vec![err!(first_line_number, "Missing paragraph class")])?
             .to_string();
         // if words.next().is_some() {
         if let Some(word) = words.next() {
-            bail!(
+            let e = err!(
                 first_line_number,
                 "Too many words in paragraph header: {}",
                 word
             );
+            return Err(vec![e.at(word)]);
         }
 
         let mut attributes = HashMap::new();
+        let mut errors = Vec::new();
+        let mut attribute_indentation = None;
         for (i, line) in lines.iter().enumerate().skip(1) {
             if line.is_empty() || get_indentation(line) <= indentation {
                 // end of paragraph
                 break;
             }
+            // lines indented further belong to a sub-paragraph
+            let attribute_indentation =
+                *attribute_indentation.get_or_insert(get_indentation(line));
+            if get_indentation(line) > attribute_indentation {
+                continue;
+            }
 
             let line_number = first_line_number + i as LineNumber;
 
@@ -216,31 +743,22 @@ impl Paragraph {
 
             let words: Vec<&str> = words.collect();
 
-            let value = if words.len() == 3 {
-                let parse_error = || err!(line_number, "Invalid float");
-                let x = words[0].parse::<f32>().map_err(|_| parse_error())?;
-                let y = words[1].parse::<f32>().map_err(|_| parse_error())?;
-                let z = words[2].parse::<f32>().map_err(|_| parse_error())?;
-                AttributeValue::Vector(Vector::new(x, y, z))
-            } else if words.len() == 1 {
-                // if the next line is more indented
-                if i + 1 < lines.len() && get_indentation(lines[i + 1]) > get_indentation(line) {
-                    // this is a sub-paragraph
-                    let p = Paragraph::parse(lines[i..].to_vec(), line_number);
-                    AttributeValue::SubParagraph(Box::new(p?))
-                } else {
-                    // either a float or a word
-                    match words[0].parse::<f32>() {
-                        Ok(f) => AttributeValue::Float(f),
-                        Err(_) => AttributeValue::Word(words[0].to_string()),
-                    }
-                }
+            // if the next line is more indented, this is a sub-paragraph
+            let value = if words.len() == 1
+                && i + 1 < lines.len()
+                && get_indentation(lines[i + 1]) > get_indentation(line)
+            {
+                Paragraph::parse(lines[i..].to_vec(), line_number)
+                    .map(|p| AttributeValue::SubParagraph(Box::new(p)))
             } else {
-                bail!(
-                    line_number,
-                    "Invalid word count in attribute value: {}",
-                    line
-                );
+                AttributeValue::parse(&words, line_number).map_err(|e| vec![e])
+            };
+            let value = match value {
+                Ok(value) => value,
+                Err(e) => {
+                    errors.extend(e);
+                    continue;
+                }
             };
 
             let key = key.to_string();
@@ -248,155 +766,1046 @@ impl Paragraph {
                 key: key.clone(),
                 value,
                 line_number,
+                overridden: false,
             };
             attributes.insert(key, attribute);
         }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
 
         Ok(Self {
             kind,
             class,
             attributes,
             start_line: first_line_number,
+            axis: UpAxis::default(),
+            offset: None,
         })
     }
 
-    fn into_item(self) -> Result<ParagraphItem> {
+    // "repeat Line" and "repeat Grid" paragraphs stand for copies of the
+    // object in them, each moved further along, e.g. a 10x10 grid of spheres.
+    // a named object's copies are told apart by their index, e.g. coin_0,
+    // coin_1, and so on.
+    fn expand_repeat(mut self) -> Result<Vec<Self>> {
+        // every copy is built on its own, so a slip like "count 1000 1000
+        // 1000" would take forever rather than fail
+        const MAX_COPIES: f32 = 100_000.0;
+
+        let count = self.get_attr("count")?;
+        let zero = Vector::new(0.0, 0.0, 0.0);
+        let (counts, steps) = match self.class.as_str() {
+            // count copies, each one step on from the last
+            "Line" => {
+                let step = self.get_attr("step")?.as_vector()?;
+                ([count.as_float()?, 1.0, 1.0], [step, zero, zero])
+            }
+            // count copies along each axis, spacing apart
+            "Grid" => {
+                let count = count.as_vector()?;
+                let spacing = self.get_attr("spacing")?.as_vector()?;
+                let steps = [
+                    Vector::new(spacing.x, 0.0, 0.0),
+                    Vector::new(0.0, spacing.y, 0.0),
+                    Vector::new(0.0, 0.0, spacing.z),
+                ];
+                ([count.x, count.y, count.z], steps)
+            }
+            _ => return Err(self.invalid_class("repeat")),
+        };
+        if counts
+            .iter()
+            .any(|count| *count < 1.0 || count.fract() != 0.0)
+        {
+            bail!(
+                count.line_number,
+                "Repeat count must be a whole number, at least 1"
+            );
+        }
+        let total: f32 = counts.iter().product();
+        if total > MAX_COPIES {
+            bail!(
+                count.line_number,
+                "Too many copies to repeat: {}, at most {}",
+                total as u64,
+                MAX_COPIES
+            );
+        }
+
+        let template = self.get_attr("object")?;
+        let AttributeValue::SubParagraph(template) = template.value else {
+            bail!(template.line_number, "Invalid attribute value for object");
+        };
+        self.reject_unknown()?;
+
+        let mut copies = Vec::new();
+        for k in 0..counts[2] as u32 {
+            for j in 0..counts[1] as u32 {
+                for i in 0..counts[0] as u32 {
+                    let offset = steps[0] * i as f32 + steps[1] * j as f32 + steps[2] * k as f32;
+                    let mut copy = (*template).clone();
+                    copy.offset = Some(offset);
+                    if let Some(name) = copy.attributes.get_mut("name") {
+                        if let AttributeValue::Word(name) = &mut name.value {
+                            *name = format!("{name}_{}", copies.len());
+                        }
+                    }
+                    copies.push(copy);
+                }
+            }
+        }
+        Ok(copies)
+    }
+
+    fn into_item(mut self, time: f32, names: &Names) -> Result<ParagraphItem> {
         match self.kind.as_str() {
             "light" => Ok(ParagraphItem::Light(self.into_light()?)),
-            "object" => Ok(ParagraphItem::Object(self.into_object()?)),
+            "object" => {
+                let keyframes = self.take_keyframes()?;
+                let motion = self.take_motion()?;
+                let visibility = self.take_visibility()?;
+                let offset = self.offset;
+                let mut object = self.into_object(names)?;
+                if let Some(offset) = offset {
+                    object.apply_transform(&Transform::from_translation(offset));
+                }
+                if let Some(keyframes) = keyframes {
+                    let offset = keyframes.at(time);
+                    object.apply_transform(&Transform::from_translation(offset));
+                    // keep moving while the shutter is open
+                    object = Animated::new(object, keyframes, time);
+                }
+                if let Some(motion) = motion {
+                    object = Moving::new(object, motion);
+                }
+                if visibility != Visibility::ALL {
+                    object = PartlyVisible::new(object, visibility);
+                }
+                Ok(ParagraphItem::Object(object))
+            }
             "material" => Ok(ParagraphItem::Material(self.into_material()?)),
             "scene" => Ok(ParagraphItem::Env(self.into_scene()?)),
-            "camera" => Ok(ParagraphItem::Camera(self.into_camera()?)),
+            "animation" => Ok(ParagraphItem::Animation(self.into_animation()?)),
+            "volume" => Ok(ParagraphItem::Volume(self.into_volume(names)?)),
+            "camera" => {
+                // inactive cameras aren't rendered from, they only show up
+                // as gizmos when debugging the scene layout
+                let active = self
+                    .get_attr_or("active", AttributeValue::Float(1.0))
+                    .as_float()?
+                    != 0.0;
+                let name = match self.attributes.remove("name") {
+                    Some(name) => Some(name.as_word()?),
+                    None => None,
+                };
+                let keyframes = self.take_keyframes()?;
+                let mut camera = self.into_camera()?;
+                camera.name = name;
+                camera.frame_time = time;
+                if let Some(keyframes) = keyframes {
+                    camera.position += keyframes.at(time);
+                    camera.keyframes = Some(keyframes);
+                }
+                if active {
+                    Ok(ParagraphItem::Camera(camera))
+                } else {
+                    Ok(ParagraphItem::InactiveCamera(camera))
+                }
+            }
             _ => bail!(self.start_line, "Invalid paragraph kind: {}", self.kind),
         }
     }
 
-    fn is_scene(&self) -> bool {
-        self.kind == "scene"
+    fn collect_assets(&self, assets: &mut Vec<PathBuf>) -> Result<()> {
+        for attribute in self.attributes.values() {
+            match (&attribute.value, attribute.key.as_str()) {
+                (AttributeValue::SubParagraph(p), _) => p.collect_assets(assets)?,
+                (_, "obj" | "obj_end" | "bpt" | "points") => {
+                    let model_path = attribute.as_word()?;
+                    assets.push(PathBuf::from("assets").join("models").join(model_path));
+                }
+                (_, "displacement") => {
+                    let name = attribute.as_word()?;
+                    assets.push(PathBuf::from("assets").join("textures").join(name));
+                }
+                (_, "image") if self.class == "Projector" => {
+                    let name = attribute.as_word()?;
+                    assets.push(PathBuf::from("assets").join("textures").join(name));
+                }
+                (_, "name") if self.class.contains("Texture") => {
+                    let name = attribute.as_word()?;
+                    assets.push(PathBuf::from("assets").join("textures").join(name));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
-    fn into_scene(self) -> Result<Box<dyn Environment>> {
-        let scene: Box<dyn Environment> = match self.class.as_str() {
-            "Scene" => Box::new(Scene::new()),
-            "PhotonScene" => Box::new(PhotonScene::new()),
-            _ => bail!(self.start_line, "Invalid scene class: {}", self.class),
+    // keyframes
+    //     <time> <offset>
+    //     ...
+    fn take_keyframes(&mut self) -> Result<Option<Keyframes>> {
+        let Some(attribute) = self.attributes.remove("keyframes") else {
+            return Ok(None);
         };
-        Ok(scene)
-    }
-
-    fn into_light(mut self) -> Result<Box<dyn Light>> {
-        let light: Box<dyn Light> = match self.class.as_str() {
-            "Directional" => DirectionalLight::new(
-                self.get_attr("direction")?.as_vector()?,
-                self.get_attr_or("colour", AttributeValue::Float(1.0))
-                    .as_colour()?,
-            ),
-            "Point" => PointLight::new(
-                self.get_attr("position")?.as_vertex()?,
-                self.get_attr_or("colour", AttributeValue::Float(1.0))
-                    .as_colour()?,
-            ),
-            "DirPoint" => DPLight::new(
-                self.get_attr("position")?.as_vertex()?,
-                self.get_attr("direction")?.as_vector()?,
-                self.get_attr_or("colour", AttributeValue::Float(1.0))
-                    .as_colour()?,
-            ),
-            _ => bail!(self.start_line, "Invalid light class: {}", self.class),
+        let AttributeValue::SubParagraph(keyframes) = attribute.value else {
+            bail!(attribute.line_number, "keyframes must be a sub-paragraph");
         };
-        Ok(light)
+        if keyframes.class != "Translate" {
+            return Err(keyframes.invalid_class("keyframes"));
+        }
+
+        let mut keys = Vec::new();
+        for (key, attribute) in keyframes.attributes {
+            let time = key
+                .parse::<f32>()
+                .map_err(|_| err!(attribute.line_number, "Invalid keyframe time: {}", key))?;
+            keys.push((time, attribute.as_vector()?));
+        }
+        if keys.is_empty() {
+            bail!(keyframes.start_line, "keyframes needs at least one key");
+        }
+
+        Ok(Some(Keyframes::new(keys)))
     }
 
-    fn into_object(mut self) -> Result<Box<dyn Object>> {
-        let object: Box<dyn Object> = match self.class.as_str() {
-            "Plane" => Plane::new(
-                &self.get_attr("point")?.as_vertex()?,
-                self.get_attr("up")?.as_vector()?,
-                self.get_attr("normal")?.as_vector()?,
-                self.get_attr("material")?.into_material()?,
-            ),
-            "Sphere" => Sphere::new(
-                self.get_attr("centre")?.as_vertex()?,
-                self.get_attr("radius")?.as_float()?,
-                self.get_attr("material")?.into_material()?,
-            ),
-            "Cuboid" => Cuboid::new(
-                self.get_attr("corner")?.as_vertex()?,
-                self.get_attr("size")?.as_vector()?,
-                self.get_attr("material")?.into_material()?,
-            ),
-            "Quadratic" => {
-                let mut quadratic = Quadratic::new(
-                    (
-                        self.get_attr_or("a", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("b", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("c", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("d", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("e", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("f", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("g", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("h", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("i", AttributeValue::Float(0.0))
-                            .as_float()?,
-                        self.get_attr_or("j", AttributeValue::Float(0.0))
-                            .as_float()?,
-                    ),
-                    self.get_attr("material")?.into_material()?,
-                );
-                if let Ok(transform) = self.get_attr("translate") {
-                    let transform = transform.as_vector()?;
-                    let transform = Transform::from_translation(transform);
-                    quadratic.apply_transform(&transform);
-                }
-                quadratic
+    // where an object is when the shutter opens and time seconds later, for
+    // motion blur, each as a Transform sub-paragraph:
+    // transform_start Transform
+    //     scale, rotate (degrees), translate, pivot
+    // transform_end Transform
+    //     ..., time
+    fn take_motion(&mut self) -> Result<Option<Motion>> {
+        let start = match self.take_transform("transform_start")? {
+            Some(mut start) => {
+                let pose = start.take_pose()?;
+                start.reject_unknown()?;
+                Some(pose)
             }
-            "Model" => {
-                let obj_path = self.get_attr("obj")?.as_word()?;
-                let obj_path = PathBuf::from("assets").join("models").join(obj_path);
-                let mut model = PolyMesh::from_obj_file(
-                    obj_path,
-                    self.get_attr("material")?.into_material()?,
-                    self.get_attr_or("smooth", AttributeValue::Float(0.0))
-                        .as_float()?
-                        != 0.0,
-                );
+            None => None,
+        };
+        let Some(mut end) = self.take_transform("transform_end")? else {
+            // still, but somewhere else
+            return Ok(start.map(|start| Motion {
+                start,
+                end: start,
+                end_time: 1.0,
+            }));
+        };
 
-                // tmp: special fix for teapot model
-                if self.get_attr("rotate_teapot").is_ok() {
-                    // rotate the teapot by 90 degrees
-                    let rotation = Transform::from_rotation_matrix([
-                        [1.0, 0.0, 0.0],
-                        [0.0, 0.0, 1.0],
-                        [0.0, -1.0, 0.0],
-                    ]);
-                    model.apply_transform(&rotation);
-                }
+        let time = end.get_attr("time")?;
+        let end_time = time.as_float()?;
+        if end_time <= 0.0 {
+            bail!(time.line_number, "time must be positive");
+        }
+        let pose = end.take_pose()?;
+        end.reject_unknown()?;
+        Ok(Some(Motion {
+            start: start.unwrap_or_default(),
+            end: pose,
+            end_time,
+        }))
+    }
 
-                let mut transform = Transform::identity();
+    fn take_transform(&mut self, key: &str) -> Result<Option<Box<Paragraph>>> {
+        let Some(attribute) = self.attributes.remove(key) else {
+            return Ok(None);
+        };
+        let AttributeValue::SubParagraph(transform) = attribute.value else {
+            bail!(attribute.line_number, "{} must be a sub-paragraph", key);
+        };
+        if transform.class != "Transform" {
+            return Err(transform.invalid_class(key));
+        }
+        Ok(Some(transform))
+    }
 
+    // the attributes of a Transform, nothing by default
+    fn take_pose(&mut self) -> Result<Pose> {
+        let mut pose = Pose::default();
+        if let Some(scale) = self.attributes.remove("scale") {
+            pose.scale = scale.as_vector()?;
+        }
+        if let Some(rotate) = self.take_rotation()? {
+            pose.rotate = rotate;
+        }
+        if let Some(translate) = self.attributes.remove("translate") {
+            pose.translate = translate.as_vector()?;
+        }
+        if let Some(pivot) = self.attributes.remove("pivot") {
+            pose.pivot = pivot.as_vector()?;
+        }
+        Ok(pose)
+    }
+
+    // rotate, written in degrees around x, then y, then z, in radians
+    fn take_rotation(&mut self) -> Result<Option<Vector>> {
+        let Some(rotate) = self.attributes.remove("rotate") else {
+            return Ok(None);
+        };
+        let degrees = rotate.as_vector()?;
+        Ok(Some(Vector::new(
+            degrees.x.to_radians(),
+            degrees.y.to_radians(),
+            degrees.z.to_radians(),
+        )))
+    }
+
+    fn into_animation(mut self) -> Result<Animation> {
+        if self.class != "Animation" {
+            return Err(self.invalid_class("animation"));
+        }
+
+        let fps = self
+            .get_attr_or("fps", AttributeValue::Float(24.0))
+            .as_float()?;
+        let frames = self.get_attr("frames")?.as_float()?;
+        if fps <= 0.0 || frames < 1.0 {
+            bail!(self.start_line, "Animation needs positive fps and frames");
+        }
+        self.reject_unknown()?;
+
+        Ok(Animation {
+            fps,
+            frames: 0..frames as u32,
+        })
+    }
+
+    // which rays can see an object, all of them unless turned off
+    fn take_visibility(&mut self) -> Result<Visibility> {
+        let mut flag = |key| -> Result<bool> {
+            Ok(self
+                .get_attr_or(key, AttributeValue::Float(1.0))
+                .as_float()?
+                != 0.0)
+        };
+        Ok(Visibility {
+            camera: flag("visible_to_camera")?,
+            reflections: flag("visible_in_reflections")?,
+            shadows: flag("casts_shadows")?,
+        })
+    }
+
+    // an object's name (if it has one), and whether it is hidden
+    fn take_name(&mut self, named: &HashMap<String, Paragraph>) -> Result<(Option<String>, bool)> {
+        let hidden = self
+            .get_attr_or("hidden", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        let Some(name) = self.attributes.remove("name") else {
+            return Ok((None, hidden));
+        };
+        let name_word = name.as_word()?;
+        if named.contains_key(&name_word) {
+            bail!(name.line_number, "Duplicate object name: {}", name_word);
+        }
+        Ok((Some(name_word), hidden))
+    }
+
+    fn is_scene(&self) -> bool {
+        self.kind == "scene"
+    }
+
+    fn into_scene(mut self) -> Result<Box<dyn Environment>> {
+        let mut trace_limit = TraceLimit::default();
+        if let Some(max_distance) = self.attributes.remove("max_trace_distance") {
+            trace_limit.max_distance = max_distance.as_float()?;
+            if trace_limit.max_distance <= 0.0 {
+                bail!(
+                    max_distance.line_number,
+                    "max_trace_distance must be positive"
+                );
+            }
+
+            // fade over the last fraction of the distance, none by default
+            let fade = self
+                .get_attr_or("trace_fade", AttributeValue::Float(0.0))
+                .as_float()?;
+            trace_limit.fade_start = trace_limit.max_distance * (1.0 - fade.clamp(0.0, 1.0));
+        }
+
+        // without a seed, every render comes out a little different
+        let seed = match self.attributes.remove("seed") {
+            Some(seed) => {
+                let value = seed.as_float()?;
+                if value < 0.0 || value.fract() != 0.0 {
+                    bail!(seed.line_number, "seed must be a whole number");
+                }
+                Some(value as u64)
+            }
+            None => None,
+        };
+        random::set_seed(seed);
+
+        // one thread per core unless set, see core::platform
+        let threads = match self.attributes.remove("threads") {
+            Some(threads) => {
+                let value = threads.as_float()?;
+                if value < 1.0 || value.fract() != 0.0 {
+                    bail!(
+                        threads.line_number,
+                        "threads must be a positive whole number"
+                    );
+                }
+                Some(value as u32)
+            }
+            None => None,
+        };
+        platform::set_num_threads(threads);
+        let reserve_core = self.get_attr_or("reserve_core", AttributeValue::Float(0.0));
+        platform::set_reserve_core(reserve_core.as_float()? != 0.0);
+        let pin_threads = self.get_attr_or("pin_threads", AttributeValue::Float(0.0));
+        platform::set_pin_threads(pin_threads.as_float()? != 0.0);
+
+        let fog = match self.attributes.remove("fog") {
+            Some(density) => Some(self.take_medium(density, "fog_colour")?),
+            None => None,
+        };
+        let gpu = self.get_attr_or("gpu", AttributeValue::Float(0.0));
+        let use_gpu = gpu.as_float()? != 0.0;
+        #[cfg(not(feature = "gpu"))]
+        if use_gpu {
+            bail!(gpu.line_number, "gpu needs the gpu feature");
+        }
+
+        let scene: Box<dyn Environment> = match self.class.as_str() {
+            "Scene" => {
+                let mut scene = Scene::new();
+                scene.fog = fog;
+                scene.trace_limit = trace_limit;
+                scene.settings = self.take_render_settings()?;
+                #[cfg(feature = "gpu")]
+                {
+                    scene.gpu = use_gpu;
+                }
+                if self.attributes.contains_key("ambient_occlusion") {
+                    scene.ambient_occlusion = Some(self.take_ambient_occlusion(
+                        "ambient_occlusion",
+                        "ambient_occlusion_samples",
+                    )?);
+                }
+                Box::new(scene)
+            }
+            "PhotonScene" => {
+                let mut scene = PhotonScene::new();
+                let preview = self
+                    .get_attr_or("preview", AttributeValue::Float(0.0))
+                    .as_float()?;
+                if preview != 0.0 {
+                    scene.quality = PhotonQuality::preview();
+                }
+                scene.direct_lighting = self
+                    .get_attr_or("direct_lighting", AttributeValue::Float(0.0))
+                    .as_float()?
+                    != 0.0;
+                scene.fog = fog;
+                scene.trace_limit = trace_limit;
+                scene.settings = self.take_render_settings()?;
+                scene.bake = self.take_photon_slice()?;
+                #[cfg(feature = "gpu")]
+                {
+                    scene.gpu = use_gpu;
+                    // only hash grids can be gathered from on the GPU
+                    if use_gpu {
+                        scene.gather_method = GatherMethod::HashGrid;
+                    }
+                }
+                if let Some(roughness) = self.attributes.remove("caustic_roughness") {
+                    scene.caustic_roughness = roughness.as_float()?;
+                    if !(0.0..=1.0).contains(&scene.caustic_roughness) {
+                        bail!(
+                            roughness.line_number,
+                            "caustic_roughness must be between 0 and 1"
+                        );
+                    }
+                }
+                if let Some(gather) = self.attributes.remove("gather") {
+                    let name = gather.as_word()?;
+                    scene.gather_method = GatherMethod::from_name(&name).ok_or_else(|| {
+                        err!(gather.line_number, "Unknown gather method: {}", name)
+                    })?;
+                }
+                if let Some(filter) = self.attributes.remove("gather_filter") {
+                    let name = filter.as_word()?;
+                    scene.gather_filter = GatherFilter::from_name(&name).ok_or_else(|| {
+                        err!(filter.line_number, "Unknown gather filter: {}", name)
+                    })?;
+                }
+                if let Some(view) = self.attributes.remove("show_photons") {
+                    let name = view.as_word()?;
+                    scene.photon_view = Some(PhotonView::from_name(&name).ok_or_else(|| {
+                        err!(view.line_number, "Unknown photon view: {}", name)
+                    })?);
+                }
+                if let Some(resolution) = self.attributes.remove("density_map") {
+                    let value = resolution.as_float()?;
+                    if value < 1.0 {
+                        bail!(resolution.line_number, "density_map must be at least 1");
+                    }
+                    scene.density_map_resolution = Some(value as u32);
+                }
+                if let Some(spacing) = self.attributes.remove("irradiance_cache") {
+                    let spacing_value = spacing.as_float()?;
+                    if spacing_value <= 0.0 {
+                        bail!(spacing.line_number, "irradiance_cache must be positive");
+                    }
+                    scene.irradiance_cache = Some(IrradianceCache::new(spacing_value));
+                }
+                Box::new(scene)
+            }
+            _ => {
+                let Some(factory) = ENVIRONMENTS.get(&self.class) else {
+                    return Err(self.invalid_class("scene"));
+                };
+                if fog.is_some() {
+                    bail!(self.start_line, "{} scenes don't support fog", self.class);
+                }
+                if use_gpu {
+                    bail!(gpu.line_number, "{} scenes don't support gpu", self.class);
+                }
+                factory.build(&mut SceneAttributes { paragraph: &mut self })?
+            }
+        };
+        self.reject_unknown()?;
+        Ok(scene)
+    }
+
+    // samples_key rays (16 by default), blocked by anything closer than
+    // radius_key (1 by default)
+    fn take_ambient_occlusion(
+        &mut self,
+        radius_key: &str,
+        samples_key: &str,
+    ) -> Result<AmbientOcclusion> {
+        let radius = self.get_attr_or(radius_key, AttributeValue::Float(1.0));
+        let radius_value = radius.as_float()?;
+        if radius_value <= 0.0 {
+            bail!(radius.line_number, "{} must be positive", radius.key);
+        }
+        let samples = self.get_attr_or(samples_key, AttributeValue::Float(16.0));
+        let samples_value = samples.as_float()?;
+        if samples_value < 1.0 || samples_value.fract() != 0.0 {
+            bail!(
+                samples.line_number,
+                "{} must be a whole number of at least 1",
+                samples.key
+            );
+        }
+        Ok(AmbientOcclusion::new(samples_value as u32, radius_value))
+    }
+
+    // a medium with the given density, and the colour in colour_key (white
+    // by default)
+    fn take_medium(&mut self, density: Attribute, colour_key: &str) -> Result<Medium> {
+        let colour = self
+            .get_attr_or(colour_key, AttributeValue::Float(1.0))
+            .as_colour()?;
+        let density_value = density.as_float()?;
+        if density_value <= 0.0 {
+            bail!(density.line_number, "{} must be positive", density.key);
+        }
+        Ok(Medium::new(density_value, colour))
+    }
+
+    // fog filling the inside of a named object
+    fn into_volume(mut self, names: &Names) -> Result<Volume> {
+        if self.class != "Homogeneous" {
+            return Err(self.invalid_class("volume"));
+        }
+
+        let bounds = names.object(&self.get_attr("bounds")?)?;
+        let density = self.get_attr("density")?;
+        let medium = self.take_medium(density, "colour")?;
+        self.reject_unknown()?;
+        Ok(Volume::new(bounds, medium))
+    }
+
+    fn into_light(mut self) -> Result<Box<dyn Light>> {
+        let light: Box<dyn Light> = match self.class.as_str() {
+            "Directional" => DirectionalLight::new(
+                self.get_attr("direction")?.as_vector()?,
+                self.get_attr_or("colour", AttributeValue::Float(1.0))
+                    .as_colour()?,
+            ),
+            "Point" => PointLight::new(
+                self.get_attr("position")?.as_vertex()?,
+                self.get_attr_or("colour", AttributeValue::Float(1.0))
+                    .as_colour()?,
+            ),
+            "DirPoint" => DPLight::new(
+                self.get_attr("position")?.as_vertex()?,
+                self.get_attr("direction")?.as_vector()?,
+                self.get_attr_or("colour", AttributeValue::Float(1.0))
+                    .as_colour()?,
+            ),
+            "Projector" => {
+                let image = self.get_attr("image")?;
+                let slide = Image::import_diffuse(&image.as_word()?).map_err(|e| {
+                    err!(image.line_number, "Could not load projector image: {}", e)
+                })?;
+                let fov = self.get_attr_or("fov", AttributeValue::Float(30.0));
+                let fov_value = fov.as_float()?;
+                if fov_value <= 0.0 || fov_value >= 180.0 {
+                    bail!(fov.line_number, "fov must be between 0 and 180");
+                }
+                ProjectorLight::new(
+                    self.get_attr("position")?.as_vertex()?,
+                    self.get_attr("direction")?.as_vector()?,
+                    self.get_attr_or("up", AttributeValue::Vector(Vector::new(0.0, 1.0, 0.0)))
+                        .as_vector()?,
+                    fov_value,
+                    slide,
+                    self.get_attr_or("colour", AttributeValue::Float(1.0))
+                        .as_colour()?,
+                )
+            }
+            "SunSky" => {
+                let elevation = self.get_attr("elevation")?;
+                let elevation_value = elevation.as_float()?;
+                if elevation_value <= 0.0 || elevation_value > 90.0 {
+                    bail!(elevation.line_number, "elevation must be above 0 and at most 90");
+                }
+                let turbidity = self.get_attr_or("turbidity", AttributeValue::Float(3.0));
+                let turbidity_value = turbidity.as_float()?;
+                if !(1.7..=10.0).contains(&turbidity_value) {
+                    bail!(turbidity.line_number, "turbidity must be between 1.7 and 10");
+                }
+                let mut sun_sky = SunSky::new(
+                    self.get_attr_or("azimuth", AttributeValue::Float(0.0))
+                        .as_float()?,
+                    elevation_value,
+                    turbidity_value,
+                    self.get_attr_or("colour", AttributeValue::Float(1.0))
+                        .as_colour()?,
+                    self.get_attr_or("sky", AttributeValue::Float(0.5))
+                        .as_float()?,
+                );
+                for quad in self.take_windows()? {
+                    sun_sky.add_window(quad);
+                }
+                sun_sky
+            }
+            _ => {
+                let Some(factory) = LIGHTS.get(&self.class) else {
+                    return Err(self.invalid_class("light"));
+                };
+                factory.build(&mut SceneAttributes { paragraph: &mut self })?
+            }
+        };
+        self.reject_unknown()?;
+        Ok(light)
+    }
+
+    // every sub-paragraph (the attributes with one), in the order they're
+    // written. their keys are only labels.
+    fn take_sub_paragraphs(&mut self) -> Vec<Attribute> {
+        let keys: Vec<String> = self
+            .attributes
+            .iter()
+            .filter(|(_, attribute)| matches!(attribute.value, AttributeValue::SubParagraph(_)))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut sub_paragraphs: Vec<Attribute> = keys
+            .iter()
+            .filter_map(|key| self.attributes.remove(key))
+            .collect();
+        sub_paragraphs.sort_by(|a, b| (a.line_number, &a.key).cmp(&(b.line_number, &b.key)));
+        sub_paragraphs
+    }
+
+    // a Model's scale and then translate, which Fur shares to grow from it
+    fn take_model_transform(&mut self) -> Result<Transform> {
+        let mut transform = Transform::identity();
+
+        if let Some(translate) = self.attributes.remove("translate") {
+            let translate = translate.as_vector()?;
+            transform[3][0] = translate.x;
+            transform[3][1] = translate.y;
+            transform[3][2] = translate.z;
+        }
+
+        if let Some(scale) = self.attributes.remove("scale") {
+            let scale = scale.as_float()?;
+            transform[0][0] = scale;
+            transform[1][1] = scale;
+            transform[2][2] = scale;
+        }
+
+        Ok(transform.transposed())
+    }
+
+    // radius at the root of a Curve or Fur and tip_radius at the other end,
+    // the same unless it tapers
+    fn take_strand_radius(&mut self) -> Result<[f32; 2]> {
+        let radius = self.get_attr("radius")?;
+        let radius_value = radius.as_float()?;
+        if radius_value <= 0.0 {
+            bail!(radius.line_number, "radius must be positive");
+        }
+        let tip = self.get_attr_or("tip_radius", AttributeValue::Float(radius_value));
+        let tip_value = tip.as_float()?;
+        if tip_value < 0.0 {
+            bail!(tip.line_number, "tip_radius cannot be negative");
+        }
+        Ok([radius_value, tip_value])
+    }
+
+    // how many times a fractal's formula is repeated, more for finer detail
+    fn take_iterations(&mut self, default: u32) -> Result<u32> {
+        let iterations = self.get_attr_or("iterations", AttributeValue::Float(default as f32));
+        let value = iterations.as_float()?;
+        if value < 1.0 || value.fract() != 0.0 {
+            bail!(
+                iterations.line_number,
+                "iterations must be a whole number of at least 1"
+            );
+        }
+        Ok(value as u32)
+    }
+
+    // every sub-paragraph of a SunSky is a window:
+    // <label> Window
+    //     centre, normal (pointing inside), up, width and height
+    fn take_windows(&mut self) -> Result<Vec<Quad>> {
+        let mut quads = Vec::new();
+        for window in self.take_sub_paragraphs() {
+            let AttributeValue::SubParagraph(mut paragraph) = window.value else {
+                unreachable!();
+            };
+            if paragraph.class != "Window" {
+                return Err(paragraph.invalid_class("window"));
+            }
+            quads.push(paragraph.take_quad("", None)?);
+            paragraph.reject_unknown()?;
+        }
+        Ok(quads)
+    }
+
+    fn into_object(mut self, names: &Names) -> Result<Box<dyn Object>> {
+        let object: Box<dyn Object> = match self.class.as_str() {
+            "Plane" => Plane::new(
+                &self.get_attr("point")?.as_vertex()?,
+                self.get_attr("up")?.as_vector()?,
+                self.get_attr("normal")?.as_vector()?,
+                self.get_attr("material")?.into_material()?,
+            ),
+            "Sphere" => {
+                let mut sphere = Sphere::new(
+                    self.get_attr("centre")?.as_vertex()?,
+                    self.get_attr("radius")?.as_float()?,
+                    self.get_attr("material")?.into_material()?,
+                );
+                // the texture's poles are along y and its middle faces z,
+                // unless turned
+                let pole = self.attributes.remove("pole");
+                let rotation = self.attributes.remove("rotation");
+                if pole.is_some() || rotation.is_some() {
+                    let pole = match pole {
+                        Some(pole) if pole.as_vector()?.len_sqrd() == 0.0 => {
+                            bail!(pole.line_number, "pole cannot be zero")
+                        }
+                        Some(pole) => pole.as_vector()?,
+                        None => Vector::new(0.0, 1.0, 0.0),
+                    };
+                    let rotation = match rotation {
+                        Some(rotation) => rotation.as_float()?,
+                        None => 0.0,
+                    };
+                    sphere.orient_texture(pole, rotation.to_radians());
+                }
+                // stretched along each axis around its centre, into an
+                // ellipsoid
+                if let Some(scale) = self.attributes.remove("scale") {
+                    let scale = scale.as_vector()?;
+                    let centre = sphere.centre.vector();
+                    let transform = Transform::from_translation(centre)
+                        * Transform::from_matrix([
+                            [scale.x, 0.0, 0.0, 0.0],
+                            [0.0, scale.y, 0.0, 0.0],
+                            [0.0, 0.0, scale.z, 0.0],
+                            [0.0, 0.0, 0.0, 1.0],
+                        ])
+                        * Transform::from_translation(-centre);
+                    sphere.apply_transform(&transform);
+                }
+                sphere
+            }
+            "Cuboid" => Cuboid::new(
+                self.get_attr("corner")?.as_vertex()?,
+                self.get_attr("size")?.as_vector()?,
+                self.get_attr("material")?.into_material()?,
+            ),
+            "Mandelbulb" | "Julia" | "Menger" => {
+                let kind = match self.class.as_str() {
+                    "Mandelbulb" => FractalKind::Mandelbulb {
+                        power: self
+                            .get_attr_or("power", AttributeValue::Float(8.0))
+                            .as_float()?,
+                        iterations: self.take_iterations(8)?,
+                    },
+                    "Julia" => {
+                        let c =
+                            self.get_attr_or("c", AttributeValue::Rectangle([-0.2, 0.6, 0.2, 0.2]));
+                        // any four numbers
+                        let AttributeValue::Rectangle(c) = c.value else {
+                            bail!(c.line_number, "c must be four numbers");
+                        };
+                        FractalKind::Julia {
+                            c,
+                            iterations: self.take_iterations(10)?,
+                        }
+                    }
+                    _ => FractalKind::Menger {
+                        iterations: self.take_iterations(4)?,
+                    },
+                };
+                let detail = self.get_attr_or("detail", AttributeValue::Float(0.001));
+                let detail_value = detail.as_float()?;
+                if detail_value <= 0.0 {
+                    bail!(detail.line_number, "detail must be positive");
+                }
+                Fractal::new(
+                    kind,
+                    self.get_attr_or("centre", AttributeValue::Float(0.0))
+                        .as_vector()?,
+                    self.get_attr_or("size", AttributeValue::Float(1.0))
+                        .as_float()?,
+                    detail_value,
+                    self.get_attr_or("colour", AttributeValue::Float(1.0))
+                        .as_colour()?,
+                    self.get_attr("material")?.into_material()?,
+                )
+            }
+            "Csg" => {
+                let mode = self.get_attr("mode")?;
+                let mode_name = mode.as_word()?;
+                let mode = CsgMode::from_name(&mode_name)
+                    .ok_or_else(|| err!(mode.line_number, "Unknown CSG mode: {}", mode_name))?;
+                Csg::new(
+                    mode,
+                    names.object(&self.get_attr("left")?)?,
+                    names.object(&self.get_attr("right")?)?,
+                )
+            }
+            // a copy of a named object, moved somewhere else
+            "Instance" => {
+                let mut object = names.object(&self.get_attr("of")?)?;
+                // one number, or one for each axis
+                if let Some(scale) = self.attributes.remove("scale") {
+                    let scale = scale.as_vector()?;
+                    object.apply_transform(&Transform::from_matrix([
+                        [scale.x, 0.0, 0.0, 0.0],
+                        [0.0, scale.y, 0.0, 0.0],
+                        [0.0, 0.0, scale.z, 0.0],
+                        [0.0, 0.0, 0.0, 1.0],
+                    ]));
+                }
+                // degrees around x, then y, then z
+                if let Some(radians) = self.take_rotation()? {
+                    object.apply_transform(&Transform::from_rotation(radians));
+                }
                 if let Some(translate) = self.attributes.remove("translate") {
                     let translate = translate.as_vector()?;
-                    transform[3][0] = translate.x;
-                    transform[3][1] = translate.y;
-                    transform[3][2] = translate.z;
+                    object.apply_transform(&Transform::from_translation(translate));
+                }
+                object
+            }
+            "Mirror" => Mirror::new(self.take_quad("", None)?),
+            "Portal" => {
+                // the exit defaults to the same size and orientation as the
+                // entrance, so only its centre is needed
+                let entrance = self.take_quad("", None)?;
+                let exit = self.take_quad("exit_", Some(&entrance))?;
+                Portal::new(entrance, exit)
+            }
+            "Quadratic" => {
+                let mut quadratic = Quadratic::new(
+                    (
+                        self.get_attr_or("a", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("b", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("c", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("d", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("e", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("f", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("g", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("h", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("i", AttributeValue::Float(0.0))
+                            .as_float()?,
+                        self.get_attr_or("j", AttributeValue::Float(0.0))
+                            .as_float()?,
+                    ),
+                    self.get_attr("material")?.into_material()?,
+                );
+                if let Ok(transform) = self.get_attr("translate") {
+                    let transform = transform.as_vector()?;
+                    let transform = Transform::from_translation(transform);
+                    quadratic.apply_transform(&transform);
+                }
+                quadratic
+            }
+            "Bezier" => {
+                let bpt = self.get_attr("bpt")?;
+                let bpt_path = PathBuf::from("assets").join("models").join(bpt.as_word()?);
+                let material = self.get_attr("material")?.into_material()?;
+                let up_axis = match self.attributes.remove("up_axis") {
+                    Some(up_axis) => up_axis.as_up_axis()?,
+                    None => self.axis,
+                };
+                let mut surface = BezierSurface::from_bpt_file(bpt_path, material)
+                    .map_err(|e| err!(bpt.line_number, "{}", e))?;
+                surface.apply_transform(&up_axis.to_y_up());
+                surface
+            }
+            "PointCloud" => {
+                let points = self.get_attr("points")?;
+                let points_path = PathBuf::from("assets")
+                    .join("models")
+                    .join(points.as_word()?);
+                let radius = self.get_attr("radius")?;
+                let radius_value = radius.as_float()?;
+                if radius_value <= 0.0 {
+                    bail!(radius.line_number, "radius must be positive");
+                }
+                let material = self.get_attr("material")?.into_material()?;
+                let up_axis = match self.attributes.remove("up_axis") {
+                    Some(up_axis) => up_axis.as_up_axis()?,
+                    None => self.axis,
+                };
+                let mut cloud = PointCloud::from_file(points_path, radius_value, material)
+                    .map_err(|e| err!(points.line_number, "{}", e))?;
+                cloud.apply_transform(&up_axis.to_y_up());
+                cloud
+            }
+            "Curve" => {
+                // a Catmull-Rom curve through point1, point2, ...
+                let mut points = vec![
+                    self.get_attr("point1")?.as_vector()?,
+                    self.get_attr("point2")?.as_vector()?,
+                ];
+                loop {
+                    let key = format!("point{}", points.len() + 1);
+                    let Some(point) = self.attributes.remove(&key) else {
+                        break;
+                    };
+                    points.push(point.as_vector()?);
+                }
+                let radius = self.take_strand_radius()?;
+                Curves::new(
+                    Curve::catmull_rom(&points, radius),
+                    self.get_attr("material")?.into_material()?,
+                )
+            }
+            "Fur" => {
+                let obj = self.get_attr("obj")?;
+                let obj_path = PathBuf::from("assets").join("models").join(obj.as_word()?);
+                let up_axis = match self.attributes.remove("up_axis") {
+                    Some(up_axis) => up_axis.as_up_axis()?,
+                    None => self.axis,
+                };
+                let hairs = self.get_attr("hairs")?;
+                let hairs_value = hairs.as_float()?;
+                if hairs_value < 1.0 || hairs_value.fract() != 0.0 {
+                    bail!(
+                        hairs.line_number,
+                        "hairs must be a whole number of at least 1"
+                    );
+                }
+                let length = self.get_attr("length")?;
+                let length_value = length.as_float()?;
+                if length_value <= 0.0 {
+                    bail!(length.line_number, "length must be positive");
                 }
+                let radius = self.take_strand_radius()?;
+                let gravity = self
+                    .get_attr_or("gravity", AttributeValue::Float(0.0))
+                    .as_float()?;
+                let randomness = self
+                    .get_attr_or("randomness", AttributeValue::Float(0.2))
+                    .as_float()?;
+                let seed = self.get_attr_or("seed", AttributeValue::Float(0.0));
+                let seed_value = seed.as_float()?;
+                if seed_value < 0.0 || seed_value.fract() != 0.0 {
+                    bail!(seed.line_number, "seed must be a whole number");
+                }
+                let material = self.get_attr("material")?.into_material()?;
 
-                if let Some(scale) = self.attributes.remove("scale") {
-                    let scale = scale.as_float()?;
-                    transform[0][0] = scale;
-                    transform[1][1] = scale;
-                    transform[2][2] = scale;
+                // the fur only, the skin is a Model of its own
+                let mut mesh = PolyMesh::from_obj_file(obj_path, material.clone(), true)
+                    .map_err(|e| err!(obj.line_number, "{}", e))?;
+                mesh.apply_transform(&up_axis.to_y_up());
+                let roots = mesh.sample_surface(hairs_value as usize, seed_value as u64);
+                let curves = grow_fur(
+                    &roots,
+                    length_value,
+                    gravity,
+                    randomness,
+                    radius,
+                    seed_value as u64,
+                );
+                // lengths are in the model's units, like its size
+                let mut fur = Curves::new(curves, material);
+                fur.apply_transform(&self.take_model_transform()?);
+                fur
+            }
+            "Model" => {
+                let obj = self.get_attr("obj")?;
+                let obj_path = PathBuf::from("assets").join("models").join(obj.as_word()?);
+                let material = self.get_attr("material")?.into_material()?;
+                let smooth = self
+                    .get_attr_or("smooth", AttributeValue::Float(0.0))
+                    .as_float()?
+                    != 0.0;
+                let up_axis = match self.attributes.remove("up_axis") {
+                    Some(up_axis) => up_axis.as_up_axis()?,
+                    None => self.axis,
+                };
+                let subdivide = self.take_subdivide()?;
+                let displacement = self.take_displacement()?;
+                let mut model = PolyMesh::from_obj_file(obj_path, material.clone(), smooth)
+                    .map_err(|e| err!(obj.line_number, "{}", e))?;
+                if subdivide > 0 {
+                    model.subdivide(subdivide);
+                }
+                if let Some((image, scale, subdivisions)) = &displacement {
+                    model.displace(image, *scale, *subdivisions);
+                }
+                model.apply_transform(&up_axis.to_y_up());
+
+                // deformation blur towards a second OBJ with the same faces,
+                // reached end_time seconds after the shutter opens
+                if let Some(obj_end) = self.attributes.remove("obj_end") {
+                    let end_path = PathBuf::from("assets")
+                        .join("models")
+                        .join(obj_end.as_word()?);
+                    let mut end = PolyMesh::from_obj_file(end_path, material, smooth)
+                        .map_err(|e| err!(obj_end.line_number, "{}", e))?;
+                    if subdivide > 0 {
+                        end.subdivide(subdivide);
+                    }
+                    if let Some((image, scale, subdivisions)) = &displacement {
+                        end.displace(image, *scale, *subdivisions);
+                    }
+                    end.apply_transform(&up_axis.to_y_up());
+                    let end_time = self.get_attr("end_time")?.as_float()?;
+                    if end_time <= 0.0 {
+                        bail!(obj_end.line_number, "end_time must be positive");
+                    }
+                    model
+                        .deform_to(end, end_time)
+                        .map_err(|e| err!(obj_end.line_number, "{}", e))?;
                 }
 
-                model.apply_transform(&transform.transposed());
+                model.apply_transform(&self.take_model_transform()?);
 
                 Box::new(model)
             }
@@ -501,8 +1910,14 @@ impl Paragraph {
 
                 coin
             }
-            _ => bail!(self.start_line, "Invalid object class: {}", self.class),
+            _ => {
+                let Some(factory) = OBJECTS.get(&self.class) else {
+                    return Err(self.invalid_class("object"));
+                };
+                factory.build(&mut SceneAttributes { paragraph: &mut self })?
+            }
         };
+        self.reject_unknown()?;
         Ok(object)
     }
 
@@ -521,38 +1936,196 @@ impl Paragraph {
                 self.get_attr_or("shininess", AttributeValue::Float(100.0))
                     .as_float()?,
             ),
-            "Global" => GlobalMaterial::new(
-                self.get_attr("reflect")?.as_float()?,
-                self.get_attr("refract")?.as_float()?,
-                self.get_attr("ior")?.as_float()?,
-            ),
-            "Monochrome" => Monochrome::new(
+            "Compound" => self.take_compound()?,
+            "Global" => {
+                let roughness = self.get_attr_or("roughness", AttributeValue::Float(0.0));
+                let roughness_value = roughness.as_float()?;
+                if !(0.0..=1.0).contains(&roughness_value) {
+                    bail!(roughness.line_number, "roughness must be between 0 and 1");
+                }
+                GlobalMaterial::new_rough(
+                    self.get_attr("reflect")?.as_float()?,
+                    self.get_attr("refract")?.as_float()?,
+                    self.get_attr("ior")?.as_float()?,
+                    roughness_value,
+                )
+            }
+            "Monochrome" => Monochrome::new_varied(
                 self.get_attr("colour")?.as_colour()?,
                 self.get_attr_or("ambient", AttributeValue::Float(0.1))
                     .as_float()?,
                 self.get_attr("shininess")?.as_float()?,
+                self.get_attr_or("hue_variation", AttributeValue::Float(0.0))
+                    .as_float()?,
+                self.get_attr_or("seed", AttributeValue::Float(0.0))
+                    .as_float()? as u32,
             ),
             // "Texture" => Texture::import(name, scale, ambient_strength, shininess)
-            "Texture" => Texture::import(
-                self.get_attr("name")?.as_word()?,
-                self.get_attr("scale")?.as_float()?,
-                self.get_attr("ambient")?.as_float()?,
+            "Texture" => {
+                let name = self.get_attr("name")?;
+                // e.g. Linear for a diffuse image that holds data, not colours
+                let colour_space = match self.attributes.remove("colour_space") {
+                    Some(attr) => {
+                        let name = attr.as_word()?;
+                        ColourSpace::from_name(&name).ok_or_else(|| {
+                            err!(attr.line_number, "Unknown colour space: {}", name)
+                        })?
+                    }
+                    None => ColourSpace::Srgb,
+                };
+                Texture::import(
+                    name.as_word()?,
+                    self.get_attr("scale")?.as_float()?,
+                    self.get_attr("ambient")?.as_float()?,
+                    self.get_attr("shininess")?.as_float()?,
+                    colour_space,
+                )
+                .map_err(|e| err!(name.line_number, "Could not load texture: {}", e))?
+            }
+            "TransparentTexture" => {
+                let name = self.get_attr("name")?;
+                CompoundMaterial::new_textured(
+                    name.as_word()?,
+                    self.get_attr("scale")?.as_float()?,
+                    self.get_attr("transparency")?.as_float()?,
+                )
+                .map_err(|e| err!(name.line_number, "Could not load texture: {}", e))?
+            }
+            "Weathered" => Weathered::new(
+                self.get_attr("colour")?.as_colour()?,
+                self.get_attr_or("wear_colour", AttributeValue::Float(1.0))
+                    .as_colour()?,
+                self.get_attr_or("dirt_colour", AttributeValue::Float(0.0))
+                    .as_colour()?,
+                self.get_attr_or("wear", AttributeValue::Float(2.0))
+                    .as_float()?,
+                self.get_attr_or("dirt", AttributeValue::Float(2.0))
+                    .as_float()?,
+                self.get_attr_or("ambient", AttributeValue::Float(0.1))
+                    .as_float()?,
                 self.get_attr("shininess")?.as_float()?,
             ),
-            "TransparentTexture" => CompoundMaterial::new_textured(
-                self.get_attr("name")?.as_word()?,
-                self.get_attr("scale")?.as_float()?,
-                self.get_attr("transparency")?.as_float()?,
-            ),
             "FalseColour" => Arc::new(FalseColour::new()),
-            _ => bail!(self.start_line, "Invalid material class: {}", self.class),
-        };
-        Ok(material)
-    }
+            "Wireframe" => {
+                let width = self.get_attr_or("width", AttributeValue::Float(0.02));
+                let width_value = width.as_float()?;
+                if !(0.0..=1.0).contains(&width_value) {
+                    bail!(width.line_number, "width must be between 0 and 1");
+                }
+                Arc::new(Wireframe::new(
+                    self.get_attr_or("colour", AttributeValue::Float(0.0))
+                        .as_colour()?,
+                    self.get_attr_or("fill", AttributeValue::Float(1.0))
+                        .as_colour()?,
+                    width_value,
+                ))
+            }
+            "UvChecker" => {
+                let squares = self.get_attr_or("squares", AttributeValue::Float(8.0));
+                let squares_value = squares.as_float()?;
+                if squares_value <= 0.0 {
+                    bail!(squares.line_number, "squares must be positive");
+                }
+                Arc::new(UvChecker::new(squares_value))
+            }
+            "DepthGradient" => {
+                let near = self
+                    .get_attr_or("near", AttributeValue::Float(0.0))
+                    .as_float()?;
+                let far = self.get_attr("far")?;
+                let far_value = far.as_float()?;
+                if far_value <= near {
+                    bail!(far.line_number, "far must be further than near");
+                }
+                Arc::new(DepthGradient::new(near, far_value))
+            }
+            "AmbientOcclusion" => Arc::new(Occlusion::new(
+                self.take_ambient_occlusion("radius", "samples")?,
+            )),
+            "Hair" => Hair::new(
+                self.get_attr("colour")?.as_colour()?,
+                self.get_attr_or("ambient", AttributeValue::Float(0.1))
+                    .as_float()?,
+                self.get_attr("shininess")?.as_float()?,
+                self.get_attr_or("shift", AttributeValue::Float(0.1))
+                    .as_float()?,
+            ),
+            "VertexColour" => VertexColour::new(
+                self.get_attr_or("colour", AttributeValue::Float(1.0))
+                    .as_colour()?,
+                self.get_attr_or("ambient", AttributeValue::Float(0.1))
+                    .as_float()?,
+                self.get_attr("shininess")?.as_float()?,
+            ),
+            _ => {
+                let Some(factory) = MATERIALS.get(&self.class) else {
+                    return Err(self.invalid_class("material"));
+                };
+                factory.build(&mut SceneAttributes { paragraph: &mut self })?
+            }
+        };
+        self.reject_unknown()?;
+        Ok(material)
+    }
+
+    // material Compound
+    //     blend Fresnel        (optional, Weighted by default)
+    //     ior 1.5              (Fresnel only)
+    //     <label> <material class>
+    //         weight <weight>  (optional, an equal share by default)
+    //         ...
+    //     coat <material class>
+    //         ...
+    fn take_compound(&mut self) -> Result<Arc<CompoundMaterial>> {
+        let mut compound = CompoundMaterial::new();
+        let mut fresnel = false;
+        if let Some(blend) = self.attributes.remove("blend") {
+            match blend.as_word()?.as_str() {
+                "Weighted" => {}
+                "Fresnel" => {
+                    let ior = self
+                        .get_attr_or("ior", AttributeValue::Float(1.5))
+                        .as_float()?;
+                    compound.set_blend(Blend::Fresnel { ior });
+                    fresnel = true;
+                }
+                name => bail!(blend.line_number, "Unknown blend: {}", name),
+            }
+        }
+
+        // every sub-paragraph is a layer
+        let layers = self.take_sub_paragraphs();
+        if layers.is_empty() {
+            bail!(self.start_line, "Compound needs at least one layer");
+        }
+        if fresnel && !layers.iter().any(|layer| layer.key == "coat") {
+            bail!(self.start_line, "Fresnel blend needs a coat layer");
+        }
+
+        for layer in layers {
+            let AttributeValue::SubParagraph(mut paragraph) = layer.value else {
+                unreachable!();
+            };
+            let weight = match paragraph.attributes.remove("weight") {
+                Some(weight) => {
+                    let value = weight.as_float()?;
+                    if value < 0.0 {
+                        bail!(weight.line_number, "weight can't be negative");
+                    }
+                    Some(value)
+                }
+                None => None,
+            };
+            let material = paragraph.into_material()?;
+            compound.add_layer(material, weight, layer.key == "coat");
+        }
+
+        Ok(Arc::new(compound))
+    }
 
     fn into_camera(mut self) -> Result<Box<FullCamera>> {
         if self.class != "Camera" {
-            bail!(self.start_line, "Invalid camera class: {}", self.class);
+            return Err(self.invalid_class("camera"));
         }
 
         let res = self.get_attr_or("res", AttributeValue::Float(1024.0)).value;
@@ -581,10 +2154,433 @@ impl Paragraph {
             )
             .as_vector()?;
 
-        let camera = FullCamera::new(width as u32, height as u32, fov, position, lookat, up);
+        let mut camera = FullCamera::new(width as u32, height as u32, fov, position, lookat, up);
+
+        let samples = self
+            .get_attr_or("samples", AttributeValue::Float(1.0))
+            .as_float()?;
+        if samples < 1.0 {
+            bail!(self.start_line, "Camera samples must be at least 1");
+        }
+        camera.samples = samples as u32;
+        camera.progressive = self
+            .get_attr_or("progressive", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        camera.shutter = self
+            .get_attr_or("shutter", AttributeValue::Float(0.0))
+            .as_float()?;
+        if camera.shutter < 0.0 {
+            bail!(self.start_line, "Camera shutter cannot be negative");
+        }
+        camera.auto_frame = self
+            .get_attr_or("auto_frame", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        camera.show_gizmos = self
+            .get_attr_or("gizmos", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        camera.write_aovs = self
+            .get_attr_or("aovs", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        camera.write_lighting_passes = self
+            .get_attr_or("lighting_passes", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        let denoise_passes = self
+            .get_attr_or("denoise", AttributeValue::Float(0.0))
+            .as_float()?;
+        if denoise_passes < 0.0 {
+            bail!(self.start_line, "Camera denoise passes cannot be negative");
+        }
+        camera.denoise_passes = denoise_passes as u32;
+        camera.outlier_rejection = self
+            .get_attr_or("reject_outliers", AttributeValue::Float(0.0))
+            .as_float()?;
+        if camera.outlier_rejection < 0.0 {
+            bail!(self.start_line, "Camera reject_outliers cannot be negative");
+        }
+        camera.transient = self.take_transient_bins()?;
+        camera.depth_of_field = self.take_depth_of_field()?;
+        camera.bloom = self.take_bloom()?;
+
+        if let Some(depth_resolve) = self.attributes.remove("depth_resolve") {
+            let name = depth_resolve.as_word()?;
+            camera.depth_resolve = ResolvePolicy::from_name(&name).ok_or_else(|| {
+                err!(
+                    depth_resolve.line_number,
+                    "Unknown resolve policy: {}",
+                    name
+                )
+            })?;
+        }
+
+        if let Some(sampler) = self.attributes.remove("sampler") {
+            let name = sampler.as_word()?;
+            camera.sampler = Sampler::from_name(&name)
+                .ok_or_else(|| err!(sampler.line_number, "Unknown sampler: {}", name))?;
+        }
+
+        if let Some(colour_space) = self.attributes.remove("colour_space") {
+            let name = colour_space.as_word()?;
+            camera.colour_space = ColourSpace::from_name(&name)
+                .ok_or_else(|| err!(colour_space.line_number, "Unknown colour space: {}", name))?;
+        }
+
+        if let Some(miss_depth) = self.attributes.remove("miss_depth") {
+            let name = miss_depth.as_word()?;
+            camera.miss_depth = MissDepth::from_name(&name)
+                .ok_or_else(|| err!(miss_depth.line_number, "Unknown miss depth: {}", name))?;
+        }
+        self.take_depth_output(&mut camera.depth_output)?;
+        self.take_clipping(&mut camera)?;
+
+        self.reject_unknown()?;
         Ok(Box::new(camera))
     }
 
+    // the camera's clip_near, clip_far and region
+    fn take_clipping(&mut self, camera: &mut FullCamera) -> Result<()> {
+        let near = self.get_attr_or("clip_near", AttributeValue::Float(0.0));
+        camera.near = near.as_float()?;
+        if camera.near < 0.0 {
+            bail!(near.line_number, "clip_near cannot be negative");
+        }
+        if let Some(far) = self.attributes.remove("clip_far") {
+            camera.far = far.as_float()?;
+            if camera.far <= camera.near {
+                bail!(far.line_number, "clip_far must be further than clip_near");
+            }
+        }
+
+        if let Some(region) = self.attributes.remove("region") {
+            let AttributeValue::Rectangle([x0, y0, x1, y1]) = region.value else {
+                bail!(region.line_number, "region must be x0 y0 x1 y1");
+            };
+            let inside = |start: f32, end: f32| 0.0 <= start && start < end && end <= 1.0;
+            if !inside(x0, x1) || !inside(y0, y1) {
+                bail!(
+                    region.line_number,
+                    "region must be within 0 to 1, with x0 < x1 and y0 < y1"
+                );
+            }
+            camera.region = Some([x0, y0, x1, y1]);
+        }
+        Ok(())
+    }
+
+    // the camera's depth_near, depth_far, depth_inverse and depth_format
+    fn take_depth_output(&mut self, output: &mut DepthOutput) -> Result<()> {
+        for (key, value) in [
+            ("depth_near", &mut output.near),
+            ("depth_far", &mut output.far),
+        ] {
+            if let Some(attribute) = self.attributes.remove(key) {
+                let distance = attribute.as_float()?;
+                if distance < 0.0 {
+                    bail!(attribute.line_number, "{} cannot be negative", key);
+                }
+                *value = Some(distance);
+            }
+        }
+        if let (Some(near), Some(far)) = (output.near, output.far) {
+            if near >= far {
+                bail!(self.start_line, "depth_near must be closer than depth_far");
+            }
+        }
+
+        output.inverse = self
+            .get_attr_or("depth_inverse", AttributeValue::Float(0.0))
+            .as_float()?
+            != 0.0;
+        // 1 / depth blows up at the camera
+        if output.inverse && output.near == Some(0.0) {
+            bail!(
+                self.start_line,
+                "depth_near must be above 0 for inverse depth"
+            );
+        }
+
+        if let Some(format) = self.attributes.remove("depth_format") {
+            let name = format.as_word()?;
+            output.format = DepthFormat::from_name(&name)
+                .ok_or_else(|| err!(format.line_number, "Unknown depth format: {}", name))?;
+        }
+        Ok(())
+    }
+
+    // how many levels of loop subdivision to smooth a Model with. each one
+    // makes 4 times as many triangles.
+    fn take_subdivide(&mut self) -> Result<u32> {
+        let Some(attribute) = self.attributes.remove("subdivide") else {
+            return Ok(0);
+        };
+        let value = attribute.as_float()?;
+        if !(0.0..=5.0).contains(&value) || value.fract() != 0.0 {
+            bail!(attribute.line_number, "subdivide must be a whole number from 0 to 5");
+        }
+        Ok(value as u32)
+    }
+
+    // a Model's displacement map (the displacement.jpg in a texture folder),
+    // how far white pushes the surface out, and how many times to split the
+    // triangles first so there are enough vertices to push
+    fn take_displacement(&mut self) -> Result<Option<(Image, f32, u32)>> {
+        let Some(displacement) = self.attributes.remove("displacement") else {
+            return Ok(None);
+        };
+        let image = Image::import_displacement(&displacement.as_word()?).map_err(|e| {
+            err!(displacement.line_number, "Could not load displacement map: {}", e)
+        })?;
+        let scale = self
+            .get_attr_or("displacement_scale", AttributeValue::Float(0.1))
+            .as_float()?;
+
+        let subdivisions = match self.attributes.remove("subdivisions") {
+            Some(attribute) => {
+                let value = attribute.as_float()?;
+                if !(0.0..=6.0).contains(&value) || value.fract() != 0.0 {
+                    bail!(attribute.line_number, "subdivisions must be a whole number from 0 to 6");
+                }
+                value as u32
+            }
+            None => 0,
+        };
+
+        Ok(Some((image, scale, subdivisions)))
+    }
+
+    // epsilon, max_indirect, and max_depth and roulette_depth (both at
+    // least 1)
+    fn take_render_settings(&mut self) -> Result<RenderSettings> {
+        let mut settings = RenderSettings::default();
+        if let Some(epsilon) = self.attributes.remove("epsilon") {
+            settings.epsilon = epsilon.as_float()?;
+            if settings.epsilon <= 0.0 {
+                bail!(epsilon.line_number, "epsilon must be positive");
+            }
+        }
+        if let Some(max_indirect) = self.attributes.remove("max_indirect") {
+            let max = max_indirect.as_float()?;
+            if max <= 0.0 {
+                bail!(max_indirect.line_number, "max_indirect must be positive");
+            }
+            settings.max_indirect = Some(max);
+        }
+
+        let path_depth = &mut settings.path_depth;
+        for (key, depth) in [
+            ("max_depth", &mut path_depth.max_depth),
+            ("roulette_depth", &mut path_depth.roulette_depth),
+        ] {
+            if let Some(attribute) = self.attributes.remove(key) {
+                let value = attribute.as_float()?;
+                if !(1.0..=255.0).contains(&value) || value.fract() != 0.0 {
+                    bail!(attribute.line_number, "{} must be a whole number from 1 to 255", key);
+                }
+                *depth = value as u8;
+            }
+        }
+        Ok(settings)
+    }
+
+    // a transient Transient sub-paragraph, the time-of-flight bins to render
+    fn take_transient_bins(&mut self) -> Result<Option<TransientBins>> {
+        let Some(attribute) = self.attributes.remove("transient") else {
+            return Ok(None);
+        };
+        let AttributeValue::SubParagraph(mut transient) = attribute.value else {
+            bail!(attribute.line_number, "transient must be a sub-paragraph");
+        };
+        if transient.class != "Transient" {
+            return Err(transient.invalid_class("transient"));
+        }
+
+        let bins = transient.get_attr("bins")?.as_float()?;
+        let bin_length = transient.get_attr("bin_length")?.as_float()?;
+        if bins < 1.0 || bin_length <= 0.0 {
+            bail!(
+                transient.start_line,
+                "Transient needs at least 1 bin and a positive bin_length"
+            );
+        }
+        let start = transient
+            .get_attr_or("start", AttributeValue::Float(0.0))
+            .as_float()?;
+        transient.reject_unknown()?;
+
+        Ok(Some(TransientBins {
+            start,
+            bin_length,
+            bins: bins as u32,
+        }))
+    }
+
+    // depth_of_field DepthOfField
+    //     focus <distance>, the middle of the image's depth if not given
+    //     blur <fraction of the image width>
+    fn take_depth_of_field(&mut self) -> Result<Option<DepthOfField>> {
+        let Some(attribute) = self.attributes.remove("depth_of_field") else {
+            return Ok(None);
+        };
+        let AttributeValue::SubParagraph(mut depth_of_field) = attribute.value else {
+            bail!(attribute.line_number, "depth_of_field must be a sub-paragraph");
+        };
+        if depth_of_field.class != "DepthOfField" {
+            return Err(depth_of_field.invalid_class("depth_of_field"));
+        }
+
+        let focus = match depth_of_field.attributes.remove("focus") {
+            Some(focus) => Some(focus.as_float()?),
+            None => None,
+        };
+        let blur = depth_of_field
+            .get_attr_or("blur", AttributeValue::Float(0.01))
+            .as_float()?;
+        if focus.is_some_and(|focus| focus <= 0.0) || blur < 0.0 {
+            bail!(
+                depth_of_field.start_line,
+                "DepthOfField needs a positive focus, and blur can't be negative"
+            );
+        }
+        depth_of_field.reject_unknown()?;
+
+        Ok(Some(DepthOfField { focus, blur }))
+    }
+
+    // bloom Bloom
+    //     threshold <brightness>
+    //     radius <fraction of the image width>
+    //     strength <amount>
+    fn take_bloom(&mut self) -> Result<Option<Bloom>> {
+        let Some(attribute) = self.attributes.remove("bloom") else {
+            return Ok(None);
+        };
+        let AttributeValue::SubParagraph(mut bloom) = attribute.value else {
+            bail!(attribute.line_number, "bloom must be a sub-paragraph");
+        };
+        if bloom.class != "Bloom" {
+            return Err(bloom.invalid_class("bloom"));
+        }
+
+        let threshold = bloom
+            .get_attr_or("threshold", AttributeValue::Float(1.0))
+            .as_float()?;
+        let radius = bloom
+            .get_attr_or("radius", AttributeValue::Float(0.01))
+            .as_float()?;
+        let strength = bloom
+            .get_attr_or("strength", AttributeValue::Float(0.5))
+            .as_float()?;
+        if threshold < 0.0 || radius <= 0.0 || strength < 0.0 {
+            bail!(
+                bloom.start_line,
+                "Bloom needs a positive radius, and threshold and strength can't be negative"
+            );
+        }
+        bloom.reject_unknown()?;
+
+        Ok(Some(Bloom {
+            threshold,
+            radius,
+            strength,
+        }))
+    }
+
+    // a bake PhotonSlice sub-paragraph, the rectangle to bake a photon heat
+    // map over
+    fn take_photon_slice(&mut self) -> Result<Option<PhotonSlice>> {
+        let Some(attribute) = self.attributes.remove("bake") else {
+            return Ok(None);
+        };
+        let AttributeValue::SubParagraph(mut slice) = attribute.value else {
+            bail!(attribute.line_number, "bake must be a sub-paragraph");
+        };
+        if slice.class != "PhotonSlice" {
+            return Err(slice.invalid_class("bake"));
+        }
+
+        let quad = slice.take_quad("", None)?;
+        let resolution = slice
+            .get_attr_or("res", AttributeValue::Float(256.0))
+            .as_float()?;
+        if resolution < 1.0 {
+            bail!(slice.start_line, "Bake res must be at least 1");
+        }
+        slice.reject_unknown()?;
+
+        Ok(Some(PhotonSlice {
+            quad,
+            resolution: resolution as u32,
+        }))
+    }
+
+    // a rectangle from <prefix>centre, normal, up, width and height.
+    // missing attributes are taken from like if given.
+    fn take_quad(&mut self, prefix: &str, like: Option<&Quad>) -> Result<Quad> {
+        let centre = self.get_attr(&format!("{prefix}centre"))?.as_vertex()?;
+        let (normal, up, width, height) = match like {
+            Some(like) => (
+                self.get_attr_or(
+                    &format!("{prefix}normal"),
+                    AttributeValue::Vector(like.normal),
+                )
+                .as_vector()?,
+                self.get_attr_or(&format!("{prefix}up"), AttributeValue::Vector(like.up))
+                    .as_vector()?,
+                self.get_attr_or(&format!("{prefix}width"), AttributeValue::Float(like.width))
+                    .as_float()?,
+                self.get_attr_or(
+                    &format!("{prefix}height"),
+                    AttributeValue::Float(like.height),
+                )
+                .as_float()?,
+            ),
+            None => {
+                let normal = self.get_attr(&format!("{prefix}normal"))?.as_vector()?;
+                // y is up, unless the quad is facing up or down
+                let default_up = if normal.x == 0.0 && normal.z == 0.0 {
+                    Vector::new(0.0, 0.0, 1.0)
+                } else {
+                    Vector::new(0.0, 1.0, 0.0)
+                };
+                let up = self
+                    .get_attr_or(&format!("{prefix}up"), AttributeValue::Vector(default_up))
+                    .as_vector()?;
+                let width = self.get_attr(&format!("{prefix}width"))?.as_float()?;
+                let height = self
+                    .get_attr_or(&format!("{prefix}height"), AttributeValue::Float(width))
+                    .as_float()?;
+                (normal, up, width, height)
+            }
+        };
+
+        if width <= 0.0 || height <= 0.0 {
+            bail!(
+                self.start_line,
+                "{}width and {}height must be positive",
+                prefix,
+                prefix
+            );
+        }
+        if normal.cross(&up).length() < 0.0001 {
+            bail!(
+                self.start_line,
+                "{}up cannot be parallel to {}normal",
+                prefix,
+                prefix
+            );
+        }
+        Ok(Quad::new(centre, normal, up, width, height))
+    }
+
+    // e.g. "Invalid object class: Spehre", pointing at the class
+    fn invalid_class(&self, kind: &str) -> ParseError {
+        err!(self.start_line, "Invalid {} class: {}", kind, self.class).at(&self.class)
+    }
+
     fn get_attr(&mut self, key: &str) -> Result<Attribute> {
         self.attributes
             .remove(key)
@@ -596,23 +2592,231 @@ impl Paragraph {
             key: key.to_string(),
             value: default,
             line_number: 0,
+            overridden: false,
         })
     }
+
+    // call once everything has been taken. whatever is left over wasn't
+    // used, which is most likely a typo, e.g. colur instead of colour.
+    fn reject_unknown(&self) -> Result<()> {
+        let unknown = self
+            .attributes
+            .values()
+            // "_ _" fills in sub-paragraphs that need no attributes
+            .filter(|attribute| !attribute.overridden && attribute.key != "_")
+            .min_by_key(|attribute| attribute.line_number);
+        if let Some(attribute) = unknown {
+            bail!(
+                attribute.line_number,
+                "Unknown attribute for {} {}: {}",
+                self.kind,
+                self.class,
+                attribute.key
+            );
+        }
+        Ok(())
+    }
 }
 
+// the named object paragraphs, and the names being built right now to catch
+// objects that (eventually) reference themselves
+struct Names<'a> {
+    paragraphs: &'a HashMap<String, Paragraph>,
+    resolving: Vec<String>,
+}
+
+impl Names<'_> {
+    // build a fresh copy of the object an attribute names
+    fn object(&self, attribute: &Attribute) -> Result<Box<dyn Object>> {
+        let name = attribute.as_word()?;
+        let paragraph = self
+            .paragraphs
+            .get(&name)
+            .ok_or_else(|| err!(attribute.line_number, "Unknown object name: {}", name))?;
+        if self.resolving.contains(&name) {
+            bail!(attribute.line_number, "Object {} references itself", name);
+        }
+
+        let mut resolving = self.resolving.clone();
+        resolving.push(name);
+        let names = Names {
+            paragraphs: self.paragraphs,
+            resolving,
+        };
+        paragraph.clone().into_object(&names)
+    }
+}
+
+// top level material paragraphs with an id, e.g. `id bricks`. anything
+// that takes a material can use one by its id, either as is (`material
+// bricks`) or as the class of a material sub-paragraph whose attributes
+// override the named one's (e.g. the same texture, but shinier). named
+// materials can build on each other the same way.
+struct NamedMaterials {
+    paragraphs: HashMap<String, Paragraph>,
+}
+
+impl NamedMaterials {
+    // removes the named materials from paragraphs
+    fn take(paragraphs: &mut Vec<Paragraph>, errors: &mut Vec<ParseError>) -> Self {
+        let mut named = HashMap::new();
+        let mut rest = Vec::new();
+        for mut paragraph in paragraphs.drain(..) {
+            if paragraph.kind != "material" {
+                rest.push(paragraph);
+                continue;
+            }
+            let Some(id) = paragraph.attributes.remove("id") else {
+                // reported when it's built
+                rest.push(paragraph);
+                continue;
+            };
+            match Self::check_id(&id, &named) {
+                Ok(id) => {
+                    named.insert(id, paragraph);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        *paragraphs = rest;
+        Self { paragraphs: named }
+    }
+
+    fn check_id(id: &Attribute, named: &HashMap<String, Paragraph>) -> Result<String> {
+        let word = id.as_word()?;
+        // classes are capitalised, so they can't be mistaken for each other
+        if !word.starts_with(|c: char| c.is_lowercase()) {
+            bail!(
+                id.line_number,
+                "Material ids must start with a lowercase letter: {}",
+                word
+            );
+        }
+        if named.contains_key(&word) {
+            bail!(id.line_number, "Duplicate material id: {}", word);
+        }
+        Ok(word)
+    }
+
+    // paragraph with every named material in it (and its sub-paragraphs)
+    // merged in. resolving holds the ids being merged right now, to catch
+    // materials that (eventually) build on themselves.
+    fn resolve(&self, mut paragraph: Paragraph, resolving: &mut Vec<String>) -> Result<Paragraph> {
+        if let Some(base) = self.paragraphs.get(&paragraph.class) {
+            let id = paragraph.class.clone();
+            if resolving.contains(&id) {
+                bail!(paragraph.start_line, "Material {} builds on itself", id);
+            }
+            resolving.push(id);
+            let base = self.resolve(base.clone(), resolving)?;
+            resolving.pop();
+
+            let mut attributes = base.attributes;
+            attributes.extend(paragraph.attributes);
+            paragraph = Paragraph {
+                class: base.class,
+                attributes,
+                ..paragraph
+            };
+        }
+
+        let axis = paragraph.axis;
+        // every attribute of a compound material is a layer
+        let takes_materials = |key: &str| key == "material" || paragraph.class == "Compound";
+        for attribute in paragraph.attributes.values_mut() {
+            let value = std::mem::replace(&mut attribute.value, AttributeValue::Float(0.0));
+            attribute.value = match value {
+                AttributeValue::SubParagraph(sub) => {
+                    AttributeValue::SubParagraph(Box::new(self.resolve(*sub, resolving)?))
+                }
+                // used as is
+                AttributeValue::Word(id)
+                    if takes_materials(&attribute.key) && self.paragraphs.contains_key(&id) =>
+                {
+                    let material = Paragraph {
+                        kind: attribute.key.clone(),
+                        class: id,
+                        attributes: HashMap::new(),
+                        start_line: attribute.line_number,
+                        axis,
+                        offset: None,
+                    };
+                    AttributeValue::SubParagraph(Box::new(self.resolve(material, resolving)?))
+                }
+                value => value,
+            };
+        }
+        Ok(paragraph)
+    }
+}
+
+#[derive(Clone)]
 struct Attribute {
     key: String,
     value: AttributeValue,
     line_number: LineNumber,
+    // set by a SceneOverride, which applies to every paragraph of a kind
+    // whether it uses the key or not
+    overridden: bool,
 }
 
+#[derive(Clone)]
 enum AttributeValue {
     Word(String),
     Float(f32),
     Vector(Vector),
+    Rectangle([f32; 4]), // x0 y0 x1 y1
+    Colour(Colour),      // sRGB encoded, from "rgb 255 136 0" or "hsv 32 1 1"
     SubParagraph(Box<Paragraph>),
 }
 
+impl AttributeValue {
+    // parse the words after an attribute key, except for sub-paragraphs
+    fn parse(words: &[&str], line_number: LineNumber) -> Result<Self> {
+        Ok(match words {
+            [x, y, z] => {
+                let parse_error = |word: &str| err!(line_number, "Invalid float: {}", word).at(word);
+                let x = x.parse::<f32>().map_err(|_| parse_error(x))?;
+                let y = y.parse::<f32>().map_err(|_| parse_error(y))?;
+                let z = z.parse::<f32>().map_err(|_| parse_error(z))?;
+                AttributeValue::Vector(Vector::new(x, y, z))
+            }
+            // a colour given some other way than 0 to 1 floats
+            [space @ ("rgb" | "hsv"), a, b, c] => {
+                let parse_error = |word: &str| err!(line_number, "Invalid float: {}", word).at(word);
+                let a = a.parse::<f32>().map_err(|_| parse_error(a))?;
+                let b = b.parse::<f32>().map_err(|_| parse_error(b))?;
+                let c = c.parse::<f32>().map_err(|_| parse_error(c))?;
+                let colour = match *space {
+                    "rgb" => Colour::new(a, b, c) / 255.0,
+                    _ => Colour::from_hsv(a, b, c),
+                };
+                AttributeValue::Colour(colour)
+            }
+            [_, _, _, _] => {
+                let mut corners = [0.0; 4];
+                for (corner, word) in corners.iter_mut().zip(words) {
+                    *corner = word
+                        .parse::<f32>()
+                        .map_err(|_| err!(line_number, "Invalid float: {}", word).at(word))?;
+                }
+                AttributeValue::Rectangle(corners)
+            }
+            // either a float or a word. words like "Infinity" and "NaN"
+            // parse as floats, but are meant as words
+            [word] => match word.parse::<f32>() {
+                Ok(f) if f.is_finite() => AttributeValue::Float(f),
+                _ => AttributeValue::Word(word.to_string()),
+            },
+            _ => bail!(
+                line_number,
+                "Invalid word count in attribute value: {}",
+                words.join(" ")
+            ),
+        })
+    }
+}
+
 impl Attribute {
     fn as_word(&self) -> Result<String> {
         Ok(match &self.value {
@@ -625,11 +2829,19 @@ impl Attribute {
         Ok(match &self.value {
             AttributeValue::Vector(v) => Colour::new(v.x, v.y, v.z),
             AttributeValue::Float(f) => Colour::new(*f, *f, *f),
-            AttributeValue::Word(w) => match w.as_str() {
-                "White" => Colour::white(),
-                "Black" => Colour::black(),
-                _ => bail!(self.line_number, "Unknown colour name: {}", w),
-            },
+            // colour pickers give sRGB values, so these come out as picked
+            AttributeValue::Colour(c) => ColourSpace::Srgb.decode(*c),
+            AttributeValue::Word(w) => {
+                let colour = match w.strip_prefix('#') {
+                    Some(_) => Colour::from_hex(w).ok_or_else(|| {
+                        err!(self.line_number, "Invalid hex colour: {}", w).at(w)
+                    })?,
+                    None => colour_names::named(w).ok_or_else(|| {
+                        err!(self.line_number, "Unknown colour name: {}", w).at(w)
+                    })?,
+                };
+                ColourSpace::Srgb.decode(colour)
+            }
             _ => bail!(self.line_number, "Invalid attribute value for colour"),
         })
     }
@@ -654,20 +2866,252 @@ impl Attribute {
         })
     }
 
+    fn as_up_axis(&self) -> Result<UpAxis> {
+        let name = self.as_word()?;
+        UpAxis::from_name(&name)
+            .ok_or_else(|| err!(self.line_number, "Unknown axis: {}", name).at(&name))
+    }
+
     fn into_material(self) -> Result<Arc<dyn Material>> {
-        let AttributeValue::SubParagraph(p) = self.value else {
-            bail!(self.line_number, "Invalid attribute value for material");
-        };
-        p.into_material()
+        match self.value {
+            AttributeValue::SubParagraph(p) => p.into_material(),
+            // named materials have already been swapped in
+            AttributeValue::Word(w) => {
+                Err(err!(self.line_number, "Unknown material id: {}", w).at(&w))
+            }
+            _ => bail!(self.line_number, "Invalid attribute value for material"),
+        }
+    }
+}
+
+// builds environments from other crates for "scene <class>" paragraphs,
+// see register_environment()
+pub trait EnvironmentFactory: Send + Sync {
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Environment>>;
+}
+
+impl<F> EnvironmentFactory for F
+where
+    F: Fn(&mut SceneAttributes) -> Result<Box<dyn Environment>> + Send + Sync,
+{
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Environment>> {
+        self(attributes)
+    }
+}
+
+// the same for "object <class>" paragraphs, see register_object()
+pub trait ObjectFactory: Send + Sync {
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Object>>;
+}
+
+impl<F> ObjectFactory for F
+where
+    F: Fn(&mut SceneAttributes) -> Result<Box<dyn Object>> + Send + Sync,
+{
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Object>> {
+        self(attributes)
+    }
+}
+
+// and for materials, see register_material()
+pub trait MaterialFactory: Send + Sync {
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Arc<dyn Material>>;
+}
+
+impl<F> MaterialFactory for F
+where
+    F: Fn(&mut SceneAttributes) -> Result<Arc<dyn Material>> + Send + Sync,
+{
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Arc<dyn Material>> {
+        self(attributes)
+    }
+}
+
+// and for lights, see register_light()
+pub trait LightFactory: Send + Sync {
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Light>>;
+}
+
+impl<F> LightFactory for F
+where
+    F: Fn(&mut SceneAttributes) -> Result<Box<dyn Light>> + Send + Sync,
+{
+    fn build(&self, attributes: &mut SceneAttributes) -> Result<Box<dyn Light>> {
+        self(attributes)
+    }
+}
+
+// factories by class name
+struct Registry<T: ?Sized>(OnceLock<RwLock<HashMap<String, Arc<T>>>>);
+
+impl<T: ?Sized> Registry<T> {
+    const fn new() -> Self {
+        Self(OnceLock::new())
+    }
+
+    fn insert(&self, class: &str, factory: Arc<T>) {
+        let mut factories = self.0.get_or_init(Default::default).write().unwrap();
+        factories.insert(class.to_string(), factory);
+    }
+
+    fn get(&self, class: &str) -> Option<Arc<T>> {
+        let factories = self.0.get()?.read().unwrap();
+        factories.get(class).cloned()
+    }
+}
+
+static ENVIRONMENTS: Registry<dyn EnvironmentFactory> = Registry::new();
+static OBJECTS: Registry<dyn ObjectFactory> = Registry::new();
+static MATERIALS: Registry<dyn MaterialFactory> = Registry::new();
+static LIGHTS: Registry<dyn LightFactory> = Registry::new();
+
+// let scene files use an environment by its class name, e.g.
+//     register_environment("MyScene", |attributes: &mut SceneAttributes| { ... });
+// the built in Scene and PhotonScene classes can't be replaced, and seed and
+// the thread settings are handled for every class.
+pub fn register_environment(class: &str, factory: impl EnvironmentFactory + 'static) {
+    ENVIRONMENTS.insert(class, Arc::new(factory));
+}
+
+// the same for objects. keyframes, hidden, name and the visibility flags
+// work on every class.
+pub fn register_object(class: &str, factory: impl ObjectFactory + 'static) {
+    OBJECTS.insert(class, Arc::new(factory));
+}
+
+pub fn register_material(class: &str, factory: impl MaterialFactory + 'static) {
+    MATERIALS.insert(class, Arc::new(factory));
+}
+
+pub fn register_light(class: &str, factory: impl LightFactory + 'static) {
+    LIGHTS.insert(class, Arc::new(factory));
+}
+
+// the attributes of a registered class's paragraph. each one can be taken
+// once, and is None if it isn't there.
+pub struct SceneAttributes<'a> {
+    paragraph: &'a mut Paragraph,
+}
+
+impl SceneAttributes<'_> {
+    pub fn float(&mut self, key: &str) -> Result<Option<f32>> {
+        self.take(key, Attribute::as_float)
+    }
+
+    pub fn word(&mut self, key: &str) -> Result<Option<String>> {
+        self.take(key, Attribute::as_word)
+    }
+
+    pub fn vector(&mut self, key: &str) -> Result<Option<Vector>> {
+        self.take(key, Attribute::as_vector)
+    }
+
+    pub fn vertex(&mut self, key: &str) -> Result<Option<Vertex>> {
+        self.take(key, Attribute::as_vertex)
+    }
+
+    pub fn colour(&mut self, key: &str) -> Result<Option<Colour>> {
+        self.take(key, Attribute::as_colour)
+    }
+
+    // a material sub-paragraph, e.g. an object's material
+    pub fn material(&mut self, key: &str) -> Result<Option<Arc<dyn Material>>> {
+        match self.paragraph.attributes.remove(key) {
+            Some(attribute) => attribute.into_material().map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // an error pointing at the paragraph, e.g. for invalid combinations
+    pub fn error(&self, message: &str) -> ParseError {
+        err!(self.paragraph.start_line, "{}", message)
+    }
+
+    fn take<T>(&mut self, key: &str, value: fn(&Attribute) -> Result<T>) -> Result<Option<T>> {
+        match self.paragraph.attributes.remove(key) {
+            Some(attribute) => value(&attribute).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// an attribute set from outside the scene file, e.g. "camera.fov=60". it is
+// applied to every paragraph of that kind, replacing any existing value. the
+// key "class" changes the paragraph's class instead, e.g.
+// "scene.class=PhotonScene".
+#[derive(Clone)]
+pub struct SceneOverride {
+    kind: String,
+    key: String,
+    value: String,
+}
+
+impl SceneOverride {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (path, value) = s.split_once('=')?;
+        let (kind, key) = path.split_once('.')?;
+        if kind.is_empty() || key.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            kind: kind.to_string(),
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    // overrides don't come from a line in the file, so errors point at line 0
+    fn apply(&self, paragraphs: &mut [Paragraph]) -> Result<()> {
+        let words: Vec<&str> = self.value.split_whitespace().collect();
+
+        let mut matched = false;
+        for paragraph in paragraphs.iter_mut().filter(|p| p.kind == self.kind) {
+            matched = true;
+            if self.key == "class" {
+                let [class] = words[..] else {
+                    bail!(0, "Override {}.class must be one word", self.kind);
+                };
+                paragraph.class = class.to_string();
+                continue;
+            }
+
+            let attribute = Attribute {
+                key: self.key.clone(),
+                value: AttributeValue::parse(&words, 0)?,
+                line_number: paragraph.start_line,
+                overridden: true,
+            };
+            paragraph.attributes.insert(self.key.clone(), attribute);
+        }
+
+        if !matched {
+            bail!(
+                0,
+                "Override {}.{} matches no paragraphs",
+                self.kind,
+                self.key
+            );
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SceneOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}={}", self.kind, self.key, self.value)
     }
 }
 
 enum ParagraphItem {
     Env(Box<dyn Environment>),
     Camera(Box<FullCamera>),
+    InactiveCamera(Box<FullCamera>),
+    Animation(Animation),
     Light(Box<dyn Light>),
     Object(Box<dyn Object>),
     Material(Arc<dyn Material>),
+    Volume(Volume),
 }
 
 struct SceneFileParagraphs {
@@ -681,3 +3125,77 @@ impl Iterator for SceneFileParagraphs {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the offsets of the copies the file's repeat paragraph expands into
+    fn repeat_offsets(contents: &str) -> Result<Vec<[f32; 3]>> {
+        let paragraphs = Paragraph::parse_whole_file(contents.to_string())?;
+        let offsets = paragraphs
+            .iter()
+            .map(|copy| {
+                assert_eq!(
+                    (copy.kind.as_str(), copy.class.as_str()),
+                    ("object", "Sphere")
+                );
+                let offset = copy.offset.unwrap();
+                [offset.x, offset.y, offset.z]
+            })
+            .collect();
+        Ok(offsets)
+    }
+
+    const SPHERE: &str = "    object Sphere\n        centre 0.0 0.0 0.0\n        radius 0.5\n";
+
+    #[test]
+    fn repeat_line() {
+        let contents = format!("repeat Line\n    count 3\n    step 1.0 0.0 2.0\n{SPHERE}");
+        assert_eq!(
+            repeat_offsets(&contents).unwrap(),
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 2.0], [2.0, 0.0, 4.0]]
+        );
+    }
+
+    #[test]
+    fn repeat_grid() {
+        let contents = format!("repeat Grid\n    count 2 3 1\n    spacing 1.0 2.0 3.0\n{SPHERE}");
+        let offsets = repeat_offsets(&contents).unwrap();
+        assert_eq!(offsets.len(), 6);
+        assert_eq!(offsets[1], [1.0, 0.0, 0.0]);
+        assert_eq!(offsets[2], [0.0, 2.0, 0.0]);
+        assert_eq!(offsets[5], [1.0, 4.0, 0.0]);
+    }
+
+    #[test]
+    fn invalid_repeats() {
+        for repeat in [
+            "repeat Line\n    count 0\n    step 1.0 0.0 0.0\n",
+            "repeat Line\n    count 1.5\n    step 1.0 0.0 0.0\n",
+            "repeat Line\n    count 2\n",
+            "repeat Grid\n    count 2 0 1\n    spacing 1.0 1.0 1.0\n",
+            "repeat Spiral\n    count 2\n",
+            "repeat Line\n    count 2\n    step 1.0 0.0 0.0\n    colour 1.0\n",
+            "repeat Grid\n    count 1000 1000 1\n    spacing 1.0 1.0 1.0\n",
+        ] {
+            assert!(
+                repeat_offsets(&format!("{repeat}{SPHERE}")).is_err(),
+                "{repeat}"
+            );
+        }
+        assert!(repeat_offsets("repeat Line\n    count 2\n    step 1.0 0.0 0.0\n").is_err());
+    }
+
+    #[test]
+    fn repeat_names() {
+        let contents =
+            format!("repeat Line\n    count 3\n    step 1.0 0.0 0.0\n{SPHERE}        name ball\n");
+        let names: Vec<String> = Paragraph::parse_whole_file(contents)
+            .unwrap()
+            .into_iter()
+            .map(|mut copy| copy.get_attr("name").unwrap().as_word().unwrap())
+            .collect();
+        assert_eq!(names, ["ball_0", "ball_1", "ball_2"]);
+    }
+}
@@ -0,0 +1,118 @@
+// Scene files written as JSON or YAML, for tools that generate scenes. The
+// file is a list of paragraphs, each a map with its kind, class and
+// attributes:
+//
+//     [
+//         { "kind": "camera", "class": "Camera", "position": [0, 0, -4.5] },
+//         { "kind": "object", "class": "Sphere", "centre": "0 0 1", "radius": 1,
+//           "material": { "class": "FalseColour" } }
+//     ]
+//
+// numbers, strings (read like the text format's values) and lists of three
+// numbers are attribute values, and maps are sub-paragraphs, named by their
+// key. they become the same paragraphs as the text format, so everything
+// else works the same. there are no line numbers once the file has been
+// read, so errors give the paragraph's position in the list instead.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::core::{transform::UpAxis, vector::Vector};
+
+use super::{Attribute, AttributeValue, LineNumber, Paragraph, ParseError, Result};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Bool(bool),
+    Number(f32),
+    Text(String),
+    List(Vec<f32>),
+    Map(HashMap<String, Value>),
+}
+
+type Paragraphs = Vec<HashMap<String, Value>>;
+
+#[cfg(feature = "json")]
+pub fn parse_json(contents: &str) -> (Vec<Paragraph>, Vec<ParseError>) {
+    match serde_json::from_str::<Paragraphs>(contents) {
+        Ok(paragraphs) => into_paragraphs(paragraphs),
+        Err(e) => (Vec::new(), vec![err!(e.line() as LineNumber, "{}", e)]),
+    }
+}
+
+#[cfg(feature = "yaml")]
+pub fn parse_yaml(contents: &str) -> (Vec<Paragraph>, Vec<ParseError>) {
+    match serde_yaml::from_str::<Paragraphs>(contents) {
+        Ok(paragraphs) => into_paragraphs(paragraphs),
+        Err(e) => {
+            let line = e.location().map_or(0, |location| location.line());
+            (Vec::new(), vec![err!(line as LineNumber, "{}", e)])
+        }
+    }
+}
+
+fn into_paragraphs(paragraphs: Paragraphs) -> (Vec<Paragraph>, Vec<ParseError>) {
+    let results = paragraphs
+        .into_iter()
+        .enumerate()
+        .map(|(i, map)| into_paragraph(map, None, i as LineNumber + 1).map_err(|e| vec![e]))
+        .collect();
+    Paragraph::expand_all(results)
+}
+
+// kind is None for top level paragraphs, which say what they are
+fn into_paragraph(
+    mut map: HashMap<String, Value>,
+    kind: Option<String>,
+    number: LineNumber,
+) -> Result<Paragraph> {
+    let mut take_word = |key: &str| match map.remove(key) {
+        Some(Value::Text(word)) => Ok(word),
+        Some(_) => bail!(number, "{} must be a string", key),
+        None => bail!(number, "Missing paragraph {}", key),
+    };
+    let kind = match kind {
+        Some(kind) => kind,
+        None => take_word("kind")?,
+    };
+    let class = take_word("class")?;
+
+    let mut attributes = HashMap::new();
+    for (key, value) in map {
+        let value = match value {
+            Value::Bool(b) => AttributeValue::Float(if b { 1.0 } else { 0.0 }),
+            Value::Number(f) => AttributeValue::Float(f),
+            Value::Text(text) => {
+                let words: Vec<&str> = text.split_whitespace().collect();
+                AttributeValue::parse(&words, number)?
+            }
+            Value::List(list) => match list[..] {
+                [x, y, z] => AttributeValue::Vector(Vector::new(x, y, z)),
+                [x0, y0, x1, y1] => AttributeValue::Rectangle([x0, y0, x1, y1]),
+                _ => bail!(number, "{} must have three or four numbers", key),
+            },
+            Value::Map(map) => {
+                let paragraph = into_paragraph(map, Some(key.clone()), number)?;
+                AttributeValue::SubParagraph(Box::new(paragraph))
+            }
+        };
+        let attribute = Attribute {
+            key: key.clone(),
+            value,
+            line_number: number,
+            overridden: false,
+        };
+        attributes.insert(key, attribute);
+    }
+
+    Ok(Paragraph {
+        kind,
+        class,
+        attributes,
+        start_line: number,
+        axis: UpAxis::default(),
+        offset: None,
+    })
+}
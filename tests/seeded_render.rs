@@ -0,0 +1,60 @@
+// a seeded scene has to render the same however many threads share the work
+
+use rust_raytracer::{cameras::camera::Camera, scene_file::SceneFile};
+
+const OBJECTS: &str = "
+camera Camera
+    width 64
+    height 48
+    samples 2
+    position 0.0 3.0 0.5
+    lookat 0.0 0.4 1.0
+
+light Point
+    position 0.0 4.0 3.0
+    colour 1.0
+
+object Plane
+    point 0.0 0.0 0.0
+    up 0.0 0.0 1.0
+    normal 0.0 1.0 0.0
+    material Monochrome
+        colour 0.8 0.8 0.8
+        shininess 100.0
+
+object Plane
+    point -5.0 0.0 0.0
+    up 0.0 1.0 0.0
+    normal 1.0 0.0 0.0
+    material Monochrome
+        colour 0.5 0.0 0.0
+        shininess 100.0
+
+object Sphere
+    centre 0.0 1.0 4.0
+    radius 1.0
+    material Monochrome
+        colour 0.2 0.4 0.9
+        shininess 20.0
+";
+
+fn render(scene: &str, threads: u32) -> Vec<u8> {
+    let contents = format!("{scene}    seed 42\n    threads {threads}\n{OBJECTS}");
+    let mut loaded = SceneFile::from_contents(contents, &[], 0.0).unwrap();
+    loaded
+        .camera
+        .render(loaded.environment.as_mut(), &mut |_| {})
+        .to_bytes()
+}
+
+#[test]
+fn same_render_with_any_number_of_threads() {
+    let scenes = [
+        "scene Scene\n    ambient_occlusion 1.0\n    ambient_occlusion_samples 4\n",
+        "scene PhotonScene\n    preview 1.0\n    irradiance_cache 0.5\n",
+    ];
+    for scene in scenes {
+        let one_thread = render(scene, 1);
+        assert!(one_thread == render(scene, 4), "{scene} differs");
+    }
+}